@@ -0,0 +1,107 @@
+//! Extensions: drivers and filesystems that live in crates outside this one,
+//! such as a board vendor's, and are linked into the kernel image, usually
+//! behind a feature of the kernel crate.
+//!
+//! An extension declares itself by adding an [`Extension`] to
+//! [`EXTENSIONS`] with `#[linkme::distributed_slice]`; its `init` function
+//! registers what it provides, through
+//! [`register_driver`](crate::vfs::dev::hotplug::register_driver) and
+//! [`register_filesystem`](crate::vfs::fs_type::register_filesystem).
+//! Nothing has to change in this crate for an extension to be picked up.
+//!
+//! Extensions are initialized once the VFS is mounted, each after those it
+//! depends on and otherwise in order of name. An extension is skipped if a
+//! dependency is missing, failed to initialize or depends on it in turn.
+
+use alloc::{
+    collections::{btree_map::BTreeMap, btree_set::BTreeSet},
+    vec::Vec,
+};
+
+use axerrno::AxResult;
+use linkme::distributed_slice;
+use spin::Mutex;
+
+/// An extension linked into the kernel image.
+pub struct Extension {
+    /// The name of the extension, which others use to depend on it.
+    pub name: &'static str,
+    /// The names of the extensions to initialize first.
+    pub depends: &'static [&'static str],
+    /// Registers the drivers and filesystems of the extension.
+    pub init: fn() -> AxResult<()>,
+}
+
+/// The extensions linked into the kernel image.
+#[distributed_slice]
+pub static EXTENSIONS: [Extension];
+
+/// Whether each extension handled so far was initialized successfully.
+static LOADED: Mutex<BTreeMap<&'static str, bool>> = Mutex::new(BTreeMap::new());
+
+/// Returns whether the extension `name` was initialized successfully.
+pub fn is_loaded(name: &str) -> bool {
+    LOADED.lock().get(name).copied().unwrap_or(false)
+}
+
+/// Initializes the extensions, in dependency order.
+pub(crate) fn init() {
+    let mut pending = EXTENSIONS.iter().collect::<Vec<_>>();
+    pending.sort_by_key(|ext| ext.name);
+    pending.dedup_by(|ext, prev| {
+        if ext.name == prev.name {
+            warn!("extension {}: declared more than once", ext.name);
+        }
+        ext.name == prev.name
+    });
+    let names = pending.iter().map(|ext| ext.name).collect::<BTreeSet<_>>();
+
+    // The lock isn't held across `init`, which may ask what is loaded.
+    let set_loaded = |name, ok| LOADED.lock().insert(name, ok);
+    loop {
+        let count = pending.len();
+        pending.retain(|ext| {
+            let mut ready = true;
+            for dep in ext.depends {
+                let state = LOADED.lock().get(dep).copied();
+                match state {
+                    Some(true) => {}
+                    Some(false) => {
+                        warn!("extension {}: dependency {dep} failed", ext.name);
+                        set_loaded(ext.name, false);
+                        return false;
+                    }
+                    None if names.contains(dep) => ready = false,
+                    None => {
+                        warn!("extension {}: missing dependency {dep}", ext.name);
+                        set_loaded(ext.name, false);
+                        return false;
+                    }
+                }
+            }
+            if !ready {
+                return true;
+            }
+
+            info!("Initialize extension {}...", ext.name);
+            let result = (ext.init)();
+            if let Err(err) = &result {
+                warn!("extension {}: initialization failed: {err:?}", ext.name);
+            }
+            set_loaded(ext.name, result.is_ok());
+            false
+        });
+
+        if pending.is_empty() {
+            break;
+        }
+        // No extension could be initialized, so those left wait on each other.
+        if pending.len() == count {
+            for ext in pending {
+                warn!("extension {}: circular dependency", ext.name);
+                set_loaded(ext.name, false);
+            }
+            break;
+        }
+    }
+}
@@ -8,9 +8,10 @@
 
 use alloc::{
     borrow::Cow,
-    collections::vec_deque::VecDeque,
+    collections::{btree_map::BTreeMap, vec_deque::VecDeque},
     sync::{Arc, Weak},
     task::Wake,
+    vec::Vec,
 };
 use core::{
     hash::{Hash, Hasher},
@@ -23,7 +24,7 @@ use axpoll::{IoEvents, PollSet, Pollable};
 use bitflags::bitflags;
 use hashbrown::HashMap;
 use kspin::SpinNoPreempt;
-use linux_raw_sys::general::{EPOLLET, EPOLLONESHOT, epoll_event};
+use linux_raw_sys::general::{EPOLLET, EPOLLEXCLUSIVE, EPOLLONESHOT, epoll_event};
 
 use crate::file::{FileLike, get_file_like};
 
@@ -38,6 +39,7 @@ bitflags! {
     pub struct EpollFlags: u32 {
         const EDGE_TRIGGER = EPOLLET;
         const ONESHOT = EPOLLONESHOT;
+        const EXCLUSIVE = EPOLLEXCLUSIVE;
     }
 }
 
@@ -118,6 +120,12 @@ impl EntryKey {
     fn get_file(&self) -> Option<Arc<dyn FileLike>> {
         self.file.upgrade()
     }
+
+    /// Identifies the file, regardless of the fd it was added through.
+    #[inline]
+    fn file_id(&self) -> usize {
+        self.file.as_ptr() as *const () as usize
+    }
 }
 
 impl Hash for EntryKey {
@@ -138,6 +146,7 @@ struct EpollInterest {
     event: EpollEvent,
     mode: SpinNoPreempt<TriggerMode>,
     in_ready_queue: AtomicBool,
+    exclusive: bool,
 }
 
 impl EpollInterest {
@@ -147,6 +156,7 @@ impl EpollInterest {
             event,
             mode: SpinNoPreempt::new(TriggerMode::from_flags(flags)),
             in_ready_queue: AtomicBool::new(false),
+            exclusive: flags.contains(EpollFlags::EXCLUSIVE),
         }
     }
 
@@ -207,6 +217,57 @@ impl EpollInterest {
     }
 }
 
+/// The `EPOLLEXCLUSIVE` interests in one file, across all epoll instances.
+///
+/// A wakeup of the file queues only one of them. The others are parked, with
+/// no waker registered on the file, until the queued one has been consumed.
+#[derive(Default)]
+struct ExclusiveGroup {
+    /// The interest queued on behalf of the group.
+    holder: Weak<EpollInterest>,
+    parked: Vec<(Weak<EpollInner>, Weak<EpollInterest>)>,
+}
+
+/// Exclusive groups, keyed by [`EntryKey::file_id`].
+static EXCLUSIVE_GROUPS: SpinNoPreempt<BTreeMap<usize, ExclusiveGroup>> =
+    SpinNoPreempt::new(BTreeMap::new());
+
+/// Decides whether a woken exclusive interest may be queued, parking it if
+/// another interest of its group already is.
+fn claim_exclusive(epoll: &Weak<EpollInner>, interest: &Arc<EpollInterest>) -> bool {
+    let mut groups = EXCLUSIVE_GROUPS.lock();
+    let group = groups.entry(interest.key.file_id()).or_default();
+    let busy = match group.holder.upgrade() {
+        Some(holder) => !Arc::ptr_eq(&holder, interest) && holder.is_in_queue(),
+        None => false,
+    };
+    if busy {
+        group.parked.push((epoll.clone(), Arc::downgrade(interest)));
+        return false;
+    }
+    group.holder = Arc::downgrade(interest);
+    true
+}
+
+/// Ends the turn of `interest` in its group, and lets the parked interests
+/// compete for the events that are still pending.
+fn release_exclusive(interest: &Arc<EpollInterest>) {
+    let parked = {
+        let mut groups = EXCLUSIVE_GROUPS.lock();
+        let id = interest.key.file_id();
+        match groups.get(&id) {
+            Some(group) if group.holder.ptr_eq(&Arc::downgrade(interest)) => {}
+            _ => return,
+        }
+        groups.remove(&id).unwrap().parked
+    };
+    for (epoll, interest) in parked {
+        if let (Some(epoll), Some(interest)) = (epoll.upgrade(), interest.upgrade()) {
+            epoll.check_and_register_waker(&interest);
+        }
+    }
+}
+
 struct InterestWaker {
     epoll: Weak<EpollInner>,
     interest: Weak<EpollInterest>,
@@ -226,6 +287,10 @@ impl Wake for InterestWaker {
             return;
         };
 
+        if interest.exclusive && !claim_exclusive(&self.epoll, &interest) {
+            return;
+        }
+
         if interest.try_mark_in_queue() {
             epoll
                 .ready_queue
@@ -240,10 +305,19 @@ impl Wake for InterestWaker {
     }
 }
 
+/// How deep epoll instances may be nested in each other, as on Linux.
+const EP_MAX_NESTS: usize = 4;
+
+/// Serializes the changes to the nesting of epoll instances, so that
+/// concurrent additions can't create a cycle together.
+static NESTING: SpinNoPreempt<()> = SpinNoPreempt::new(());
+
 struct EpollInner {
     interests: SpinNoPreempt<HashMap<EntryKey, Arc<EpollInterest>>>,
     ready_queue: SpinNoPreempt<VecDeque<Weak<EpollInterest>>>,
     poll_ready: PollSet,
+    /// The epoll instances watching this one.
+    parents: SpinNoPreempt<Vec<Weak<EpollInner>>>,
 }
 
 impl Default for EpollInner {
@@ -252,41 +326,75 @@ impl Default for EpollInner {
             interests: SpinNoPreempt::new(HashMap::new()),
             ready_queue: SpinNoPreempt::new(VecDeque::new()),
             poll_ready: PollSet::new(),
+            parents: SpinNoPreempt::new(Vec::new()),
         }
     }
 }
 
-#[derive(Default)]
-pub struct Epoll {
-    inner: Arc<EpollInner>,
-}
-
-impl Epoll {
-    pub fn new() -> Self {
-        Self::default()
+impl EpollInner {
+    /// Returns the epoll instances watched by this one.
+    fn nested(&self) -> Vec<Arc<EpollInner>> {
+        self.interests
+            .lock()
+            .keys()
+            .filter_map(|key| Some(key.get_file()?.downcast_ref::<Epoll>()?.inner.clone()))
+            .collect()
     }
 
-    // only register waker, not add to ready queue
-    fn register_waker_only(&self, interest: &Arc<EpollInterest>) {
-        let Some(file) = interest.key.get_file() else {
-            return;
-        };
+    /// Returns how many levels of epoll instances are stacked above this one.
+    fn height(&self) -> usize {
+        self.parents
+            .lock()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .map(|parent| parent.height() + 1)
+            .max()
+            .unwrap_or(0)
+    }
 
-        if !interest.is_enabled() {
-            return;
+    /// Returns how many levels of epoll instances are nested in this one.
+    /// Fails with `ELOOP` if `ancestor` is one of them.
+    fn depth(&self, ancestor: &Arc<EpollInner>) -> AxResult<usize> {
+        let mut depth = 0;
+        for child in self.nested() {
+            if Arc::ptr_eq(&child, ancestor) {
+                return Err(AxError::FilesystemLoop);
+            }
+            depth = depth.max(child.depth(ancestor)? + 1);
         }
+        Ok(depth)
+    }
 
-        let waker = Waker::from(Arc::new(InterestWaker {
-            epoll: Arc::downgrade(&self.inner),
-            interest: Arc::downgrade(interest),
-        }));
+    /// Checks that this instance may watch `child`, which must not lead to a
+    /// cycle or to too deep a nesting. Must be called with [`NESTING`] held.
+    fn check_nesting(self: &Arc<Self>, child: &Arc<EpollInner>) -> AxResult<()> {
+        if Arc::ptr_eq(self, child) {
+            return Err(AxError::InvalidInput);
+        }
+        if self.height() + 1 + child.depth(self)? > EP_MAX_NESTS {
+            return Err(AxError::FilesystemLoop);
+        }
+        Ok(())
+    }
 
-        let mut context = Context::from_waker(&waker);
-        file.register(&mut context, interest.event.events);
+    /// Returns whether any of the queued interests has events to report.
+    ///
+    /// Entries stay queued until they are consumed, even if their file is no
+    /// longer ready, so the queue alone would report spurious readiness to
+    /// the instances watching this one.
+    fn has_events(&self) -> bool {
+        let queue = self.ready_queue.lock().clone();
+        queue.iter().filter_map(Weak::upgrade).any(|interest| {
+            interest.is_enabled()
+                && interest
+                    .key
+                    .get_file()
+                    .is_some_and(|file| !(file.poll() & interest.event.events).is_empty())
+        })
     }
 
     // for add/modify
-    fn check_and_register_waker(&self, interest: &Arc<EpollInterest>) {
+    fn check_and_register_waker(self: &Arc<Self>, interest: &Arc<EpollInterest>) {
         let Some(file) = interest.key.get_file() else {
             return;
         };
@@ -296,7 +404,7 @@ impl Epoll {
         }
 
         let waker = Waker::from(Arc::new(InterestWaker {
-            epoll: Arc::downgrade(&self.inner),
+            epoll: Arc::downgrade(self),
             interest: Arc::downgrade(interest),
         }));
 
@@ -314,9 +422,66 @@ impl Epoll {
             }
         }
     }
+}
+
+impl Drop for EpollInner {
+    fn drop(&mut self) {
+        // Don't leave the other members of a group parked behind an interest
+        // that is going away.
+        for interest in self.interests.lock().values() {
+            if interest.exclusive {
+                release_exclusive(interest);
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Epoll {
+    inner: Arc<EpollInner>,
+}
+
+impl Epoll {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // only register waker, not add to ready queue
+    fn register_waker_only(&self, interest: &Arc<EpollInterest>) {
+        let Some(file) = interest.key.get_file() else {
+            return;
+        };
+
+        if !interest.is_enabled() {
+            return;
+        }
+
+        let waker = Waker::from(Arc::new(InterestWaker {
+            epoll: Arc::downgrade(&self.inner),
+            interest: Arc::downgrade(interest),
+        }));
+
+        let mut context = Context::from_waker(&waker);
+        file.register(&mut context, interest.event.events);
+    }
 
     pub fn add(&self, fd: i32, event: EpollEvent, flags: EpollFlags) -> AxResult<()> {
+        // Like Linux, exclusive wakeups can't be combined with one-shot mode.
+        if flags.contains(EpollFlags::EXCLUSIVE | EpollFlags::ONESHOT) {
+            return Err(AxError::InvalidInput);
+        }
         let key = EntryKey::new(fd)?;
+        let nested = key
+            .get_file()
+            .and_then(|file| Some(file.downcast_ref::<Epoll>()?.inner.clone()));
+        let nesting = nested.as_ref().map(|_| NESTING.lock());
+        if let Some(child) = &nested {
+            if flags.contains(EpollFlags::EXCLUSIVE) {
+                return Err(AxError::InvalidInput);
+            }
+            self.inner.check_nesting(child)?;
+        }
+
         let interest = Arc::new(EpollInterest::new(key.clone(), event, flags));
         let mut guard = self.inner.interests.lock();
         if guard.contains_key(&key) {
@@ -324,8 +489,14 @@ impl Epoll {
         }
         guard.insert(key.clone(), Arc::clone(&interest));
         drop(guard);
+        if let Some(child) = nested {
+            let mut parents = child.parents.lock();
+            parents.retain(|it| it.strong_count() > 0);
+            parents.push(Arc::downgrade(&self.inner));
+        }
+        drop(nesting);
         trace!("Epoll add fd: {} interest {:?} ", fd, interest.event.events);
-        self.check_and_register_waker(&interest);
+        self.inner.check_and_register_waker(&interest);
         Ok(())
     }
 
@@ -335,6 +506,10 @@ impl Epoll {
 
         let mut guard = self.inner.interests.lock();
         let old = guard.get_mut(&key).ok_or(AxError::NotFound)?;
+        // Exclusive interests can only be added or deleted.
+        if interest.exclusive || old.exclusive {
+            return Err(AxError::InvalidInput);
+        }
 
         // update new interest if old already in ready queue
         if old.is_in_queue() {
@@ -347,17 +522,31 @@ impl Epoll {
             fd, interest.event.events
         );
         // reset waker
-        self.check_and_register_waker(&interest);
+        self.inner.check_and_register_waker(&interest);
         Ok(())
     }
 
     pub fn delete(&self, fd: i32) -> AxResult<()> {
         let key = EntryKey::new(fd)?;
-        self.inner
+        let interest = self
+            .inner
             .interests
             .lock()
             .remove(&key)
             .ok_or(AxError::NotFound)?;
+        if interest.exclusive {
+            release_exclusive(&interest);
+        }
+        if let Some(file) = interest.key.get_file()
+            && let Some(child) = file.downcast_ref::<Epoll>()
+        {
+            let _nesting = NESTING.lock();
+            child
+                .inner
+                .parents
+                .lock()
+                .retain(|it| it.strong_count() > 0 && it.as_ptr() != Arc::as_ptr(&self.inner));
+        }
         trace!("Epoll: delete fd={fd}");
         Ok(())
     }
@@ -422,6 +611,9 @@ impl Epoll {
                     self.register_waker_only(&interest);
                 }
             }
+            if interest.exclusive {
+                release_exclusive(&interest);
+            }
         }
 
         if count == 0 {
@@ -440,10 +632,10 @@ impl FileLike for Epoll {
 
 impl Pollable for Epoll {
     fn poll(&self) -> IoEvents {
-        if self.inner.ready_queue.lock().is_empty() {
-            IoEvents::empty()
-        } else {
+        if self.inner.has_events() {
             IoEvents::IN
+        } else {
+            IoEvents::empty()
         }
     }
 
@@ -6,16 +6,25 @@ use core::{
     task::Context,
 };
 
-use axerrno::{AxError, AxResult};
-use axfs::{FS_CONTEXT, FsContext};
-use axfs_ng_vfs::{Location, Metadata, NodeFlags};
+use axerrno::{AxError, AxResult, LinuxError};
+use axfs::{FS_CONTEXT, FileFlags, FsContext};
+use axfs_ng_vfs::{Location, Metadata, NodeFlags, NodeType};
+use axio::{IoBuf, Seek, SeekFrom};
 use axpoll::{IoEvents, Pollable};
 use axsync::Mutex;
-use axtask::future::{block_on, poll_io};
-use linux_raw_sys::general::{AT_EMPTY_PATH, AT_FDCWD, AT_SYMLINK_NOFOLLOW};
+use axtask::{
+    current,
+    future::{block_on, poll_io},
+};
+use linux_raw_sys::general::{AT_EMPTY_PATH, AT_FDCWD, AT_SYMLINK_NOFOLLOW, RLIMIT_FSIZE};
+use starry_core::task::{AsThread, send_signal_to_thread};
+use starry_signal::{SignalInfo, Signo};
 
 use super::{FileLike, Kstat, get_file_like};
-use crate::file::{IoDst, IoSrc};
+use crate::{
+    file::{IoDst, IoSrc},
+    io::TakeBuf,
+};
 
 pub fn with_fs<R>(dirfd: c_int, f: impl FnOnce(&mut FsContext) -> AxResult<R>) -> AxResult<R> {
     let mut fs = FS_CONTEXT.lock();
@@ -96,6 +105,39 @@ pub fn metadata_to_kstat(metadata: &Metadata) -> Kstat {
     }
 }
 
+fn file_size_limit() -> u64 {
+    current().as_thread().proc_data.rlim.read()[RLIMIT_FSIZE].current
+}
+
+/// Raises `SIGXFSZ` for a file growing past `RLIMIT_FSIZE`, returning the
+/// error to fail with.
+fn file_too_large() -> AxError {
+    let tid = current().as_thread().tid();
+    send_signal_to_thread(None, tid, Some(SignalInfo::new_kernel(Signo::SIGXFSZ))).ok();
+    AxError::from(LinuxError::EFBIG)
+}
+
+/// Returns how much of a write of `len` bytes at `offset` `RLIMIT_FSIZE`
+/// allows. A write that starts at or past the limit fails with `EFBIG`.
+pub fn check_file_size(offset: u64, len: usize) -> AxResult<usize> {
+    let limit = file_size_limit();
+    if len == 0 || offset.saturating_add(len as u64) <= limit {
+        Ok(len)
+    } else if offset >= limit {
+        Err(file_too_large())
+    } else {
+        Ok((limit - offset) as usize)
+    }
+}
+
+/// Checks that a file may be resized to `len` bytes under `RLIMIT_FSIZE`.
+pub fn check_file_len(len: u64) -> AxResult<()> {
+    if len > file_size_limit() {
+        return Err(file_too_large());
+    }
+    Ok(())
+}
+
 /// File wrapper for `axfs::fops::File`.
 pub struct File {
     inner: axfs::File,
@@ -117,6 +159,30 @@ impl File {
     fn is_blocking(&self) -> bool {
         self.inner.location().flags().contains(NodeFlags::BLOCKING)
     }
+
+    /// Whether I/O on the file counts as block I/O in the resource usage.
+    fn is_block_io(&self) -> bool {
+        matches!(
+            self.inner.location().node_type(),
+            NodeType::RegularFile | NodeType::BlockDevice
+        )
+    }
+
+    /// Counts `len` bytes read from the file in the resource usage of the
+    /// current thread.
+    pub fn account_read(&self, len: usize) {
+        if self.is_block_io() {
+            current().as_thread().usage.read(len);
+        }
+    }
+
+    /// Counts `len` bytes written to the file in the resource usage of the
+    /// current thread.
+    pub fn account_write(&self, len: usize) {
+        if self.is_block_io() {
+            current().as_thread().usage.write(len);
+        }
+    }
 }
 
 fn path_for(loc: &Location) -> Cow<'static, str> {
@@ -134,10 +200,23 @@ impl FileLike for File {
                 inner.read(&mut *dst)
             }))
         }
+        .inspect(|&len| self.account_read(len))
     }
 
     fn write(&self, src: &mut IoSrc) -> AxResult<usize> {
         let inner = self.inner();
+        // `RLIMIT_FSIZE` only applies to regular files.
+        let allowed = if inner.location().node_type() == NodeType::RegularFile {
+            let offset = if inner.flags().contains(FileFlags::APPEND) {
+                inner.location().len()?
+            } else {
+                inner.seek(SeekFrom::Current(0))?
+            };
+            check_file_size(offset, src.remaining())?
+        } else {
+            src.remaining()
+        };
+        let src: &mut IoSrc = &mut TakeBuf::new(src, allowed);
         if likely(self.is_blocking()) {
             inner.write(src)
         } else {
@@ -145,6 +224,7 @@ impl FileLike for File {
                 inner.write(&mut *src)
             }))
         }
+        .inspect(|&len| self.account_write(len))
     }
 
     fn stat(&self) -> AxResult<Kstat> {
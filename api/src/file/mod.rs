@@ -2,9 +2,11 @@ pub mod epoll;
 pub mod event;
 mod fs;
 mod net;
+pub mod perf;
 mod pidfd;
 mod pipe;
 pub mod signalfd;
+pub mod watch_queue;
 
 use alloc::{borrow::Cow, sync::Arc};
 use core::{ffi::c_int, time::Duration};
@@ -22,7 +24,10 @@ use spin::RwLock;
 use starry_core::{resources::AX_FILE_LIMIT, task::AsThread};
 
 pub use self::{
-    fs::{Directory, File, ResolveAtResult, metadata_to_kstat, resolve_at, with_fs},
+    fs::{
+        Directory, File, ResolveAtResult, check_file_len, check_file_size, metadata_to_kstat,
+        resolve_at, with_fs,
+    },
     net::Socket,
     pidfd::PidFd,
     pipe::Pipe,
@@ -199,15 +204,25 @@ pub fn get_file_like(fd: c_int) -> AxResult<Arc<dyn FileLike>> {
         .ok_or(AxError::BadFileDescriptor)
 }
 
+/// Returns the `RLIMIT_NOFILE` soft limit of the current process: file
+/// descriptors must be below it.
+pub fn nofile_limit() -> u64 {
+    current().as_thread().proc_data.rlim.read()[RLIMIT_NOFILE].current
+}
+
 /// Add a file to the file descriptor table.
 pub fn add_file_like(f: Arc<dyn FileLike>, cloexec: bool) -> AxResult<c_int> {
-    let max_nofile = current().as_thread().proc_data.rlim.read()[RLIMIT_NOFILE].current;
+    let max_nofile = nofile_limit();
     let mut table = FD_TABLE.write();
-    if table.count() as u64 >= max_nofile {
+    let fd = FileDescriptor { inner: f, cloexec };
+    let fd = table.add(fd).map_err(|_| AxError::TooManyOpenFiles)?;
+    // The lowest free descriptor is taken, so if it's past the limit, all
+    // the ones below are in use.
+    if fd as u64 >= max_nofile {
+        table.remove(fd);
         return Err(AxError::TooManyOpenFiles);
     }
-    let fd = FileDescriptor { inner: f, cloexec };
-    Ok(table.add(fd).map_err(|_| AxError::TooManyOpenFiles)? as c_int)
+    Ok(fd as c_int)
 }
 
 /// Close a file by `fd`.
@@ -1,29 +1,240 @@
-use alloc::{borrow::Cow, format, sync::Arc};
-use core::{ffi::c_int, ops::Deref, task::Context};
+use alloc::{borrow::Cow, collections::vec_deque::VecDeque, format, sync::Arc};
+use core::{
+    ffi::c_int,
+    ops::Deref,
+    sync::atomic::{AtomicU32, Ordering},
+    task::Context,
+    time::Duration,
+};
 
 use axerrno::{AxError, AxResult};
+use axhal::time::monotonic_time;
 use axnet::{
     SocketOps,
     options::{Configurable, GetSocketOption, SetSocketOption},
 };
 use axpoll::{IoEvents, Pollable};
-use linux_raw_sys::general::S_IFSOCK;
+use axsync::Mutex;
+use axtask::{
+    current,
+    future::{self, block_on, poll_io},
+};
+use linux_raw_sys::{general::S_IFSOCK, net::SOMAXCONN};
+use starry_core::task::AsThread;
 
 use super::{FileLike, Kstat};
 use crate::file::{IoDst, IoSrc, get_file_like};
 
-pub struct Socket(pub axnet::Socket);
+/// The longest `SO_BUSY_POLL` timeout, in microseconds; longer ones are
+/// capped, so that a socket can't keep a CPU spinning for long.
+const MAX_BUSY_POLL: u32 = 100_000;
+
+/// Accept-side state of a listening socket.
+#[derive(Default)]
+struct AcceptQueue {
+    /// The backlog passed to `listen(2)`, or `None` if the socket is not
+    /// listening.
+    backlog: Option<usize>,
+    /// The `TCP_DEFER_ACCEPT` timeout.
+    defer: Duration,
+    /// Connections already taken from the protocol stack but not yet handed
+    /// out by `accept(2)`, along with the deadline after which they are handed
+    /// out even if no data has arrived.
+    pending: VecDeque<(Socket, Duration)>,
+}
+
+impl AcceptQueue {
+    /// Returns the index of the first connection that can be handed out.
+    fn ready_index(&self) -> Option<usize> {
+        let now = monotonic_time();
+        self.pending.iter().position(|(conn, deadline)| {
+            now >= *deadline
+                || conn
+                    .poll()
+                    .intersects(IoEvents::IN | IoEvents::HUP | IoEvents::ERR)
+        })
+    }
+
+    fn take_ready(&mut self) -> Option<Socket> {
+        let index = self.ready_index()?;
+        self.pending.remove(index).map(|(conn, _)| conn)
+    }
+
+    /// Returns the time until the next pending connection expires.
+    fn next_timeout(&self) -> Option<Duration> {
+        let now = monotonic_time();
+        let earliest = self.pending.iter().map(|(_, deadline)| *deadline).min();
+        match earliest {
+            Some(deadline) => Some(deadline.saturating_sub(now).min(self.defer)),
+            None if !self.defer.is_zero() => Some(self.defer),
+            None => None,
+        }
+    }
+}
+
+pub struct Socket {
+    inner: axnet::Socket,
+    accept_queue: Mutex<AcceptQueue>,
+    /// Whether the socket was created with `IPPROTO_MPTCP`.
+    mptcp: bool,
+    /// The `SO_BUSY_POLL` timeout, in microseconds.
+    busy_poll: AtomicU32,
+}
+
+impl Socket {
+    pub fn new(inner: axnet::Socket) -> Self {
+        Self {
+            inner,
+            accept_queue: Mutex::new(AcceptQueue::default()),
+            mptcp: false,
+            busy_poll: AtomicU32::new(0),
+        }
+    }
+
+    /// Creates a Multipath TCP socket on top of a TCP socket.
+    ///
+    /// The protocol stack has no MPTCP support, so the connection always
+    /// consists of a single plain TCP subflow, as if the peer had fallen back
+    /// to TCP.
+    pub fn new_mptcp(inner: axnet::Socket) -> Self {
+        Self {
+            mptcp: true,
+            ..Self::new(inner)
+        }
+    }
+
+    /// Whether the socket was created with `IPPROTO_MPTCP`.
+    pub fn is_mptcp(&self) -> bool {
+        self.mptcp
+    }
+
+    /// Returns the `SO_BUSY_POLL` timeout, in microseconds.
+    pub fn busy_poll(&self) -> u32 {
+        self.busy_poll.load(Ordering::Relaxed)
+    }
+
+    /// Sets the `SO_BUSY_POLL` timeout, in microseconds, capped at
+    /// [`MAX_BUSY_POLL`]. Zero disables busy polling.
+    pub fn set_busy_poll(&self, usecs: u32) {
+        self.busy_poll
+            .store(usecs.min(MAX_BUSY_POLL), Ordering::Relaxed);
+    }
+
+    /// Before a blocking operation waiting for `events`, spins for up to the
+    /// `SO_BUSY_POLL` timeout in the hope that they arrive without the task
+    /// having to sleep.
+    ///
+    /// Each round yields to other runnable tasks, and the spinning stops as
+    /// soon as a signal the task doesn't block is pending, leaving it to the
+    /// blocking operation to be interrupted.
+    pub fn busy_wait(&self, events: IoEvents) {
+        let usecs = self.busy_poll();
+        if usecs == 0 || self.nonblocking() {
+            return;
+        }
+        let curr = current();
+        let signal = &curr.as_thread().signal;
+        let deadline = monotonic_time() + Duration::from_micros(usecs as u64);
+        while !self.poll().intersects(events | IoEvents::HUP | IoEvents::ERR)
+            && monotonic_time() < deadline
+        {
+            let pending = signal.pending();
+            if pending & signal.blocked() != pending {
+                break;
+            }
+            axtask::yield_now();
+        }
+    }
+
+    /// Starts listening for incoming connections.
+    ///
+    /// Like Linux, a `backlog` larger than `SOMAXCONN` (or negative) is
+    /// silently capped.
+    pub fn listen(&self, backlog: i32) -> AxResult<()> {
+        self.inner.listen()?;
+        self.accept_queue.lock().backlog = Some((backlog as u32).min(SOMAXCONN) as usize);
+        Ok(())
+    }
+
+    /// Whether the socket is listening for connections (`SO_ACCEPTCONN`).
+    pub fn is_listening(&self) -> bool {
+        self.accept_queue.lock().backlog.is_some()
+    }
+
+    /// Returns the `TCP_DEFER_ACCEPT` timeout.
+    pub fn defer_accept(&self) -> Duration {
+        self.accept_queue.lock().defer
+    }
+
+    /// Sets the `TCP_DEFER_ACCEPT` timeout. Zero disables deferring.
+    pub fn set_defer_accept(&self, timeout: Duration) {
+        self.accept_queue.lock().defer = timeout;
+    }
+
+    /// Returns the number of connections in the accept queue and the
+    /// backlog, or `None` if the socket is not listening.
+    ///
+    /// Connections still held by the protocol stack are not counted, since
+    /// they can't be looked at without accepting them.
+    pub fn backlog_stats(&self) -> Option<(usize, usize)> {
+        let queue = self.accept_queue.lock();
+        Some((queue.pending.len(), queue.backlog?))
+    }
+
+    /// Moves established connections from the protocol stack to the accept
+    /// queue, as long as the backlog allows. Only `accept` does this, so that
+    /// polling has no side effects.
+    fn fill_accept_queue(&self, queue: &mut AcceptQueue) -> AxResult<()> {
+        let Some(backlog) = queue.backlog else {
+            return Ok(());
+        };
+        // Checking for readiness first ensures `accept` never blocks here.
+        while queue.pending.len() < backlog.max(1) && self.inner.poll().contains(IoEvents::IN) {
+            let conn = Socket {
+                mptcp: self.mptcp,
+                ..Socket::new(self.inner.accept()?)
+            };
+            queue
+                .pending
+                .push_back((conn, monotonic_time() + queue.defer));
+        }
+        Ok(())
+    }
+
+    /// Accepts a new connection.
+    ///
+    /// With `TCP_DEFER_ACCEPT` set, a connection is only handed out once data
+    /// has arrived on it or its timeout has expired.
+    pub fn accept(&self) -> AxResult<Socket> {
+        self.busy_wait(IoEvents::IN);
+        loop {
+            let timeout = self.accept_queue.lock().next_timeout();
+            let result = block_on(future::timeout(
+                timeout,
+                poll_io(self, IoEvents::IN, self.nonblocking(), || {
+                    let mut queue = self.accept_queue.lock();
+                    self.fill_accept_queue(&mut queue)?;
+                    queue.take_ready().ok_or(AxError::WouldBlock)
+                }),
+            ));
+            if let Ok(result) = result {
+                return result;
+            }
+        }
+    }
+}
 
 impl Deref for Socket {
     type Target = axnet::Socket;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.inner
     }
 }
 
 impl FileLike for Socket {
     fn read(&self, dst: &mut IoDst) -> AxResult<usize> {
+        self.busy_wait(IoEvents::IN);
         self.recv(dst, axnet::RecvOptions::default())
     }
 
@@ -48,7 +259,7 @@ impl FileLike for Socket {
     }
 
     fn set_nonblocking(&self, nonblocking: bool) -> AxResult<()> {
-        self.0
+        self.inner
             .set_option(SetSocketOption::NonBlocking(&nonblocking))
     }
 
@@ -67,10 +278,29 @@ impl FileLike for Socket {
 }
 impl Pollable for Socket {
     fn poll(&self) -> IoEvents {
-        self.0.poll()
+        let queue = self.accept_queue.lock();
+        let mut events = self.inner.poll();
+        let Some(backlog) = queue.backlog else {
+            return events;
+        };
+        // A listening socket is readable when a queued connection can be
+        // handed out, or the protocol stack holds one that `accept` has room
+        // to queue. With deferred accepting, the latter may turn out to have
+        // no data yet, in which case `accept` queues it and keeps waiting.
+        let room = queue.pending.len() < backlog.max(1);
+        events.set(
+            IoEvents::IN,
+            queue.ready_index().is_some() || (room && events.contains(IoEvents::IN)),
+        );
+        events
     }
 
     fn register(&self, context: &mut Context<'_>, events: IoEvents) {
-        self.0.register(context, events);
+        self.inner.register(context, events);
+        if events.contains(IoEvents::IN) {
+            for (conn, _) in &self.accept_queue.lock().pending {
+                conn.register(context, IoEvents::IN);
+            }
+        }
     }
 }
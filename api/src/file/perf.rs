@@ -0,0 +1,191 @@
+//! Counting perf events, as created by `perf_event_open`.
+
+use alloc::{borrow::Cow, vec::Vec};
+use core::{
+    sync::atomic::{AtomicU64, Ordering},
+    task::Context,
+};
+
+use axerrno::{AxError, AxResult, LinuxError};
+use axhal::time::monotonic_time_nanos;
+use axpoll::{IoEvents, Pollable};
+use axtask::WeakAxTaskRef;
+use spin::Mutex;
+use starry_core::{rusage::thread_cpu_time, task::AsThread};
+use starry_vm::VmMutPtr;
+
+use crate::file::{FileLike, IoDst};
+
+const PERF_FORMAT_TOTAL_TIME_ENABLED: u64 = 1 << 0;
+const PERF_FORMAT_TOTAL_TIME_RUNNING: u64 = 1 << 1;
+const PERF_FORMAT_ID: u64 = 1 << 2;
+/// The `read_format` bits a counting event supports.
+pub const PERF_FORMAT_SUPPORTED: u64 =
+    PERF_FORMAT_TOTAL_TIME_ENABLED | PERF_FORMAT_TOTAL_TIME_RUNNING | PERF_FORMAT_ID;
+
+/// `_IO('$', 0)`
+const PERF_EVENT_IOC_ENABLE: u32 = 0x2400;
+/// `_IO('$', 1)`
+const PERF_EVENT_IOC_DISABLE: u32 = 0x2401;
+/// `_IO('$', 3)`
+const PERF_EVENT_IOC_RESET: u32 = 0x2403;
+/// `_IOR('$', 7, u64 *)`
+const PERF_EVENT_IOC_ID: u32 = 0x8008_2407;
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// What a perf event counts.
+pub enum PerfCounter {
+    /// `PERF_COUNT_SW_CPU_CLOCK`: elapsed time, in nanoseconds.
+    CpuClock,
+    /// `PERF_COUNT_SW_TASK_CLOCK`: the CPU time of a task, in nanoseconds.
+    TaskClock(WeakAxTaskRef),
+}
+
+impl PerfCounter {
+    /// Returns the current value of the underlying clock, or `None` if the
+    /// task has exited.
+    fn read(&self) -> Option<u64> {
+        match self {
+            PerfCounter::CpuClock => Some(monotonic_time_nanos()),
+            PerfCounter::TaskClock(task) => {
+                let task = task.upgrade()?;
+                let (utime, stime) = thread_cpu_time(task.as_thread());
+                Some((utime + stime).as_nanos() as u64)
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct PerfState {
+    /// The count and enabled time accumulated before the event was last
+    /// enabled.
+    count: u64,
+    time_enabled: u64,
+    /// The counter and the time when the event was last enabled, if it is
+    /// enabled.
+    enabled_at: Option<(u64, u64)>,
+    /// The last value read from the counter, used once the task has exited.
+    last: u64,
+}
+
+pub struct PerfEvent {
+    id: u64,
+    counter: PerfCounter,
+    read_format: u64,
+    state: Mutex<PerfState>,
+}
+
+impl PerfEvent {
+    pub fn new(counter: PerfCounter, read_format: u64, enabled: bool) -> Self {
+        let event = Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            counter,
+            read_format,
+            state: Mutex::default(),
+        };
+        if enabled {
+            event.enable();
+        }
+        event
+    }
+
+    fn sample(&self, state: &mut PerfState) -> u64 {
+        if let Some(value) = self.counter.read() {
+            state.last = value;
+        }
+        state.last
+    }
+
+    fn enable(&self) {
+        let mut state = self.state.lock();
+        if state.enabled_at.is_none() {
+            let value = self.sample(&mut state);
+            state.enabled_at = Some((value, monotonic_time_nanos()));
+        }
+    }
+
+    fn disable(&self) {
+        let mut state = self.state.lock();
+        let (count, time_enabled) = self.totals(&mut state);
+        state.count = count;
+        state.time_enabled = time_enabled;
+        state.enabled_at = None;
+    }
+
+    fn reset(&self) {
+        let mut state = self.state.lock();
+        state.count = 0;
+        if state.enabled_at.is_some() {
+            let value = self.sample(&mut state);
+            state.enabled_at = Some((value, state.enabled_at.unwrap().1));
+        }
+    }
+
+    /// Returns the count and the total time the event has been enabled.
+    fn totals(&self, state: &mut PerfState) -> (u64, u64) {
+        match state.enabled_at {
+            Some((base, since)) => {
+                let value = self.sample(state);
+                (
+                    state.count + value.saturating_sub(base),
+                    state.time_enabled + monotonic_time_nanos().saturating_sub(since),
+                )
+            }
+            None => (state.count, state.time_enabled),
+        }
+    }
+}
+
+impl FileLike for PerfEvent {
+    fn read(&self, dst: &mut IoDst) -> AxResult<usize> {
+        let (count, time_enabled) = self.totals(&mut self.state.lock());
+        let mut values = Vec::with_capacity(4);
+        values.push(count);
+        if self.read_format & PERF_FORMAT_TOTAL_TIME_ENABLED != 0 {
+            values.push(time_enabled);
+        }
+        // Software events are never multiplexed, so they run whenever they
+        // are enabled.
+        if self.read_format & PERF_FORMAT_TOTAL_TIME_RUNNING != 0 {
+            values.push(time_enabled);
+        }
+        if self.read_format & PERF_FORMAT_ID != 0 {
+            values.push(self.id);
+        }
+
+        let size = values.len() * size_of::<u64>();
+        if dst.remaining_mut() < size {
+            return Err(AxError::from(LinuxError::ENOSPC));
+        }
+        for value in values {
+            dst.write(&value.to_ne_bytes())?;
+        }
+        Ok(size)
+    }
+
+    fn path(&self) -> Cow<'_, str> {
+        "anon_inode:[perf_event]".into()
+    }
+
+    fn ioctl(&self, cmd: u32, arg: usize) -> AxResult<usize> {
+        match cmd {
+            PERF_EVENT_IOC_ENABLE => self.enable(),
+            PERF_EVENT_IOC_DISABLE => self.disable(),
+            PERF_EVENT_IOC_RESET => self.reset(),
+            PERF_EVENT_IOC_ID => (arg as *mut u64).vm_write(self.id)?,
+            _ => return Err(AxError::NotATty),
+        }
+        Ok(0)
+    }
+}
+
+impl Pollable for PerfEvent {
+    fn poll(&self) -> IoEvents {
+        // Counting events have no ring buffer to signal.
+        IoEvents::empty()
+    }
+
+    fn register(&self, _context: &mut Context<'_>, _events: IoEvents) {}
+}
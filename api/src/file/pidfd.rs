@@ -2,40 +2,81 @@ use alloc::{
     borrow::Cow,
     sync::{Arc, Weak},
 };
-use core::task::Context;
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    task::Context,
+};
 
 use axerrno::{AxError, AxResult};
 use axpoll::{IoEvents, PollSet, Pollable};
 use starry_core::task::ProcessData;
+use starry_process::Pid;
 
 use crate::file::FileLike;
 
+/// A file descriptor referring to a process, as created by `pidfd_open` or
+/// `CLONE_PIDFD`.
+///
+/// It becomes readable once the process has exited.
 pub struct PidFd {
+    pid: Pid,
     proc_data: Weak<ProcessData>,
     exit_event: Arc<PollSet>,
+    non_blocking: AtomicBool,
 }
 impl PidFd {
     pub fn new(proc_data: &Arc<ProcessData>) -> Self {
         Self {
+            pid: proc_data.proc.pid(),
             proc_data: Arc::downgrade(proc_data),
             exit_event: proc_data.exit_event.clone(),
+            non_blocking: AtomicBool::new(false),
         }
     }
 
+    /// Returns the PID of the process, which outlives its [`ProcessData`].
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
     pub fn process_data(&self) -> AxResult<Arc<ProcessData>> {
         self.proc_data.upgrade().ok_or(AxError::NoSuchProcess)
     }
+
+    /// Returns the process if it's still running.
+    pub fn live_process_data(&self) -> AxResult<Arc<ProcessData>> {
+        let proc_data = self.process_data()?;
+        if proc_data.proc.is_zombie() {
+            return Err(AxError::NoSuchProcess);
+        }
+        Ok(proc_data)
+    }
 }
 impl FileLike for PidFd {
     fn path(&self) -> Cow<'_, str> {
         "anon_inode:[pidfd]".into()
     }
+
+    fn nonblocking(&self) -> bool {
+        self.non_blocking.load(Ordering::Acquire)
+    }
+
+    fn set_nonblocking(&self, non_blocking: bool) -> AxResult {
+        self.non_blocking.store(non_blocking, Ordering::Release);
+        Ok(())
+    }
 }
 
 impl Pollable for PidFd {
     fn poll(&self) -> IoEvents {
+        // Readable once the process has exited.
         let mut events = IoEvents::empty();
-        events.set(IoEvents::IN, self.proc_data.strong_count() > 0);
+        events.set(
+            IoEvents::IN,
+            self.proc_data
+                .upgrade()
+                .is_none_or(|proc_data| proc_data.proc.is_zombie()),
+        );
         events
     }
 
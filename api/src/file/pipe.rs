@@ -1,11 +1,16 @@
-use alloc::{borrow::Cow, format, sync::Arc};
+use alloc::{
+    borrow::Cow,
+    format,
+    sync::{Arc, Weak},
+    vec,
+};
 use core::{
     mem,
     sync::atomic::{AtomicBool, Ordering},
     task::Context,
 };
 
-use axerrno::{AxError, AxResult};
+use axerrno::{AxError, AxResult, LinuxError};
 use axpoll::{IoEvents, PollSet, Pollable};
 use axsync::Mutex;
 use axtask::{
@@ -22,16 +27,47 @@ use starry_core::task::{AsThread, send_signal_to_process};
 use starry_signal::{SignalInfo, Signo};
 use starry_vm::VmMutPtr;
 
-use super::{FileLike, Kstat};
+use super::{
+    FileLike, Kstat,
+    watch_queue::{
+        HEADER_SIZE, IOC_WATCH_QUEUE_SET_FILTER, IOC_WATCH_QUEUE_SET_SIZE, Notification,
+        WatchQueue, queue_size, record_len,
+    },
+};
 use crate::file::{IoDst, IoSrc};
 
 const RING_BUFFER_INIT_SIZE: usize = 65536; // 64 KiB
 
-struct Shared {
+pub(super) struct Shared {
     buffer: Mutex<HeapRb<u8>>,
     poll_rx: PollSet,
     poll_tx: PollSet,
     poll_close: PollSet,
+    /// Set for notification pipes.
+    watch_queue: Option<WatchQueue>,
+}
+
+impl Shared {
+    /// Queues a record for the watch with the given ID, if the queue's filter
+    /// lets it through. If there is no room for it, the record is dropped and
+    /// the reader is told so on its next read.
+    pub(super) fn post_notification(&self, n: &Notification, id: u8) {
+        let Some(queue) = &self.watch_queue else {
+            return;
+        };
+        if !queue.accepts(n) {
+            return;
+        }
+        let record = n.encode(id);
+        let mut buffer = self.buffer.lock();
+        if buffer.vacant_len() < record.len() {
+            queue.mark_lost();
+        } else {
+            buffer.push_slice(&record);
+        }
+        drop(buffer);
+        self.poll_rx.wake();
+    }
 }
 
 pub struct Pipe {
@@ -47,11 +83,22 @@ impl Drop for Pipe {
 
 impl Pipe {
     pub fn new() -> (Pipe, Pipe) {
+        Self::new_with(None)
+    }
+
+    /// Creates a notification pipe, which carries records posted by the kernel
+    /// and can't be written to.
+    pub fn new_notification() -> (Pipe, Pipe) {
+        Self::new_with(Some(WatchQueue::default()))
+    }
+
+    fn new_with(watch_queue: Option<WatchQueue>) -> (Pipe, Pipe) {
         let shared = Arc::new(Shared {
             buffer: Mutex::new(HeapRb::new(RING_BUFFER_INIT_SIZE)),
             poll_rx: PollSet::new(),
             poll_tx: PollSet::new(),
             poll_close: PollSet::new(),
+            watch_queue,
         });
         let read_end = Pipe {
             read_side: true,
@@ -78,6 +125,51 @@ impl Pipe {
         Arc::strong_count(&self.shared) == 1
     }
 
+    /// Returns the queue records are posted to, if this is a notification
+    /// pipe.
+    pub(super) fn watch_queue_ref(&self) -> Option<Weak<Shared>> {
+        self.shared
+            .watch_queue
+            .is_some()
+            .then(|| Arc::downgrade(&self.shared))
+    }
+
+    /// Reads whole records from a notification pipe.
+    fn read_notifications(&self, queue: &WatchQueue, dst: &mut IoDst) -> AxResult<usize> {
+        block_on(poll_io(self, IoEvents::IN, self.nonblocking(), || {
+            let mut read = 0;
+            if dst.remaining_mut() >= HEADER_SIZE
+                && let Some(record) = queue.take_loss_record()
+            {
+                read += dst.write(&record)?;
+            }
+            let mut buffer = self.shared.buffer.lock();
+            while buffer.occupied_len() >= HEADER_SIZE {
+                let mut header = [0; HEADER_SIZE];
+                buffer.peek_slice(&mut header);
+                let len = record_len(&header);
+                if dst.remaining_mut() < len {
+                    if read == 0 {
+                        return Err(AxError::from(LinuxError::ENOBUFS));
+                    }
+                    break;
+                }
+                let mut record = vec![0; len];
+                buffer.pop_slice(&mut record);
+                read += dst.write(&record)?;
+            }
+            drop(buffer);
+            if read > 0 {
+                self.shared.poll_tx.wake();
+                Ok(read)
+            } else if self.closed() {
+                Ok(0)
+            } else {
+                Err(AxError::WouldBlock)
+            }
+        }))
+    }
+
     pub fn capacity(&self) -> usize {
         self.shared.buffer.lock().capacity().get()
     }
@@ -117,6 +209,9 @@ impl FileLike for Pipe {
         if dst.is_full() {
             return Ok(0);
         }
+        if let Some(queue) = &self.shared.watch_queue {
+            return self.read_notifications(queue, dst);
+        }
 
         block_on(poll_io(self, IoEvents::IN, self.nonblocking(), || {
             let read = {
@@ -144,6 +239,9 @@ impl FileLike for Pipe {
         if !self.is_write() {
             return Err(AxError::BadFileDescriptor);
         }
+        if self.shared.watch_queue.is_some() {
+            return Err(AxError::from(LinuxError::EXDEV));
+        }
         let size = src.remaining();
         if size == 0 {
             return Ok(0);
@@ -204,6 +302,14 @@ impl FileLike for Pipe {
                 (arg as *mut u32).vm_write(self.shared.buffer.lock().occupied_len() as u32)?;
                 Ok(0)
             }
+            IOC_WATCH_QUEUE_SET_SIZE if self.shared.watch_queue.is_some() => {
+                self.resize(queue_size(arg)?)?;
+                Ok(0)
+            }
+            IOC_WATCH_QUEUE_SET_FILTER => match &self.shared.watch_queue {
+                Some(queue) => queue.set_filter(arg).map(|_| 0),
+                None => Err(AxError::NotATty),
+            },
             _ => Err(AxError::NotATty),
         }
     }
@@ -214,7 +320,12 @@ impl Pollable for Pipe {
         let mut events = IoEvents::empty();
         let buf = self.shared.buffer.lock();
         if self.read_side {
-            events.set(IoEvents::IN, buf.occupied_len() > 0);
+            let lost = self
+                .shared
+                .watch_queue
+                .as_ref()
+                .is_some_and(|it| it.is_lost());
+            events.set(IoEvents::IN, buf.occupied_len() > 0 || lost);
             events.set(IoEvents::HUP, self.closed());
         } else {
             events.set(IoEvents::OUT, buf.vacant_len() > 0);
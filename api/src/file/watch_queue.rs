@@ -0,0 +1,252 @@
+//! Notification pipes, created with `pipe2(O_NOTIFICATION_PIPE)`.
+//!
+//! Instead of data written by user space, such a pipe carries records posted
+//! by the kernel to the watches set up on it. Every record starts with a
+//! `struct watch_notification` header, and is always read in one piece.
+//!
+//! A source of notifications keeps the watches on it in a [`WatchList`], and
+//! posts [`Notification`]s to all of them at once. Keys are the only source
+//! so far, watched with `KEYCTL_WATCH_KEY`.
+
+use alloc::{sync::Weak, vec::Vec};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use axerrno::{AxError, AxResult, LinuxError};
+use spin::Mutex;
+
+use super::{Pipe, pipe::Shared};
+use crate::mm::UserConstPtr;
+
+/// `WATCH_TYPE_META`: records about the queue itself.
+pub const WATCH_TYPE_META: u32 = 0;
+/// `WATCH_TYPE_KEY_NOTIFY`: changes to keys and keyrings.
+pub const WATCH_TYPE_KEY_NOTIFY: u32 = 1;
+const WATCH_TYPE_NR: u32 = 2;
+
+/// `WATCH_META_REMOVAL_NOTIFICATION`: a watched object went away.
+const WATCH_META_REMOVAL_NOTIFICATION: u8 = 0;
+/// `WATCH_META_LOSS_NOTIFICATION`: records were dropped because the queue
+/// was full.
+const WATCH_META_LOSS_NOTIFICATION: u8 = 1;
+
+const WATCH_INFO_LENGTH: u32 = 0x7f;
+const WATCH_INFO_ID_SHIFT: u32 = 8;
+/// The bits of `info` whose meaning depends on the type of the record.
+pub const WATCH_INFO_TYPE_INFO: u32 = 0xffff_0000;
+
+/// Size of `struct watch_notification`.
+pub(super) const HEADER_SIZE: usize = 8;
+/// Largest record, header included.
+pub const MAX_RECORD_SIZE: usize = WATCH_INFO_LENGTH as usize;
+
+/// `_IO('W', 0x60)`
+pub(super) const IOC_WATCH_QUEUE_SET_SIZE: u32 = 0x5760;
+/// `_IO('W', 0x61)`
+pub(super) const IOC_WATCH_QUEUE_SET_FILTER: u32 = 0x5761;
+
+/// Buffer space reserved per note requested with `IOC_WATCH_QUEUE_SET_SIZE`.
+const NOTE_SIZE: usize = 128;
+const MAX_NOTES: usize = 512;
+const MAX_FILTERS: u32 = 16;
+
+/// A record to post to notification pipes.
+pub struct Notification<'a> {
+    pub ty: u32,
+    pub subtype: u8,
+    /// Type-specific flags, within [`WATCH_INFO_TYPE_INFO`].
+    pub info: u32,
+    /// What follows the header, at most `MAX_RECORD_SIZE - 8` bytes.
+    pub payload: &'a [u8],
+}
+
+impl Notification<'_> {
+    /// Encodes the record as seen by the watch with the given ID.
+    pub(super) fn encode(&self, id: u8) -> Vec<u8> {
+        let len = HEADER_SIZE + self.payload.len();
+        debug_assert!(len <= MAX_RECORD_SIZE);
+        let info =
+            (self.info & WATCH_INFO_TYPE_INFO) | ((id as u32) << WATCH_INFO_ID_SHIFT) | len as u32;
+        let mut buf = Vec::with_capacity(len);
+        buf.extend_from_slice(&(self.ty | ((self.subtype as u32) << 24)).to_ne_bytes());
+        buf.extend_from_slice(&info.to_ne_bytes());
+        buf.extend_from_slice(self.payload);
+        buf
+    }
+}
+
+/// Returns the length of the record starting with `header`.
+pub(super) fn record_len(header: &[u8; HEADER_SIZE]) -> usize {
+    let info = u32::from_ne_bytes(header[4..].try_into().unwrap());
+    (info & WATCH_INFO_LENGTH) as usize
+}
+
+/// `struct watch_notification_type_filter`
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct TypeFilter {
+    ty: u32,
+    info_filter: u32,
+    info_mask: u32,
+    subtype_filter: [u32; 8],
+}
+
+impl TypeFilter {
+    fn matches(&self, n: &Notification) -> bool {
+        let subtype = n.subtype as usize;
+        self.ty == n.ty
+            && self.subtype_filter[subtype / 32] & (1 << (subtype % 32)) != 0
+            && n.info & self.info_mask == self.info_filter
+    }
+}
+
+/// `struct watch_notification_filter`, without the trailing filters.
+#[repr(C)]
+struct FilterHeader {
+    nr_filters: u32,
+    reserved: u32,
+}
+
+/// The per-queue state of a notification pipe.
+#[derive(Default)]
+pub(super) struct WatchQueue {
+    /// Only records matching one of these are queued, if set.
+    filter: Mutex<Option<Vec<TypeFilter>>>,
+    /// Whether records were dropped since the last read.
+    lost: AtomicBool,
+}
+
+impl WatchQueue {
+    pub(super) fn accepts(&self, n: &Notification) -> bool {
+        // Meta records can't be filtered out.
+        n.ty == WATCH_TYPE_META
+            || self
+                .filter
+                .lock()
+                .as_ref()
+                .is_none_or(|filters| filters.iter().any(|it| it.matches(n)))
+    }
+
+    pub(super) fn mark_lost(&self) {
+        self.lost.store(true, Ordering::Release);
+    }
+
+    pub(super) fn is_lost(&self) -> bool {
+        self.lost.load(Ordering::Acquire)
+    }
+
+    /// Returns the record reporting lost notifications, if there were any.
+    pub(super) fn take_loss_record(&self) -> Option<Vec<u8>> {
+        self.lost.swap(false, Ordering::AcqRel).then(|| {
+            Notification {
+                ty: WATCH_TYPE_META,
+                subtype: WATCH_META_LOSS_NOTIFICATION,
+                info: 0,
+                payload: &[],
+            }
+            .encode(0)
+        })
+    }
+
+    /// Handles `IOC_WATCH_QUEUE_SET_FILTER`. A null `arg` removes the filter.
+    pub(super) fn set_filter(&self, arg: usize) -> AxResult<()> {
+        if arg == 0 {
+            *self.filter.lock() = None;
+            return Ok(());
+        }
+        let header = UserConstPtr::<FilterHeader>::from(arg).get_as_ref()?;
+        if header.reserved != 0 || header.nr_filters > MAX_FILTERS {
+            return Err(AxError::InvalidInput);
+        }
+        let filters = UserConstPtr::<TypeFilter>::from(arg + size_of::<FilterHeader>())
+            .get_as_slice(header.nr_filters as usize)?
+            .iter()
+            // Filters for unknown types are ignored, as on Linux.
+            .filter(|it| it.ty < WATCH_TYPE_NR)
+            .copied()
+            .collect();
+        *self.filter.lock() = Some(filters);
+        Ok(())
+    }
+}
+
+/// Returns the buffer size of a queue holding `nr_notes` notes, as requested
+/// with `IOC_WATCH_QUEUE_SET_SIZE`.
+pub(super) fn queue_size(nr_notes: usize) -> AxResult<usize> {
+    if nr_notes == 0 || nr_notes > MAX_NOTES {
+        return Err(AxError::InvalidInput);
+    }
+    Ok(nr_notes * NOTE_SIZE)
+}
+
+struct Watch {
+    queue: Weak<Shared>,
+    id: u8,
+}
+
+/// The watches on one source of notifications.
+///
+/// When the list is dropped, every queue still watching it gets a removal
+/// record.
+#[derive(Default)]
+pub struct WatchList {
+    watches: Mutex<Vec<Watch>>,
+}
+
+impl WatchList {
+    /// Adds a watch delivering to the notification pipe `pipe`, tagging the
+    /// records it gets with `id`. Each pipe watches a source at most once.
+    pub fn add(&self, pipe: &Pipe, id: u8) -> AxResult<()> {
+        let queue = pipe.watch_queue_ref().ok_or(AxError::InvalidInput)?;
+        let mut watches = self.watches.lock();
+        watches.retain(|it| it.queue.strong_count() > 0);
+        if watches.iter().any(|it| Weak::ptr_eq(&it.queue, &queue)) {
+            return Err(AxError::from(LinuxError::EBUSY));
+        }
+        watches.push(Watch { queue, id });
+        Ok(())
+    }
+
+    /// Removes the watch delivering to `pipe`, posting a removal record to
+    /// it.
+    pub fn remove(&self, pipe: &Pipe) -> AxResult<()> {
+        let queue = pipe.watch_queue_ref().ok_or(AxError::InvalidInput)?;
+        let mut watches = self.watches.lock();
+        let index = watches
+            .iter()
+            .position(|it| Weak::ptr_eq(&it.queue, &queue))
+            .ok_or(AxError::from(LinuxError::EBADSLT))?;
+        let watch = watches.remove(index);
+        drop(watches);
+        post_removal(&watch);
+        Ok(())
+    }
+
+    /// Posts `n` to every queue watching this source.
+    pub fn post(&self, n: &Notification) {
+        for watch in self.watches.lock().iter() {
+            if let Some(queue) = watch.queue.upgrade() {
+                queue.post_notification(n, watch.id);
+            }
+        }
+    }
+}
+
+impl Drop for WatchList {
+    fn drop(&mut self) {
+        for watch in self.watches.get_mut().iter() {
+            post_removal(watch);
+        }
+    }
+}
+
+fn post_removal(watch: &Watch) {
+    if let Some(queue) = watch.queue.upgrade() {
+        let n = Notification {
+            ty: WATCH_TYPE_META,
+            subtype: WATCH_META_REMOVAL_NOTIFICATION,
+            info: 0,
+            payload: &[],
+        };
+        queue.post_notification(&n, watch.id);
+    }
+}
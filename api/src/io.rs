@@ -3,6 +3,7 @@ use core::mem::{self, MaybeUninit};
 use axerrno::{AxError, AxResult};
 use axio::prelude::*;
 use bytemuck::AnyBitPattern;
+use starry_core::sched::CondResched;
 use starry_vm::{VmPtr, vm_read_slice, vm_write_slice};
 
 #[repr(C)]
@@ -105,7 +106,9 @@ impl IoVectorBufIo {
 impl Read for IoVectorBufIo {
     fn read(&mut self, buf: &mut [u8]) -> AxResult<usize> {
         let mut count = 0;
+        let mut resched = CondResched::new();
         loop {
+            resched.cond_resched();
             self.skip_empty()?;
             if self.start >= self.inner.iovcnt {
                 break;
@@ -129,7 +132,9 @@ impl Read for IoVectorBufIo {
 impl Write for IoVectorBufIo {
     fn write(&mut self, buf: &[u8]) -> AxResult<usize> {
         let mut count = 0;
+        let mut resched = CondResched::new();
         loop {
+            resched.cond_resched();
             self.skip_empty()?;
             if self.start >= self.inner.iovcnt {
                 break;
@@ -166,3 +171,30 @@ impl IoBufMut for IoVectorBufIo {
         self.inner.len
     }
 }
+
+/// A source that yields at most `limit` bytes of another.
+pub struct TakeBuf<'a, R: Read + IoBuf + ?Sized> {
+    inner: &'a mut R,
+    limit: usize,
+}
+
+impl<'a, R: Read + IoBuf + ?Sized> TakeBuf<'a, R> {
+    pub fn new(inner: &'a mut R, limit: usize) -> Self {
+        Self { inner, limit }
+    }
+}
+
+impl<R: Read + IoBuf + ?Sized> Read for TakeBuf<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> AxResult<usize> {
+        let len = buf.len().min(self.limit);
+        let read = self.inner.read(&mut buf[..len])?;
+        self.limit -= read;
+        Ok(read)
+    }
+}
+
+impl<R: Read + IoBuf + ?Sized> IoBuf for TakeBuf<'_, R> {
+    fn remaining(&self) -> usize {
+        self.inner.remaining().min(self.limit)
+    }
+}
@@ -5,10 +5,11 @@
 #![allow(clippy::not_unsafe_ptr_arg_deref)]
 
 #[macro_use]
-extern crate axlog;
+extern crate starry_core;
 
 extern crate alloc;
 
+pub mod extension;
 pub mod file;
 pub mod io;
 pub mod mm;
@@ -24,16 +25,40 @@ pub mod vfs;
 
 /// Initialize.
 pub fn init() {
+    starry_core::logfilter::init();
+
+    info!("Initialize realtime clock from RTC...");
+    starry_core::timekeeping::init(vfs::dev::rtc_time());
+
+    info!("Initialize random number generator...");
+    starry_core::random::init();
+
+    info!("Initialize ACPI...");
+    starry_core::firmware::acpi::init();
+
+    info!("Initialize DMI...");
+    vfs::dmi::init();
+
+    info!("Probe CPU vulnerabilities...");
+    starry_core::mitigations::init();
+
     info!("Initialize VFS...");
     vfs::mount_all().expect("Failed to mount vfs");
 
+    info!("Initialize extensions...");
+    extension::init();
+
     info!("Initialize /proc/interrupts...");
     axtask::register_timer_callback(|_| {
+        starry_core::trace::trace(starry_core::trace::TracePoint::IrqEntry, [0; 3]);
         time::inc_irq_cnt();
+        vfs::dev::add_timer_randomness();
+        starry_core::trace::wake_readers();
     });
 
     info!("Initialize alarm...");
     starry_core::time::spawn_alarm_task();
+    starry_core::hrtimer::spawn_hrtimer_task();
 
     #[cfg(feature = "tee_test")]
     {
@@ -15,7 +15,12 @@ use axhal::{
 use axio::prelude::*;
 use axtask::current;
 use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr};
-use starry_core::{mm::access_user_memory, task::AsThread};
+use starry_core::{
+    mm::{access_user_memory, grow_stack},
+    rusage,
+    task::AsThread,
+    trace::{TracePoint, trace},
+};
 use starry_vm::{vm_load, vm_load_until_nul, vm_read_slice, vm_write_slice};
 
 fn check_region(start: VirtAddr, layout: Layout, access_flags: MappingFlags) -> AxResult<()> {
@@ -246,11 +251,20 @@ fn handle_page_fault(vaddr: VirtAddr, access_flags: MappingFlags) -> bool {
     if unlikely(!thr.is_accessing_user_memory()) {
         return false;
     }
+    trace(
+        TracePoint::PageFaultKernel,
+        [vaddr.as_usize() as _, access_flags.bits() as _, 0],
+    );
 
-    thr.proc_data
-        .aspace
-        .lock()
-        .handle_page_fault(vaddr, access_flags)
+    let mut aspace = thr.proc_data.aspace.lock();
+    let handled = aspace.handle_page_fault(vaddr, access_flags)
+        || (grow_stack(&thr.proc_data, &mut aspace, vaddr)
+            && aspace.handle_page_fault(vaddr, access_flags));
+    drop(aspace);
+    if handled {
+        rusage::page_fault(thr, vaddr);
+    }
+    handled
 }
 
 pub fn vm_load_string(ptr: *const c_char) -> AxResult<String> {
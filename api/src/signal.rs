@@ -1,18 +1,92 @@
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::{
+    future::poll_fn,
+    sync::atomic::{AtomicBool, Ordering},
+    task::Poll,
+};
 
 use axerrno::AxResult;
 use axhal::uspace::UserContext;
-use axtask::current;
-use starry_core::task::{AsThread, Thread};
-use starry_signal::{SignalOSAction, SignalSet};
+use axtask::{current, future::block_on};
+use starry_core::task::{AsThread, Thread, TraceStop, get_task, notify_tracer};
+use starry_signal::{SignalOSAction, SignalSet, Signo};
 
 use crate::task::do_exit;
 
+/// Waits until the current process is continued, if it is stopped.
+///
+/// A traced thread reports the group stop to its tracer instead, and then
+/// runs on once the tracer continues it.
+fn wait_while_stopped(thr: &Thread) {
+    let proc_data = &thr.proc_data;
+    match proc_data.stop_signal() {
+        Some(signo) => {
+            if thr.trace.begin_group_stop() {
+                trace_stop(thr, TraceStop::Group(signo));
+            }
+            if thr.trace.is_traced() {
+                return;
+            }
+        }
+        None => thr.trace.end_group_stop(),
+    }
+    block_on(poll_fn(|cx| {
+        if proc_data.is_stopped() {
+            proc_data.continue_event.register(cx.waker());
+        }
+        if proc_data.is_stopped() {
+            Poll::Pending
+        } else {
+            Poll::Ready(())
+        }
+    }));
+}
+
+/// Puts the current thread in a ptrace stop until its tracer ends it.
+///
+/// `SIGKILL` ends the stop too, so that the thread can exit.
+fn trace_stop(thr: &Thread, stop: TraceStop) {
+    let Some(tracer) = thr.trace.enter_stop(stop) else {
+        return;
+    };
+    notify_tracer(tracer);
+    let proc_data = &thr.proc_data;
+    block_on(poll_fn(|cx| {
+        thr.trace.register(cx.waker());
+        proc_data.continue_event.register(cx.waker());
+        if thr.trace.stop_ended(proc_data) || thr.signal.pending().has(Signo::SIGKILL) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }));
+    thr.trace.leave_stop();
+}
+
+/// Stops the current process on `signo` until it is continued.
+fn do_stop(thr: &Thread, signo: Signo) {
+    let proc_data = &thr.proc_data;
+    proc_data.stop(signo);
+    // The other threads stop once they next check for signals.
+    let curr_tid = thr.tid();
+    for tid in proc_data.proc.threads() {
+        if tid != curr_tid
+            && let Ok(task) = get_task(tid)
+        {
+            task.interrupt();
+        }
+    }
+    wait_while_stopped(thr);
+}
+
 pub fn check_signals(
     thr: &Thread,
     uctx: &mut UserContext,
     restore_blocked: Option<SignalSet>,
 ) -> bool {
+    if thr.trace.take_interrupt() {
+        trace_stop(thr, TraceStop::Interrupt);
+    }
+    wait_while_stopped(thr);
     let Some((sig, os_action)) = thr.signal.check_signals(uctx, restore_blocked) else {
         return false;
     };
@@ -26,12 +100,9 @@ pub fn check_signals(
             // TODO: implement core dump
             do_exit(128 + signo as i32, true);
         }
-        SignalOSAction::Stop => {
-            // TODO: implement stop
-            do_exit(1, true);
-        }
+        SignalOSAction::Stop => do_stop(thr, signo),
         SignalOSAction::Continue => {
-            // TODO: implement continue
+            // The process was continued as the signal was sent.
         }
         SignalOSAction::Handler => {
             // do nothing
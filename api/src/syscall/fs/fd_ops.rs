@@ -16,11 +16,11 @@ use starry_core::{task::AsThread, vfs::Device};
 use crate::{
     file::{
         Directory, FD_TABLE, File, FileLike, Pipe, add_file_like, close_file_like, get_file_like,
-        with_fs,
+        nofile_limit, with_fs,
     },
     mm::{UserPtr, vm_load_string},
     syscall::sys::{sys_getegid, sys_geteuid},
-    vfs::dev::tty,
+    vfs::dev::{kmsg, tty, watchdog},
 };
 
 /// Convert open flags to [`OpenOptions`].
@@ -60,8 +60,33 @@ fn flags_to_options(flags: c_int, mode: __kernel_mode_t, (uid, gid): (u32, u32))
     options
 }
 
+/// Returns a location in /dev/pts for a new file on a pseudo-terminal.
+fn pty_location(file: &axfs::File, device: Arc<Device>, pty_number: u32) -> AxResult<Location> {
+    // TODO: this is cursed
+    let pts = FS_CONTEXT.lock().resolve("/dev/pts")?;
+    let entry = DirEntry::new_file(
+        FileNode::new(device),
+        NodeType::CharacterDevice,
+        Reference::new(Some(pts.entry().clone()), pty_number.to_string()),
+    );
+    Ok(Location::new(file.location().mountpoint().clone(), entry))
+}
+
+/// Whether `file` is on a device of type `T`.
+fn is_device<T: 'static>(file: &axfs::File) -> bool {
+    file.location()
+        .entry()
+        .downcast::<Device>()
+        .is_ok_and(|device| device.inner().as_any().is::<T>())
+}
+
 fn add_to_fd(result: OpenResult, flags: u32) -> AxResult<i32> {
     let f: Arc<dyn FileLike> = match result {
+        // Files on /dev/kmsg each keep their own position in the kernel log.
+        OpenResult::File(file) if is_device::<kmsg::Kmsg>(&file) => Arc::new(kmsg::KmsgFile::new()),
+        OpenResult::File(file) if is_device::<watchdog::Watchdog>(&file) => {
+            Arc::new(watchdog::WatchdogFile::open()?)
+        }
         OpenResult::File(mut file) => {
             // /dev/xx handling
             if let Ok(device) = file.location().entry().downcast::<Device>() {
@@ -69,14 +94,7 @@ fn add_to_fd(result: OpenResult, flags: u32) -> AxResult<i32> {
                 if let Some(ptmx) = inner.downcast_ref::<tty::Ptmx>() {
                     // Opening /dev/ptmx creates a new pseudo-terminal
                     let (master, pty_number) = ptmx.create_pty()?;
-                    // TODO: this is cursed
-                    let pts = FS_CONTEXT.lock().resolve("/dev/pts")?;
-                    let entry = DirEntry::new_file(
-                        FileNode::new(master),
-                        NodeType::CharacterDevice,
-                        Reference::new(Some(pts.entry().clone()), pty_number.to_string()),
-                    );
-                    let loc = Location::new(file.location().mountpoint().clone(), entry);
+                    let loc = pty_location(&file, master, pty_number)?;
                     file = axfs::File::new(FileBackend::Direct(loc), file.flags());
                 } else if inner.is::<tty::CurrentTty>() {
                     let term = current()
@@ -98,6 +116,18 @@ fn add_to_fd(result: OpenResult, flags: u32) -> AxResult<i32> {
                     file = axfs::File::new(FileBackend::Direct(loc), file.flags());
                 }
             }
+            // Each file on the slave of a pseudo-terminal gets a device of its
+            // own, so that the master can tell when the last one is closed.
+            if let Ok(device) = file.location().entry().downcast::<Device>()
+                && let Some(pts) = device.inner().as_any().downcast_ref::<tty::PtyDriver>()
+            {
+                if flags & O_NOCTTY == 0 {
+                    pts.acquire_on_open(&current().as_thread().proc_data.proc);
+                }
+                let pty_number = pts.pty_number();
+                let loc = pty_location(&file, tty::open_slave(pty_number)?, pty_number)?;
+                file = axfs::File::new(FileBackend::Direct(loc), file.flags());
+            }
             Arc::new(File::new(file))
         }
         OpenResult::Dir(dir) => Arc::new(Directory::new(dir)),
@@ -220,6 +250,9 @@ pub fn sys_dup3(old_fd: c_int, new_fd: c_int, flags: c_int) -> AxResult<isize> {
     if old_fd == new_fd {
         return Err(AxError::InvalidInput);
     }
+    if new_fd < 0 || new_fd as u64 >= nofile_limit() {
+        return Err(AxError::BadFileDescriptor);
+    }
 
     let mut fd_table = FD_TABLE.write();
     let mut f = fd_table
@@ -6,16 +6,17 @@ use core::{
 
 use axerrno::{AxError, AxResult};
 use axfs::{FS_CONTEXT, FileFlags, OpenOptions};
-use axio::{Seek, SeekFrom};
+use axio::{IoBuf, Seek, SeekFrom};
 use axpoll::{IoEvents, Pollable};
 use axtask::current;
 use linux_raw_sys::general::__kernel_off_t;
+use starry_core::sched::CondResched;
 use starry_vm::{VmMutPtr, VmPtr};
 use syscalls::Sysno;
 
 use crate::{
-    file::{File, FileLike, Pipe, get_file_like},
-    io::{IoVec, IoVectorBuf},
+    file::{File, FileLike, Pipe, check_file_len, check_file_size, get_file_like},
+    io::{IoVec, IoVectorBuf, TakeBuf},
     mm::{UserConstPtr, VmBytes, VmBytesMut},
 };
 
@@ -95,6 +96,7 @@ pub fn sys_truncate(path: UserConstPtr<c_char>, length: __kernel_off_t) -> AxRes
         .write(true)
         .open(&FS_CONTEXT.lock(), path)?
         .into_file()?;
+    check_file_len(length as _)?;
     file.access(FileFlags::WRITE)?.set_len(length as _)?;
     Ok(0)
 }
@@ -102,6 +104,7 @@ pub fn sys_truncate(path: UserConstPtr<c_char>, length: __kernel_off_t) -> AxRes
 pub fn sys_ftruncate(fd: c_int, length: __kernel_off_t) -> AxResult<isize> {
     debug!("sys_ftruncate <= {fd} {length}");
     let f = File::from_fd(fd)?;
+    check_file_len(length as _)?;
     f.inner().access(FileFlags::WRITE)?.set_len(length as _)?;
     Ok(0)
 }
@@ -119,6 +122,7 @@ pub fn sys_fallocate(
     let f = File::from_fd(fd)?;
     let inner = f.inner();
     let file = inner.access(FileFlags::WRITE)?;
+    check_file_len(offset as u64 + len as u64)?;
     file.set_len(file.location().len()?.max(offset as u64 + len as u64))?;
     Ok(0)
 }
@@ -159,6 +163,7 @@ pub fn sys_pread64(fd: c_int, buf: *mut u8, len: usize, offset: __kernel_off_t)
         return Err(AxError::InvalidInput);
     }
     let read = f.inner().read_at(VmBytesMut::new(buf, len), offset as _)?;
+    f.account_read(read);
     Ok(read as _)
 }
 
@@ -172,7 +177,9 @@ pub fn sys_pwrite64(
         return Ok(0);
     }
     let f = File::from_fd(fd)?;
+    let len = check_file_size(offset as _, len)?;
     let write = f.inner().write_at(VmBytes::new(buf, len), offset as _)?;
+    f.account_write(write);
     Ok(write as _)
 }
 
@@ -205,6 +212,7 @@ pub fn sys_preadv2(
     let f = File::from_fd(fd)?;
     f.inner()
         .read_at(IoVectorBuf::new(iov, iovcnt)?.into_io(), offset as _)
+        .inspect(|&n| f.account_read(n))
         .map(|n| n as _)
 }
 
@@ -217,8 +225,11 @@ pub fn sys_pwritev2(
 ) -> AxResult<isize> {
     debug!("sys_pwritev2 <= fd: {fd}, iovcnt: {iovcnt}, offset: {offset}, flags: {_flags}");
     let f = File::from_fd(fd)?;
+    let mut src = IoVectorBuf::new(iov, iovcnt)?.into_io();
+    let len = check_file_size(offset as _, src.remaining())?;
     f.inner()
-        .read_at(IoVectorBuf::new(iov, iovcnt)?.into_io(), offset as _)
+        .write_at(TakeBuf::new(&mut src, len), offset as _)
+        .inspect(|&n| f.account_write(n))
         .map(|n| n as _)
 }
 
@@ -242,6 +253,7 @@ impl SendFile {
             SendFile::Offset(file, offset) => {
                 let off = offset.vm_read()?;
                 let bytes_read = file.inner().read_at(&mut buf, off)?;
+                file.account_read(bytes_read);
                 offset.vm_write(off + bytes_read as u64)?;
                 Ok(bytes_read)
             }
@@ -254,6 +266,7 @@ impl SendFile {
             SendFile::Offset(file, offset) => {
                 let off = offset.vm_read()?;
                 let bytes_written = file.inner().write_at(buf, off)?;
+                file.account_write(bytes_written);
                 offset.vm_write(off + bytes_written as u64)?;
                 Ok(bytes_written)
             }
@@ -265,8 +278,10 @@ fn do_send(mut src: SendFile, mut dst: SendFile, len: usize) -> AxResult<usize>
     let mut buf = vec![0; 0x1000];
     let mut total_written = 0;
     let mut remaining = len;
+    let mut resched = CondResched::new();
 
     while remaining > 0 {
+        resched.cond_resched();
         if total_written > 0 && !src.has_data() {
             break;
         }
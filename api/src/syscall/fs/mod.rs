@@ -4,11 +4,13 @@ mod fd_ops;
 mod io;
 mod memfd;
 mod mount;
+mod perf;
 mod pidfd;
 mod pipe;
 mod signalfd;
 mod stat;
 
 pub use self::{
-    ctl::*, event::*, fd_ops::*, io::*, memfd::*, mount::*, pidfd::*, pipe::*, signalfd::*, stat::*,
+    ctl::*, event::*, fd_ops::*, io::*, memfd::*, mount::*, perf::*, pidfd::*, pipe::*,
+    signalfd::*, stat::*,
 };
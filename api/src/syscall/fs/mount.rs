@@ -2,29 +2,87 @@ use core::ffi::{c_char, c_void};
 
 use axerrno::{AxError, AxResult};
 use axfs::FS_CONTEXT;
+use linux_raw_sys::general::{MS_PRIVATE, MS_REC, MS_REMOUNT, MS_SHARED, MS_SLAVE, MS_UNBINDABLE};
+use starry_vm::VmPtr;
 
-use crate::{mm::vm_load_string, vfs::MemoryFs};
+use crate::{
+    mm::vm_load_string,
+    vfs::{
+        MemoryFs, ProcFsOptions,
+        dev::tty,
+        fs_type::find_filesystem,
+        mounts::{self, MountFlags, Propagation},
+        new_procfs,
+    },
+};
 
 pub fn sys_mount(
     source: *const c_char,
     target: *const c_char,
     fs_type: *const c_char,
-    _flags: i32,
-    _data: *const c_void,
+    flags: i32,
+    data: *const c_void,
 ) -> AxResult<isize> {
-    let source = vm_load_string(source)?;
+    let flags = flags as u32;
     let target = vm_load_string(target)?;
-    let fs_type = vm_load_string(fs_type)?;
-    debug!("sys_mount <= source: {source:?}, target: {target:?}, fs_type: {fs_type:?}");
+    let data = data
+        .cast::<c_char>()
+        .nullable()
+        .map(vm_load_string)
+        .transpose()?;
 
-    if fs_type != "tmpfs" {
-        return Err(AxError::NoSuchDevice);
+    // Changing the propagation type or remounting ignores the source and
+    // filesystem type, which are usually null.
+    let propagation = match flags & (MS_SHARED | MS_PRIVATE | MS_SLAVE | MS_UNBINDABLE) {
+        0 => None,
+        MS_SHARED => Some(Propagation::Shared(0)),
+        MS_PRIVATE => Some(Propagation::Private),
+        MS_SLAVE => Some(Propagation::Slave(0)),
+        MS_UNBINDABLE => Some(Propagation::Unbindable),
+        _ => return Err(AxError::InvalidInput),
+    };
+    if let Some(propagation) = propagation {
+        debug!("sys_mount <= target: {target:?}, propagation: {propagation:?}");
+        let target = FS_CONTEXT.lock().resolve(target)?;
+        mounts::set_propagation(&target, propagation, flags & MS_REC != 0)?;
+        return Ok(0);
+    }
+    let mount_flags = MountFlags::from_bits_truncate(flags);
+    if flags & MS_REMOUNT != 0 {
+        debug!("sys_mount <= target: {target:?}, remount: {mount_flags:?}, data: {data:?}");
+        let target = FS_CONTEXT.lock().resolve(target)?;
+        mounts::remount(&target, mount_flags, data.as_deref())?;
+        return Ok(0);
     }
 
-    let fs = MemoryFs::new();
+    let source = vm_load_string(source)?;
+    let fs_type = vm_load_string(fs_type)?;
+    debug!(
+        "sys_mount <= source: {source:?}, target: {target:?}, fs_type: {fs_type:?}, flags: \
+         {mount_flags:?}, data: {data:?}"
+    );
 
-    let target = FS_CONTEXT.lock().resolve(target)?;
-    target.mount(&fs)?;
+    let fs = match fs_type.as_str() {
+        "tmpfs" => MemoryFs::new(),
+        "devpts" => tty::new_devpts(),
+        "proc" => {
+            let options = ProcFsOptions::parse(data.as_deref().unwrap_or_default())
+                .ok_or(AxError::InvalidInput)?;
+            new_procfs(options)
+        }
+        name => find_filesystem(name)
+            .ok_or(AxError::NoSuchDevice)?
+            .mount(&source, data.as_deref().unwrap_or_default())?,
+    };
+
+    let fs_ctx = FS_CONTEXT.lock();
+    fs_ctx.resolve(&target)?.mount(&fs)?;
+    mounts::add_mount(
+        &fs_ctx.resolve(&target)?,
+        &source,
+        mount_flags,
+        data.as_deref().unwrap_or_default(),
+    )?;
 
     Ok(0)
 }
@@ -33,6 +91,8 @@ pub fn sys_umount2(target: *const c_char, _flags: i32) -> AxResult<isize> {
     let target = vm_load_string(target)?;
     debug!("sys_umount2 <= target: {target:?}");
     let target = FS_CONTEXT.lock().resolve(target)?;
+    let path = target.absolute_path()?;
     target.unmount()?;
+    mounts::remove_mount(path.as_str());
     Ok(0)
 }
@@ -0,0 +1,97 @@
+use alloc::sync::Arc;
+
+use axerrno::{AxError, AxResult};
+use axtask::current;
+use starry_core::task::get_task;
+
+use crate::{
+    file::{
+        add_file_like,
+        perf::{PERF_FORMAT_SUPPORTED, PerfCounter, PerfEvent},
+    },
+    mm::UserConstPtr,
+};
+
+const PERF_TYPE_SOFTWARE: u32 = 1;
+
+const PERF_COUNT_SW_CPU_CLOCK: u64 = 0;
+const PERF_COUNT_SW_TASK_CLOCK: u64 = 1;
+
+/// `PERF_ATTR_SIZE_VER0`
+const PERF_ATTR_SIZE_MIN: u32 = 64;
+
+/// The `disabled` bit of `perf_event_attr`'s flags.
+const ATTR_FLAG_DISABLED: u64 = 1 << 0;
+/// The `freq` bit of `perf_event_attr`'s flags.
+const ATTR_FLAG_FREQ: u64 = 1 << 10;
+
+const PERF_FLAG_FD_CLOEXEC: u32 = 1 << 3;
+
+/// The leading fields of `struct perf_event_attr`, which is extensible and
+/// at least [`PERF_ATTR_SIZE_MIN`] bytes long.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct PerfEventAttrHead {
+    ty: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+}
+
+pub fn sys_perf_event_open(
+    attr: usize,
+    pid: i32,
+    cpu: i32,
+    group_fd: i32,
+    flags: u32,
+) -> AxResult<isize> {
+    let attr = *UserConstPtr::<PerfEventAttrHead>::from(attr).get_as_ref()?;
+    debug!(
+        "sys_perf_event_open <= attr: {attr:?}, pid: {pid}, cpu: {cpu}, group_fd: {group_fd}, \
+         flags: {flags:#x}"
+    );
+
+    if flags & !PERF_FLAG_FD_CLOEXEC != 0 || pid < -1 || cpu < -1 || (pid == -1 && cpu == -1) {
+        return Err(AxError::InvalidInput);
+    }
+    if attr.size != 0 && attr.size < PERF_ATTR_SIZE_MIN {
+        return Err(AxError::InvalidInput);
+    }
+    // Event groups are not supported.
+    if group_fd != -1 {
+        return Err(AxError::InvalidInput);
+    }
+    if attr.read_format & !PERF_FORMAT_SUPPORTED != 0 {
+        return Err(AxError::InvalidInput);
+    }
+    // Only counting is supported: there are no sampling hooks to fill a
+    // ring buffer from.
+    if attr.sample_period != 0 || attr.flags & ATTR_FLAG_FREQ != 0 {
+        return Err(AxError::OperationNotSupported);
+    }
+
+    let counter = match (attr.ty, attr.config) {
+        (PERF_TYPE_SOFTWARE, PERF_COUNT_SW_CPU_CLOCK) => PerfCounter::CpuClock,
+        (PERF_TYPE_SOFTWARE, PERF_COUNT_SW_TASK_CLOCK) => {
+            let task = match pid {
+                // A task clock needs a task to count.
+                -1 => return Err(AxError::InvalidInput),
+                0 => current().clone(),
+                pid => get_task(pid as _)?,
+            };
+            PerfCounter::TaskClock(Arc::downgrade(&task))
+        }
+        // Hardware events would need a PMU driver.
+        _ => return Err(AxError::NotFound),
+    };
+
+    let event = PerfEvent::new(
+        counter,
+        attr.read_format,
+        attr.flags & ATTR_FLAG_DISABLED == 0,
+    );
+    add_file_like(Arc::new(event), flags & PERF_FLAG_FD_CLOEXEC != 0).map(|fd| fd as _)
+}
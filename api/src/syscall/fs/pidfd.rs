@@ -1,21 +1,36 @@
 use axerrno::{AxError, AxResult};
-use starry_core::task::{get_process_data, send_signal_to_process};
+use linux_raw_sys::general::{O_NONBLOCK, SI_USER};
+use starry_core::task::{get_process_data, get_task, send_signal_to_process};
 use starry_signal::SignalInfo;
 
 use crate::{
     file::{FD_TABLE, FileLike, PidFd, add_file_like},
-    syscall::signal::make_queue_signal_info,
+    syscall::signal::{make_queue_signal_info, make_siginfo},
+    task::may_access,
 };
 
-pub fn sys_pidfd_open(pid: u32, flags: u32) -> AxResult<isize> {
+/// Makes the pidfd non-blocking, so that `waitid` on it fails with `EAGAIN`
+/// instead of waiting for the process to exit.
+const PIDFD_NONBLOCK: u32 = O_NONBLOCK;
+
+pub fn sys_pidfd_open(pid: i32, flags: u32) -> AxResult<isize> {
     debug!("sys_pidfd_open <= pid: {pid}, flags: {flags}");
 
-    if flags != 0 {
+    if flags & !PIDFD_NONBLOCK != 0 || pid <= 0 {
         return Err(AxError::InvalidInput);
     }
 
-    let task = get_process_data(pid)?;
-    let fd = PidFd::new(&task);
+    let pid = pid as _;
+    let proc_data = get_process_data(pid).map_err(|err| {
+        // A thread that doesn't lead its thread group can't be referred to.
+        if get_task(pid).is_ok() {
+            AxError::InvalidInput
+        } else {
+            err
+        }
+    })?;
+    let fd = PidFd::new(&proc_data);
+    fd.set_nonblocking(flags & PIDFD_NONBLOCK != 0)?;
 
     fd.add_to_fd_table(true).map(|fd| fd as _)
 }
@@ -23,33 +38,47 @@ pub fn sys_pidfd_open(pid: u32, flags: u32) -> AxResult<isize> {
 pub fn sys_pidfd_getfd(pidfd: i32, target_fd: i32, flags: u32) -> AxResult<isize> {
     debug!("sys_pidfd_getfd <= pidfd: {pidfd}, target_fd: {target_fd}, flags: {flags}");
 
+    if flags != 0 {
+        return Err(AxError::InvalidInput);
+    }
+
     let pidfd = PidFd::from_fd(pidfd)?;
-    let proc_data = pidfd.process_data()?;
-    FD_TABLE
+    let proc_data = pidfd.live_process_data()?;
+    if !may_access(&proc_data) {
+        return Err(AxError::OperationNotPermitted);
+    }
+    let file = FD_TABLE
         .scope(&proc_data.scope.read())
         .read()
         .get(target_fd as usize)
-        .ok_or(AxError::BadFileDescriptor)
-        .and_then(|fd| {
-            let fd = add_file_like(fd.inner.clone(), true)?;
-            Ok(fd as isize)
-        })
+        .ok_or(AxError::BadFileDescriptor)?
+        .inner
+        .clone();
+    // The new descriptor is always close-on-exec.
+    add_file_like(file, true).map(|fd| fd as _)
 }
 
 pub fn sys_pidfd_send_signal(
     pidfd: i32,
     signo: u32,
-    sig: *mut SignalInfo,
+    sig: *const SignalInfo,
     flags: u32,
 ) -> AxResult<isize> {
+    debug!("sys_pidfd_send_signal <= pidfd: {pidfd}, signo: {signo}, flags: {flags}");
+
     if flags != 0 {
         return Err(AxError::InvalidInput);
     }
 
     let pidfd = PidFd::from_fd(pidfd)?;
-    let pid = pidfd.process_data()?.proc.pid();
+    let pid = pidfd.live_process_data()?.proc.pid();
 
-    let sig = make_queue_signal_info(pid, signo, sig)?;
+    // Without a `siginfo`, the signal is sent as by kill(2).
+    let sig = if sig.is_null() {
+        make_siginfo(signo, SI_USER as _)?
+    } else {
+        make_queue_signal_info(pid, signo, sig)?
+    };
     send_signal_to_process(pid, sig)?;
     Ok(0)
 }
@@ -11,6 +11,7 @@ use starry_vm::{VmMutPtr, VmPtr};
 use crate::{
     file::{File, FileLike, resolve_at},
     mm::vm_load_string,
+    vfs::mounts,
 };
 
 /// Get the file metadata by `path` and write into `statbuf`.
@@ -143,13 +144,18 @@ fn statfs(loc: &Location) -> AxResult<statfs> {
     result.f_bavail = stat.blocks_available as _;
     result.f_files = stat.file_count as _;
     result.f_ffree = stat.free_file_count as _;
+    let device = loc.mountpoint().device();
     // TODO: fsid
     result.f_fsid = __kernel_fsid_t {
-        val: [0, loc.mountpoint().device() as _],
+        val: [0, device as _],
     };
     result.f_namelen = stat.name_length as _;
-    result.f_frsize = stat.fragment_size as _;
-    result.f_flags = stat.mount_flags as _;
+    result.f_frsize = if stat.fragment_size == 0 {
+        stat.block_size
+    } else {
+        stat.fragment_size
+    } as _;
+    result.f_flags = (stat.mount_flags as u32 | mounts::statfs_flags(device as _)) as _;
     Ok(result)
 }
 
@@ -11,6 +11,7 @@ use axtask::current;
 use linux_raw_sys::general::*;
 use memory_addr::{PAGE_SIZE_4K, VirtAddr, VirtAddrRange};
 use starry_core::{
+    mm,
     shm::{SHM_MANAGER, ShmInner, ShmidDs},
     task::AsThread,
 };
@@ -136,12 +137,28 @@ pub fn sys_shmat(shmid: i32, addr: usize, shmflg: u32) -> AxResult<isize> {
         // Another proccess has attached the shared memory
         // TODO(mivik): shm page size
         let backend = Backend::new_shared(start_addr, phys_pages);
-        aspace.map(start_addr, length, mapping_flags, false, backend)?;
+        mm::map(
+            proc_data,
+            &mut aspace,
+            start_addr,
+            length,
+            mapping_flags,
+            false,
+            backend,
+        )?;
     } else {
         // This is the first process to attach the shared memory
         let pages = Arc::new(SharedPages::new(length, PageSize::Size4K)?);
         let backend = Backend::new_shared(start_addr, pages.clone());
-        aspace.map(start_addr, length, mapping_flags, false, backend)?;
+        mm::map(
+            proc_data,
+            &mut aspace,
+            start_addr,
+            length,
+            mapping_flags,
+            false,
+            backend,
+        )?;
 
         shm_inner.map_to_phys(pages);
     }
@@ -214,7 +231,7 @@ pub fn sys_shmdt(shmaddr: usize) -> AxResult<isize> {
     let va_range = shm_inner.get_addr_range(pid).ok_or(AxError::InvalidInput)?;
 
     let mut aspace = proc_data.aspace.lock();
-    aspace.unmap(va_range.start, va_range.size())?;
+    mm::unmap(proc_data, &mut aspace, va_range.start, va_range.size())?;
 
     let mut shm_manager = SHM_MANAGER.lock();
     shm_manager.remove_shmaddr(pid, shmaddr);
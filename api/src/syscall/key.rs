@@ -0,0 +1,244 @@
+use alloc::{collections::btree_map::BTreeMap, sync::Arc, vec::Vec};
+use core::ffi::c_char;
+
+use axerrno::{AxError, AxResult, LinuxError};
+use axtask::current;
+use spin::Mutex;
+use starry_core::{
+    keys::{Key, KeyType, MAX_PAYLOAD, user_keyrings},
+    task::AsThread,
+};
+use starry_vm::{vm_load, vm_write_slice};
+
+use crate::{
+    file::{
+        FileLike, Pipe,
+        watch_queue::{Notification, WATCH_TYPE_KEY_NOTIFY, WatchList},
+    },
+    mm::vm_load_string,
+};
+
+const KEY_SPEC_THREAD_KEYRING: i32 = -1;
+const KEY_SPEC_PROCESS_KEYRING: i32 = -2;
+const KEY_SPEC_SESSION_KEYRING: i32 = -3;
+const KEY_SPEC_USER_KEYRING: i32 = -4;
+const KEY_SPEC_USER_SESSION_KEYRING: i32 = -5;
+
+const KEYCTL_GET_KEYRING_ID: u32 = 0;
+const KEYCTL_REVOKE: u32 = 3;
+const KEYCTL_DESCRIBE: u32 = 6;
+const KEYCTL_READ: u32 = 11;
+const KEYCTL_WATCH_KEY: u32 = 32;
+
+/// Subtypes of key notifications, from `enum key_notification_subtype`.
+const NOTIFY_KEY_UPDATED: u8 = 1;
+const NOTIFY_KEY_LINKED: u8 = 2;
+const NOTIFY_KEY_REVOKED: u8 = 5;
+
+/// The largest payload `add_key` takes, whatever the key type.
+const MAX_ADD_KEY_PAYLOAD: usize = 1024 * 1024 - 1;
+
+/// Looks up a key by serial number, resolving the special keyring IDs of the
+/// current process. With `create`, the process and session keyrings are
+/// created if missing.
+fn lookup_key(id: i32, create: bool) -> AxResult<Arc<Key>> {
+    let curr = current();
+    let proc_data = &curr.as_thread().proc_data;
+    match id {
+        KEY_SPEC_THREAD_KEYRING => {
+            warn!("thread keyrings are not supported");
+            Err(AxError::InvalidInput)
+        }
+        KEY_SPEC_PROCESS_KEYRING => {
+            let mut keyring = proc_data.process_keyring.lock();
+            if keyring.is_none() && create {
+                *keyring = Some(Key::new_keyring("_pid".into()));
+            }
+            keyring.clone().ok_or(AxError::from(LinuxError::ENOKEY))
+        }
+        KEY_SPEC_SESSION_KEYRING => {
+            let mut keyring = proc_data.session_keyring.lock();
+            if keyring.is_none() && create {
+                *keyring = Some(Key::new_keyring("_ses".into()));
+            }
+            // Without a session keyring, the user session keyring stands in.
+            Ok(keyring.clone().unwrap_or_else(|| user_keyrings().1))
+        }
+        KEY_SPEC_USER_KEYRING => Ok(user_keyrings().0),
+        KEY_SPEC_USER_SESSION_KEYRING => Ok(user_keyrings().1),
+        id if id > 0 => Key::get(id),
+        _ => Err(AxError::InvalidInput),
+    }
+}
+
+/// The watches set with `KEYCTL_WATCH_KEY`, by key serial number.
+///
+/// Lists of keys that went away are dropped the next time a watch is set,
+/// which posts removal records to the queues still watching them.
+static KEY_WATCHES: Mutex<BTreeMap<i32, Arc<WatchList>>> = Mutex::new(BTreeMap::new());
+
+/// Posts a change of `key` to the queues watching it, as a `struct
+/// key_notification`.
+fn notify_key(key: &Key, subtype: u8, aux: i32) {
+    let Some(watches) = KEY_WATCHES.lock().get(&key.serial()).cloned() else {
+        return;
+    };
+    let mut payload = [0; 8];
+    payload[..4].copy_from_slice(&key.serial().to_ne_bytes());
+    payload[4..].copy_from_slice(&aux.to_ne_bytes());
+    watches.post(&Notification {
+        ty: WATCH_TYPE_KEY_NOTIFY,
+        subtype,
+        info: 0,
+        payload: &payload,
+    });
+}
+
+/// Adds or, if `watch_id` is -1, removes a watch on `key` delivering to the
+/// notification pipe `fd`, as `KEYCTL_WATCH_KEY` does.
+fn watch_key(key: &Key, fd: i32, watch_id: i32) -> AxResult<()> {
+    let pipe = Pipe::from_fd(fd)?;
+    let mut watches = KEY_WATCHES.lock();
+    watches.retain(|serial, _| Key::get(*serial).is_ok());
+    match watch_id {
+        -1 => watches
+            .get(&key.serial())
+            .ok_or(AxError::from(LinuxError::EBADSLT))?
+            .remove(&pipe),
+        0..=0xff => watches
+            .entry(key.serial())
+            .or_default()
+            .add(&pipe, watch_id as u8),
+        _ => Err(AxError::InvalidInput),
+    }
+}
+
+/// Looks up the keyring a key is added or linked to.
+fn lookup_keyring(id: i32) -> AxResult<Arc<Key>> {
+    let keyring = lookup_key(id, true)?;
+    if keyring.key_type() != KeyType::Keyring {
+        return Err(AxError::NotADirectory);
+    }
+    Ok(keyring)
+}
+
+fn load_key_type(key_type: *const c_char) -> AxResult<KeyType> {
+    let name = vm_load_string(key_type)?;
+    // Types starting with a dot are internal to the kernel.
+    if name.starts_with('.') {
+        return Err(AxError::OperationNotPermitted);
+    }
+    KeyType::from_name(&name)
+}
+
+pub fn sys_add_key(
+    key_type: *const c_char,
+    description: *const c_char,
+    payload: *const u8,
+    plen: usize,
+    keyring: i32,
+) -> AxResult<isize> {
+    let key_type = load_key_type(key_type)?;
+    if description.is_null() {
+        return Err(AxError::InvalidInput);
+    }
+    let description = vm_load_string(description)?;
+    debug!("sys_add_key <= type: {key_type:?}, description: {description:?}, plen: {plen}");
+    if description.is_empty() || plen > MAX_ADD_KEY_PAYLOAD {
+        return Err(AxError::InvalidInput);
+    }
+
+    let data = if payload.is_null() {
+        Vec::new()
+    } else {
+        vm_load(payload, plen)?
+    };
+    match key_type {
+        KeyType::Keyring if !data.is_empty() => return Err(AxError::InvalidInput),
+        KeyType::User | KeyType::Logon if data.is_empty() || data.len() > MAX_PAYLOAD => {
+            return Err(AxError::InvalidInput);
+        }
+        _ => {}
+    }
+
+    let keyring = lookup_keyring(keyring)?;
+    let (key, updated) = keyring.add(key_type, description, data)?;
+    if updated {
+        notify_key(&key, NOTIFY_KEY_UPDATED, 0);
+    } else {
+        notify_key(&keyring, NOTIFY_KEY_LINKED, key.serial());
+    }
+    Ok(key.serial() as _)
+}
+
+pub fn sys_request_key(
+    key_type: *const c_char,
+    description: *const c_char,
+    callout_info: *const c_char,
+    dest_keyring: i32,
+) -> AxResult<isize> {
+    let key_type = load_key_type(key_type)?;
+    let description = vm_load_string(description)?;
+    debug!("sys_request_key <= type: {key_type:?}, description: {description:?}");
+
+    // The keyrings of the process are searched in the order Linux uses; the
+    // user keyring is linked to the user session keyring.
+    let key = [KEY_SPEC_PROCESS_KEYRING, KEY_SPEC_SESSION_KEYRING]
+        .into_iter()
+        .filter_map(|id| lookup_key(id, false).ok())
+        .find_map(|keyring| keyring.search(key_type, &description));
+    let Some(key) = key else {
+        // Keys can't be constructed by calling out to user space.
+        if !callout_info.is_null() {
+            warn!("sys_request_key: key construction is not supported");
+        }
+        return Err(AxError::from(LinuxError::ENOKEY));
+    };
+    if dest_keyring != 0 {
+        let keyring = lookup_keyring(dest_keyring)?;
+        keyring.link(key.clone())?;
+        notify_key(&keyring, NOTIFY_KEY_LINKED, key.serial());
+    }
+    Ok(key.serial() as _)
+}
+
+/// Copies `data` to the user buffer as `KEYCTL_DESCRIBE` and `KEYCTL_READ`
+/// do, returning the full length.
+fn copy_out(data: &[u8], buf: *mut u8, buflen: usize, partial: bool) -> AxResult<isize> {
+    if !buf.is_null() && (partial || buflen >= data.len()) {
+        vm_write_slice(buf, &data[..data.len().min(buflen)])?;
+    }
+    Ok(data.len() as _)
+}
+
+pub fn sys_keyctl(op: u32, arg2: usize, arg3: usize, arg4: usize, _arg5: usize) -> AxResult<isize> {
+    debug!("sys_keyctl <= op: {op}, arg2: {arg2:#x}, arg3: {arg3:#x}");
+
+    let id = arg2 as i32;
+    match op {
+        KEYCTL_GET_KEYRING_ID => Ok(lookup_key(id, arg3 != 0)?.serial() as _),
+        KEYCTL_REVOKE => {
+            let key = lookup_key(id, false)?;
+            key.revoke();
+            notify_key(&key, NOTIFY_KEY_REVOKED, 0);
+            Ok(0)
+        }
+        KEYCTL_DESCRIBE => {
+            let mut desc = lookup_key(id, false)?.describe()?.into_bytes();
+            desc.push(0);
+            copy_out(&desc, arg3 as _, arg4, false)
+        }
+        KEYCTL_READ => {
+            let data = lookup_key(id, false)?.read()?;
+            copy_out(&data, arg3 as _, arg4, true)
+        }
+        KEYCTL_WATCH_KEY => {
+            watch_key(&lookup_key(id, false)?, arg3 as _, arg4 as _)?;
+            Ok(0)
+        }
+        _ => {
+            warn!("sys_keyctl: unsupported operation {op}");
+            Err(AxError::OperationNotSupported)
+        }
+    }
+}
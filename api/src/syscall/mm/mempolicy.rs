@@ -0,0 +1,170 @@
+use alloc::vec;
+
+use axerrno::{AxError, AxResult};
+use axtask::current;
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr, VirtAddrRange, align_up_4k};
+use starry_core::{
+    mempolicy::{MPOL_DEFAULT, MPOL_INTERLEAVE, MemPolicy, NR_NODES, online_nodes},
+    mlock,
+    task::AsThread,
+};
+use starry_vm::{VmMutPtr, vm_load, vm_write_slice};
+
+const MPOL_F_NODE: u32 = 1 << 0;
+const MPOL_F_ADDR: u32 = 1 << 1;
+const MPOL_F_MEMS_ALLOWED: u32 = 1 << 2;
+
+const MPOL_MF_STRICT: u32 = 1 << 0;
+const MPOL_MF_MOVE: u32 = 1 << 1;
+const MPOL_MF_MOVE_ALL: u32 = 1 << 2;
+
+/// Reads a node mask of `maxnode` bits from user space. Like Linux, the last
+/// bit is dropped.
+fn read_nodemask(nmask: *const u8, maxnode: usize) -> AxResult<u64> {
+    let maxnode = maxnode.saturating_sub(1);
+    if nmask.is_null() || maxnode == 0 {
+        return Ok(0);
+    }
+    if maxnode > PAGE_SIZE_4K * 8 {
+        return Err(AxError::InvalidInput);
+    }
+    let bytes = vm_load(nmask, maxnode.div_ceil(64) * 8)?;
+    let mut nodes = 0;
+    for (i, word) in bytes.chunks_exact(8).enumerate() {
+        let mut word = u64::from_ne_bytes(word.try_into().unwrap());
+        let bits = maxnode - i * 64;
+        if bits < 64 {
+            word &= (1 << bits) - 1;
+        }
+        if i == 0 {
+            nodes = word;
+        } else if word != 0 {
+            // Past the largest node ID there can be.
+            return Err(AxError::InvalidInput);
+        }
+    }
+    Ok(nodes)
+}
+
+/// Writes `nodes` as a node mask of `maxnode` bits to user space, zeroing
+/// the rest of it.
+fn write_nodemask(nmask: *mut u8, maxnode: usize, nodes: u64) -> AxResult<()> {
+    if nmask.is_null() {
+        return Ok(());
+    }
+    let len = maxnode.saturating_sub(1).next_multiple_of(64) / 8;
+    if len > PAGE_SIZE_4K {
+        return Err(AxError::InvalidInput);
+    }
+    let mut bytes = vec![0; len];
+    let word = nodes.to_ne_bytes();
+    let n = len.min(word.len());
+    bytes[..n].copy_from_slice(&word[..n]);
+    vm_write_slice(nmask, &bytes)?;
+    Ok(())
+}
+
+pub fn sys_set_mempolicy(mode: u32, nmask: *const u8, maxnode: usize) -> AxResult<isize> {
+    debug!("sys_set_mempolicy <= mode: {mode:#x}, maxnode: {maxnode}");
+    let policy = MemPolicy::new(mode, read_nodemask(nmask, maxnode)?)?;
+    current().as_thread().set_mempolicy(policy);
+    Ok(0)
+}
+
+pub fn sys_get_mempolicy(
+    policy: *mut i32,
+    nmask: *mut u8,
+    maxnode: usize,
+    addr: usize,
+    flags: u32,
+) -> AxResult<isize> {
+    debug!("sys_get_mempolicy <= maxnode: {maxnode}, addr: {addr:#x}, flags: {flags:#x}");
+    if flags & !(MPOL_F_NODE | MPOL_F_ADDR | MPOL_F_MEMS_ALLOWED) != 0 {
+        return Err(AxError::InvalidInput);
+    }
+    if !nmask.is_null() && maxnode < NR_NODES {
+        return Err(AxError::InvalidInput);
+    }
+
+    let curr = current();
+    let thr = curr.as_thread();
+    let (mode, nodes) = if flags & MPOL_F_MEMS_ALLOWED != 0 {
+        if flags & (MPOL_F_NODE | MPOL_F_ADDR) != 0 {
+            return Err(AxError::InvalidInput);
+        }
+        (MPOL_DEFAULT, online_nodes())
+    } else {
+        let pol = if flags & MPOL_F_ADDR != 0 {
+            let aspace = thr.proc_data.aspace.lock();
+            aspace
+                .find_area(VirtAddr::from(addr))
+                .ok_or(AxError::BadAddress)?;
+            thr.proc_data
+                .mempolicy
+                .lock()
+                .get(VirtAddr::from(addr))
+                .unwrap_or_default()
+        } else {
+            if addr != 0 {
+                return Err(AxError::InvalidInput);
+            }
+            thr.mempolicy()
+        };
+        if flags & MPOL_F_NODE != 0 {
+            // With MPOL_F_ADDR, this is the node the page is on; otherwise
+            // the next node to interleave onto. There is only node 0.
+            if flags & MPOL_F_ADDR == 0 && pol.mode != MPOL_INTERLEAVE {
+                return Err(AxError::InvalidInput);
+            }
+            (0, pol.nodes)
+        } else {
+            (pol.mode | pol.flags, pol.nodes)
+        }
+    };
+
+    if !policy.is_null() {
+        policy.vm_write(mode as i32)?;
+    }
+    write_nodemask(nmask, maxnode, nodes)?;
+    Ok(0)
+}
+
+pub fn sys_mbind(
+    addr: usize,
+    length: usize,
+    mode: u32,
+    nmask: *const u8,
+    maxnode: usize,
+    flags: u32,
+) -> AxResult<isize> {
+    debug!(
+        "sys_mbind <= addr: {addr:#x}, length: {length:x}, mode: {mode:#x}, maxnode: {maxnode}, \
+         flags: {flags:#x}"
+    );
+    if flags & !(MPOL_MF_STRICT | MPOL_MF_MOVE | MPOL_MF_MOVE_ALL) != 0 {
+        return Err(AxError::InvalidInput);
+    }
+    if !addr.is_multiple_of(PAGE_SIZE_4K) {
+        return Err(AxError::InvalidInput);
+    }
+    let end = addr
+        .checked_add(align_up_4k(length))
+        .ok_or(AxError::InvalidInput)?;
+    let policy = MemPolicy::new(mode, read_nodemask(nmask, maxnode)?)?;
+    if policy.mode == MPOL_DEFAULT && flags != 0 {
+        return Err(AxError::InvalidInput);
+    }
+    if end == addr {
+        return Ok(0);
+    }
+
+    let range = VirtAddrRange::new(VirtAddr::from(addr), VirtAddr::from(end));
+    let curr = current();
+    let proc_data = &curr.as_thread().proc_data;
+    let aspace = proc_data.aspace.lock();
+    mlock::mapped_areas(&aspace, range).map_err(|_| AxError::BadAddress)?;
+    // With one node, every page is already where any policy wants it, so
+    // there is nothing to move or to fail MPOL_MF_STRICT over.
+    proc_data.mempolicy.lock().set(range, policy);
+    Ok(0)
+}
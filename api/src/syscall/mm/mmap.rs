@@ -1,4 +1,5 @@
 use alloc::sync::Arc;
+use core::sync::atomic::Ordering;
 
 use axerrno::{AxError, AxResult};
 use axfs::FileBackend;
@@ -6,8 +7,10 @@ use axhal::paging::{MappingFlags, PageSize};
 use axmm::backend::{Backend, SharedPages};
 use axtask::current;
 use linux_raw_sys::general::*;
-use memory_addr::{MemoryAddr, VirtAddr, VirtAddrRange, align_up_4k};
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr, VirtAddrRange, align_up_4k};
 use starry_core::{
+    mlock,
+    mm::{self, MMAP_MIN_ADDR},
     task::AsThread,
     vfs::{Device, DeviceMmap},
 };
@@ -75,6 +78,8 @@ bitflags::bitflags! {
         const NORESERVE = MAP_NORESERVE;
         /// Allocation is for a stack.
         const STACK = MAP_STACK;
+        /// The mapping grows down on faults just below it.
+        const GROWSDOWN = MAP_GROWSDOWN;
         /// Huge page
         const HUGE = MAP_HUGETLB;
         /// Huge page 1g size
@@ -145,30 +150,57 @@ pub fn sys_mmap(
     };
 
     let start = addr.align_down(page_size);
-    let end = (addr + length).align_up(page_size);
+    let end = addr
+        .checked_add(length)
+        .and_then(|end| end.checked_add(page_size as usize - 1))
+        .ok_or(AxError::NoMemory)?
+        .align_down(page_size);
     let mut length = end - start;
 
+    let align = page_size as usize;
+    // The sysctl only takes page-aligned values below the end of user space.
+    let min_addr = VirtAddr::from(MMAP_MIN_ADDR.load(Ordering::Relaxed)).max(aspace.base());
+    if min_addr >= aspace.end() {
+        return Err(AxError::NoMemory);
+    }
+    // Whether the mapping replaces whatever is mapped in its range, which is
+    // only unmapped once nothing else can fail.
+    let mut replace = false;
     let start = if map_flags.intersects(MmapFlags::FIXED | MmapFlags::FIXED_NOREPLACE) {
         let dst_addr = VirtAddr::from(start);
-        if !map_flags.contains(MmapFlags::FIXED_NOREPLACE) {
-            aspace.unmap(dst_addr, length)?;
+        if dst_addr < min_addr {
+            return Err(AxError::OperationNotPermitted);
+        }
+        if map_flags.contains(MmapFlags::FIXED_NOREPLACE) {
+            // The range is free only if the first free area at or above it
+            // starts right at it.
+            let range = VirtAddrRange::from_start_size(dst_addr, length);
+            if aspace.find_free_area(dst_addr, length, range, align) != Some(dst_addr) {
+                return Err(AxError::AlreadyExists);
+            }
+        } else {
+            replace = true;
         }
         dst_addr
     } else {
-        let align = page_size as usize;
-        aspace
-            .find_free_area(
-                VirtAddr::from(start),
-                length,
-                VirtAddrRange::new(aspace.base(), aspace.end()),
-                align,
-            )
-            .or(aspace.find_free_area(
-                aspace.base(),
-                length,
-                VirtAddrRange::new(aspace.base(), aspace.end()),
-                align,
-            ))
+        let proc_data = &curr.as_thread().proc_data;
+        let limit = VirtAddrRange::new(min_addr, aspace.end());
+        let base = VirtAddr::from(proc_data.mmap_base());
+        let found = if start != 0 {
+            aspace.find_free_area(VirtAddr::from(start).max(min_addr), length, limit, align)
+        } else {
+            None
+        };
+        // As in Linux, a top-down search that fails falls back to bottom-up.
+        found
+            .or_else(|| {
+                if proc_data.mmap_top_down() {
+                    mm::find_free_area_top_down(&aspace, base, length, limit, align)
+                } else {
+                    aspace.find_free_area(base.max(min_addr), length, limit, align)
+                }
+            })
+            .or_else(|| aspace.find_free_area(min_addr, length, limit, align))
             .ok_or(AxError::NoMemory)?
     };
 
@@ -177,6 +209,7 @@ pub fn sys_mmap(
     } else {
         None
     };
+    let mapped_file = file.as_ref().map(|file| file.inner().location().clone());
 
     let backend = match map_type {
         MmapFlags::SHARED | MmapFlags::SHARED_VALIDATE => {
@@ -243,8 +276,39 @@ pub fn sys_mmap(
         _ => return Err(AxError::InvalidInput),
     };
 
+    let proc_data = &curr.as_thread().proc_data;
+    let range = VirtAddrRange::from_start_size(start, length);
+    let replaced = if replace {
+        mm::mapped_size(&aspace, range)
+    } else {
+        0
+    };
+    mm::check_as_limit(proc_data, length - replaced)?;
+    if replace {
+        mm::unmap(proc_data, &mut aspace, start, length)?;
+    }
     let populate = map_flags.contains(MmapFlags::POPULATE);
-    aspace.map(start, length, permission_flags.into(), populate, backend)?;
+    mm::map(
+        proc_data,
+        &mut aspace,
+        start,
+        length,
+        permission_flags.into(),
+        populate,
+        backend,
+    )?;
+    if let Err(err) = mlock::lock_new_mapping(proc_data, &mut aspace, range) {
+        mm::unmap(proc_data, &mut aspace, start, length)?;
+        return Err(err);
+    }
+    if map_flags.contains(MmapFlags::GROWSDOWN) {
+        proc_data.stacks.lock().push(range);
+    }
+    let mut mapped_files = proc_data.mapped_files.lock();
+    match mapped_file {
+        Some(loc) => mapped_files.insert(range, loc, offset as u64),
+        None => mapped_files.remove(range),
+    }
 
     Ok(start.as_usize() as _)
 }
@@ -252,10 +316,15 @@ pub fn sys_mmap(
 pub fn sys_munmap(addr: usize, length: usize) -> AxResult<isize> {
     debug!("sys_munmap <= addr: {addr:#x}, length: {length:x}");
     let curr = current();
-    let mut aspace = curr.as_thread().proc_data.aspace.lock();
+    let proc_data = &curr.as_thread().proc_data;
+    let mut aspace = proc_data.aspace.lock();
     let length = align_up_4k(length);
     let start_addr = VirtAddr::from(addr);
-    aspace.unmap(start_addr, length)?;
+    mm::unmap(proc_data, &mut aspace, start_addr, length)?;
+    let range = VirtAddrRange::from_start_size(start_addr, length);
+    proc_data.mlock.lock().unlock(range);
+    proc_data.mempolicy.lock().remove(range);
+    proc_data.mapped_files.lock().remove(range);
     Ok(0)
 }
 
@@ -293,10 +362,23 @@ pub fn sys_mremap(addr: usize, old_size: usize, new_size: usize, flags: u32) ->
     let addr = VirtAddr::from(addr);
 
     let curr = current();
-    let aspace = curr.as_thread().proc_data.aspace.lock();
+    let proc_data = &curr.as_thread().proc_data;
+    let aspace = proc_data.aspace.lock();
     let old_size = align_up_4k(old_size);
     let new_size = align_up_4k(new_size);
 
+    // A locked mapping stays locked where it moves to, and what it grows by
+    // counts against RLIMIT_MEMLOCK.
+    let locked = {
+        let locks = proc_data.mlock.lock();
+        let locked = locks.is_locked(VirtAddrRange::from_start_size(addr, old_size));
+        let grown = new_size.saturating_sub(old_size);
+        if locked && locks.locked_bytes().saturating_add(grown) > mlock::memlock_limit(proc_data) {
+            return Err(AxError::WouldBlock);
+        }
+        locked
+    };
+
     let flags = aspace.find_area(addr).ok_or(AxError::NoMemory)?.flags();
     drop(aspace);
     let new_addr = sys_mmap(
@@ -314,6 +396,18 @@ pub fn sys_mremap(addr: usize, old_size: usize, new_size: usize, flags: u32) ->
 
     sys_munmap(addr.as_usize(), old_size)?;
 
+    if locked {
+        let range = VirtAddrRange::from_start_size(VirtAddr::from(new_addr), new_size);
+        proc_data
+            .mlock
+            .lock()
+            .lock(range, mlock::memlock_limit(proc_data))
+            .map_err(|_| AxError::WouldBlock)?;
+        let mut aspace = proc_data.aspace.lock();
+        let areas = mlock::mapped_areas(&aspace, range)?;
+        mlock::populate(&mut aspace, &areas)?;
+    }
+
     Ok(new_addr as isize)
 }
 
@@ -332,6 +426,88 @@ pub fn sys_mlock(addr: usize, length: usize) -> AxResult<isize> {
     sys_mlock2(addr, length, 0)
 }
 
-pub fn sys_mlock2(_addr: usize, _length: usize, _flags: u32) -> AxResult<isize> {
+/// Returns the pages spanned by `length` bytes at `addr`.
+fn page_range(addr: usize, length: usize) -> AxResult<VirtAddrRange> {
+    let start = VirtAddr::from(addr).align_down_4k();
+    let end = addr
+        .checked_add(length)
+        .filter(|end| *end <= usize::MAX - PAGE_SIZE_4K)
+        .ok_or(AxError::NoMemory)?;
+    Ok(VirtAddrRange::new(start, VirtAddr::from(end).align_up_4k()))
+}
+
+pub fn sys_mlock2(addr: usize, length: usize, flags: u32) -> AxResult<isize> {
+    debug!("sys_mlock2 <= addr: {addr:#x}, length: {length:x}, flags: {flags:#x}");
+    if flags & !MLOCK_ONFAULT != 0 {
+        return Err(AxError::InvalidInput);
+    }
+    let range = page_range(addr, length)?;
+    if range.is_empty() {
+        return Ok(0);
+    }
+
+    let curr = current();
+    let proc_data = &curr.as_thread().proc_data;
+    let mut aspace = proc_data.aspace.lock();
+    let areas = mlock::mapped_areas(&aspace, range)?;
+    proc_data
+        .mlock
+        .lock()
+        .lock(range, mlock::memlock_limit(proc_data))?;
+    if flags & MLOCK_ONFAULT == 0 {
+        mlock::populate(&mut aspace, &areas)?;
+    }
+    Ok(0)
+}
+
+pub fn sys_munlock(addr: usize, length: usize) -> AxResult<isize> {
+    debug!("sys_munlock <= addr: {addr:#x}, length: {length:x}");
+    let range = page_range(addr, length)?;
+
+    let curr = current();
+    let proc_data = &curr.as_thread().proc_data;
+    let aspace = proc_data.aspace.lock();
+    mlock::mapped_areas(&aspace, range)?;
+    proc_data.mlock.lock().unlock(range);
+    Ok(0)
+}
+
+pub fn sys_mlockall(flags: u32) -> AxResult<isize> {
+    debug!("sys_mlockall <= flags: {flags:#x}");
+    let known = MCL_CURRENT | MCL_FUTURE | MCL_ONFAULT;
+    if flags == 0 || flags & !known != 0 || flags == MCL_ONFAULT {
+        return Err(AxError::InvalidInput);
+    }
+
+    let curr = current();
+    let proc_data = &curr.as_thread().proc_data;
+    let mut aspace = proc_data.aspace.lock();
+    let mut locks = proc_data.mlock.lock();
+    if flags & MCL_CURRENT != 0 {
+        let areas = mlock::all_areas(&aspace);
+        let limit = mlock::memlock_limit(proc_data);
+        // Check up front, so that nothing is locked on failure.
+        if areas.iter().map(|(range, _)| range.size()).sum::<usize>() > limit {
+            return Err(if limit == 0 {
+                AxError::OperationNotPermitted
+            } else {
+                AxError::NoMemory
+            });
+        }
+        for (range, _) in &areas {
+            locks.lock(*range, limit)?;
+        }
+        if flags & MCL_ONFAULT == 0 {
+            mlock::populate(&mut aspace, &areas)?;
+        }
+    }
+    locks.future = flags & MCL_FUTURE != 0;
+    locks.future_on_fault = locks.future && flags & MCL_ONFAULT != 0;
+    Ok(0)
+}
+
+pub fn sys_munlockall() -> AxResult<isize> {
+    debug!("sys_munlockall");
+    current().as_thread().proc_data.mlock.lock().clear();
     Ok(0)
 }
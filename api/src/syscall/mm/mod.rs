@@ -1,5 +1,6 @@
 mod brk;
+mod mempolicy;
 mod mincore;
 mod mmap;
 
-pub use self::{brk::*, mincore::*, mmap::*};
+pub use self::{brk::*, mempolicy::*, mincore::*, mmap::*};
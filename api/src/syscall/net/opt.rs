@@ -1,6 +1,11 @@
+use core::time::Duration;
+
 use axerrno::{AxError, AxResult, LinuxError};
 use axnet::options::{Configurable, GetSocketOption, SetSocketOption};
-use linux_raw_sys::net::socklen_t;
+use linux_raw_sys::net::{
+    SO_ACCEPTCONN, SO_BUSY_POLL, SOL_SOCKET, TCP_DEFER_ACCEPT, TCP_INFO, socklen_t, tcp_info,
+};
+use zerocopy::{Immutable, IntoBytes};
 
 use crate::{
     file::{FileLike, Socket},
@@ -11,6 +16,70 @@ const PROTO_TCP: u32 = linux_raw_sys::net::IPPROTO_TCP as u32;
 
 const PROTO_IP: u32 = linux_raw_sys::net::IPPROTO_IP as u32;
 
+/// `TCP_LISTEN` from `enum tcp_state`.
+const TCP_LISTEN: u8 = 10;
+
+/// `SOL_MPTCP` from `<linux/socket.h>`.
+const SOL_MPTCP: u32 = 284;
+
+/// `MPTCP_INFO` from `<linux/mptcp.h>`.
+const MPTCP_INFO: u32 = 1;
+
+/// `struct mptcp_info` from `<linux/mptcp.h>`, with its padding spelled out.
+#[repr(C)]
+#[derive(Default, Immutable, IntoBytes)]
+struct MptcpInfo {
+    subflows: u8,
+    add_addr_signal: u8,
+    add_addr_accepted: u8,
+    subflows_max: u8,
+    add_addr_signal_max: u8,
+    add_addr_accepted_max: u8,
+    _pad0: [u8; 2],
+    flags: u32,
+    token: u32,
+    write_seq: u64,
+    snd_una: u64,
+    rcv_nxt: u64,
+    local_addr_used: u8,
+    local_addr_max: u8,
+    csum_enabled: u8,
+    _pad1: u8,
+    retransmits: u32,
+    bytes_retrans: u64,
+    bytes_sent: u64,
+    bytes_received: u64,
+    bytes_acked: u64,
+    subflows_total: u8,
+    _reserved: [u8; 3],
+    last_data_sent: u32,
+    last_data_recv: u32,
+    last_ack_recv: u32,
+}
+
+/// The start of `struct tcp_info` from `<linux/tcp.h>`, up to the fields
+/// reported for listening sockets. The rest of the structure reads as zero.
+#[repr(C)]
+#[derive(Default, Immutable, IntoBytes)]
+struct TcpInfoHead {
+    state: u8,
+    ca_state: u8,
+    retransmits: u8,
+    probes: u8,
+    backoff: u8,
+    options: u8,
+    /// `tcpi_snd_wscale` and `tcpi_rcv_wscale`, four bits each.
+    wscale: u8,
+    /// `tcpi_delivery_rate_app_limited` and `tcpi_fastopen_client_fail`.
+    flags: u8,
+    rto: u32,
+    ato: u32,
+    snd_mss: u32,
+    rcv_mss: u32,
+    unacked: u32,
+    sacked: u32,
+}
+
 mod conv {
     use axerrno::{AxError, AxResult};
     use axnet::options::UnixCredentials;
@@ -138,6 +207,60 @@ pub fn sys_getsockopt(
     }
 
     let socket = Socket::from_fd(fd)?;
+
+    // Options tracked by `Socket` itself rather than the protocol stack
+    match (level, optname) {
+        (SOL_SOCKET, SO_ACCEPTCONN) => {
+            *get(optval, optlen)? = conv::IntBool::rust_to_sys(socket.is_listening())?;
+            return Ok(0);
+        }
+        (SOL_SOCKET, SO_BUSY_POLL) => {
+            *get(optval, optlen)? = conv::Int::<u32>::rust_to_sys(socket.busy_poll())?;
+            return Ok(0);
+        }
+        (PROTO_TCP, TCP_DEFER_ACCEPT) => {
+            let secs = socket.defer_accept().as_secs() as u32;
+            *get(optval, optlen)? = conv::Int::<u32>::rust_to_sys(secs)?;
+            return Ok(0);
+        }
+        (PROTO_TCP, TCP_INFO) => {
+            // For listeners Linux reports the accept queue occupancy in
+            // `tcpi_unacked` and the backlog in `tcpi_sacked`.
+            if let Some((pending, backlog)) = socket.backlog_stats() {
+                let head = TcpInfoHead {
+                    state: TCP_LISTEN,
+                    unacked: pending as _,
+                    sacked: backlog as _,
+                    ..Default::default()
+                };
+                // Like Linux, fill in as much as fits.
+                let len = (*optlen as usize).min(size_of::<tcp_info>());
+                let buf = optval.get_as_mut_slice(len)?;
+                buf.fill(0);
+                let head_len = len.min(size_of::<TcpInfoHead>());
+                buf[..head_len].copy_from_slice(&head.as_bytes()[..head_len]);
+                *optlen = len as _;
+                return Ok(0);
+            }
+        }
+        (SOL_MPTCP, MPTCP_INFO) if socket.is_mptcp() => {
+            // The connection never has more than its initial subflow.
+            let info = MptcpInfo {
+                subflows_total: 1,
+                ..Default::default()
+            };
+            // Like Linux, copy as much as fits, so that a zero length can be
+            // used to check for MPTCP without caring about the contents.
+            let len = (*optlen as usize).min(size_of::<MptcpInfo>());
+            optval
+                .get_as_mut_slice(len)?
+                .copy_from_slice(&info.as_bytes()[..len]);
+            *optlen = len as _;
+            return Ok(0);
+        }
+        _ => {}
+    }
+
     macro_rules! dispatch {
         ($which:ident) => {
             socket.get_option(GetSocketOption::$which(get(optval, optlen)?))?;
@@ -177,6 +300,21 @@ pub fn sys_setsockopt(
     }
 
     let socket = Socket::from_fd(fd)?;
+
+    match (level, optname) {
+        (PROTO_TCP, TCP_DEFER_ACCEPT) => {
+            let secs = *get::<i32>(optval, optlen)?;
+            socket.set_defer_accept(Duration::from_secs(secs.max(0) as u64));
+            return Ok(0);
+        }
+        (SOL_SOCKET, SO_BUSY_POLL) => {
+            let usecs = conv::Int::<u32>::sys_to_rust(*get(optval, optlen)?)?;
+            socket.set_busy_poll(usecs);
+            return Ok(0);
+        }
+        _ => {}
+    }
+
     macro_rules! dispatch {
         ($which:ident) => {
             socket.set_option(SetSocketOption::$which(get(optval, optlen)?))?;
@@ -23,6 +23,9 @@ use crate::{
     socket::SocketAddrExt,
 };
 
+/// `IPPROTO_MPTCP` from `<netinet/in.h>`.
+const IPPROTO_MPTCP: u32 = 262;
+
 pub fn sys_socket(domain: u32, raw_ty: u32, proto: u32) -> AxResult<isize> {
     debug!("sys_socket <= domain: {domain}, ty: {raw_ty}, proto: {proto}");
     let ty = raw_ty & 0xFF;
@@ -30,7 +33,7 @@ pub fn sys_socket(domain: u32, raw_ty: u32, proto: u32) -> AxResult<isize> {
     let pid = current().as_thread().proc_data.proc.pid();
     let socket = match (domain, ty) {
         (AF_INET, SOCK_STREAM) => {
-            if proto != 0 && proto != IPPROTO_TCP as _ {
+            if proto != 0 && proto != IPPROTO_TCP as _ && proto != IPPROTO_MPTCP {
                 return Err(AxError::from(LinuxError::EPROTONOSUPPORT));
             }
             axnet::Socket::Tcp(TcpSocket::new())
@@ -55,7 +58,11 @@ pub fn sys_socket(domain: u32, raw_ty: u32, proto: u32) -> AxResult<isize> {
             return Err(AxError::from(LinuxError::EAFNOSUPPORT));
         }
     };
-    let socket = Socket(socket);
+    let socket = if domain == AF_INET && proto == IPPROTO_MPTCP {
+        Socket::new_mptcp(socket)
+    } else {
+        Socket::new(socket)
+    };
 
     if raw_ty & O_NONBLOCK != 0 {
         socket.set_nonblocking(true)?;
@@ -96,7 +103,7 @@ pub fn sys_listen(fd: i32, backlog: i32) -> AxResult<isize> {
         return Err(AxError::InvalidInput);
     }
 
-    Socket::from_fd(fd)?.listen()?;
+    Socket::from_fd(fd)?.listen(backlog)?;
 
     Ok(0)
 }
@@ -120,7 +127,7 @@ pub fn sys_accept4(
     let cloexec = flags & O_CLOEXEC != 0;
 
     let socket = Socket::from_fd(fd)?;
-    let socket = Socket(socket.accept()?);
+    let socket = socket.accept()?;
     if flags & O_NONBLOCK != 0 {
         socket.set_nonblocking(true)?;
     }
@@ -177,8 +184,8 @@ pub fn sys_socketpair(
             return Err(AxError::from(LinuxError::ESOCKTNOSUPPORT));
         }
     };
-    let sock1 = Socket(axnet::Socket::Unix(sock1));
-    let sock2 = Socket(axnet::Socket::Unix(sock2));
+    let sock1 = Socket::new(axnet::Socket::Unix(sock1));
+    let sock2 = Socket::new(axnet::Socket::Unix(sock2));
 
     if raw_ty & O_NONBLOCK != 0 {
         sock1.set_nonblocking(true)?;
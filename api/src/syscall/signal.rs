@@ -7,8 +7,8 @@ use axtask::{
     future::{self, block_on},
 };
 use linux_raw_sys::general::{
-    MINSIGSTKSZ, SI_TKILL, SI_USER, SIG_BLOCK, SIG_SETMASK, SIG_UNBLOCK, kernel_sigaction, siginfo,
-    timespec,
+    MINSIGSTKSZ, SI_TKILL, SI_USER, SIG_BLOCK, SIG_SETMASK, SIG_UNBLOCK, SS_AUTODISARM, SS_DISABLE,
+    SS_ONSTACK, kernel_sigaction, siginfo, timespec,
 };
 use starry_core::task::{
     AsThread, processes, send_signal_to_process, send_signal_to_process_group,
@@ -99,7 +99,7 @@ pub fn sys_rt_sigpending(set: *mut SignalSet, sigsetsize: usize) -> AxResult<isi
     Ok(0)
 }
 
-fn make_siginfo(signo: u32, code: i32) -> AxResult<Option<SignalInfo>> {
+pub(crate) fn make_siginfo(signo: u32, code: i32) -> AxResult<Option<SignalInfo>> {
     if signo == 0 {
         return Ok(None);
     }
@@ -297,20 +297,62 @@ pub fn sys_rt_sigsuspend(
     Err(AxError::Interrupted)
 }
 
-pub fn sys_sigaltstack(ss: *const SignalStack, old_ss: *mut SignalStack) -> AxResult<isize> {
+/// Returns whether `sp` is within the alternate signal stack `stack`.
+fn on_sig_stack(stack: &SignalStack, sp: usize) -> bool {
+    stack.flags & SS_DISABLE == 0 && sp > stack.sp && sp - stack.sp <= stack.size
+}
+
+pub fn sys_sigaltstack(
+    uctx: &UserContext,
+    ss: *const SignalStack,
+    old_ss: *mut SignalStack,
+) -> AxResult<isize> {
     let curr = current();
     let sig = &curr.as_thread().signal;
 
-    if let Some(old_ss) = old_ss.nullable() {
-        old_ss.vm_write(sig.stack())?;
-    }
+    let stack = sig.stack();
+    let on_stack = on_sig_stack(&stack, uctx.sp());
+    let state = if on_stack {
+        SS_ONSTACK
+    } else {
+        stack.flags & SS_DISABLE
+    };
+    let old = SignalStack {
+        sp: stack.sp,
+        flags: state | (stack.flags & SS_AUTODISARM),
+        size: stack.size,
+    };
 
     if let Some(ss) = ss.nullable() {
-        let ss = unsafe { ss.vm_read_uninit()?.assume_init() };
-        if ss.size <= MINSIGSTKSZ as usize {
-            return Err(AxError::NoMemory);
+        let mut ss = unsafe { ss.vm_read_uninit()?.assume_init() };
+        debug!(
+            "sys_sigaltstack <= sp: {:#x}, flags: {:#x}, size: {:#x}",
+            ss.sp, ss.flags, ss.size
+        );
+        // The stack in use can't be changed.
+        if on_stack {
+            return Err(AxError::OperationNotPermitted);
+        }
+        match ss.flags & !SS_AUTODISARM {
+            SS_DISABLE => {
+                ss.sp = 0;
+                ss.size = 0;
+            }
+            // `SS_ONSTACK` is accepted for compatibility and means the same
+            // as 0.
+            0 | SS_ONSTACK => {
+                if ss.size < MINSIGSTKSZ as usize {
+                    return Err(AxError::NoMemory);
+                }
+                ss.flags &= SS_AUTODISARM;
+            }
+            _ => return Err(AxError::InvalidInput),
         }
         sig.set_stack(ss);
     }
+
+    if let Some(old_ss) = old_ss.nullable() {
+        old_ss.vm_write(old)?;
+    }
     Ok(0)
 }
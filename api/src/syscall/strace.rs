@@ -0,0 +1,131 @@
+//! Per-process syscall logging, enabled through /proc/[pid]/syscall_trace.
+//!
+//! Each syscall of a traced process is logged as a line in the style of
+//! strace: its name, its decoded arguments and its return value. Only the
+//! most recent [`LOG_CAPACITY`] lines are kept.
+
+use alloc::{format, string::String, vec::Vec};
+use core::{ffi::c_char, sync::atomic::Ordering};
+
+use axerrno::{AxResult, LinuxError};
+use axhal::uspace::UserContext;
+use axtask::current;
+use linux_raw_sys::general::AT_FDCWD;
+use starry_core::task::AsThread;
+use syscalls::Sysno;
+
+use crate::mm::vm_load_string;
+
+/// The number of lines kept per process.
+pub const LOG_CAPACITY: usize = 256;
+
+/// Strings longer than this are cut short.
+const MAX_STR_LEN: usize = 32;
+
+#[derive(Clone, Copy)]
+enum Arg {
+    Int,
+    Hex,
+    Fd,
+    Str,
+}
+
+/// Returns how the arguments of `sysno` are shown. Syscalls not listed here
+/// show all six arguments in hexadecimal.
+fn arg_kinds(sysno: Sysno) -> &'static [Arg] {
+    use Arg::*;
+    match sysno {
+        Sysno::read | Sysno::write => &[Fd, Hex, Int],
+        Sysno::pread64 | Sysno::pwrite64 => &[Fd, Hex, Int, Int],
+        Sysno::openat => &[Fd, Str, Hex, Hex],
+        Sysno::close | Sysno::fsync | Sysno::fchdir => &[Fd],
+        Sysno::lseek => &[Fd, Int, Int],
+        Sysno::ioctl => &[Fd, Hex, Hex],
+        Sysno::dup3 => &[Fd, Fd, Hex],
+        Sysno::getdents64 => &[Fd, Hex, Int],
+        Sysno::execve => &[Str, Hex, Hex],
+        Sysno::chdir => &[Str],
+        Sysno::mkdirat => &[Fd, Str, Hex],
+        Sysno::unlinkat => &[Fd, Str, Hex],
+        Sysno::readlinkat => &[Fd, Str, Hex, Int],
+        Sysno::faccessat => &[Fd, Str, Hex],
+        Sysno::mmap => &[Hex, Int, Hex, Hex, Fd, Hex],
+        Sysno::munmap => &[Hex, Int],
+        Sysno::mprotect => &[Hex, Int, Hex],
+        Sysno::brk => &[Hex],
+        Sysno::pipe2 => &[Hex, Hex],
+        Sysno::wait4 => &[Int, Hex, Hex, Hex],
+        Sysno::waitid => &[Int, Int, Hex, Hex, Hex],
+        Sysno::kill => &[Int, Int],
+        Sysno::ptrace => &[Hex, Int, Hex, Hex],
+        Sysno::exit | Sysno::exit_group => &[Int],
+        Sysno::getpid | Sysno::getppid | Sysno::gettid | Sysno::sched_yield => &[],
+        _ => &[Hex, Hex, Hex, Hex, Hex, Hex],
+    }
+}
+
+fn format_arg(kind: Arg, value: usize) -> String {
+    match kind {
+        Arg::Int => format!("{}", value as isize),
+        Arg::Hex => format!("{value:#x}"),
+        Arg::Fd if value as i32 == AT_FDCWD => "AT_FDCWD".into(),
+        Arg::Fd => format!("{}", value as i32),
+        Arg::Str => match vm_load_string(value as *const c_char) {
+            Ok(s) if s.len() > MAX_STR_LEN => {
+                let end = s.floor_char_boundary(MAX_STR_LEN);
+                format!("{:?}...", &s[..end])
+            }
+            Ok(s) => format!("{s:?}"),
+            Err(_) => format!("{value:#x}"),
+        },
+    }
+}
+
+/// Decodes the syscall about to be made, if the current process is traced.
+///
+/// Arguments are decoded before the syscall runs, since it may change or
+/// replace the memory they point to.
+pub fn enter(sysno: Sysno, uctx: &UserContext) -> Option<String> {
+    let curr = current();
+    let thr = curr.try_as_thread()?;
+    if !thr.proc_data.syscall_trace.load(Ordering::Relaxed) {
+        return None;
+    }
+    let args = [
+        uctx.arg0(),
+        uctx.arg1(),
+        uctx.arg2(),
+        uctx.arg3(),
+        uctx.arg4(),
+        uctx.arg5(),
+    ];
+    let args = arg_kinds(sysno)
+        .iter()
+        .zip(args)
+        .map(|(kind, value)| format_arg(*kind, value))
+        .collect::<Vec<_>>();
+    Some(format!(
+        "[{}] {sysno}({})",
+        curr.id().as_u64(),
+        args.join(", ")
+    ))
+}
+
+/// Logs the syscall decoded by [`enter`] along with its result.
+pub fn exit(call: String, result: &AxResult<isize>) {
+    let line = match result {
+        Ok(value) => format!("{call} = {value}"),
+        Err(err) => {
+            let errno = LinuxError::from(*err);
+            format!("{call} = -1 {errno:?} ({})", errno.as_str())
+        }
+    };
+    let curr = current();
+    // The log may have been turned off in the meantime.
+    if let Some(log) = curr.as_thread().proc_data.syscall_log.lock().as_mut() {
+        if log.len() == LOG_CAPACITY {
+            log.pop_front();
+        }
+        log.push_back(line);
+    }
+}
@@ -1,53 +1,111 @@
-use alloc::vec;
-use core::ffi::c_char;
+use alloc::{vec, vec::Vec};
+use core::{ffi::c_char, future::poll_fn, task::Poll};
 
 use axconfig::ARCH;
 use axerrno::{AxError, AxResult};
-use axfs::FS_CONTEXT;
+use axtask::{
+    current,
+    future::{block_on, interruptible},
+};
 use linux_raw_sys::{
-    general::{GRND_INSECURE, GRND_NONBLOCK, GRND_RANDOM},
+    general::{
+        GRND_INSECURE, GRND_NONBLOCK, GRND_RANDOM, LINUX_REBOOT_CMD_CAD_OFF,
+        LINUX_REBOOT_CMD_CAD_ON, LINUX_REBOOT_CMD_HALT, LINUX_REBOOT_CMD_POWER_OFF,
+        LINUX_REBOOT_CMD_RESTART, LINUX_REBOOT_CMD_RESTART2, LINUX_REBOOT_MAGIC1,
+        LINUX_REBOOT_MAGIC2, LINUX_REBOOT_MAGIC2A, LINUX_REBOOT_MAGIC2B, LINUX_REBOOT_MAGIC2C,
+    },
     system::{new_utsname, sysinfo},
 };
-use starry_core::task::processes;
-use starry_vm::{VmMutPtr, vm_write_slice};
+use memory_addr::PAGE_SIZE_4K;
+use starry_core::{
+    cred::{Credentials, NGROUPS_MAX},
+    kmsg,
+    power::{self, ResetKind},
+    random,
+    task::{AsThread, processes},
+};
+use starry_vm::{VmMutPtr, vm_load, vm_write_slice};
 
-pub fn sys_getuid() -> AxResult<isize> {
+use crate::mm::vm_load_string;
+
+/// Returns a copy of the credentials of the current process.
+fn current_cred() -> Credentials {
+    current().as_thread().proc_data.cred.read().clone()
+}
+
+/// Changes the credentials of the current process with `f`, which is also
+/// told whether the process is privileged.
+pub(crate) fn update_cred(
+    f: impl FnOnce(&mut Credentials, bool) -> AxResult<()>,
+) -> AxResult<isize> {
+    let curr = current();
+    let mut cred = curr.as_thread().proc_data.cred.write();
+    let privileged = cred.is_privileged();
+    f(&mut cred, privileged)?;
     Ok(0)
 }
 
+/// Maps the `-1` that leaves an ID alone to `None`.
+pub(crate) fn optional_id(id: u32) -> Option<u32> {
+    (id != u32::MAX).then_some(id)
+}
+
+pub fn sys_getuid() -> AxResult<isize> {
+    Ok(current_cred().uid.real as _)
+}
+
 pub fn sys_geteuid() -> AxResult<isize> {
-    Ok(0)
+    Ok(current_cred().uid.effective as _)
 }
 
 pub fn sys_getgid() -> AxResult<isize> {
-    Ok(0)
+    Ok(current_cred().gid.real as _)
 }
 
 pub fn sys_getegid() -> AxResult<isize> {
-    Ok(0)
+    Ok(current_cred().gid.effective as _)
 }
 
-pub fn sys_setuid(_uid: u32) -> AxResult<isize> {
-    debug!("sys_setuid <= uid: {_uid}");
-    Ok(0)
+pub fn sys_setuid(uid: u32) -> AxResult<isize> {
+    debug!("sys_setuid <= uid: {uid}");
+    update_cred(|cred, privileged| cred.uid.set(uid, privileged))
 }
 
-pub fn sys_setgid(_gid: u32) -> AxResult<isize> {
-    debug!("sys_setgid <= gid: {_gid}");
-    Ok(0)
+pub fn sys_setgid(gid: u32) -> AxResult<isize> {
+    debug!("sys_setgid <= gid: {gid}");
+    update_cred(|cred, privileged| cred.gid.set(gid, privileged))
 }
 
 pub fn sys_getgroups(size: usize, list: *mut u32) -> AxResult<isize> {
     debug!("sys_getgroups <= size: {size}");
-    if size < 1 {
+    let groups = current_cred().groups;
+    if size == 0 {
+        return Ok(groups.len() as _);
+    }
+    if size < groups.len() {
         return Err(AxError::InvalidInput);
     }
-    vm_write_slice(list, &[0])?;
-    Ok(1)
+    vm_write_slice(list, &groups)?;
+    Ok(groups.len() as _)
 }
 
-pub fn sys_setgroups(_size: usize, _list: *const u32) -> AxResult<isize> {
-    Ok(0)
+pub fn sys_setgroups(size: usize, list: *const u32) -> AxResult<isize> {
+    debug!("sys_setgroups <= size: {size}");
+    if size > NGROUPS_MAX {
+        return Err(AxError::InvalidInput);
+    }
+    let groups = if size == 0 {
+        Vec::new()
+    } else {
+        vm_load(list, size)?
+    };
+    update_cred(|cred, privileged| {
+        if !privileged {
+            return Err(AxError::OperationNotPermitted);
+        }
+        cred.groups = groups;
+        Ok(())
+    })
 }
 
 const fn pad_str(info: &str) -> [c_char; 65] {
@@ -74,6 +132,17 @@ pub fn sys_uname(name: *mut new_utsname) -> AxResult<isize> {
     Ok(0)
 }
 
+pub fn sys_personality(persona: u32) -> AxResult<isize> {
+    let proc_data = &current().as_thread().proc_data;
+    let old = proc_data.personality();
+    // 0xffffffff only queries the current persona.
+    if persona != u32::MAX {
+        debug!("sys_personality <= persona: {persona:#x}");
+        proc_data.set_personality(persona);
+    }
+    Ok(old as isize)
+}
+
 pub fn sys_sysinfo(info: *mut sysinfo) -> AxResult<isize> {
     // FIXME: Zeroable
     let mut kinfo: sysinfo = unsafe { core::mem::zeroed() };
@@ -83,7 +152,111 @@ pub fn sys_sysinfo(info: *mut sysinfo) -> AxResult<isize> {
     Ok(0)
 }
 
-pub fn sys_syslog(_type: i32, _buf: *mut c_char, _len: usize) -> AxResult<isize> {
+const SYSLOG_ACTION_CLOSE: i32 = 0;
+const SYSLOG_ACTION_OPEN: i32 = 1;
+const SYSLOG_ACTION_READ: i32 = 2;
+const SYSLOG_ACTION_READ_ALL: i32 = 3;
+const SYSLOG_ACTION_READ_CLEAR: i32 = 4;
+const SYSLOG_ACTION_CLEAR: i32 = 5;
+const SYSLOG_ACTION_CONSOLE_OFF: i32 = 6;
+const SYSLOG_ACTION_CONSOLE_ON: i32 = 7;
+const SYSLOG_ACTION_CONSOLE_LEVEL: i32 = 8;
+const SYSLOG_ACTION_SIZE_UNREAD: i32 = 9;
+const SYSLOG_ACTION_SIZE_BUFFER: i32 = 10;
+
+pub fn sys_syslog(ty: i32, buf: *mut c_char, len: i32) -> AxResult<isize> {
+    debug!("sys_syslog <= type: {ty}, buf: {buf:?}, len: {len}");
+
+    let read_len = || -> AxResult<usize> {
+        if buf.is_null() || len < 0 {
+            return Err(AxError::InvalidInput);
+        }
+        Ok(len as usize)
+    };
+    let text = match ty {
+        SYSLOG_ACTION_CLOSE | SYSLOG_ACTION_OPEN => return Ok(0),
+        SYSLOG_ACTION_READ => {
+            let len = read_len()?;
+            if len == 0 {
+                return Ok(0);
+            }
+            block_on(interruptible(poll_fn(|cx| {
+                if kmsg::syslog_pending() {
+                    return Poll::Ready(());
+                }
+                kmsg::register_reader(cx.waker());
+                if kmsg::syslog_pending() {
+                    Poll::Ready(())
+                } else {
+                    Poll::Pending
+                }
+            })))?;
+            kmsg::syslog_read(len)
+        }
+        SYSLOG_ACTION_READ_ALL | SYSLOG_ACTION_READ_CLEAR => {
+            let text = kmsg::syslog_read_all(read_len()?);
+            if ty == SYSLOG_ACTION_READ_CLEAR {
+                kmsg::clear();
+            }
+            text
+        }
+        SYSLOG_ACTION_CLEAR => {
+            kmsg::clear();
+            return Ok(0);
+        }
+        SYSLOG_ACTION_CONSOLE_OFF => {
+            kmsg::console_off();
+            return Ok(0);
+        }
+        SYSLOG_ACTION_CONSOLE_ON => {
+            kmsg::console_on();
+            return Ok(0);
+        }
+        SYSLOG_ACTION_CONSOLE_LEVEL => {
+            if !(1..=8).contains(&len) {
+                return Err(AxError::InvalidInput);
+            }
+            kmsg::set_console_loglevel(len as u8);
+            return Ok(0);
+        }
+        SYSLOG_ACTION_SIZE_UNREAD => return Ok(kmsg::syslog_unread_size() as isize),
+        SYSLOG_ACTION_SIZE_BUFFER => return Ok(kmsg::LOG_BUF_LEN as isize),
+        _ => return Err(AxError::InvalidInput),
+    };
+    vm_write_slice(buf.cast::<u8>(), text.as_bytes())?;
+    Ok(text.len() as isize)
+}
+
+pub fn sys_reboot(magic1: u32, magic2: u32, cmd: u32, arg: *const c_char) -> AxResult<isize> {
+    debug!("sys_reboot <= magic1: {magic1:#x}, magic2: {magic2:#x}, cmd: {cmd:#x}");
+
+    if magic1 != LINUX_REBOOT_MAGIC1
+        || ![
+            LINUX_REBOOT_MAGIC2,
+            LINUX_REBOOT_MAGIC2A,
+            LINUX_REBOOT_MAGIC2B,
+            LINUX_REBOOT_MAGIC2C,
+        ]
+        .contains(&magic2)
+    {
+        return Err(AxError::InvalidInput);
+    }
+    let kind = match cmd {
+        // Ctrl-Alt-Del isn't trapped, so there is nothing to turn on or off.
+        LINUX_REBOOT_CMD_CAD_ON | LINUX_REBOOT_CMD_CAD_OFF => return Ok(0),
+        LINUX_REBOOT_CMD_HALT | LINUX_REBOOT_CMD_POWER_OFF => {
+            kmsg::printk(0, format_args!("reboot: Power down"));
+            power::power_off()
+        }
+        LINUX_REBOOT_CMD_RESTART => ResetKind::Cold,
+        LINUX_REBOOT_CMD_RESTART2 => match vm_load_string(arg)?.as_str() {
+            "warm" | "soft" => ResetKind::Warm,
+            _ => ResetKind::Cold,
+        },
+        _ => return Err(AxError::InvalidInput),
+    };
+    kmsg::printk(0, format_args!("reboot: Restarting system"));
+    power::reset(kind)?;
     Ok(0)
 }
 
@@ -97,26 +270,40 @@ bitflags::bitflags! {
 }
 
 pub fn sys_getrandom(buf: *mut u8, len: usize, flags: u32) -> AxResult<isize> {
+    let flags = GetRandomFlags::from_bits(flags).ok_or(AxError::InvalidInput)?;
+    debug!("sys_getrandom <= buf: {buf:p}, len: {len}, flags: {flags:?}");
+
+    if flags.contains(GetRandomFlags::INSECURE | GetRandomFlags::RANDOM) {
+        return Err(AxError::InvalidInput);
+    }
+    // Only `GRND_INSECURE` gives out bytes before the generator is seeded.
+    if !flags.contains(GetRandomFlags::INSECURE) && !random::crng_ready() {
+        if flags.contains(GetRandomFlags::NONBLOCK) {
+            return Err(AxError::WouldBlock);
+        }
+        random::wait_for_crng()?;
+    }
     if len == 0 {
         return Ok(0);
     }
-    let flags = GetRandomFlags::from_bits_retain(flags);
-
-    debug!("sys_getrandom <= buf: {buf:p}, len: {len}, flags: {flags:?}");
 
-    let path = if flags.contains(GetRandomFlags::RANDOM) {
-        "/dev/random"
-    } else {
-        "/dev/urandom"
-    };
-
-    let f = FS_CONTEXT.lock().resolve(path)?;
-    let mut kbuf = vec![0; len];
-    let len = f.entry().as_file()?.read_at(&mut kbuf, 0)?;
-
-    vm_write_slice(buf, &kbuf)?;
-
-    Ok(len as _)
+    // Filled a page at a time, so that a large request doesn't need a buffer
+    // as large.
+    let mut kbuf = vec![0; len.min(PAGE_SIZE_4K)];
+    let mut written = 0;
+    while written < len {
+        let chunk = &mut kbuf[..(len - written).min(PAGE_SIZE_4K)];
+        random::get_random_bytes(chunk);
+        // Like `read`, a fault after some bytes were copied is not an error.
+        if let Err(err) = vm_write_slice(buf.wrapping_add(written), chunk) {
+            if written == 0 {
+                return Err(err.into());
+            }
+            break;
+        }
+        written += chunk.len();
+    }
+    Ok(written as _)
 }
 
 pub fn sys_seccomp(_op: u32, _flags: u32, _args: *const ()) -> AxResult<isize> {
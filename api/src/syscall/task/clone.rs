@@ -1,22 +1,29 @@
 use alloc::sync::Arc;
+use core::{
+    mem,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
-use axerrno::{AxError, AxResult};
+use axerrno::{AxError, AxResult, LinuxError};
 use axfs::FS_CONTEXT;
 use axhal::uspace::UserContext;
 use axtask::{AxTaskExt, current, spawn_task};
 use bitflags::bitflags;
 use kspin::SpinNoIrq;
 use linux_raw_sys::general::*;
+use memory_addr::PAGE_SIZE_4K;
 use starry_core::{
     mm::copy_from_kernel,
-    task::{AsThread, ProcessData, Thread, add_task_to_table},
+    sched,
+    task::{AsThread, ProcessData, Thread, add_task_to_table, tasks},
+    time::TimeNamespace,
 };
 use starry_process::Pid;
-use starry_signal::Signo;
-use starry_vm::VmMutPtr;
+use starry_signal::{SignalDisposition, Signo};
+use starry_vm::{VmMutPtr, vm_load};
 
 use crate::{
-    file::{FD_TABLE, FileLike, PidFd},
+    file::{Directory, FD_TABLE, FileLike, PidFd},
     task::new_user_task,
 };
 
@@ -82,9 +89,27 @@ bitflags! {
         const NEWNET = CLONE_NEWNET;
         /// The new process shares an I/O context with the calling process.
         const IO = CLONE_IO;
+        /// Children of the calling process are created in a new time
+        /// namespace. Only valid for [`sys_unshare`], since it overlaps with
+        /// the exit signal of `clone`.
+        const NEWTIME = CLONE_NEWTIME;
     }
 }
 
+/// The arguments of a clone, as taken by both `clone` and `clone3`.
+struct CloneArgs {
+    flags: CloneFlags,
+    /// Cleared signal handlers in the child (`CLONE_CLEAR_SIGHAND`).
+    clear_sighand: bool,
+    exit_signal: u32,
+    stack: usize,
+    parent_tid: usize,
+    child_tid: usize,
+    tls: usize,
+    /// Where the pidfd of the child is stored with `CLONE_PIDFD`.
+    pidfd: usize,
+}
+
 pub fn sys_clone(
     uctx: &UserContext,
     flags: u32,
@@ -96,28 +121,169 @@ pub fn sys_clone(
 ) -> AxResult<isize> {
     const FLAG_MASK: u32 = 0xff;
     let exit_signal = flags & FLAG_MASK;
-    let mut flags = CloneFlags::from_bits_truncate(flags & !FLAG_MASK);
-    if flags.contains(CloneFlags::VFORK) {
-        debug!("sys_clone: CLONE_VFORK slow path");
-        flags.remove(CloneFlags::VM);
-    }
+    let flags = CloneFlags::from_bits_truncate(flags & !FLAG_MASK);
 
     debug!(
         "sys_clone <= flags: {flags:?}, exit_signal: {exit_signal}, stack: {stack:#x}, ptid: \
          {parent_tid:#x}, ctid: {child_tid:#x}, tls: {tls:#x}"
     );
 
-    if exit_signal != 0 && flags.contains(CloneFlags::THREAD | CloneFlags::PARENT) {
+    // `clone` stores both the pidfd and the parent TID through `parent_tid`,
+    // so only one of them can be asked for.
+    if flags.contains(CloneFlags::PIDFD | CloneFlags::PARENT_SETTID) {
+        return Err(AxError::InvalidInput);
+    }
+
+    do_clone(
+        uctx,
+        CloneArgs {
+            flags,
+            clear_sighand: false,
+            exit_signal,
+            stack,
+            parent_tid,
+            child_tid,
+            tls,
+            pidfd: parent_tid,
+        },
+    )
+}
+
+/// The size of the first version of `struct clone_args`.
+const CLONE_ARGS_SIZE_VER0: usize = 64;
+/// Resets the signal handlers of the child to their defaults.
+const CLONE_CLEAR_SIGHAND: u64 = 0x1_0000_0000;
+/// Places the child in the cgroup given by `cgroup`.
+const CLONE_INTO_CGROUP: u64 = 0x2_0000_0000;
+
+pub fn sys_clone3(uctx: &UserContext, args: *const u8, size: usize) -> AxResult<isize> {
+    if size < CLONE_ARGS_SIZE_VER0 {
+        return Err(AxError::InvalidInput);
+    }
+    if size > PAGE_SIZE_4K {
+        return Err(AxError::from(LinuxError::E2BIG));
+    }
+    let bytes = vm_load(args, size)?;
+    // Fields added by newer versions of the structure must be left unset.
+    if bytes[size.min(size_of::<clone_args>())..]
+        .iter()
+        .any(|&b| b != 0)
+    {
+        return Err(AxError::from(LinuxError::E2BIG));
+    }
+    let field = |index: usize| {
+        bytes
+            .get(index * 8..index * 8 + 8)
+            .map_or(0, |it| u64::from_ne_bytes(it.try_into().unwrap()))
+    };
+    let args = clone_args {
+        flags: field(0),
+        pidfd: field(1),
+        child_tid: field(2),
+        parent_tid: field(3),
+        exit_signal: field(4),
+        stack: field(5),
+        stack_size: field(6),
+        tls: field(7),
+        set_tid: field(8),
+        set_tid_size: field(9),
+        cgroup: field(10),
+    };
+    debug!("sys_clone3 <= args: {args:?}");
+
+    // The exit signal has its own field.
+    if args.flags & 0xff != 0
+        || args.flags & !(u32::MAX as u64 | CLONE_CLEAR_SIGHAND | CLONE_INTO_CGROUP) != 0
+        || args.exit_signal > 0xff
+        || (args.stack == 0) != (args.stack_size == 0)
+    {
+        return Err(AxError::InvalidInput);
+    }
+    let flags = CloneFlags::from_bits(args.flags as u32).ok_or(AxError::InvalidInput)?;
+    let clear_sighand = args.flags & CLONE_CLEAR_SIGHAND != 0;
+    if clear_sighand && flags.contains(CloneFlags::SIGHAND) {
+        return Err(AxError::InvalidInput);
+    }
+    // Threads and siblings don't choose an exit signal. `clone` ignores it
+    // instead, as on Linux.
+    if args.exit_signal != 0 && flags.intersects(CloneFlags::THREAD | CloneFlags::PARENT) {
         return Err(AxError::InvalidInput);
     }
+
+    // There is a single PID namespace, so at most one TID can be given. TIDs
+    // are handed out by axtask, which can't reserve a chosen one.
+    match (args.set_tid, args.set_tid_size) {
+        (0, 0) => {}
+        (_, 1) if args.set_tid != 0 => return Err(AxError::OperationNotSupported),
+        _ => return Err(AxError::InvalidInput),
+    }
+
+    // There is no cgroup filesystem yet, so no descriptor can refer to a
+    // cgroup to place the child in.
+    if args.flags & CLONE_INTO_CGROUP != 0 {
+        Directory::from_fd(args.cgroup as _).map_err(|_| AxError::BadFileDescriptor)?;
+        return Err(AxError::OperationNotSupported);
+    }
+
+    do_clone(
+        uctx,
+        CloneArgs {
+            flags,
+            clear_sighand,
+            exit_signal: args.exit_signal as _,
+            // The stack is given as a region; it grows down from its end.
+            stack: args
+                .stack
+                .checked_add(args.stack_size)
+                .ok_or(AxError::InvalidInput)? as _,
+            parent_tid: args.parent_tid as _,
+            child_tid: args.child_tid as _,
+            tls: args.tls as _,
+            pidfd: args.pidfd as _,
+        },
+    )
+}
+
+fn do_clone(uctx: &UserContext, args: CloneArgs) -> AxResult<isize> {
+    let CloneArgs {
+        mut flags,
+        clear_sighand,
+        exit_signal,
+        stack,
+        parent_tid,
+        child_tid,
+        tls,
+        pidfd,
+    } = args;
+    if flags.contains(CloneFlags::VFORK) {
+        debug!("sys_clone: CLONE_VFORK slow path");
+        flags.remove(CloneFlags::VM);
+    }
+
     if flags.contains(CloneFlags::THREAD) && !flags.contains(CloneFlags::VM | CloneFlags::SIGHAND) {
         return Err(AxError::InvalidInput);
     }
-    if flags.contains(CloneFlags::PIDFD | CloneFlags::PARENT_SETTID) {
+    // A pidfd refers to a whole process, not a thread.
+    if flags.contains(CloneFlags::PIDFD | CloneFlags::THREAD) {
         return Err(AxError::InvalidInput);
     }
     let exit_signal = Signo::from_repr(exit_signal as u8);
 
+    // Like Linux, `RLIMIT_NPROC` counts the threads of the user, and doesn't
+    // apply to privileged callers.
+    let caller = &current().as_thread().proc_data;
+    let nproc_limit = caller.rlim.read()[RLIMIT_NPROC].current;
+    if !caller.is_privileged() {
+        let euid = caller.euid();
+        let nproc = tasks()
+            .iter()
+            .filter(|task| task.as_thread().proc_data.euid() == euid)
+            .count();
+        if nproc as u64 >= nproc_limit {
+            return Err(AxError::WouldBlock);
+        }
+    }
+
     let mut new_uctx = *uctx;
     if stack != 0 {
         new_uctx.set_sp(stack);
@@ -155,14 +321,21 @@ pub fn sys_clone(
             old_proc_data.proc.clone()
         }
         .fork(tid);
+        // A sibling notifies the shared parent as the caller does.
+        let exit_signal = if flags.contains(CloneFlags::PARENT) {
+            old_proc_data.exit_signal
+        } else {
+            exit_signal
+        };
 
-        let aspace = if flags.contains(CloneFlags::VM) {
-            old_proc_data.aspace.clone()
+        let (aspace, vm_size) = if flags.contains(CloneFlags::VM) {
+            (old_proc_data.aspace.clone(), old_proc_data.vm_size.clone())
         } else {
             let mut aspace = old_proc_data.aspace.lock();
             let aspace = aspace.try_clone()?;
             copy_from_kernel(&mut aspace.lock())?;
-            aspace
+            let vm_size = old_proc_data.vm_size.load(Ordering::Relaxed);
+            (aspace, Arc::new(AtomicUsize::new(vm_size)))
         };
         new_task
             .ctx_mut()
@@ -171,19 +344,41 @@ pub fn sys_clone(
         let signal_actions = if flags.contains(CloneFlags::SIGHAND) {
             old_proc_data.signal.actions.clone()
         } else {
-            Arc::new(SpinNoIrq::new(old_proc_data.signal.actions.lock().clone()))
+            let mut actions = old_proc_data.signal.actions.lock().clone();
+            if clear_sighand {
+                // Handlers are reset, but ignored signals stay ignored.
+                let old_actions = mem::take(&mut actions);
+                for signo in (1..=64).filter_map(Signo::from_repr) {
+                    if matches!(old_actions[signo].disposition, SignalDisposition::Ignore) {
+                        actions[signo] = old_actions[signo].clone();
+                    }
+                }
+            }
+            Arc::new(SpinNoIrq::new(actions))
         };
         let proc_data = ProcessData::new(
             proc,
             old_proc_data.exe_path.read().clone(),
             old_proc_data.cmdline.read().clone(),
             aspace,
+            vm_size,
             signal_actions,
             exit_signal,
         );
         proc_data.set_umask(old_proc_data.umask());
+        *proc_data.rlim.write() = old_proc_data.rlim.read().clone();
+        *proc_data.environ.write() = old_proc_data.environ.read().clone();
+        *proc_data.cred.write() = old_proc_data.cred.read().clone();
+        let time_ns = old_proc_data.time_ns_for_children.read().clone();
+        time_ns.enter();
+        *proc_data.time_ns.write() = time_ns.clone();
+        *proc_data.time_ns_for_children.write() = time_ns;
         // Inherit heap pointers from parent to ensure child's heap state is consistent after fork
-        proc_data.set_heap_top(old_proc_data.get_heap_top());
+        proc_data.inherit_layout(old_proc_data);
+        proc_data.set_personality(old_proc_data.personality());
+        *proc_data.mempolicy.lock() = old_proc_data.mempolicy.lock().clone();
+        *proc_data.mapped_files.lock() = old_proc_data.mapped_files.lock().clone();
+        *proc_data.session_keyring.lock() = old_proc_data.session_keyring.lock().clone();
 
         {
             let mut scope = proc_data.scope.write();
@@ -212,11 +407,14 @@ pub fn sys_clone(
     new_proc_data.proc.add_thread(tid);
 
     if flags.contains(CloneFlags::PIDFD) {
-        let pidfd = PidFd::new(&new_proc_data);
-        (parent_tid as *mut i32).vm_write(pidfd.add_to_fd_table(true)?)?;
+        let fd = PidFd::new(&new_proc_data).add_to_fd_table(true)?;
+        (pidfd as *mut i32).vm_write(fd)?;
     }
 
     let thr = Thread::new(tid, new_proc_data);
+    thr.set_mempolicy(curr.as_thread().mempolicy());
+    thr.set_nice(curr.as_thread().nice());
+    thr.set_pending_affinity(Some(sched::affinity(&curr)));
     if flags.contains(CloneFlags::CHILD_CLEARTID) {
         thr.set_clear_child_tid(child_tid);
     }
@@ -228,6 +426,23 @@ pub fn sys_clone(
     Ok(tid as _)
 }
 
+pub fn sys_unshare(flags: u32) -> AxResult<isize> {
+    let flags = CloneFlags::from_bits(flags).ok_or(AxError::InvalidInput)?;
+    debug!("sys_unshare <= flags: {flags:?}");
+
+    if !(flags - CloneFlags::NEWTIME).is_empty() {
+        warn!("sys_unshare: unsupported flags {flags:?}");
+        return Err(AxError::InvalidInput);
+    }
+
+    let proc_data = &current().as_thread().proc_data;
+    if flags.contains(CloneFlags::NEWTIME) {
+        *proc_data.time_ns_for_children.write() = Arc::new(TimeNamespace::new());
+    }
+
+    Ok(0)
+}
+
 #[cfg(target_arch = "x86_64")]
 pub fn sys_fork(uctx: &UserContext) -> AxResult<isize> {
     sys_clone(uctx, SIGCHLD, 0, 0, 0, 0)
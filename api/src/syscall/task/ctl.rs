@@ -1,16 +1,24 @@
+use alloc::sync::Arc;
 use core::ffi::c_char;
 
 use axerrno::{AxError, AxResult};
 use axtask::current;
 use linux_raw_sys::general::{__user_cap_data_struct, __user_cap_header_struct};
-use starry_core::task::{AsThread, get_process_data};
+use starry_core::{
+    cred::IdSet,
+    task::{AsThread, ProcessData, get_process_data},
+};
 use starry_vm::{VmMutPtr, VmPtr, vm_write_slice};
 
-use crate::mm::vm_load_string;
+use crate::{
+    mm::vm_load_string,
+    syscall::sys::{optional_id, update_cred},
+};
 
 const CAPABILITY_VERSION_3: u32 = 0x20080522;
 
-fn validate_cap_header(header_ptr: *mut __user_cap_header_struct) -> AxResult<()> {
+/// Checks the version in `header_ptr`, and returns the process it refers to.
+fn validate_cap_header(header_ptr: *mut __user_cap_header_struct) -> AxResult<Arc<ProcessData>> {
     // FIXME: AnyBitPattern
     let mut header = unsafe { header_ptr.vm_read_uninit()?.assume_init() };
     if header.version != CAPABILITY_VERSION_3 {
@@ -18,20 +26,26 @@ fn validate_cap_header(header_ptr: *mut __user_cap_header_struct) -> AxResult<()
         header_ptr.vm_write(header)?;
         return Err(AxError::InvalidInput);
     }
-    let _ = get_process_data(header.pid as u32)?;
-    Ok(())
+    if header.pid == 0 {
+        return Ok(current().as_thread().proc_data.clone());
+    }
+    get_process_data(header.pid as u32)
 }
 
 pub fn sys_capget(
     header: *mut __user_cap_header_struct,
     data: *mut __user_cap_data_struct,
 ) -> AxResult<isize> {
-    validate_cap_header(header)?;
-
+    // Root has every capability, and other users none.
+    let caps = if validate_cap_header(header)?.is_privileged() {
+        u32::MAX
+    } else {
+        0
+    };
     data.vm_write(__user_cap_data_struct {
-        effective: u32::MAX,
-        permitted: u32::MAX,
-        inheritable: u32::MAX,
+        effective: caps,
+        permitted: caps,
+        inheritable: caps,
     })?;
     Ok(0)
 }
@@ -51,29 +65,60 @@ pub fn sys_umask(mask: u32) -> AxResult<isize> {
     Ok(old as isize)
 }
 
-pub fn sys_setreuid(_ruid: u32, _euid: u32) -> AxResult<isize> {
-    Ok(0)
+pub fn sys_setreuid(ruid: u32, euid: u32) -> AxResult<isize> {
+    update_cred(|cred, privileged| {
+        cred.uid
+            .set_re(optional_id(ruid), optional_id(euid), privileged)
+    })
 }
 
-pub fn sys_setresuid(_ruid: u32, _euid: u32, _suid: u32) -> AxResult<isize> {
-    Ok(0)
+pub fn sys_setregid(rgid: u32, egid: u32) -> AxResult<isize> {
+    update_cred(|cred, privileged| {
+        cred.gid
+            .set_re(optional_id(rgid), optional_id(egid), privileged)
+    })
 }
 
-pub fn sys_setresgid(_rgid: u32, _egid: u32, _sgid: u32) -> AxResult<isize> {
-    Ok(0)
+pub fn sys_setresuid(ruid: u32, euid: u32, suid: u32) -> AxResult<isize> {
+    update_cred(|cred, privileged| {
+        cred.uid.set_res(
+            optional_id(ruid),
+            optional_id(euid),
+            optional_id(suid),
+            privileged,
+        )
+    })
 }
 
-pub fn sys_get_mempolicy(
-    _policy: *mut i32,
-    _nodemask: *mut usize,
-    _maxnode: usize,
-    _addr: usize,
-    _flags: usize,
-) -> AxResult<isize> {
-    warn!("Dummy get_mempolicy called");
+pub fn sys_setresgid(rgid: u32, egid: u32, sgid: u32) -> AxResult<isize> {
+    update_cred(|cred, privileged| {
+        cred.gid.set_res(
+            optional_id(rgid),
+            optional_id(egid),
+            optional_id(sgid),
+            privileged,
+        )
+    })
+}
+
+/// Writes the real, effective and saved IDs in `ids` to user memory.
+fn write_ids(ids: IdSet, real: *mut u32, effective: *mut u32, saved: *mut u32) -> AxResult<isize> {
+    real.vm_write(ids.real)?;
+    effective.vm_write(ids.effective)?;
+    saved.vm_write(ids.saved)?;
     Ok(0)
 }
 
+pub fn sys_getresuid(ruid: *mut u32, euid: *mut u32, suid: *mut u32) -> AxResult<isize> {
+    let ids = current().as_thread().proc_data.cred.read().uid;
+    write_ids(ids, ruid, euid, suid)
+}
+
+pub fn sys_getresgid(rgid: *mut u32, egid: *mut u32, sgid: *mut u32) -> AxResult<isize> {
+    let ids = current().as_thread().proc_data.cred.read().gid;
+    write_ids(ids, rgid, egid, sgid)
+}
+
 /// prctl() is called with a first argument describing what to do, and further
 /// arguments with a significance depending on the first one.
 /// The first argument can be:
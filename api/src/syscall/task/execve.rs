@@ -1,14 +1,45 @@
 use alloc::{string::ToString, sync::Arc, vec::Vec};
-use core::ffi::c_char;
+use core::{ffi::c_char, future::poll_fn, sync::atomic::Ordering, task::Poll};
 
 use axerrno::{AxError, AxResult};
 use axfs::FS_CONTEXT;
 use axhal::uspace::UserContext;
-use axtask::current;
-use starry_core::{config::USER_HEAP_BASE, mm::load_user_app, task::AsThread};
+use axtask::{current, future::block_on};
+use starry_core::{
+    mm::{self, UserLayout, load_user_app},
+    rusage,
+    task::{AsThread, take_over_leadership},
+};
 use starry_vm::vm_load_until_nul;
 
-use crate::{file::FD_TABLE, mm::vm_load_string};
+use crate::{file::FD_TABLE, mm::vm_load_string, task::kill_other_threads};
+
+/// Kills the other threads of the current process and waits for them to
+/// exit, so that the new program starts with a single thread. A thread other
+/// than the leader takes over the PID as its TID.
+fn de_thread() -> AxResult<()> {
+    let curr = current().clone();
+    let thr = curr.as_thread();
+    let proc_data = &thr.proc_data;
+    // Another thread is already taking the process down.
+    if !proc_data.begin_group_exit() {
+        return Err(AxError::WouldBlock);
+    }
+    kill_other_threads(thr);
+    block_on(poll_fn(|cx| {
+        if proc_data.proc.threads().len() > 1 {
+            proc_data.thread_exit_event.register(cx.waker());
+        }
+        if proc_data.proc.threads().len() > 1 {
+            Poll::Pending
+        } else {
+            Poll::Ready(())
+        }
+    }));
+    take_over_leadership(&curr);
+    proc_data.end_group_exit();
+    Ok(())
+}
 
 pub fn sys_execve(
     uctx: &mut UserContext,
@@ -44,14 +75,24 @@ pub fn sys_execve(
     let proc_data = &curr.as_thread().proc_data;
 
     if proc_data.proc.threads().len() > 1 {
-        // TODO: handle multi-thread case
-        error!("sys_execve: multi-thread not supported");
-        return Err(AxError::WouldBlock);
+        de_thread()?;
     }
 
+    // The old image is about to go; keep its peak resident set size.
+    rusage::sample_rss(proc_data);
+    let layout = UserLayout::new(proc_data.personality());
     let mut aspace = proc_data.aspace.lock();
-    let (entry_point, user_stack_base) =
-        load_user_app(&mut aspace, Some(path.as_str()), &args, &envs)?;
+    let (entry_point, user_stack_base) = load_user_app(
+        &mut aspace,
+        &mut proc_data.mapped_files.lock(),
+        Some(path.as_str()),
+        &args,
+        &envs,
+        &layout,
+    )?;
+    proc_data
+        .vm_size
+        .store(mm::vm_size(&aspace), Ordering::Relaxed);
     drop(aspace);
 
     let loc = FS_CONTEXT.lock().resolve(&path)?;
@@ -59,8 +100,17 @@ pub fn sys_execve(
 
     *proc_data.exe_path.write() = loc.absolute_path()?.to_string();
     *proc_data.cmdline.write() = Arc::new(args);
-
-    proc_data.set_heap_top(USER_HEAP_BASE);
+    *proc_data.environ.write() = Arc::new(envs);
+    let time_ns = proc_data.time_ns_for_children.read().clone();
+    time_ns.enter();
+    *proc_data.time_ns.write() = time_ns;
+
+    proc_data.set_layout(&layout);
+    proc_data.mlock.lock().clear();
+    proc_data.mempolicy.lock().clear();
+    proc_data.posix_timers.lock().clear();
+    // The process keyring doesn't survive `execve`, unlike the session one.
+    *proc_data.process_keyring.lock() = None;
 
     *proc_data.signal.actions.lock() = Default::default();
 
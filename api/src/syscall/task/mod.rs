@@ -3,8 +3,11 @@ mod ctl;
 mod execve;
 mod exit;
 mod job;
+mod ptrace;
 mod schedule;
 mod thread;
 mod wait;
 
-pub use self::{clone::*, ctl::*, execve::*, exit::*, job::*, schedule::*, thread::*, wait::*};
+pub use self::{
+    clone::*, ctl::*, execve::*, exit::*, job::*, ptrace::*, schedule::*, thread::*, wait::*,
+};
@@ -0,0 +1,83 @@
+use axerrno::{AxError, AxResult};
+use axtask::current;
+use starry_core::task::{AsThread, get_task, send_signal_to_thread};
+use starry_process::Pid;
+use starry_signal::{SignalInfo, Signo};
+
+use crate::task::may_access;
+
+const PTRACE_CONT: u32 = 7;
+const PTRACE_DETACH: u32 = 17;
+const PTRACE_SEIZE: u32 = 0x4206;
+const PTRACE_INTERRUPT: u32 = 0x4207;
+const PTRACE_LISTEN: u32 = 0x4208;
+
+/// The `PTRACE_O_*` options that may be given to `PTRACE_SEIZE`.
+const PTRACE_O_MASK: usize = 0x0030_00ff;
+
+/// Sends the signal `data` given to `PTRACE_CONT` or `PTRACE_DETACH` to the
+/// tracee, if any.
+fn inject_signal(tid: Pid, data: usize) -> AxResult<()> {
+    if data == 0 {
+        return Ok(());
+    }
+    let signo = Signo::from_repr(data as u8)
+        .filter(|signo| *signo as usize == data)
+        .ok_or(AxError::InvalidInput)?;
+    send_signal_to_thread(None, tid, Some(SignalInfo::new_kernel(signo)))
+}
+
+pub fn sys_ptrace(request: u32, pid: Pid, addr: usize, data: usize) -> AxResult<isize> {
+    debug!("sys_ptrace <= request: {request:#x}, pid: {pid}, addr: {addr:#x}, data: {data:#x}");
+
+    let curr = current();
+    let tracer = curr.as_thread().proc_data.proc.pid();
+    let task = get_task(pid)?;
+    let thr = task.try_as_thread().ok_or(AxError::OperationNotPermitted)?;
+    if thr.pending_exit() {
+        return Err(AxError::NoSuchProcess);
+    }
+
+    if request == PTRACE_SEIZE {
+        if addr != 0 {
+            return Err(AxError::Io);
+        }
+        if data & !PTRACE_O_MASK != 0 {
+            return Err(AxError::InvalidInput);
+        }
+        if thr.proc_data.proc.pid() == tracer || !may_access(&thr.proc_data) {
+            return Err(AxError::OperationNotPermitted);
+        }
+        thr.trace.seize(tracer, data as u32)?;
+        return Ok(0);
+    }
+
+    // Other requests are only for the tracer.
+    if thr.trace.tracer() != Some(tracer) {
+        return Err(AxError::NoSuchProcess);
+    }
+    match request {
+        PTRACE_INTERRUPT => {
+            if thr.trace.interrupt() {
+                task.interrupt();
+            }
+        }
+        PTRACE_LISTEN => thr.trace.listen()?,
+        PTRACE_CONT => {
+            inject_signal(pid, data)?;
+            thr.trace.resume()?;
+        }
+        PTRACE_DETACH => {
+            if !thr.trace.is_stopped() {
+                return Err(AxError::NoSuchProcess);
+            }
+            inject_signal(pid, data)?;
+            thr.trace.detach();
+        }
+        _ => {
+            warn!("sys_ptrace: unsupported request {request:#x}");
+            return Err(AxError::Io);
+        }
+    }
+    Ok(0)
+}
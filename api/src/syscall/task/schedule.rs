@@ -1,14 +1,19 @@
+use alloc::{vec, vec::Vec};
+
 use axerrno::{AxError, AxResult};
 use axhal::time::TimeValue;
 use axtask::{
-    AxCpuMask, current,
+    AxCpuMask, AxTaskRef, current,
     future::{block_on, interruptible, sleep},
 };
 use linux_raw_sys::general::{
-    __kernel_clockid_t, CLOCK_MONOTONIC, CLOCK_REALTIME, PRIO_PGRP, PRIO_PROCESS, PRIO_USER,
-    SCHED_RR, TIMER_ABSTIME, timespec,
+    __kernel_clockid_t, CLOCK_BOOTTIME, CLOCK_MONOTONIC, CLOCK_REALTIME, PRIO_PGRP, PRIO_PROCESS,
+    PRIO_USER, SCHED_NORMAL, TIMER_ABSTIME, timespec,
+};
+use starry_core::{
+    sched::{self, MAX_NICE, MIN_NICE},
+    task::{AsThread, get_process_group, get_task, tasks},
 };
-use starry_core::task::{get_process_data, get_process_group};
 use starry_vm::{VmMutPtr, VmPtr, vm_load, vm_write_slice};
 
 use crate::time::TimeValueLike;
@@ -30,13 +35,28 @@ fn sleep_impl(clock: impl Fn() -> TimeValue, dur: TimeValue) -> TimeValue {
     clock() - start
 }
 
+/// Sleeps for `dur` on the monotonic clock of the time namespace of the
+/// caller, so that its warp applies, and returns how long that clock
+/// advanced.
+fn sleep_monotonic(dur: TimeValue) -> TimeValue {
+    debug!("sleep_monotonic <= {dur:?}");
+
+    let time_ns = current().as_thread().proc_data.time_ns.read().clone();
+    let start = time_ns.monotonic_time();
+
+    // We detect EINTR manually if the slept time is not enough.
+    let _ = block_on(interruptible(time_ns.sleep_until(start + dur)));
+
+    time_ns.monotonic_time() - start
+}
+
 /// Sleep some nanoseconds
 pub fn sys_nanosleep(req: *const timespec, rem: *mut timespec) -> AxResult<isize> {
     // FIXME: AnyBitPattern
     let req = unsafe { req.vm_read_uninit()?.assume_init() }.try_into_time_value()?;
     debug!("sys_nanosleep <= req: {req:?}");
 
-    let actual = sleep_impl(axhal::time::monotonic_time, req);
+    let actual = sleep_monotonic(req);
 
     if let Some(diff) = req.checked_sub(actual) {
         debug!("sys_nanosleep => rem: {diff:?}");
@@ -55,25 +75,36 @@ pub fn sys_clock_nanosleep(
     req: *const timespec,
     rem: *mut timespec,
 ) -> AxResult<isize> {
-    let clock = match clock_id as u32 {
-        CLOCK_REALTIME => axhal::time::wall_time,
-        CLOCK_MONOTONIC => axhal::time::monotonic_time,
-        _ => {
-            warn!("Unsupported clock_id: {clock_id}");
-            return Err(AxError::InvalidInput);
-        }
-    };
+    if !matches!(
+        clock_id as u32,
+        CLOCK_REALTIME | CLOCK_MONOTONIC | CLOCK_BOOTTIME
+    ) {
+        warn!("Unsupported clock_id: {clock_id}");
+        return Err(AxError::InvalidInput);
+    }
 
     let req = unsafe { req.vm_read_uninit()?.assume_init() }.try_into_time_value()?;
     debug!("sys_clock_nanosleep <= clock_id: {clock_id}, flags: {flags}, req: {req:?}");
 
     let dur = if flags & TIMER_ABSTIME != 0 {
-        req.saturating_sub(clock())
+        // Absolute deadlines are given in the clocks of the time namespace.
+        let time_ns = current().as_thread().proc_data.time_ns.read().clone();
+        let now = match clock_id as u32 {
+            CLOCK_REALTIME => time_ns.wall_time(),
+            CLOCK_BOOTTIME => time_ns.boottime(),
+            _ => time_ns.monotonic_time(),
+        };
+        req.saturating_sub(now)
     } else {
         req
     };
 
-    let actual = sleep_impl(clock, dur);
+    // `CLOCK_REALTIME` is not warped, unlike the others.
+    let actual = if clock_id as u32 == CLOCK_REALTIME {
+        sleep_impl(axhal::time::wall_time, dur)
+    } else {
+        sleep_monotonic(dur)
+    };
 
     if let Some(diff) = dur.checked_sub(actual) {
         debug!("sys_clock_nanosleep => rem: {diff:?}");
@@ -87,16 +118,11 @@ pub fn sys_clock_nanosleep(
 }
 
 pub fn sys_sched_getaffinity(pid: i32, cpusetsize: usize, user_mask: *mut u8) -> AxResult<isize> {
-    if cpusetsize * 8 < axconfig::plat::CPU_NUM {
+    if cpusetsize * 8 < axconfig::plat::CPU_NUM || !cpusetsize.is_multiple_of(size_of::<usize>()) {
         return Err(AxError::InvalidInput);
     }
 
-    // TODO: support other threads
-    if pid != 0 {
-        return Err(AxError::OperationNotPermitted);
-    }
-
-    let mask = current().cpumask();
+    let mask = sched::affinity(&get_task(pid as _)?);
     let mask_bytes = mask.as_bytes();
 
     vm_write_slice(user_mask, mask_bytes)?;
@@ -104,11 +130,7 @@ pub fn sys_sched_getaffinity(pid: i32, cpusetsize: usize, user_mask: *mut u8) ->
     Ok(mask_bytes.len() as _)
 }
 
-pub fn sys_sched_setaffinity(
-    _pid: i32,
-    cpusetsize: usize,
-    user_mask: *const u8,
-) -> AxResult<isize> {
+pub fn sys_sched_setaffinity(pid: i32, cpusetsize: usize, user_mask: *const u8) -> AxResult<isize> {
     let size = cpusetsize.min(axconfig::plat::CPU_NUM.div_ceil(8));
     let user_mask = vm_load(user_mask, size)?;
     let mut cpu_mask = AxCpuMask::new();
@@ -119,14 +141,13 @@ pub fn sys_sched_setaffinity(
         }
     }
 
-    // TODO: support other threads
-    axtask::set_current_affinity(cpu_mask);
+    sched::set_affinity(&get_task(pid as _)?, cpu_mask)?;
 
     Ok(0)
 }
 
 pub fn sys_sched_getscheduler(_pid: i32) -> AxResult<isize> {
-    Ok(SCHED_RR as _)
+    Ok(SCHED_NORMAL as _)
 }
 
 pub fn sys_sched_setscheduler(_pid: i32, _policy: i32, _param: *const ()) -> AxResult<isize> {
@@ -137,29 +158,63 @@ pub fn sys_sched_getparam(_pid: i32, _param: *mut ()) -> AxResult<isize> {
     Ok(0)
 }
 
-pub fn sys_getpriority(which: u32, who: u32) -> AxResult<isize> {
-    debug!("sys_getpriority <= which: {which}, who: {who}");
-
-    match which {
-        PRIO_PROCESS => {
-            if who != 0 {
-                let _proc = get_process_data(who)?;
-            }
-            Ok(20)
-        }
+/// Finds the threads `getpriority` and `setpriority` act on.
+fn priority_targets(which: u32, who: u32) -> AxResult<Vec<AxTaskRef>> {
+    let targets = match which {
+        PRIO_PROCESS => vec![get_task(who)?],
         PRIO_PGRP => {
-            if who != 0 {
-                let _pg = get_process_group(who)?;
-            }
-            Ok(20)
+            let pgid = if who == 0 {
+                current().as_thread().proc_data.proc.group().pgid()
+            } else {
+                who
+            };
+            get_process_group(pgid)?
+                .processes()
+                .into_iter()
+                .flat_map(|proc| proc.threads())
+                .filter_map(|tid| get_task(tid).ok())
+                .collect()
         }
         PRIO_USER => {
-            if who == 0 {
-                Ok(20)
+            let uid = if who == 0 {
+                current().as_thread().proc_data.cred.read().uid.real
             } else {
-                Err(AxError::NoSuchProcess)
-            }
+                who
+            };
+            tasks()
+                .into_iter()
+                .filter(|task| {
+                    task.try_as_thread()
+                        .is_some_and(|thr| thr.proc_data.cred.read().uid.real == uid)
+                })
+                .collect()
         }
-        _ => Err(AxError::InvalidInput),
+        _ => return Err(AxError::InvalidInput),
+    };
+    if targets.is_empty() {
+        return Err(AxError::NoSuchProcess);
+    }
+    Ok(targets)
+}
+
+pub fn sys_getpriority(which: u32, who: u32) -> AxResult<isize> {
+    debug!("sys_getpriority <= which: {which}, who: {who}");
+
+    // The syscall returns 20 - nice, so that the result is never negative.
+    let nice = priority_targets(which, who)?
+        .iter()
+        .map(|task| task.as_thread().nice())
+        .min()
+        .unwrap_or(0);
+    Ok((20 - nice) as _)
+}
+
+pub fn sys_setpriority(which: u32, who: u32, prio: i32) -> AxResult<isize> {
+    debug!("sys_setpriority <= which: {which}, who: {who}, prio: {prio}");
+
+    let nice = prio.clamp(MIN_NICE, MAX_NICE);
+    for task in priority_targets(which, who)? {
+        task.as_thread().set_nice(nice);
     }
+    Ok(0)
 }
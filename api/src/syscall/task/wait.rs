@@ -1,5 +1,5 @@
 use alloc::vec::Vec;
-use core::{future::poll_fn, task::Poll};
+use core::{ffi::c_long, future::poll_fn, task::Poll};
 
 use axerrno::{AxError, AxResult, LinuxError};
 use axtask::{
@@ -8,12 +8,24 @@ use axtask::{
 };
 use bitflags::bitflags;
 use linux_raw_sys::general::{
-    __WALL, __WCLONE, __WNOTHREAD, WCONTINUED, WEXITED, WNOHANG, WNOWAIT, WUNTRACED,
+    __WALL, __WCLONE, __WNOTHREAD, CLD_CONTINUED, CLD_DUMPED, CLD_EXITED, CLD_KILLED, CLD_STOPPED,
+    CLD_TRAPPED, P_ALL, P_PGID, P_PID, P_PIDFD, WCONTINUED, WEXITED, WNOHANG, WNOWAIT, WUNTRACED,
+    rusage,
+};
+use starry_core::{
+    rusage::{Rusage, process_usage, reap, thread_usage, zombie_usage},
+    task::{AsThread, StopReport, Thread, TraceStop, get_process_data, tracees},
+    time::clock_ticks,
 };
-use starry_core::task::AsThread;
 use starry_process::{Pid, Process};
+use starry_signal::Signo;
 use starry_vm::{VmMutPtr, VmPtr};
 
+use crate::{
+    file::{FileLike, PidFd},
+    syscall::resources::to_rusage,
+};
+
 bitflags! {
     #[derive(Debug)]
     struct WaitOptions: u32 {
@@ -57,60 +69,249 @@ impl WaitPid {
             WaitPid::Pgid(pgid) => child.group().pgid() == *pgid,
         }
     }
+
+    fn apply_tracee(&self, thr: &Thread) -> bool {
+        match self {
+            WaitPid::Any => true,
+            WaitPid::Pid(pid) => thr.tid() == *pid,
+            WaitPid::Pgid(pgid) => thr.proc_data.proc.group().pgid() == *pgid,
+        }
+    }
 }
 
-pub fn sys_waitpid(pid: i32, exit_code: *mut i32, options: u32) -> AxResult<isize> {
-    let options = WaitOptions::from_bits_truncate(options);
-    info!("sys_waitpid <= pid: {pid:?}, options: {options:?}");
+/// The event reported along with a ptrace stop that isn't a signal delivery.
+const PTRACE_EVENT_STOP: i32 = 128;
+
+/// A change of state of a child, as reported by `wait4` and `waitid`.
+enum WaitEvent {
+    /// The child exited, with the raw exit status.
+    Exited(i32),
+    /// The child was stopped by a signal.
+    Stopped(Signo),
+    /// The child was continued by `SIGCONT`.
+    Continued,
+    /// The tracee entered a ptrace stop.
+    Trapped(TraceStop),
+}
+
+impl WaitEvent {
+    /// Encodes the event as the status returned by `wait4`.
+    fn wait_status(&self) -> i32 {
+        match self {
+            WaitEvent::Exited(status) => *status,
+            // Stopped children report `0x7f` along with the signal, and
+            // continued ones `0xffff`.
+            WaitEvent::Stopped(signo) => ((*signo as i32) << 8) | 0x7f,
+            WaitEvent::Continued => 0xffff,
+            WaitEvent::Trapped(stop) => {
+                (PTRACE_EVENT_STOP << 16) | ((stop.signo() as i32) << 8) | 0x7f
+            }
+        }
+    }
 
+    /// Returns the `si_code` and `si_status` reported by `waitid`.
+    fn code_and_status(&self) -> (u32, i32) {
+        match self {
+            WaitEvent::Exited(status) if status & 0x7f == 0 => (CLD_EXITED, (status >> 8) & 0xff),
+            WaitEvent::Exited(status) if status & 0x80 != 0 => (CLD_DUMPED, status & 0x7f),
+            WaitEvent::Exited(status) => (CLD_KILLED, status & 0x7f),
+            WaitEvent::Stopped(signo) => (CLD_STOPPED, *signo as i32),
+            WaitEvent::Continued => (CLD_CONTINUED, Signo::SIGCONT as i32),
+            WaitEvent::Trapped(stop) => (CLD_TRAPPED, stop.signo() as i32),
+        }
+    }
+}
+
+/// Waits for a change of state of a child or tracee selected by `target`.
+///
+/// Ptrace stops of tracees are reported whether or not `WUNTRACED` is given.
+///
+/// Returns `None` if there is none and `WNOHANG` is given.
+fn wait_child(target: WaitPid, options: WaitOptions) -> AxResult<Option<(Pid, WaitEvent, Rusage)>> {
     let curr = current();
     let proc_data = &curr.as_thread().proc_data;
-    let proc = &proc_data.proc;
-
-    let pid = if pid == -1 {
-        WaitPid::Any
-    } else if pid == 0 {
-        WaitPid::Pgid(proc.group().pgid())
-    } else if pid > 0 {
-        WaitPid::Pid(pid as _)
-    } else {
-        WaitPid::Pgid(-pid as _)
-    };
 
     // FIXME: add back support for WALL & WCLONE, since ProcessData may drop before
     // Process now.
-    let children = proc
+    let children = proc_data
+        .proc
         .children()
         .into_iter()
-        .filter(|child| pid.apply(child))
+        .filter(|child| target.apply(child))
+        .collect::<Vec<_>>();
+    let tracees = tracees(proc_data.proc.pid())
+        .into_iter()
+        .filter(|task| target.apply_tracee(task.as_thread()))
         .collect::<Vec<_>>();
-    if children.is_empty() {
+    if children.is_empty() && tracees.is_empty() {
         return Err(AxError::from(LinuxError::ECHILD));
     }
 
     let check_children = || {
-        if let Some(child) = children.iter().find(|child| child.is_zombie()) {
-            if !options.contains(WaitOptions::WNOWAIT) {
+        if options.contains(WaitOptions::WEXITED)
+            && let Some(child) = children.iter().find(|child| child.is_zombie())
+        {
+            let usage = if options.contains(WaitOptions::WNOWAIT) {
+                zombie_usage(child.pid())
+            } else {
                 child.free();
+                reap(proc_data, child.pid())
+            };
+            Some(Some((
+                child.pid(),
+                WaitEvent::Exited(child.exit_code()),
+                usage,
+            )))
+        } else if let Some((child, report)) = children.iter().find_map(|child| {
+            let data = get_process_data(child.pid()).ok()?;
+            match data.take_stop_report(true)? {
+                StopReport::Stopped(_) if !options.contains(WaitOptions::WUNTRACED) => None,
+                StopReport::Continued if !options.contains(WaitOptions::WCONTINUED) => None,
+                report => Some((data, report)),
             }
-            if let Some(exit_code) = exit_code.nullable() {
-                exit_code.vm_write(child.exit_code())?;
+        }) {
+            if !options.contains(WaitOptions::WNOWAIT) {
+                child.take_stop_report(false);
+            }
+            let event = match report {
+                StopReport::Stopped(signo) => WaitEvent::Stopped(signo),
+                StopReport::Continued => WaitEvent::Continued,
+            };
+            Some(Some((child.proc.pid(), event, process_usage(&child))))
+        } else if let Some((thr, stop)) = tracees.iter().find_map(|task| {
+            let thr = task.as_thread();
+            Some((thr, thr.trace.take_report(true)?))
+        }) {
+            if !options.contains(WaitOptions::WNOWAIT) {
+                thr.trace.take_report(false);
             }
-            Ok(Some(child.pid() as _))
+            Some(Some((
+                thr.tid(),
+                WaitEvent::Trapped(stop),
+                thread_usage(thr),
+            )))
         } else if options.contains(WaitOptions::WNOHANG) {
-            Ok(Some(0))
+            Some(None)
         } else {
-            Ok(None)
+            None
         }
     };
 
-    block_on(interruptible(poll_fn(|cx| {
-        match check_children().transpose() {
+    Ok(block_on(interruptible(poll_fn(
+        |cx| match check_children() {
             Some(res) => Poll::Ready(res),
             None => {
                 proc_data.child_exit_event.register(cx.waker());
                 Poll::Pending
             }
+        },
+    )))?)
+}
+
+pub fn sys_waitpid(
+    pid: i32,
+    exit_code: *mut i32,
+    options: u32,
+    usage: *mut rusage,
+) -> AxResult<isize> {
+    let options = WaitOptions::from_bits_truncate(options);
+    info!("sys_waitpid <= pid: {pid:?}, options: {options:?}");
+
+    let target = if pid == -1 {
+        WaitPid::Any
+    } else if pid == 0 {
+        WaitPid::Pgid(current().as_thread().proc_data.proc.group().pgid())
+    } else if pid > 0 {
+        WaitPid::Pid(pid as _)
+    } else {
+        WaitPid::Pgid(-pid as _)
+    };
+
+    // Exited children are always reported.
+    let Some((pid, event, child_usage)) = wait_child(target, options | WaitOptions::WEXITED)?
+    else {
+        return Ok(0);
+    };
+    if let Some(exit_code) = exit_code.nullable() {
+        exit_code.vm_write(event.wait_status())?;
+    }
+    if let Some(usage) = usage.nullable() {
+        usage.vm_write(to_rusage(&child_usage))?;
+    }
+    Ok(pid as _)
+}
+
+/// The `siginfo_t` filled in by `waitid`, laid out as the `SIGCHLD` variant.
+#[repr(C)]
+pub struct WaitSigInfo {
+    signo: i32,
+    errno: i32,
+    code: i32,
+    /// The union of fields is aligned to pointers.
+    #[cfg(target_pointer_width = "64")]
+    _align: i32,
+    pid: Pid,
+    uid: u32,
+    status: i32,
+    utime: c_long,
+    stime: c_long,
+    _pad: [u8; 128 - 16 - 4 * size_of::<c_long>()],
+}
+
+pub fn sys_waitid(
+    idtype: u32,
+    id: u32,
+    info: *mut WaitSigInfo,
+    options: u32,
+    usage: *mut rusage,
+) -> AxResult<isize> {
+    let mut options = WaitOptions::from_bits_truncate(options);
+    info!("sys_waitid <= idtype: {idtype}, id: {id}, options: {options:?}");
+
+    if !options.intersects(WaitOptions::WEXITED | WaitOptions::WUNTRACED | WaitOptions::WCONTINUED)
+    {
+        return Err(AxError::InvalidInput);
+    }
+    let mut nonblocking_pidfd = false;
+    let target = match idtype {
+        P_ALL => WaitPid::Any,
+        P_PID if id > 0 => WaitPid::Pid(id),
+        P_PGID if id > 0 => WaitPid::Pgid(id),
+        P_PGID => WaitPid::Pgid(current().as_thread().proc_data.proc.group().pgid()),
+        P_PIDFD => {
+            let pidfd = PidFd::from_fd(id as _)?;
+            // A non-blocking pidfd fails with `EAGAIN` rather than waiting.
+            if pidfd.nonblocking() && !options.contains(WaitOptions::WNOHANG) {
+                options |= WaitOptions::WNOHANG;
+                nonblocking_pidfd = true;
+            }
+            WaitPid::Pid(pidfd.pid())
+        }
+        _ => return Err(AxError::InvalidInput),
+    };
+
+    let result = wait_child(target, options)?;
+    if nonblocking_pidfd && result.is_none() {
+        return Err(AxError::WouldBlock);
+    }
+    if let Some(info) = info.nullable() {
+        // With `WNOHANG` and no child to report, the fields are zeroed.
+        // FIXME: Zeroable
+        let mut siginfo: WaitSigInfo = unsafe { core::mem::zeroed() };
+        if let Some((pid, event, child_usage)) = &result {
+            let (code, status) = event.code_and_status();
+            siginfo.signo = Signo::SIGCHLD as _;
+            siginfo.code = code as _;
+            siginfo.pid = *pid;
+            siginfo.status = status;
+            siginfo.utime = clock_ticks(child_usage.utime) as _;
+            siginfo.stime = clock_ticks(child_usage.stime) as _;
         }
-    })))?
+        info.vm_write(siginfo)?;
+    }
+    if let Some(usage) = usage.nullable() {
+        let child_usage = result.map(|(.., usage)| usage).unwrap_or_default();
+        usage.vm_write(to_rusage(&child_usage))?;
+    }
+    Ok(0)
 }
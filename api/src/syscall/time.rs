@@ -1,29 +1,97 @@
+use alloc::sync::Arc;
+
 use axerrno::{AxError, AxResult};
-use axhal::time::{TimeValue, monotonic_time, monotonic_time_nanos, nanos_to_ticks, wall_time};
+use axhal::time::{TimeValue, monotonic_time};
 use axtask::current;
+use bytemuck::AnyBitPattern;
 use linux_raw_sys::general::{
-    __kernel_clockid_t, CLOCK_BOOTTIME, CLOCK_MONOTONIC, CLOCK_MONOTONIC_COARSE,
-    CLOCK_MONOTONIC_RAW, CLOCK_PROCESS_CPUTIME_ID, CLOCK_REALTIME, CLOCK_REALTIME_COARSE,
-    CLOCK_THREAD_CPUTIME_ID, itimerval, timespec, timeval,
+    __kernel_clockid_t, __kernel_itimerspec, __kernel_timespec, CLOCK_BOOTTIME, CLOCK_MONOTONIC,
+    CLOCK_MONOTONIC_COARSE, CLOCK_MONOTONIC_RAW, CLOCK_PROCESS_CPUTIME_ID, CLOCK_REALTIME,
+    CLOCK_REALTIME_COARSE, CLOCK_TAI, CLOCK_THREAD_CPUTIME_ID, SIGEV_NONE, SIGEV_SIGNAL,
+    SIGEV_THREAD, SIGEV_THREAD_ID, TIMER_ABSTIME, itimerval, timespec, timeval,
+};
+use starry_core::{
+    posix_timer::TimerNotify,
+    rusage::{process_usage, thread_cpu_time},
+    task::{AsThread, get_process_data, get_task, process_cpu_time},
+    time::{ITimerType, clock_ticks},
+    timekeeping::{self, Timex},
 };
-use starry_core::{task::AsThread, time::ITimerType};
+use starry_process::Pid;
+use starry_signal::Signo;
 use starry_vm::{VmMutPtr, VmPtr};
 
 use crate::time::TimeValueLike;
 
+/// Reads a CPU-time clock of a process or thread, as encoded in a negative
+/// clock ID by `clock_getcpuclockid` and `pthread_getcpuclockid`.
+fn cpu_clock_time(clock_id: __kernel_clockid_t) -> AxResult<TimeValue> {
+    const CPUCLOCK_PROF: i32 = 0;
+    const CPUCLOCK_VIRT: i32 = 1;
+    const CPUCLOCK_SCHED: i32 = 2;
+    const CPUCLOCK_PERTHREAD_MASK: i32 = 4;
+
+    // A PID of 0 stands for the calling thread or process.
+    let pid = !(clock_id >> 3) as Pid;
+    let curr = current();
+    let (utime, stime) = if clock_id & CPUCLOCK_PERTHREAD_MASK != 0 {
+        let task = if pid == 0 {
+            curr.clone()
+        } else {
+            get_task(pid).map_err(|_| AxError::InvalidInput)?
+        };
+        let thr = task.try_as_thread().ok_or(AxError::InvalidInput)?;
+        // Only threads of the calling process may be asked about.
+        if !Arc::ptr_eq(&thr.proc_data, &curr.as_thread().proc_data) {
+            return Err(AxError::InvalidInput);
+        }
+        thread_cpu_time(thr)
+    } else {
+        let proc_data = if pid == 0 {
+            curr.as_thread().proc_data.clone()
+        } else {
+            get_process_data(pid).map_err(|_| AxError::InvalidInput)?
+        };
+        let usage = process_usage(&proc_data);
+        (usage.utime, usage.stime)
+    };
+    match clock_id & 3 {
+        CPUCLOCK_PROF | CPUCLOCK_SCHED => Ok(utime + stime),
+        CPUCLOCK_VIRT => Ok(utime),
+        _ => Err(AxError::InvalidInput),
+    }
+}
+
 pub fn sys_clock_gettime(clock_id: __kernel_clockid_t, ts: *mut timespec) -> AxResult<isize> {
+    if clock_id < 0 {
+        ts.vm_write(timespec::from_time_value(cpu_clock_time(clock_id)?))?;
+        return Ok(0);
+    }
+    let curr = current();
+    let time_ns = curr.as_thread().proc_data.time_ns.read().clone();
     let now = match clock_id as u32 {
-        CLOCK_REALTIME | CLOCK_REALTIME_COARSE => wall_time(),
-        CLOCK_MONOTONIC | CLOCK_MONOTONIC_RAW | CLOCK_MONOTONIC_COARSE | CLOCK_BOOTTIME => {
-            monotonic_time()
+        CLOCK_REALTIME | CLOCK_REALTIME_COARSE => time_ns.wall_time(),
+        CLOCK_MONOTONIC | CLOCK_MONOTONIC_RAW | CLOCK_MONOTONIC_COARSE => {
+            time_ns.monotonic_time()
         }
-        CLOCK_PROCESS_CPUTIME_ID | CLOCK_THREAD_CPUTIME_ID => {
-            let (utime, stime) = current().as_thread().time.borrow().output();
+        CLOCK_BOOTTIME => time_ns.boottime(),
+        CLOCK_TAI => {
+            let tai = timekeeping::tai_offset();
+            let offset = TimeValue::from_secs(tai.unsigned_abs() as u64);
+            if tai >= 0 {
+                time_ns.wall_time() + offset
+            } else {
+                time_ns.wall_time().saturating_sub(offset)
+            }
+        }
+        CLOCK_PROCESS_CPUTIME_ID => process_cpu_time(&curr.as_thread().proc_data),
+        CLOCK_THREAD_CPUTIME_ID => {
+            let (utime, stime) = thread_cpu_time(curr.as_thread());
             utime + stime
         }
         _ => {
             warn!("Called sys_clock_gettime for unsupported clock {clock_id}");
-            wall_time()
+            time_ns.wall_time()
             // return Err(AxError::EINVAL);
         }
     };
@@ -32,16 +100,64 @@ pub fn sys_clock_gettime(clock_id: __kernel_clockid_t, ts: *mut timespec) -> AxR
 }
 
 pub fn sys_gettimeofday(ts: *mut timeval) -> AxResult<isize> {
-    ts.vm_write(timeval::from_time_value(wall_time()))?;
+    let now = current().as_thread().proc_data.time_ns.read().wall_time();
+    ts.vm_write(timeval::from_time_value(now))?;
     Ok(0)
 }
 
-pub fn sys_clock_getres(clock_id: __kernel_clockid_t, res: *mut timespec) -> AxResult<isize> {
-    if clock_id as u32 != CLOCK_MONOTONIC && clock_id as u32 != CLOCK_REALTIME {
-        warn!("Called sys_clock_getres for unsupported clock {clock_id}");
+pub fn sys_settimeofday(tv: *const timeval, _tz: *const u8) -> AxResult<isize> {
+    if let Some(tv) = tv.nullable() {
+        // FIXME: AnyBitPattern
+        let tv = unsafe { tv.vm_read_uninit()?.assume_init() };
+        timekeeping::set_realtime(tv.try_into_time_value()?);
+    }
+    Ok(0)
+}
+
+pub fn sys_clock_settime(clock_id: __kernel_clockid_t, ts: *const timespec) -> AxResult<isize> {
+    if clock_id as u32 != CLOCK_REALTIME {
+        return Err(AxError::InvalidInput);
     }
+    // FIXME: AnyBitPattern
+    let ts = unsafe { ts.vm_read_uninit()?.assume_init() };
+    timekeeping::set_realtime(ts.try_into_time_value()?);
+    Ok(0)
+}
+
+pub fn sys_adjtimex(buf: *mut Timex) -> AxResult<isize> {
+    sys_clock_adjtime(CLOCK_REALTIME as _, buf)
+}
+
+pub fn sys_clock_adjtime(clock_id: __kernel_clockid_t, buf: *mut Timex) -> AxResult<isize> {
+    if clock_id as u32 != CLOCK_REALTIME {
+        return Err(AxError::InvalidInput);
+    }
+    let mut tx = buf.vm_read()?;
+    debug!("sys_clock_adjtime <= modes: {:#x}", tx.modes);
+    let state = timekeeping::adjtimex(&mut tx)?;
+    buf.vm_write(tx)?;
+    Ok(state as _)
+}
+
+pub fn sys_clock_getres(clock_id: __kernel_clockid_t, res: *mut timespec) -> AxResult<isize> {
+    // CPU-time clocks are read to the nanosecond. `clock_getcpuclockid`
+    // also calls this to check that the process exists.
+    let resolution = if clock_id < 0 {
+        cpu_clock_time(clock_id)?;
+        TimeValue::from_nanos(1)
+    } else if matches!(
+        clock_id as u32,
+        CLOCK_PROCESS_CPUTIME_ID | CLOCK_THREAD_CPUTIME_ID
+    ) {
+        TimeValue::from_nanos(1)
+    } else {
+        if clock_id as u32 != CLOCK_MONOTONIC && clock_id as u32 != CLOCK_REALTIME {
+            warn!("Called sys_clock_getres for unsupported clock {clock_id}");
+        }
+        TimeValue::from_micros(1)
+    };
     if let Some(res) = res.nullable() {
-        res.vm_write(timespec::from_time_value(TimeValue::from_micros(1)))?;
+        res.vm_write(timespec::from_time_value(resolution))?;
     }
     Ok(0)
 }
@@ -59,16 +175,18 @@ pub struct Tms {
 }
 
 pub fn sys_times(tms: *mut Tms) -> AxResult<isize> {
-    let (utime, stime) = current().as_thread().time.borrow().output();
-    let utime = utime.as_micros() as usize;
-    let stime = stime.as_micros() as usize;
+    let curr = current();
+    let proc_data = &curr.as_thread().proc_data;
+    let usage = process_usage(proc_data);
+    let children = *proc_data.children_usage.lock();
+    let ticks = |time: TimeValue| clock_ticks(time) as usize;
     tms.vm_write(Tms {
-        tms_utime: utime,
-        tms_stime: stime,
-        tms_cutime: utime,
-        tms_cstime: stime,
+        tms_utime: ticks(usage.utime),
+        tms_stime: ticks(usage.stime),
+        tms_cutime: ticks(children.utime),
+        tms_cstime: ticks(children.stime),
     })?;
-    Ok(nanos_to_ticks(monotonic_time_nanos()) as _)
+    Ok(clock_ticks(monotonic_time()) as _)
 }
 
 pub fn sys_getitimer(which: i32, value: *mut itimerval) -> AxResult<isize> {
@@ -118,3 +236,131 @@ pub fn sys_setitimer(
     }
     Ok(0)
 }
+
+/// The parts of `struct sigevent` that `timer_create` uses.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, AnyBitPattern)]
+pub struct SigEvent {
+    value: usize,
+    signo: i32,
+    notify: i32,
+    tid: i32,
+}
+
+pub fn sys_timer_create(
+    clock_id: __kernel_clockid_t,
+    sevp: *const SigEvent,
+    timer_id: *mut i32,
+) -> AxResult<isize> {
+    let curr = current();
+    let proc_data = &curr.as_thread().proc_data;
+    let pid = proc_data.proc.pid();
+
+    let (notify, value) = match sevp.nullable() {
+        None => (TimerNotify::Signal(Signo::SIGALRM), None),
+        Some(sevp) => {
+            let event = sevp.vm_read()?;
+            debug!("sys_timer_create <= clock: {clock_id}, event: {event:?}");
+            let signo = || {
+                u8::try_from(event.signo)
+                    .ok()
+                    .and_then(Signo::from_repr)
+                    .ok_or(AxError::InvalidInput)
+            };
+            let notify = match event.notify as u32 {
+                SIGEV_NONE => TimerNotify::None,
+                // SIGEV_THREAD is implemented by libc on top of signals.
+                SIGEV_SIGNAL | SIGEV_THREAD => TimerNotify::Signal(signo()?),
+                notify if notify == SIGEV_SIGNAL | SIGEV_THREAD_ID => {
+                    let tid = event.tid as u32;
+                    let task = get_task(tid).map_err(|_| AxError::InvalidInput)?;
+                    if task.as_thread().proc_data.proc.pid() != pid {
+                        return Err(AxError::InvalidInput);
+                    }
+                    TimerNotify::Thread(signo()?, tid)
+                }
+                _ => return Err(AxError::InvalidInput),
+            };
+            (notify, Some(event.value))
+        }
+    };
+
+    let time_ns = proc_data.time_ns.read().clone();
+    let id = proc_data
+        .posix_timers
+        .lock()
+        .create(pid, clock_id as u32, time_ns, notify, value)?;
+    if let Err(err) = timer_id.vm_write(id) {
+        let _ = proc_data.posix_timers.lock().delete(id);
+        return Err(err.into());
+    }
+    Ok(0)
+}
+
+fn itimerspec_from(interval: TimeValue, value: TimeValue) -> __kernel_itimerspec {
+    __kernel_itimerspec {
+        it_interval: __kernel_timespec::from_time_value(interval),
+        it_value: __kernel_timespec::from_time_value(value),
+    }
+}
+
+pub fn sys_timer_settime(
+    timer_id: i32,
+    flags: u32,
+    new_value: *const __kernel_itimerspec,
+    old_value: *mut __kernel_itimerspec,
+) -> AxResult<isize> {
+    if flags & !TIMER_ABSTIME != 0 {
+        return Err(AxError::InvalidInput);
+    }
+    let timer = current()
+        .as_thread()
+        .proc_data
+        .posix_timers
+        .lock()
+        .get(timer_id)?;
+
+    // FIXME: AnyBitPattern
+    let new_value = unsafe { new_value.vm_read_uninit()?.assume_init() };
+    let interval = new_value.it_interval.try_into_time_value()?;
+    let value = new_value.it_value.try_into_time_value()?;
+    debug!("sys_timer_settime <= id: {timer_id}, interval: {interval:?}, value: {value:?}");
+
+    let (old_interval, old_remaining) = timer.set(value, interval, flags & TIMER_ABSTIME != 0);
+    if let Some(old_value) = old_value.nullable() {
+        old_value.vm_write(itimerspec_from(old_interval, old_remaining))?;
+    }
+    Ok(0)
+}
+
+pub fn sys_timer_gettime(timer_id: i32, value: *mut __kernel_itimerspec) -> AxResult<isize> {
+    let timer = current()
+        .as_thread()
+        .proc_data
+        .posix_timers
+        .lock()
+        .get(timer_id)?;
+    let (interval, remaining) = timer.get();
+    value.vm_write(itimerspec_from(interval, remaining))?;
+    Ok(0)
+}
+
+pub fn sys_timer_getoverrun(timer_id: i32) -> AxResult<isize> {
+    let timer = current()
+        .as_thread()
+        .proc_data
+        .posix_timers
+        .lock()
+        .get(timer_id)?;
+    Ok(timer.overrun() as _)
+}
+
+pub fn sys_timer_delete(timer_id: i32) -> AxResult<isize> {
+    current()
+        .as_thread()
+        .proc_data
+        .posix_timers
+        .lock()
+        .delete(timer_id)?;
+    Ok(0)
+}
@@ -1,18 +1,30 @@
-use core::{ffi::c_long, sync::atomic::Ordering};
+use core::{
+    ffi::c_long,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
 use axerrno::{AxError, AxResult};
-use axhal::uspace::{ExceptionKind, ReturnReason, UserContext};
+use axhal::{
+    paging::MappingFlags,
+    uspace::{ExceptionKind, ReturnReason, UserContext},
+};
 use axtask::{TaskInner, current};
 use bytemuck::AnyBitPattern;
-use linux_raw_sys::general::ROBUST_LIST_LIMIT;
+use linux_raw_sys::general::{ROBUST_LIST_LIMIT, SEGV_ACCERR, SEGV_MAPERR};
+use memory_addr::VirtAddr;
 use starry_core::{
     futex::FutexKey,
+    kmsg,
+    mm::grow_stack,
+    rusage,
+    sched::apply_pending,
     shm::SHM_MANAGER,
     task::{
-        AsThread, get_process_data, get_task, send_signal_to_process, send_signal_to_thread,
-        set_timer_state,
+        AsThread, ProcessData, Thread, check_cpu_limit, detach_all, get_process_data, get_task,
+        raw_signal_info, send_signal_to_process, send_signal_to_thread, set_timer_state,
     },
     time::TimerState,
+    trace::{TracePoint, trace},
 };
 use starry_process::Pid;
 use starry_signal::{SignalInfo, Signo};
@@ -21,8 +33,66 @@ use starry_vm::{VmMutPtr, VmPtr};
 use crate::{
     signal::{check_signals, unblock_next_signal},
     syscall::handle_syscall,
+    vfs::dev::tty::disassociate_ctty,
 };
 
+/// Whether fatal faults of user tasks are reported, as set through
+/// /proc/sys/kernel/print-fatal-signals.
+pub static PRINT_FATAL_SIGNALS: AtomicBool = AtomicBool::new(false);
+
+/// Reports a fault that is about to kill the current task with `signo`: who
+/// faulted and where, how the faulting address is mapped, and the registers.
+fn report_fatal_fault(uctx: &UserContext, signo: Signo, fault: Option<(VirtAddr, MappingFlags)>) {
+    let curr = current();
+    // As on Linux, the fault itself always makes it into the kernel log.
+    kmsg::printk(
+        6,
+        format_args!(
+            "{}[{}]: {signo:?} at ip {:#x} sp {:#x}",
+            curr.name(),
+            curr.id().as_u64(),
+            uctx.ip(),
+            uctx.sp()
+        ),
+    );
+    if !PRINT_FATAL_SIGNALS.load(Ordering::Relaxed) {
+        return;
+    }
+    let thr = curr.as_thread();
+    warn!(
+        "{}[{}]: {signo:?} at ip {:#x} sp {:#x} in {:?}",
+        curr.name(),
+        curr.id().as_u64(),
+        uctx.ip(),
+        uctx.sp(),
+        thr.proc_data.proc
+    );
+    if let Some((addr, flags)) = fault {
+        let aspace = thr.proc_data.aspace.lock();
+        match aspace.find_area(addr) {
+            Some(area) => warn!(
+                "  {flags:?} access to {addr:#x} in {:#x}-{:#x} {:?}",
+                area.start(),
+                area.end(),
+                area.flags()
+            ),
+            None => warn!("  {flags:?} access to {addr:#x} outside of any mapping"),
+        }
+        match aspace.page_table().query(addr) {
+            Ok((paddr, flags, size)) => warn!("  page: {paddr:#x} {flags:?} {size:?}"),
+            Err(err) => warn!("  page: not present ({err:?})"),
+        }
+    }
+    warn!("  registers: {uctx:#x?}");
+}
+
+/// Builds the signal for a fault at `addr`, with the `_sigfault` member of
+/// `siginfo_t` telling where it happened. Stack overflow detection in
+/// runtimes relies on it to tell a hit on the guard page.
+fn fault_signal_info(signo: Signo, code: u32, addr: VirtAddr) -> SignalInfo {
+    raw_signal_info(signo, code as i32, &(addr.as_usize() as u64).to_ne_bytes())
+}
+
 /// Create a new user task.
 pub fn new_user_task(name: &str, mut uctx: UserContext, set_child_tid: usize) -> TaskInner {
     TaskInner::new(
@@ -37,19 +107,35 @@ pub fn new_user_task(name: &str, mut uctx: UserContext, set_child_tid: usize) ->
 
             let thr = curr.as_thread();
             while !thr.pending_exit() {
+                apply_pending();
                 let reason = uctx.run();
 
                 set_timer_state(&curr, TimerState::Kernel);
+                check_cpu_limit(thr);
 
                 match reason {
                     ReturnReason::Syscall => handle_syscall(&mut uctx),
                     ReturnReason::PageFault(addr, flags) => {
-                        if !thr.proc_data.aspace.lock().handle_page_fault(addr, flags) {
+                        trace(
+                            TracePoint::PageFaultUser,
+                            [addr.as_usize() as _, uctx.ip() as _, flags.bits() as _],
+                        );
+                        let mut aspace = thr.proc_data.aspace.lock();
+                        let handled = aspace.handle_page_fault(addr, flags)
+                            || (grow_stack(&thr.proc_data, &mut aspace, addr)
+                                && aspace.handle_page_fault(addr, flags));
+                        let mapped = handled || aspace.find_area(addr).is_some();
+                        drop(aspace);
+                        if handled {
+                            rusage::page_fault(thr, addr);
+                        } else {
                             info!(
                                 "{:?}: segmentation fault at {:#x} {:?}",
                                 thr.proc_data.proc, addr, flags
                             );
-                            raise_signal_fatal(SignalInfo::new_kernel(Signo::SIGSEGV))
+                            report_fatal_fault(&uctx, Signo::SIGSEGV, Some((addr, flags)));
+                            let code = if mapped { SEGV_ACCERR } else { SEGV_MAPERR };
+                            raise_signal_fatal(fault_signal_info(Signo::SIGSEGV, code, addr))
                                 .expect("Failed to send SIGSEGV");
                         }
                     }
@@ -69,11 +155,13 @@ pub fn new_user_task(name: &str, mut uctx: UserContext, set_child_tid: usize) ->
                             ExceptionKind::IllegalInstruction => Signo::SIGILL,
                             _ => Signo::SIGTRAP,
                         };
+                        report_fatal_fault(&uctx, signo, None);
                         raise_signal_fatal(SignalInfo::new_kernel(signo))
                             .expect("Failed to send SIGTRAP");
                     }
                     r => {
                         warn!("Unexpected return reason: {r:?}");
+                        report_fatal_fault(&uctx, Signo::SIGSEGV, None);
                         raise_signal_fatal(SignalInfo::new_kernel(Signo::SIGSEGV))
                             .expect("Failed to send SIGSEGV");
                     }
@@ -158,6 +246,14 @@ pub fn do_exit(exit_code: i32, group_exit: bool) {
 
     info!("{} exit with code: {}", curr.id_name(), exit_code);
 
+    // Only the first thread to take the process down sets its exit code. The
+    // other threads are killed right away, so that they don't keep running
+    // while this one cleans up.
+    let group_exit = group_exit && thr.proc_data.begin_group_exit();
+    if group_exit {
+        kill_other_threads(thr);
+    }
+
     let clear_child_tid = thr.clear_child_tid() as *mut u32;
     if clear_child_tid.vm_write(0).is_ok() {
         let key = FutexKey::new_current(clear_child_tid as usize);
@@ -175,9 +271,17 @@ pub fn do_exit(exit_code: i32, group_exit: bool) {
         warn!("exit robust list failed: {err:?}");
     }
 
+    rusage::thread_exited(thr);
     let process = &thr.proc_data.proc;
-    if process.exit_thread(curr.id().as_u64() as Pid, exit_code) {
+    if thr.proc_data.exit_thread(thr.tid(), exit_code, group_exit) {
+        rusage::process_exited(&thr.proc_data);
+        detach_all(process.pid());
         process.exit();
+        // A session leader exiting hangs up its controlling terminal.
+        let session = process.group().session();
+        if session.sid() == process.pid() {
+            disassociate_ctty(&session);
+        }
         if let Some(parent) = process.parent() {
             if let Some(signo) = thr.proc_data.exit_signal {
                 let _ = send_signal_to_process(parent.pid(), Some(SignalInfo::new_kernel(signo)));
@@ -190,14 +294,34 @@ pub fn do_exit(exit_code: i32, group_exit: bool) {
 
         SHM_MANAGER.lock().clear_proc_shm(process.pid());
     }
-    if group_exit && !process.is_group_exited() {
-        process.group_exit();
-        let sig = SignalInfo::new_kernel(Signo::SIGKILL);
-        for tid in process.threads() {
+    thr.set_exit();
+}
+
+/// Sends `SIGKILL` to the threads of the process other than `thr`.
+pub fn kill_other_threads(thr: &Thread) {
+    // Stopped threads must run to die.
+    thr.proc_data.resume(false);
+    let sig = SignalInfo::new_kernel(Signo::SIGKILL);
+    for tid in thr.proc_data.proc.threads() {
+        if tid != thr.tid() {
             let _ = send_signal_to_thread(None, tid, Some(sig.clone()));
         }
     }
-    thr.set_exit();
+}
+
+/// Checks whether the current process may inspect or take resources from
+/// the process of `proc_data`, as `ptrace_may_access` does on Linux.
+///
+/// A process may always access itself. Another one is accessible while it
+/// has not exited, if it runs as the same user or the caller is privileged.
+pub fn may_access(proc_data: &ProcessData) -> bool {
+    let curr = current();
+    let caller = &curr.as_thread().proc_data;
+    let proc = &proc_data.proc;
+    if caller.proc.pid() == proc.pid() {
+        return true;
+    }
+    !proc.is_zombie() && (caller.euid() == proc_data.euid() || caller.is_privileged())
 }
 
 /// Sends a fatal signal to the current process.
@@ -2,7 +2,7 @@
 
 use core::{ffi::c_uint, slice};
 
-use axhal::time::wall_time;
+use axhal::time::monotonic_time;
 use tee_raw_sys::TeeTime;
 
 use crate::tee::{
@@ -14,7 +14,7 @@ use crate::tee::{
 /// TEE_GetCancellationFlag
 /// Returns 1 if the session cancel flag is set and not masked, otherwise 0.
 pub fn sys_tee_scn_get_cancellation_flag(cancel: *mut c_uint) -> TeeResult {
-    let is_cancelled = with_tee_session_ctx(|ctx| Ok(tee_ta_session_is_cancelled(ctx, None)))?;
+    let is_cancelled = tee_session_is_cancelled()?;
     let flag: u32 = if is_cancelled { 1 } else { 0 };
     copy_to_user(
         unsafe { slice::from_raw_parts_mut(cancel as _, size_of::<u32>()) },
@@ -60,6 +60,12 @@ pub fn sys_tee_scn_mask_cancellation(old_mask: *mut c_uint) -> TeeResult {
     Ok(())
 }
 
+/// Returns whether the current session is cancelled, cancellation being
+/// unmasked.
+pub(crate) fn tee_session_is_cancelled() -> TeeResult<bool> {
+    with_tee_session_ctx(|ctx| Ok(tee_ta_session_is_cancelled(ctx, None)))
+}
+
 fn tee_ta_session_is_cancelled(ctx: &TeeSessionCtx, curr_time: Option<&TeeTime>) -> bool {
     if ctx.cancel_mask {
         return false;
@@ -88,7 +94,7 @@ fn tee_ta_session_is_cancelled(ctx: &TeeSessionCtx, curr_time: Option<&TeeTime>)
 }
 
 fn tee_time_get_sys_time() -> TeeTime {
-    let systiem = wall_time();
+    let systiem = monotonic_time();
     TeeTime {
         seconds: systiem.as_secs() as u32,
         millis: systiem.subsec_millis(),
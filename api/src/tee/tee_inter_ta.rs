@@ -10,17 +10,39 @@ use core::{
     ptr::addr_of,
 };
 
-use tee_raw_sys::{TEE_UUID, utee_params};
+use tee_raw_sys::{
+    TEE_ERROR_BAD_PARAMETERS, TEE_ERROR_COMMUNICATION, TEE_ORIGIN_COMMS, TEE_ORIGIN_TEE,
+    TEE_ORIGIN_TRUSTED_APP, TEE_UUID, utee_params,
+};
 
 use crate::tee::{
     TeeResult,
     tee_ta_manager::{
-        tee_ta_close_session, tee_ta_get_session, tee_ta_init_session, tee_ta_invoke_command,
+        params_from_user, params_to_user, tee_ta_close_session, tee_ta_get_session,
+        tee_ta_init_session, tee_ta_invoke_command,
     },
-    user_access::copy_from_user,
+    user_access::{copy_from_user, copy_to_user_struct},
     uuid::Uuid,
 };
 
+/// Tells the caller where an error came from, if it asked.
+fn set_ret_orig(ret_orig: *mut c_uint, origin: u32) -> TeeResult {
+    if ret_orig.is_null() {
+        return Ok(());
+    }
+    copy_to_user_struct(unsafe { &mut *ret_orig }, &origin)
+}
+
+/// Returns the origin of an error returned by the target TA or by the way
+/// to it.
+fn ta_error_origin(err: u32) -> u32 {
+    if err == TEE_ERROR_COMMUNICATION {
+        TEE_ORIGIN_COMMS
+    } else {
+        TEE_ORIGIN_TRUSTED_APP
+    }
+}
+
 pub fn sys_tee_scn_open_ta_session(
     dest: *const TEE_UUID,
     cancel_req_to: c_ulong,
@@ -28,6 +50,9 @@ pub fn sys_tee_scn_open_ta_session(
     ta_sees: *mut c_uint,
     ret_orig: *mut c_uint,
 ) -> TeeResult {
+    if ta_sees.is_null() {
+        return Err(TEE_ERROR_BAD_PARAMETERS);
+    }
     let uuid = TEE_UUID {
         timeLow: 0,
         timeMid: 0,
@@ -35,21 +60,25 @@ pub fn sys_tee_scn_open_ta_session(
         clockSeqAndNode: [0; 8],
     };
     let uuid_size = core::mem::size_of::<TEE_UUID>();
-    copy_from_user(
+    let params = copy_from_user(
         unsafe { core::slice::from_raw_parts_mut(addr_of!(uuid) as _, uuid_size) },
         unsafe { core::slice::from_raw_parts(dest as _, uuid_size) },
         uuid_size,
-    )?;
-
-    tee_ta_init_session(Uuid::from(uuid).to_string())?;
+    )
+    .and_then(|_| params_from_user(usr_param))
+    .inspect_err(|_| {
+        let _ = set_ret_orig(ret_orig, TEE_ORIGIN_TEE);
+    })?;
 
-    Ok(())
+    let handle = tee_ta_init_session(Uuid::from(uuid).to_string(), params).inspect_err(|err| {
+        let _ = set_ret_orig(ret_orig, ta_error_origin(*err));
+    })?;
+    copy_to_user_struct(unsafe { &mut *ta_sees }, &handle)?;
+    set_ret_orig(ret_orig, TEE_ORIGIN_TRUSTED_APP)
 }
 
 pub fn sys_tee_scn_close_ta_session(ta_sees: c_ulong) -> TeeResult {
-    let sess_id = tee_ta_get_session(ta_sees as u32)?;
-    tee_ta_close_session(sess_id)?;
-    Ok(())
+    tee_ta_close_session(ta_sees as u32)
 }
 
 pub fn sys_tee_scn_invoke_ta_command(
@@ -59,7 +88,16 @@ pub fn sys_tee_scn_invoke_ta_command(
     usr_param: *mut utee_params,
     ret_orig: *mut c_uint,
 ) -> TeeResult {
-    let sess_id = tee_ta_get_session(ta_sees as u32)?;
-    tee_ta_invoke_command(sess_id, cmd_id as u32, usr_param)?;
-    Ok(())
+    let (sess_id, params) = tee_ta_get_session(ta_sees as u32)
+        .and_then(|sess_id| Ok((sess_id, params_from_user(usr_param)?)))
+        .inspect_err(|_| {
+            let _ = set_ret_orig(ret_orig, TEE_ORIGIN_TEE);
+        })?;
+
+    let params = tee_ta_invoke_command(sess_id, cmd_id as u32, params).inspect_err(|err| {
+        let _ = set_ret_orig(ret_orig, ta_error_origin(*err));
+    })?;
+    // A short output buffer is reported as the TA's answer, as on OP-TEE.
+    set_ret_orig(ret_orig, TEE_ORIGIN_TRUSTED_APP)?;
+    params_to_user(&params, usr_param)
 }
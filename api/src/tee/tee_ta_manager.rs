@@ -4,7 +4,7 @@
 //
 // This file has been created by KylinSoft on 2025.
 
-use alloc::{format, string::String, vec::Vec};
+use alloc::{format, string::String, vec, vec::Vec};
 
 use axnet::{
     RecvOptions, SendOptions, SocketAddrEx, SocketOps,
@@ -13,32 +13,154 @@ use axnet::{
 use axtask::current;
 use bincode::config;
 use starry_core::task::AsThread;
-use tee_raw_sys::{TEE_ERROR_GENERIC, TEE_ERROR_ITEM_NOT_FOUND, TEE_SUCCESS, utee_params};
+use tee_raw_sys::{
+    TEE_ERROR_BAD_PARAMETERS, TEE_ERROR_COMMUNICATION, TEE_ERROR_GENERIC, TEE_ERROR_ITEM_NOT_FOUND,
+    TEE_ERROR_SHORT_BUFFER, TEE_SUCCESS, utee_params,
+};
 
 use crate::tee::{
     TeeResult,
-    protocal::{Parameters, TeeRequest, TeeResponse},
+    protocal::{ParamType, Parameter, Parameters, TEEParam, TeeRequest, TeeResponse, Value},
     tee_session::{with_tee_ta_ctx, with_tee_ta_ctx_mut},
+    user_access::{copy_from_user, copy_from_user_struct, copy_to_user, copy_to_user_struct},
 };
 
+/// The largest response a TA may send back, which bounds the memory
+/// references passed along with a command.
+const MAX_RESPONSE_SIZE: usize = 64 * 1024;
+
 #[derive(Debug, Clone)]
 pub struct SessionIdentity {
     pub uuid: String,
     pub session_id: u32,
 }
 
-pub fn tee_ta_init_session(uuid: String) -> TeeResult<u32> {
+/// Returns the type of parameter `index` from the packed `types` of
+/// [`utee_params`], four bits per parameter.
+fn param_type(types: u64, index: usize) -> ParamType {
+    ParamType::from(((types >> (index * 4)) & 0xf) as u32)
+}
+
+fn read_utee_params(usr_param: *const utee_params) -> TeeResult<utee_params> {
+    let mut params: utee_params = unsafe { core::mem::zeroed() };
+    copy_from_user_struct(&mut params, unsafe { &*usr_param })?;
+    Ok(params)
+}
+
+/// Reads the parameters the caller passes to another TA, copying the
+/// contents of input memory references.
+///
+/// Output memory references are sent as zeroed buffers of the caller's size,
+/// for the TA to fill.
+pub fn params_from_user(usr_param: *const utee_params) -> TeeResult<Parameters> {
+    if usr_param.is_null() {
+        return Ok(Parameters::default());
+    }
+    let up = read_utee_params(usr_param)?;
+    let param = |index: usize| -> TeeResult<Parameter> {
+        let (a, b) = (up.vals[index * 2], up.vals[index * 2 + 1]);
+        let param_type = param_type(up.types, index);
+        let data = match param_type {
+            ParamType::MemrefInput | ParamType::MemrefInout | ParamType::MemrefOutput => {
+                let size = b as usize;
+                if size > MAX_RESPONSE_SIZE {
+                    return Err(TEE_ERROR_BAD_PARAMETERS);
+                }
+                let mut data = vec![0; size];
+                if !matches!(param_type, ParamType::MemrefOutput) {
+                    copy_from_user(
+                        &mut data,
+                        unsafe { core::slice::from_raw_parts(a as *const u8, size) },
+                        size,
+                    )?;
+                }
+                data
+            }
+            _ => Vec::new(),
+        };
+        Ok(Parameter {
+            raw: TEEParam {
+                data,
+                value: Value {
+                    a: a as u32,
+                    b: b as u32,
+                },
+            },
+            param_type,
+        })
+    };
+    Ok(Parameters(param(0)?, param(1)?, param(2)?, param(3)?))
+}
+
+/// Copies the output parameters returned by another TA back to the caller.
+///
+/// The types are taken from the caller's parameters, not from the TA. A
+/// memory reference too short for its output is left alone, has its size set
+/// to the size needed, and makes the call fail with
+/// `TEE_ERROR_SHORT_BUFFER`.
+pub fn params_to_user(params: &Parameters, usr_param: *mut utee_params) -> TeeResult {
+    if usr_param.is_null() {
+        return Ok(());
+    }
+    let mut up = read_utee_params(usr_param)?;
+    let mut short_buffer = false;
+    let returned = [&params.0, &params.1, &params.2, &params.3];
+    for (index, param) in returned.into_iter().enumerate() {
+        match param_type(up.types, index) {
+            ParamType::ValueOutput | ParamType::ValueInout => {
+                up.vals[index * 2] = param.raw.value.a as u64;
+                up.vals[index * 2 + 1] = param.raw.value.b as u64;
+            }
+            ParamType::MemrefOutput | ParamType::MemrefInout => {
+                let data = &param.raw.data;
+                if data.len() <= up.vals[index * 2 + 1] as usize {
+                    copy_to_user(
+                        unsafe {
+                            core::slice::from_raw_parts_mut(
+                                up.vals[index * 2] as *mut u8,
+                                data.len(),
+                            )
+                        },
+                        data,
+                        data.len(),
+                    )?;
+                } else {
+                    short_buffer = true;
+                }
+                up.vals[index * 2 + 1] = data.len() as u64;
+            }
+            _ => {}
+        }
+    }
+    copy_to_user_struct(unsafe { &mut *usr_param }, &up)?;
+    if short_buffer {
+        Err(TEE_ERROR_SHORT_BUFFER)
+    } else {
+        Ok(())
+    }
+}
+
+/// Opens a session to the TA `uuid`, returning the handle the caller refers
+/// to it by.
+///
+/// A caller may hold any number of sessions, to the same TA or to different
+/// ones. Handles start at 1, as 0 is `TEE_HANDLE_NULL`. Transport failures
+/// are reported as `TEE_ERROR_COMMUNICATION`, and other errors come from the
+/// TA.
+pub fn tee_ta_init_session(uuid: String, params: Parameters) -> TeeResult<u32> {
     // Connect to dest TA via Unix socket
     let socket = UnixSocket::new(StreamTransport::new(
         current().as_thread().proc_data.proc.pid(),
     ));
     let path = format!("/tmp/{}.sock", uuid);
     let remote_addr = SocketAddrEx::Unix(UnixSocketAddr::Path(path.into()));
-    socket.connect(remote_addr).map_err(|_| TEE_ERROR_GENERIC)?;
+    socket
+        .connect(remote_addr)
+        .map_err(|_| TEE_ERROR_COMMUNICATION)?;
 
     // Send open session request to dest TA
     let req = TeeRequest::OpenSession {
-        params: Parameters::default(),
+        params,
         uuid: uuid.clone(),
         connection_method: 0,
     };
@@ -49,39 +171,48 @@ pub fn tee_ta_init_session(uuid: String) -> TeeResult<u32> {
     let mut src = message.as_slice();
     socket
         .send(&mut src, SendOptions::default())
-        .map_err(|_| TEE_ERROR_GENERIC)?;
+        .map_err(|_| TEE_ERROR_COMMUNICATION)?;
 
     // Receive response from dest TA
-    let mut buf = [0u8; 1024];
+    let mut buf = vec![0u8; MAX_RESPONSE_SIZE];
     let mut dst = buf.as_mut_slice();
-    socket
+    let len = socket
         .recv(&mut dst, RecvOptions::default())
-        .map_err(|_| TEE_ERROR_GENERIC)?;
-    let (resp, _): (TeeResponse, _) =
-        bincode::decode_from_slice(&dst, config::standard()).map_err(|_| TEE_ERROR_GENERIC)?;
+        .map_err(|_| TEE_ERROR_COMMUNICATION)?;
+    let (resp, _): (TeeResponse, _) = bincode::decode_from_slice(&buf[..len], config::standard())
+        .map_err(|_| TEE_ERROR_COMMUNICATION)?;
     match resp {
         TeeResponse::OpenSession { session_id, result } => match result {
             TEE_SUCCESS => with_tee_ta_ctx_mut(|ctx| {
+                ctx.session_handle += 1;
                 let handle = ctx.session_handle;
                 ctx.open_sessions
                     .insert(handle, SessionIdentity { uuid, session_id });
-                ctx.session_handle += 1;
                 Ok(handle)
             }),
             _ => return Err(result),
         },
-        _ => return Err(TEE_ERROR_GENERIC),
+        _ => return Err(TEE_ERROR_COMMUNICATION),
     }
 }
 
-pub fn tee_ta_close_session(sess_id: SessionIdentity) -> TeeResult {
+/// Closes the session `handle`, which the caller can no longer use
+/// afterwards.
+pub fn tee_ta_close_session(handle: u32) -> TeeResult {
+    let sess_id = with_tee_ta_ctx_mut(|ctx| {
+        ctx.open_sessions
+            .remove(&handle)
+            .ok_or(TEE_ERROR_ITEM_NOT_FOUND)
+    })?;
     // Connect to dest TA via Unix socket
     let socket = UnixSocket::new(StreamTransport::new(
         current().as_thread().proc_data.proc.pid(),
     ));
     let path = format!("/tmp/{}.sock", sess_id.uuid);
     let remote_addr = SocketAddrEx::Unix(UnixSocketAddr::Path(path.into()));
-    socket.connect(remote_addr).map_err(|_| TEE_ERROR_GENERIC)?;
+    socket
+        .connect(remote_addr)
+        .map_err(|_| TEE_ERROR_COMMUNICATION)?;
 
     // Send close session request to dest TA
     let req = TeeRequest::CloseSession {
@@ -94,29 +225,33 @@ pub fn tee_ta_close_session(sess_id: SessionIdentity) -> TeeResult {
     let mut src = message.as_slice();
     socket
         .send(&mut src, SendOptions::default())
-        .map_err(|_| TEE_ERROR_GENERIC)?;
+        .map_err(|_| TEE_ERROR_COMMUNICATION)?;
 
     Ok(())
 }
 
+/// Invokes command `cmd_id` in the session `sess_id`, returning the
+/// parameters as updated by the TA.
 pub fn tee_ta_invoke_command(
     sess_id: SessionIdentity,
     cmd_id: u32,
-    usr_param: *mut utee_params,
-) -> TeeResult {
+    params: Parameters,
+) -> TeeResult<Parameters> {
     // Connect to dest TA via Unix socket
     let socket = UnixSocket::new(StreamTransport::new(
         current().as_thread().proc_data.proc.pid(),
     ));
     let path = format!("/tmp/{}.sock", sess_id.uuid);
     let remote_addr = SocketAddrEx::Unix(UnixSocketAddr::Path(path.into()));
-    socket.connect(remote_addr).map_err(|_| TEE_ERROR_GENERIC)?;
+    socket
+        .connect(remote_addr)
+        .map_err(|_| TEE_ERROR_COMMUNICATION)?;
 
     // Send invoke command request to dest TA
     let req = TeeRequest::InvokeCommand {
         session_id: sess_id.session_id,
         cmd_id,
-        params: Parameters::default(),
+        params,
     };
     let encoded = bincode::encode_to_vec(req, config::standard()).map_err(|_| TEE_ERROR_GENERIC)?;
     let mut message = Vec::with_capacity(4 + encoded.len());
@@ -125,22 +260,22 @@ pub fn tee_ta_invoke_command(
     let mut src = message.as_slice();
     socket
         .send(&mut src, SendOptions::default())
-        .map_err(|_| TEE_ERROR_GENERIC)?;
+        .map_err(|_| TEE_ERROR_COMMUNICATION)?;
 
     // Receive response from dest TA
-    let mut buf = [0u8; 1024];
+    let mut buf = vec![0u8; MAX_RESPONSE_SIZE];
     let mut dst = buf.as_mut_slice();
-    socket
+    let len = socket
         .recv(&mut dst, RecvOptions::default())
-        .map_err(|_| TEE_ERROR_GENERIC)?;
-    let (resp, _): (TeeResponse, _) =
-        bincode::decode_from_slice(&dst, config::standard()).map_err(|_| TEE_ERROR_GENERIC)?;
+        .map_err(|_| TEE_ERROR_COMMUNICATION)?;
+    let (resp, _): (TeeResponse, _) = bincode::decode_from_slice(&buf[..len], config::standard())
+        .map_err(|_| TEE_ERROR_COMMUNICATION)?;
     match resp {
         TeeResponse::InvokeCommand { params, result } => match result {
-            TEE_SUCCESS => Ok(()),
+            TEE_SUCCESS => Ok(params),
             _ => Err(result),
         },
-        _ => Err(TEE_ERROR_GENERIC),
+        _ => Err(TEE_ERROR_COMMUNICATION),
     }
 }
 
@@ -150,3 +285,75 @@ pub fn tee_ta_get_session(handle: u32) -> TeeResult<SessionIdentity> {
         None => Err(TEE_ERROR_ITEM_NOT_FOUND),
     })
 }
+
+// Test module for the marshalling of parameters between TAs
+// Only compiled when the tee_test feature is enabled
+#[cfg(feature = "tee_test")]
+pub mod tests_tee_ta_manager {
+    //-------- test framework import --------
+    //-------- local tests import --------
+    use super::*;
+    use crate::{
+        assert_eq,
+        tee::test::{test_framework::TestDescriptor, test_framework_basic::TestResult},
+        test_fn, tests_name,
+    };
+
+    // Value and memory reference parameters survive the round trip
+    test_fn! {
+        using TestResult;
+
+        fn test_params_round_trip() {
+            let input = [1u8, 2, 3];
+            let mut output = [0u8; 4];
+            let mut up: utee_params = unsafe { core::mem::zeroed() };
+            // value inout, memref input, memref output, none
+            up.types = 0x0653;
+            up.vals[0] = 7;
+            up.vals[1] = 8;
+            up.vals[2] = input.as_ptr() as u64;
+            up.vals[3] = input.len() as u64;
+            up.vals[4] = output.as_mut_ptr() as u64;
+            up.vals[5] = output.len() as u64;
+
+            let mut params = params_from_user(&up).unwrap();
+            assert_eq!(params.0.raw.value.a, 7);
+            assert_eq!(params.1.raw.data.as_slice(), &input[..]);
+            assert_eq!(params.2.raw.data.len(), output.len());
+
+            params.0.raw.value.b = 9;
+            params.2.raw.data = vec![4, 5];
+            params_to_user(&params, &mut up).unwrap();
+            assert_eq!(up.vals[1], 9);
+            assert_eq!(up.vals[5], 2);
+            assert_eq!(&output[..2], &[4, 5]);
+        }
+    }
+
+    // An output too large for the caller's buffer reports the size needed
+    test_fn! {
+        using TestResult;
+
+        fn test_params_short_buffer() {
+            let mut output = [0u8; 1];
+            let mut up: utee_params = unsafe { core::mem::zeroed() };
+            up.types = 0x6;
+            up.vals[0] = output.as_mut_ptr() as u64;
+            up.vals[1] = output.len() as u64;
+
+            let mut params = params_from_user(&up).unwrap();
+            params.0.raw.data = vec![1, 2, 3];
+            assert_eq!(params_to_user(&params, &mut up), Err(TEE_ERROR_SHORT_BUFFER));
+            assert_eq!(up.vals[1], 3);
+            assert_eq!(output[0], 0);
+        }
+    }
+
+    // Test suite definition
+    tests_name! {
+        TEST_TEE_TA_MANAGER;
+        //------------------------
+        test_params_round_trip,
+        test_params_short_buffer,
+    }
+}
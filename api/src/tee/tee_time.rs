@@ -6,20 +6,26 @@
 //
 
 use alloc::vec::Vec;
+use core::time::Duration;
 
-use axhal::time::{TimeValue, wall_time};
+use axhal::time::{TimeValue, monotonic_time, wall_time};
+use axtask::future::{block_on, interruptible, sleep};
 use tee_raw_sys::{
-    TEE_ERROR_BAD_PARAMETERS, TEE_ERROR_OVERFLOW, TEE_ERROR_TIME_NOT_SET, TEE_UUID, TeeTime,
+    TEE_ERROR_BAD_PARAMETERS, TEE_ERROR_CANCEL, TEE_ERROR_OVERFLOW, TEE_ERROR_TIME_NOT_SET,
+    TEE_UUID, TeeTime,
 };
 
 use crate::tee::{
     TeeResult,
+    tee_cancel::tee_session_is_cancelled,
     tee_session::{with_tee_session_ctx, with_tee_session_ctx_mut},
     user_access::{copy_from_user_struct, copy_to_user_struct},
 };
 
+/// Returns the system time, which GP requires never to go back: the time
+/// since boot, unaffected by changes to the REE clock.
 pub fn tee_time_get_sys_time() -> axhal::time::TimeValue {
-    wall_time()
+    monotonic_time()
 }
 fn tee_time_get_ree_time() -> axhal::time::TimeValue {
     wall_time()
@@ -268,30 +274,19 @@ pub fn tee_time_set_ta_time(uuid: &TEE_UUID, time: &TeeTime) -> TeeResult {
     }
 }
 
-// Busy wait function
-pub fn tee_time_busy_wait(milliseconds_delay: u32) -> TeeResult {
-    let start_time = tee_time_get_sys_time();
-    let delay_seconds = milliseconds_delay / 1000;
-    let delay_millis = milliseconds_delay % 1000;
-
-    let delay_duration = TimeValue::new(delay_seconds as u64, delay_millis as u32 * 1_000_000);
-
-    let end_time = TimeValue::from_nanos(
-        (start_time.as_nanos() + delay_duration.as_nanos())
-            .try_into()
-            .unwrap_or(u64::MAX),
-    );
-
+/// TEE_Wait: sleeps for `milliseconds_delay`, returning early with
+/// `TEE_ERROR_CANCEL` if the session is cancelled meanwhile.
+pub fn sys_tee_scn_wait(milliseconds_delay: u32) -> TeeResult {
+    let deadline = tee_time_get_sys_time() + Duration::from_millis(milliseconds_delay as u64);
     loop {
-        let current_time = tee_time_get_sys_time();
-        if current_time.as_nanos() >= end_time.as_nanos() {
-            break Ok(());
+        let now = tee_time_get_sys_time();
+        if now >= deadline {
+            return Ok(());
+        }
+        // Unmasking a pending cancellation interrupts the task.
+        let _ = block_on(interruptible(sleep(deadline - now)));
+        if tee_session_is_cancelled()? {
+            return Err(TEE_ERROR_CANCEL);
         }
-        // Can add brief CPU yield here to avoid excessive CPU usage
-        core::hint::spin_loop();
     }
 }
-
-pub fn sys_tee_scn_wait(milliseconds_delay: u32) -> TeeResult {
-    tee_time_busy_wait(milliseconds_delay)
-}
@@ -1,12 +1,39 @@
 use alloc::sync::{Arc, Weak};
-use core::task::Context;
+use core::{mem, task::Context};
 
-use axerrno::{AxResult, ax_bail};
+use axerrno::{AxError, AxResult, ax_bail};
 use axpoll::{IoEvents, PollSet, Pollable};
-use axtask::current;
+use axtask::{
+    current,
+    future::{block_on, poll_io},
+};
 use kspin::SpinNoIrq;
-use starry_core::task::AsThread;
+use starry_core::task::{AsThread, send_signal_to_process, send_signal_to_process_group};
 use starry_process::{ProcessGroup, Session};
+use starry_signal::{SignalDisposition, SignalInfo, Signo};
+
+/// Returns whether the current thread ignores or blocks `signo`.
+fn signal_ignored(signo: Signo) -> bool {
+    let curr = current();
+    let thr = curr.as_thread();
+    thr.signal.blocked().has(signo)
+        || matches!(
+            thr.proc_data.signal.actions.lock()[signo].disposition,
+            SignalDisposition::Ignore
+        )
+}
+
+/// Returns whether `pg` is orphaned, that is, no member has a parent in
+/// another process group of the same session to restart it once stopped.
+fn is_orphaned(pg: &Arc<ProcessGroup>) -> bool {
+    let session = pg.session();
+    pg.processes().iter().all(|proc| {
+        proc.parent().is_none_or(|parent| {
+            let group = parent.group();
+            Arc::ptr_eq(&group, pg) || !Arc::ptr_eq(&group.session(), &session)
+        })
+    })
+}
 
 pub struct JobControl {
     foreground: SpinNoIrq<Weak<ProcessGroup>>,
@@ -29,6 +56,51 @@ impl JobControl {
         }
     }
 
+    /// Checks that the current process may use the terminal, which is not
+    /// the case for background processes using their controlling terminal.
+    ///
+    /// Those are stopped by sending `signo`, `SIGTTIN` for reads or `SIGTTOU`
+    /// for writes and changes to the terminal, to their process group, unless
+    /// `sent` says it was sent already, and [`AxError::WouldBlock`] is
+    /// returned to wait until they are in the foreground. If the signal is
+    /// ignored or blocked, writes and changes go ahead and reads fail.
+    /// Orphaned process groups cannot be restarted once stopped, so for them
+    /// both fail instead.
+    fn check_foreground(&self, signo: Signo, sent: &mut bool) -> AxResult<()> {
+        let curr = current();
+        let pg = curr.as_thread().proc_data.proc.group();
+        let (Some(session), Some(foreground)) = (self.session(), self.foreground()) else {
+            return Ok(());
+        };
+        if !Arc::ptr_eq(&pg.session(), &session) || Arc::ptr_eq(&pg, &foreground) {
+            return Ok(());
+        }
+        if signal_ignored(signo) {
+            return if signo == Signo::SIGTTIN {
+                Err(AxError::Io)
+            } else {
+                Ok(())
+            };
+        }
+        if is_orphaned(&pg) {
+            return Err(AxError::Io);
+        }
+        if !mem::replace(sent, true) {
+            send_signal_to_process_group(pg.pgid(), Some(SignalInfo::new_kernel(signo)))?;
+        }
+        Err(AxError::WouldBlock)
+    }
+
+    /// Waits until the current process may use the terminal, as
+    /// [`check_foreground`](Self::check_foreground) decides, sending `signo`
+    /// at most once however often the wait is woken up.
+    pub fn wait_foreground(&self, signo: Signo) -> AxResult<()> {
+        let mut sent = false;
+        block_on(poll_io(self, IoEvents::IN, false, || {
+            self.check_foreground(signo, &mut sent)
+        }))
+    }
+
     pub fn current_in_foreground(&self) -> bool {
         self.foreground
             .lock()
@@ -36,6 +108,11 @@ impl JobControl {
             .is_none_or(|pg| Arc::ptr_eq(&current().as_thread().proc_data.proc.group(), &pg))
     }
 
+    /// Returns the session this is the controlling terminal of.
+    pub fn session(&self) -> Option<Arc<Session>> {
+        self.session.lock().upgrade()
+    }
+
     pub fn foreground(&self) -> Option<Arc<ProcessGroup>> {
         self.foreground.lock().upgrade()
     }
@@ -67,9 +144,27 @@ impl JobControl {
     }
 
     pub fn set_session(&self, session: &Arc<Session>) {
-        let mut guard = self.session.lock();
-        assert!(guard.upgrade().is_none());
-        *guard = Arc::downgrade(session);
+        *self.session.lock() = Arc::downgrade(session);
+    }
+
+    /// Detaches the terminal from its session, as on hangup.
+    ///
+    /// `SIGHUP` and `SIGCONT` are sent to the foreground process group if
+    /// `to_foreground` is set, or else to the session leader.
+    pub fn hang_up(&self, to_foreground: bool) {
+        let session = mem::take(&mut *self.session.lock()).upgrade();
+        let foreground = mem::take(&mut *self.foreground.lock()).upgrade();
+        self.poll_fg.wake();
+
+        for signo in [Signo::SIGHUP, Signo::SIGCONT] {
+            let sig = Some(SignalInfo::new_kernel(signo));
+            // The processes may have exited already.
+            let _ = match (&foreground, &session) {
+                (Some(pg), _) if to_foreground => send_signal_to_process_group(pg.pgid(), sig),
+                (_, Some(session)) if !to_foreground => send_signal_to_process(session.sid(), sig),
+                _ => Ok(()),
+            };
+        }
     }
 }
 
@@ -1,29 +1,40 @@
-use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use alloc::{boxed::Box, collections::VecDeque, sync::Arc, vec::Vec};
 use core::{
     future::poll_fn,
+    mem,
     ops::Range,
     sync::atomic::{AtomicBool, Ordering},
-    task::{Context, Poll, Waker},
+    task::{Poll, Waker},
+    time::Duration,
 };
 
 use axerrno::{AxError, AxResult};
-use axpoll::{IoEvents, PollSet, Pollable};
-use axtask::future::{block_on, poll_io};
+use axhal::time::wall_time;
+use axpoll::PollSet;
+use axtask::future::block_on;
+use kspin::SpinNoIrq;
 use linux_raw_sys::general::{
-    ECHOCTL, ECHOK, ICRNL, IGNCR, ISIG, VEOF, VERASE, VKILL, VMIN, VTIME,
+    ECHO, ECHOCTL, ECHOE, ECHOK, ECHOKE, ECHONL, ICRNL, IGNCR, INLCR, ISIG, ISTRIP, IXANY, IXOFF,
+    IXON, NOFLSH, ONLCR, OPOST, VEOF, VERASE, VKILL, VLNEXT, VMIN, VSTART, VSTOP, VTIME, VWERASE,
 };
 use ringbuf::{
-    CachingCons, CachingProd,
-    traits::{Consumer, Observer, Producer, Split},
+    Cons, HeapRb, Prod,
+    traits::{Consumer, Observer, Producer},
 };
-use starry_core::task::send_signal_to_process_group;
+use starry_core::{hrtimer, task::send_signal_to_process_group};
 use starry_signal::SignalInfo;
 
 use crate::terminal::{Terminal, termios::Termios2};
 
-const BUF_SIZE: usize = 80;
+/// The size of the input buffer, which also bounds the length of a line.
+const BUF_SIZE: usize = 4096;
+/// How much is taken from the device at once.
+const CHUNK_SIZE: usize = 256;
+/// With `IXOFF`, `VSTOP` is sent once less than this is left in the input
+/// buffer, and `VSTART` once less than this is queued.
+const THROTTLE_THRESHOLD: usize = 128;
 
-type ReadBuf = Arc<ringbuf::StaticRb<u8, BUF_SIZE>>;
+type ReadBuf = Arc<HeapRb<u8>>;
 
 /// How should we process inputs?
 pub enum ProcessMode {
@@ -57,7 +68,44 @@ pub trait TtyRead: Send + Sync + 'static {
     fn read(&mut self, buf: &mut [u8]) -> usize;
 }
 pub trait TtyWrite: Send + Sync + 'static {
-    fn write(&self, buf: &[u8]);
+    /// Writes as much of `buf` as there is room for, returning how much that
+    /// was.
+    fn write(&self, buf: &[u8]) -> usize;
+
+    /// Returns whether there is room to write.
+    fn poll_write(&self) -> bool {
+        true
+    }
+
+    /// Registers `waker` to be woken once there is room to write.
+    fn register_tx_waker(&self, _waker: &Waker) {}
+}
+
+/// Where a VEOF ended the input, given by how many bytes had been queued
+/// before it.
+struct EofMark {
+    pos: u64,
+    /// Whether it ends a line, rather than standing for end-of-file on its
+    /// own after being typed at the start of one.
+    line_end: bool,
+}
+
+/// State shared between the input processor and the reading side.
+#[derive(Default)]
+struct Shared {
+    /// Set by `drain_input` to have the processor drop the line being edited.
+    clear_line_buf: AtomicBool,
+    /// The VEOF marks not yet reached by `read`, in the order they were typed.
+    eof_marks: SpinNoIrq<VecDeque<EofMark>>,
+}
+
+/// The VTIME timer of a waiting read.
+#[derive(Clone, Copy)]
+struct ReadTimer {
+    /// When the read gives up waiting, on the [`hrtimer`] clock.
+    deadline: Duration,
+    /// How many bytes were ready when the timer was started.
+    available: usize,
 }
 
 struct InputReader<R, W> {
@@ -66,18 +114,31 @@ struct InputReader<R, W> {
     reader: R,
     writer: W,
 
-    buf_tx: CachingProd<ReadBuf>,
-    read_buf: [u8; BUF_SIZE],
+    buf_tx: Prod<ReadBuf>,
+    /// How many bytes have been queued to be read so far.
+    pushed: u64,
+    read_buf: [u8; CHUNK_SIZE],
     read_range: Range<usize>,
 
     line_buf: Vec<u8>,
     line_read: Option<usize>,
-    clear_line_buf: Arc<AtomicBool>,
+    /// Whether the line being queued was ended by VEOF.
+    line_eof: bool,
+    /// Whether the next character is to be taken literally, after VLNEXT.
+    literal_next: bool,
+    /// Whether `VSTOP` has been sent to hold off input, with `IXOFF`.
+    throttled: bool,
+    /// Echoes not yet written, which are written together once the input at
+    /// hand has been processed.
+    echo_buf: Vec<u8>,
+    shared: Arc<Shared>,
 }
 impl<R: TtyRead, W: TtyWrite> InputReader<R, W> {
     pub fn poll(&mut self) -> bool {
-        if self.clear_line_buf.swap(false, Ordering::Relaxed) {
+        if self.shared.clear_line_buf.swap(false, Ordering::Relaxed) {
             self.line_buf.clear();
+            self.line_read = None;
+            self.line_eof = false;
         }
         if self.read_range.is_empty() {
             let read = self.reader.read(&mut self.read_buf);
@@ -85,6 +146,7 @@ impl<R: TtyRead, W: TtyWrite> InputReader<R, W> {
         }
         let term = self.terminal.load_termios();
         let mut sent = 0;
+        self.update_throttle(&term);
         loop {
             if let Some(offset) = &mut self.line_read {
                 let read = self.buf_tx.push_slice(&self.line_buf[*offset..]);
@@ -92,10 +154,14 @@ impl<R: TtyRead, W: TtyWrite> InputReader<R, W> {
                     break;
                 }
                 sent += read;
+                self.pushed += read as u64;
                 *offset += read;
                 if *offset == self.line_buf.len() {
                     self.line_read = None;
                     self.line_buf.clear();
+                    if mem::take(&mut self.line_eof) {
+                        self.push_eof_mark(true);
+                    }
                 }
                 continue;
             }
@@ -105,98 +171,263 @@ impl<R: TtyRead, W: TtyWrite> InputReader<R, W> {
             let mut ch = self.read_buf[self.read_range.start];
             self.read_range.start += 1;
 
-            if ch == b'\r' {
-                if term.has_iflag(IGNCR) {
+            if term.has_iflag(ISTRIP) {
+                ch &= 0x7f;
+            }
+            if term.has_iflag(IXON) && !self.literal_next {
+                if term.is_special(ch, VSTOP) {
+                    self.terminal.set_output_stopped(true);
+                    continue;
+                }
+                if term.is_special(ch, VSTART) {
+                    self.terminal.set_output_stopped(false);
                     continue;
                 }
-                if term.has_iflag(ICRNL) {
-                    ch = b'\n';
+                if term.has_iflag(IXANY) && self.terminal.output_stopped() {
+                    self.terminal.set_output_stopped(false);
                 }
             }
+            if mem::take(&mut self.literal_next) {
+                if term.echo() {
+                    self.output_char(&term, ch);
+                }
+                self.push_char(&term, ch);
+                sent += usize::from(!term.canonical());
+                continue;
+            }
+            match ch {
+                b'\r' if term.has_iflag(IGNCR) => continue,
+                b'\r' if term.has_iflag(ICRNL) => ch = b'\n',
+                b'\n' if term.has_iflag(INLCR) => ch = b'\r',
+                _ => {}
+            }
 
-            self.check_send_signal(&term, ch);
-
-            if term.echo() {
-                self.output_char(&term, ch);
+            if self.check_send_signal(&term, ch) {
+                continue;
+            }
+            if term.contains_iexten() && term.is_special(ch, VLNEXT) {
+                self.literal_next = true;
+                if term.echo() && term.has_lflag(ECHOCTL) {
+                    self.echo(b"^\x08");
+                }
+                continue;
             }
+
             if !term.canonical() {
-                self.buf_tx.try_push(ch).unwrap();
+                if term.echo() {
+                    self.output_char(&term, ch);
+                }
+                self.push_char(&term, ch);
                 sent += 1;
                 continue;
             }
 
             // Canonical mode
-            if term.has_lflag(ECHOK) && ch == term.special_char(VKILL) {
-                self.line_buf.clear();
+            if term.is_special(ch, VERASE) {
+                self.erase(&term, 1);
                 continue;
             }
-            if ch == term.special_char(VERASE) {
-                self.line_buf.pop();
+            if term.contains_iexten() && term.is_special(ch, VWERASE) {
+                let line = &self.line_buf;
+                let spaces = line.iter().rev().take_while(|it| it.is_ascii_whitespace());
+                let word = line.iter().rev().skip(spaces.clone().count());
+                let count =
+                    spaces.count() + word.take_while(|it| !it.is_ascii_whitespace()).count();
+                self.erase(&term, count);
+                continue;
+            }
+            if term.is_special(ch, VKILL) {
+                if term.echo() && !term.has_lflag(ECHOKE) {
+                    self.output_char(&term, ch);
+                    if term.has_lflag(ECHOK) {
+                        self.echo(b"\n");
+                    }
+                    self.line_buf.clear();
+                } else {
+                    self.erase(&term, self.line_buf.len());
+                }
                 continue;
             }
 
-            if term.is_eol(ch) || ch == term.special_char(VEOF) {
-                if ch != term.special_char(VEOF) {
+            let eof = term.is_special(ch, VEOF);
+            if term.echo() && !eof {
+                self.output_char(&term, ch);
+            } else if ch == b'\n' && term.has_lflag(ECHONL) {
+                self.echo(b"\n");
+            }
+            if term.is_eol(ch) || eof {
+                if !eof {
                     self.line_buf.push(ch);
                 }
                 if !self.line_buf.is_empty() {
                     self.line_read = Some(0);
+                    self.line_eof = eof;
+                } else {
+                    self.push_eof_mark(false);
+                    sent += 1;
                 }
                 continue;
             }
+            self.push_char(&term, ch);
+        }
 
-            if ch.is_ascii_graphic() {
+        self.update_throttle(&term);
+        if !self.echo_buf.is_empty() {
+            // What does not fit is written once there is room.
+            let written = self.writer.write(&self.echo_buf);
+            self.echo_buf.drain(..written);
+        }
+        sent > 0
+    }
+
+    /// Registers `waker` to be woken once echoes left unwritten can go out.
+    fn register_tx_waker(&self, waker: &Waker) {
+        if !self.echo_buf.is_empty() {
+            self.writer.register_tx_waker(waker);
+        }
+    }
+
+    /// Marks the end of the input queued so far as reached by VEOF.
+    fn push_eof_mark(&self, line_end: bool) {
+        self.shared.eof_marks.lock().push_back(EofMark {
+            pos: self.pushed,
+            line_end,
+        });
+    }
+
+    /// Queues a character that needs no further processing.
+    fn push_char(&mut self, term: &Termios2, ch: u8) {
+        if term.canonical() {
+            // Like Linux, leave room for the end of the line.
+            if self.line_buf.len() < BUF_SIZE - 1 {
                 self.line_buf.push(ch);
-                continue;
             }
+        } else {
+            self.buf_tx.try_push(ch).unwrap();
+            self.pushed += 1;
         }
+    }
 
-        sent > 0
+    /// Erases up to `count` characters from the end of the line being edited.
+    fn erase(&mut self, term: &Termios2, count: usize) {
+        for _ in 0..count {
+            let Some(ch) = self.line_buf.pop() else {
+                break;
+            };
+            if term.echo() && term.has_lflag(ECHOE) {
+                // Control characters were echoed as two columns.
+                let echoed_ctl = ch.is_ascii_control() && ch != b'\t' && term.has_lflag(ECHOCTL);
+                for _ in 0..if echoed_ctl { 2 } else { 1 } {
+                    self.echo(b"\x08 \x08");
+                }
+            }
+        }
     }
 
-    fn check_send_signal(&self, term: &Termios2, ch: u8) {
-        if !term.canonical() || !term.has_lflag(ISIG) {
-            return;
+    /// Sends the signal for `ch`, if it is a signal character, returning
+    /// whether it was one.
+    fn check_send_signal(&mut self, term: &Termios2, ch: u8) -> bool {
+        if !term.has_lflag(ISIG) {
+            return false;
         }
-        if let Some(signo) = term.signo_for(ch)
-            && let Some(pg) = self.terminal.job_control.foreground()
-        {
+        let Some(signo) = term.signo_for(ch) else {
+            return false;
+        };
+        if !term.has_lflag(NOFLSH) {
+            self.line_buf.clear();
+            self.line_read = None;
+            self.line_eof = false;
+        }
+        if term.echo() {
+            self.output_char(term, ch);
+        }
+        if let Some(pg) = self.terminal.job_control.foreground() {
             let sig = SignalInfo::new_kernel(signo);
             if let Err(err) = send_signal_to_process_group(pg.pgid(), Some(sig)) {
                 warn!("Failed to send signal: {err:?}");
             }
         }
+        true
     }
 
-    fn output_char(&self, term: &Termios2, ch: u8) {
+    fn output_char(&mut self, term: &Termios2, ch: u8) {
         match ch {
-            b'\n' => self.writer.write(b"\n"),
-            b'\r' => self.writer.write(b"\r\n"),
-            ch if ch == term.special_char(VERASE) => self.writer.write(b"\x08 \x08"),
-            ch if ch == b' ' || ch.is_ascii_graphic() => self.writer.write(&[ch]),
+            b'\n' | b'\t' => self.echo(&[ch]),
             ch if ch.is_ascii_control() && term.has_lflag(ECHOCTL) => {
-                self.writer.write(&[b'^', (ch + 0x40)]);
+                self.echo(&[b'^', ch ^ 0x40]);
+            }
+            ch => self.echo(&[ch]),
+        }
+    }
+
+    fn echo(&mut self, bytes: &[u8]) {
+        // Like Linux, drop echoes rather than queue them without bound while
+        // output is held up.
+        if self.echo_buf.len() + bytes.len() <= BUF_SIZE {
+            self.echo_buf.extend_from_slice(bytes);
+        }
+    }
+
+    /// Sends `VSTOP` when the input buffer is about to fill up, and `VSTART`
+    /// once it has drained, if `IXOFF` is set.
+    fn update_throttle(&mut self, term: &Termios2) {
+        let (index, throttled) = if self.throttled {
+            if self.buf_tx.occupied_len() >= THROTTLE_THRESHOLD {
+                return;
             }
-            other => {
-                warn!("Ignored echo char: {other:#x}");
+            (VSTART, false)
+        } else {
+            if !term.has_iflag(IXOFF) || self.buf_tx.vacant_len() >= THROTTLE_THRESHOLD {
+                return;
             }
+            (VSTOP, true)
+        };
+        self.throttled = throttled;
+        let ch = term.special_char(index);
+        if ch != 0 {
+            self.echo(&[ch]);
         }
     }
 }
 
+/// Moves what the slave of a pseudo-terminal writes to the master, applying
+/// the output processing set in its termios.
 struct SimpleReader<R> {
+    terminal: Arc<Terminal>,
     reader: R,
-    read_buf: [u8; BUF_SIZE],
-    buf_tx: CachingProd<ReadBuf>,
+    read_buf: [u8; CHUNK_SIZE],
+    read_range: Range<usize>,
+    buf_tx: Prod<ReadBuf>,
 }
 impl<R: TtyRead> SimpleReader<R> {
     pub fn poll(&mut self) {
-        let read = self.reader.read(&mut self.read_buf);
-        for ch in &self.read_buf[..read] {
-            if *ch == b'\n' {
-                let _ = self.buf_tx.try_push(b'\r');
+        let term = self.terminal.load_termios();
+        let onlcr = term.has_oflag(OPOST) && term.has_oflag(ONLCR);
+        loop {
+            if self.read_range.is_empty() {
+                let read = self.reader.read(&mut self.read_buf);
+                if read == 0 {
+                    break;
+                }
+                self.read_range = 0..read;
+            }
+            // Move everything up to the next newline at once.
+            let pending = &self.read_buf[self.read_range.clone()];
+            let end = if onlcr {
+                pending.iter().position(|&ch| ch == b'\n')
+            } else {
+                None
+            };
+            let moved = self
+                .buf_tx
+                .push_slice(&pending[..end.unwrap_or(pending.len())]);
+            self.read_range.start += moved;
+            if end.is_some_and(|end| moved == end) && self.buf_tx.vacant_len() >= 2 {
+                self.buf_tx.push_slice(b"\r\n");
+                self.read_range.start += 1;
+            } else if !self.read_range.is_empty() {
+                break;
             }
-            let _ = self.buf_tx.try_push(*ch);
         }
     }
 }
@@ -209,32 +440,25 @@ enum Processor<R, W> {
 
 pub struct LineDiscipline<R, W> {
     terminal: Arc<Terminal>,
-    buf_rx: CachingCons<ReadBuf>,
+    buf_rx: Cons<ReadBuf>,
+    /// How many bytes have been taken from the input buffer so far.
+    popped: u64,
     poll_tx: Arc<PollSet>,
-    clear_line_buf: Arc<AtomicBool>,
+    shared: Arc<Shared>,
+    /// The VTIME timer of the read waiting for input, if any.
+    read_timer: Option<ReadTimer>,
+    /// Whether a read has gone on to wait since the last attempt, as opposed
+    /// to giving up with [`AxError::WouldBlock`].
+    read_waiting: bool,
     processor: Processor<R, W>,
 }
 
-struct WaitPollable<'a>(Option<&'a Arc<PollSet>>);
-impl Pollable for WaitPollable<'_> {
-    fn poll(&self) -> IoEvents {
-        unreachable!()
-    }
-
-    fn register(&self, context: &mut Context<'_>, _events: IoEvents) {
-        if let Some(set) = self.0 {
-            set.register(context.waker());
-        } else {
-            context.waker().wake_by_ref();
-        }
-    }
-}
-
 impl<R: TtyRead, W: TtyWrite> LineDiscipline<R, W> {
     pub fn new(terminal: Arc<Terminal>, config: TtyConfig<R, W>) -> Self {
-        let (buf_tx, buf_rx) = ReadBuf::default().split();
+        let buffer = Arc::new(HeapRb::new(BUF_SIZE));
+        let (buf_tx, buf_rx) = (Prod::new(buffer.clone()), Cons::new(buffer));
 
-        let clear_line_buf = Arc::new(AtomicBool::new(false));
+        let shared = Arc::new(Shared::default());
         let mut reader = InputReader {
             terminal: terminal.clone(),
 
@@ -242,12 +466,17 @@ impl<R: TtyRead, W: TtyWrite> LineDiscipline<R, W> {
             writer: config.writer,
 
             buf_tx,
-            read_buf: [0; BUF_SIZE],
+            pushed: 0,
+            read_buf: [0; CHUNK_SIZE],
             read_range: 0..0,
 
             line_buf: Vec::new(),
             line_read: None,
-            clear_line_buf: clear_line_buf.clone(),
+            line_eof: false,
+            literal_next: false,
+            throttled: false,
+            echo_buf: Vec::new(),
+            shared: shared.clone(),
         };
 
         let poll_tx = Arc::new(PollSet::new());
@@ -261,12 +490,19 @@ impl<R: TtyRead, W: TtyWrite> LineDiscipline<R, W> {
                         let poll_tx = poll_tx.clone();
                         move || {
                             block_on(poll_fn(|cx| {
+                                // Readers are woken once for everything that
+                                // has arrived, not for each chunk of it.
+                                let mut sent = false;
                                 while reader.poll() {
-                                    poll_rx.wake();
+                                    sent = true;
                                 }
                                 poll_tx.register(cx.waker());
+                                reader.register_tx_waker(cx.waker());
                                 register(cx.waker().clone());
                                 while reader.poll() {
+                                    sent = true;
+                                }
+                                if sent {
                                     poll_rx.wake();
                                 }
                                 Poll::Pending
@@ -281,8 +517,10 @@ impl<R: TtyRead, W: TtyWrite> LineDiscipline<R, W> {
                 // Destruct the reader here
                 Processor::None(
                     SimpleReader {
+                        terminal: terminal.clone(),
                         reader: reader.reader,
-                        read_buf: [0; BUF_SIZE],
+                        read_buf: [0; CHUNK_SIZE],
+                        read_range: 0..0,
                         buf_tx: reader.buf_tx,
                     },
                     poll_rx,
@@ -292,18 +530,31 @@ impl<R: TtyRead, W: TtyWrite> LineDiscipline<R, W> {
         Self {
             terminal,
             buf_rx,
+            popped: 0,
             poll_tx,
-            clear_line_buf,
+            shared,
+            read_timer: None,
+            read_waiting: false,
             processor,
         }
     }
 
     pub fn drain_input(&mut self) {
-        self.buf_rx.clear();
-        self.clear_line_buf.store(true, Ordering::Relaxed);
+        self.popped += self.buf_rx.clear() as u64;
+        self.shared.eof_marks.lock().clear();
+        self.shared.clear_line_buf.store(true, Ordering::Relaxed);
+        self.poll_tx.wake();
     }
 
-    pub fn poll_read(&mut self) -> bool {
+    /// Returns the [`PollSet`] woken when input arrives, if there is one.
+    pub fn rx_pollset(&self) -> Option<Arc<PollSet>> {
+        match &self.processor {
+            Processor::Manual(_) => None,
+            Processor::External(set) | Processor::None(_, set) => Some(set.clone()),
+        }
+    }
+
+    fn process(&mut self) {
         match &mut self.processor {
             Processor::Manual(reader) => {
                 reader.poll();
@@ -311,10 +562,40 @@ impl<R: TtyRead, W: TtyWrite> LineDiscipline<R, W> {
             Processor::None(reader, _) => reader.poll(),
             _ => {}
         }
-        !self.buf_rx.is_empty()
     }
 
-    pub fn register_rx_waker(&self, waker: &Waker) {
+    /// Returns the number of bytes ready to be read.
+    pub fn available(&mut self) -> usize {
+        self.process();
+        self.buf_rx.occupied_len()
+    }
+
+    /// Returns how many bytes a read needs before it can return, following
+    /// VMIN and VTIME in non-canonical mode.
+    fn wanted(&self, term: &Termios2, len: usize) -> usize {
+        if term.canonical() || matches!(self.processor, Processor::None(..)) {
+            return 1;
+        }
+        let vmin = term.special_char(VMIN) as usize;
+        if term.special_char(VTIME) > 0 {
+            // With VTIME, a read is satisfied by a single byte once the timer
+            // runs out.
+            return vmin.clamp(1, len.max(1));
+        }
+        vmin.min(len)
+    }
+
+    pub fn poll_read(&mut self) -> bool {
+        let available = self.available();
+        let term = self.terminal.load_termios();
+        if term.canonical() && self.at_eof() {
+            return true;
+        }
+        available > 0 && available >= self.wanted(&term, usize::MAX)
+    }
+
+    pub fn register_rx_waker(&mut self, waker: &Waker) {
+        self.read_waiting = true;
         match &self.processor {
             Processor::Manual(_) => {
                 waker.wake_by_ref();
@@ -325,47 +606,107 @@ impl<R: TtyRead, W: TtyWrite> LineDiscipline<R, W> {
         }
     }
 
+    /// Reads what is ready without waiting, or fails with
+    /// [`AxError::WouldBlock`] if the read has to wait for more input.
     pub fn read(&mut self, buf: &mut [u8]) -> AxResult<usize> {
         if buf.is_empty() {
             return Ok(0);
         }
-        if matches!(self.processor, Processor::None(_, _)) {
-            let read = self.buf_rx.pop_slice(buf);
-            return if read == 0 {
-                Err(AxError::WouldBlock)
-            } else {
-                Ok(read)
+        // A read that did not wait after the last attempt gave up, and its
+        // timer must not carry over to this one.
+        if !mem::take(&mut self.read_waiting) {
+            self.read_timer = None;
+        }
+        let available = self.available();
+        let term = self.terminal.load_termios();
+
+        if term.canonical() && !matches!(self.processor, Processor::None(..)) {
+            if self.at_eof() {
+                self.shared.eof_marks.lock().pop_front();
+                return Ok(0);
+            }
+            if available == 0 {
+                return Err(AxError::WouldBlock);
+            }
+            // A read returns at most one line, which may also be ended by
+            // VEOF.
+            let len = match self.shared.eof_marks.lock().front() {
+                Some(mark) => buf.len().min((mark.pos - self.popped) as usize),
+                None => buf.len(),
             };
+            let mut read = 0;
+            while read < len
+                && let Some(ch) = self.buf_rx.try_pop()
+            {
+                buf[read] = ch;
+                read += 1;
+                if term.is_eol(ch) {
+                    break;
+                }
+            }
+            self.popped += read as u64;
+            self.poll_tx.wake();
+            return Ok(read);
         }
 
-        let term = self.terminal.termios.lock().clone();
-        let vmin = if term.canonical() {
-            1
-        } else {
+        let wanted = self.wanted(&term, buf.len());
+        if available < wanted {
             let vtime = term.special_char(VTIME);
-            if vtime > 0 {
-                todo!();
+            // Without VMIN, the timer runs from the read; otherwise it runs
+            // between bytes, and only once one has arrived.
+            let timed_out = !matches!(self.processor, Processor::None(..))
+                && vtime > 0
+                && (term.special_char(VMIN) == 0 || available > 0)
+                && self.wait_vtime(vtime, available);
+            if !timed_out {
+                return Err(AxError::WouldBlock);
             }
-            term.special_char(VMIN) as usize
-        };
+        }
+        self.read_timer = None;
+        let read = self.buf_rx.pop_slice(buf);
+        self.popped += read as u64;
+        self.poll_tx.wake();
+        Ok(read)
+    }
 
-        if buf.len() < vmin {
-            return Err(AxError::WouldBlock);
+    /// Returns whether an end-of-file mark is the next thing to read, first
+    /// dropping the marks of lines ended by VEOF that have been read.
+    fn at_eof(&mut self) -> bool {
+        let mut marks = self.shared.eof_marks.lock();
+        while let Some(mark) = marks.front()
+            && mark.pos <= self.popped
+        {
+            if mark.pos == self.popped && !mark.line_end {
+                return true;
+            }
+            marks.pop_front();
         }
+        false
+    }
 
-        let mut total_read = 0;
-        let set = match &self.processor {
-            Processor::Manual(_) => None,
-            Processor::External(set) => Some(set),
-            _ => unreachable!(),
+    /// Waits out a VTIME of `vtime` tenths of a second with `available` bytes
+    /// ready, returning whether it has run out. The timer starts over
+    /// whenever another byte arrives.
+    fn wait_vtime(&mut self, vtime: u8, available: usize) -> bool {
+        let now = wall_time();
+        let deadline = match self.read_timer {
+            Some(timer) if timer.available == available => timer.deadline,
+            _ => {
+                let deadline = now + Duration::from_millis(vtime as u64 * 100);
+                if let Some(set) = self.rx_pollset() {
+                    hrtimer::start(deadline, move || set.wake());
+                }
+                self.read_timer = Some(ReadTimer {
+                    deadline,
+                    available,
+                });
+                deadline
+            }
         };
-        let pollable = WaitPollable(set);
-        block_on(poll_io(&pollable, IoEvents::IN, false, || {
-            total_read += self.buf_rx.pop_slice(&mut buf[total_read..]);
-            self.poll_tx.wake();
-            (total_read >= vmin)
-                .then_some(total_read)
-                .ok_or(AxError::WouldBlock)
-        }))
+        if now < deadline {
+            return false;
+        }
+        self.read_timer = None;
+        true
     }
 }
@@ -1,8 +1,9 @@
 //! Terminal module.
 
 use alloc::sync::Arc;
-use core::sync::atomic::AtomicU32;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
 
+use axpoll::PollSet;
 use bytemuck::AnyBitPattern;
 use kspin::SpinNoPreempt;
 
@@ -11,7 +12,7 @@ pub mod ldisc;
 pub mod termios;
 
 #[repr(C)]
-#[derive(Debug, Copy, Clone, AnyBitPattern)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, AnyBitPattern)]
 pub struct WindowSize {
     pub ws_row: u16,
     pub ws_col: u16,
@@ -24,6 +25,21 @@ pub struct Terminal {
     pub window_size: SpinNoPreempt<WindowSize>,
     pub termios: SpinNoPreempt<Arc<termios::Termios2>>,
     pub pty_number: AtomicU32,
+    /// Whether the slave of a pseudo-terminal is locked against opening, as
+    /// set by `TIOCSPTLCK`.
+    pub pty_locked: AtomicBool,
+    /// Whether the master of a pseudo-terminal has been closed.
+    pub master_closed: AtomicBool,
+    /// The number of open files on the slave of a pseudo-terminal.
+    pub slave_opens: AtomicUsize,
+    /// Whether the last open file on the slave of a pseudo-terminal has been
+    /// closed, until it is opened again.
+    pub slave_closed: AtomicBool,
+    /// Whether output is suspended by flow control, after `VSTOP` is typed
+    /// with `IXON` set or by `TCOOFF`.
+    pub output_stopped: AtomicBool,
+    /// Woken when suspended output is restarted.
+    pub output_started: PollSet,
 }
 impl Default for Terminal {
     fn default() -> Self {
@@ -37,6 +53,12 @@ impl Default for Terminal {
             }),
             termios: SpinNoPreempt::new(Arc::new(termios::Termios2::default())),
             pty_number: AtomicU32::new(0),
+            pty_locked: AtomicBool::new(false),
+            master_closed: AtomicBool::new(false),
+            slave_opens: AtomicUsize::new(0),
+            slave_closed: AtomicBool::new(false),
+            output_stopped: AtomicBool::new(false),
+            output_started: PollSet::new(),
         }
     }
 }
@@ -44,4 +66,16 @@ impl Terminal {
     pub fn load_termios(&self) -> Arc<termios::Termios2> {
         self.termios.lock().clone()
     }
+
+    pub fn output_stopped(&self) -> bool {
+        self.output_stopped.load(Ordering::Acquire)
+    }
+
+    /// Suspends or restarts output, as software flow control does.
+    pub fn set_output_stopped(&self, stopped: bool) {
+        self.output_stopped.store(stopped, Ordering::Release);
+        if !stopped {
+            self.output_started.wake();
+        }
+    }
 }
@@ -3,8 +3,8 @@ use core::ops::{Deref, DerefMut};
 use bytemuck::AnyBitPattern;
 use linux_raw_sys::general::{
     B38400, CREAD, CS8, ECHO, ECHOCTL, ECHOE, ECHOK, ECHOKE, ICANON, ICRNL, IEXTEN, ISIG, IXON,
-    ONLCR, OPOST, VDISCARD, VEOF, VEOL, VEOL2, VERASE, VINTR, VKILL, VLNEXT, VQUIT, VREPRINT,
-    VWERASE, speed_t, tcflag_t,
+    ONLCR, OPOST, VDISCARD, VEOF, VEOL, VEOL2, VERASE, VINTR, VKILL, VLNEXT, VMIN, VQUIT, VREPRINT,
+    VSTART, VSTOP, VSUSP, VWERASE, speed_t, tcflag_t,
 };
 use starry_signal::Signo;
 
@@ -39,6 +39,10 @@ impl Default for Termios {
             (VERASE, b'\x7f'),
             (VKILL, ctl(b'U')),
             (VEOF, ctl(b'D')),
+            (VMIN, 1),
+            (VSTART, ctl(b'Q')),
+            (VSTOP, ctl(b'S')),
+            (VSUSP, ctl(b'Z')),
             (VEOL, b'\0'),
             (VREPRINT, ctl(b'R')),
             (VDISCARD, ctl(b'O')),
@@ -58,6 +62,13 @@ impl Termios {
         self.c_cc[index as usize]
     }
 
+    /// Returns whether `ch` is the special character at `index`. A special
+    /// character of zero is disabled and matches nothing.
+    pub fn is_special(&self, ch: u8, index: u32) -> bool {
+        let special = self.special_char(index);
+        special != 0 && ch == special
+    }
+
     pub fn has_iflag(&self, flag: u32) -> bool {
         self.c_iflag & flag != 0
     }
@@ -87,11 +98,11 @@ impl Termios {
     }
 
     pub fn is_eol(&self, ch: u8) -> bool {
-        if ch == b'\n' || ch == self.special_char(VEOL) {
+        if ch == b'\n' || self.is_special(ch, VEOL) {
             return true;
         }
 
-        if self.contains_iexten() && ch == self.special_char(VEOL2) {
+        if self.contains_iexten() && self.is_special(ch, VEOL2) {
             return true;
         }
 
@@ -100,8 +111,9 @@ impl Termios {
 
     pub fn signo_for(&self, ch: u8) -> Option<Signo> {
         Some(match ch {
-            ch if ch == self.special_char(VINTR) => Signo::SIGINT,
-            ch if ch == self.special_char(VQUIT) => Signo::SIGQUIT,
+            ch if self.is_special(ch, VINTR) => Signo::SIGINT,
+            ch if self.is_special(ch, VQUIT) => Signo::SIGQUIT,
+            ch if self.is_special(ch, VSUSP) => Signo::SIGTSTP,
             _ => return None,
         })
     }
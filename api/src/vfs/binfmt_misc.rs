@@ -0,0 +1,118 @@
+//! The binfmt_misc control directory, at /proc/sys/fs/binfmt_misc.
+
+use alloc::{borrow::Cow, boxed::Box, sync::Arc, vec::Vec};
+
+use axfs_ng_vfs::{VfsError, VfsResult};
+use starry_core::{
+    binfmt,
+    vfs::{
+        DirMaker, NodeOpsMux, RwFile, SimpleDir, SimpleDirOps, SimpleFile, SimpleFileOperation,
+        SimpleFs,
+    },
+};
+
+/// Parses a write to `status` or to an entry: `1` enables, `0` disables and
+/// `-1` removes.
+///
+/// A short write reaches us merged with the rest of the old contents, so
+/// only the start of `data` is looked at.
+fn parse_command(data: &[u8]) -> VfsResult<i32> {
+    if data.starts_with(b"-1") {
+        Ok(-1)
+    } else if data.starts_with(b"1") {
+        Ok(1)
+    } else if data.starts_with(b"0") {
+        Ok(0)
+    } else {
+        Err(VfsError::InvalidInput)
+    }
+}
+
+struct BinfmtMiscDir {
+    fs: Arc<SimpleFs>,
+}
+
+impl SimpleDirOps for BinfmtMiscDir {
+    fn child_names<'a>(&'a self) -> Box<dyn Iterator<Item = Cow<'a, str>> + 'a> {
+        Box::new(
+            ["register", "status"].into_iter().map(Cow::Borrowed).chain(
+                binfmt::entries()
+                    .into_iter()
+                    .map(|it| it.name.clone().into()),
+            ),
+        )
+    }
+
+    fn lookup_child(&self, name: &str) -> VfsResult<NodeOpsMux> {
+        let fs = self.fs.clone();
+        Ok(match name {
+            "register" => SimpleFile::new_regular(
+                fs,
+                RwFile::new(|req| match req {
+                    // Nothing to read; this also keeps writes from being
+                    // merged with old contents.
+                    SimpleFileOperation::Read => Ok(Some(Vec::new())),
+                    SimpleFileOperation::Write(data) => {
+                        if !data.is_empty() {
+                            let line = str::from_utf8(data).map_err(|_| VfsError::InvalidInput)?;
+                            binfmt::register(line)?;
+                        }
+                        Ok(None)
+                    }
+                }),
+            )
+            .into(),
+            "status" => SimpleFile::new_regular(
+                fs,
+                RwFile::new(|req| match req {
+                    SimpleFileOperation::Read => Ok(Some(
+                        if binfmt::is_enabled() {
+                            "enabled\n"
+                        } else {
+                            "disabled\n"
+                        }
+                        .into(),
+                    )),
+                    SimpleFileOperation::Write(data) => {
+                        if !data.is_empty() {
+                            match parse_command(data)? {
+                                -1 => binfmt::clear(),
+                                value => binfmt::set_enabled(value == 1),
+                            }
+                        }
+                        Ok(None)
+                    }
+                }),
+            )
+            .into(),
+            _ => {
+                let entry = binfmt::get(name).ok_or(VfsError::NotFound)?;
+                SimpleFile::new_regular(
+                    fs,
+                    RwFile::new(move |req| match req {
+                        SimpleFileOperation::Read => Ok(Some(entry.describe().into_bytes())),
+                        SimpleFileOperation::Write(data) => {
+                            if !data.is_empty() {
+                                match parse_command(data)? {
+                                    -1 => binfmt::unregister(&entry.name),
+                                    value => entry.set_enabled(value == 1),
+                                }
+                            }
+                            Ok(None)
+                        }
+                    }),
+                )
+                .into()
+            }
+        })
+    }
+
+    fn is_cacheable(&self) -> bool {
+        false
+    }
+}
+
+/// Creates the /proc/sys/fs/binfmt_misc directory.
+pub fn binfmt_misc_dir(fs: Arc<SimpleFs>) -> DirMaker {
+    SimpleDir::new_maker(fs.clone(), Arc::new(BinfmtMiscDir { fs }))
+}
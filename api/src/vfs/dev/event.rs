@@ -7,24 +7,36 @@ use axdriver::prelude::{
 };
 use axerrno::{AxError, AxResult};
 use axfs_ng_vfs::{DeviceId, NodeFlags, NodeType, VfsResult};
-use axhal::time::wall_time;
+use axhal::time::{TimeValue, monotonic_time};
 use axpoll::{IoEvents, Pollable};
 use axsync::Mutex;
 use bitmaps::Bitmap;
 use linux_raw_sys::{
-    general::{__kernel_old_time_t, __kernel_suseconds_t},
+    general::{
+        __kernel_old_time_t, __kernel_suseconds_t, CLOCK_BOOTTIME, CLOCK_MONOTONIC, CLOCK_REALTIME,
+    },
     ioctl::{EVIOCGID, EVIOCGRAB, EVIOCGVERSION},
 };
-use starry_core::vfs::{Device, DeviceOps, DirMapping, SimpleFs};
+use starry_core::{
+    hrtimer, timekeeping,
+    vfs::{Device, DeviceOps, DirMapping, SimpleFs},
+};
 use zerocopy::{FromBytes, Immutable, IntoBytes};
 
 use crate::mm::UserPtr;
 const KEY_CNT: usize = EventType::Key.bits_count();
 
+/// How often a device is checked for events while someone waits on it, as
+/// the drivers have no interrupt to wake them.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
 struct Inner {
     device: AxInputDevice,
-    read_ahead: Option<(Duration, Event)>,
+    /// The next event, along with the monotonic time it was read.
+    read_ahead: Option<(TimeValue, Event)>,
     key_state: Bitmap<KEY_CNT>,
+    /// The clock that event times are given in, as set by `EVIOCSCLOCKID`.
+    clock: u32,
 }
 impl Inner {
     fn has_event(&mut self) -> bool {
@@ -38,7 +50,7 @@ impl Inner {
                             self.key_state.set(event.code as usize, true);
                         }
                     }
-                    self.read_ahead = Some((wall_time(), event));
+                    self.read_ahead = Some((monotonic_time(), event));
                 }
                 Err(DevError::Again) => {}
                 Err(err) => {
@@ -87,6 +99,7 @@ impl EventDev {
                 device,
                 read_ahead: None,
                 key_state: Bitmap::new(),
+                clock: CLOCK_REALTIME,
             }),
             ev_bits,
         }
@@ -112,6 +125,34 @@ impl EventDev {
     }
 }
 
+impl EventDev {
+    fn get_abs_info(&self, arg: usize, size: usize, axis: u8) -> AxResult<usize> {
+        let mut bits = [0u8; EventType::Absolute.bits_count().div_ceil(8)];
+        let supported = self
+            .inner
+            .lock()
+            .device
+            .get_event_bits(EventType::Absolute, &mut bits)
+            .unwrap_or(false);
+        if !supported || bits[axis as usize / 8] & (1 << (axis % 8)) == 0 {
+            return Err(AxError::InvalidInput);
+        }
+        // The drivers don't report axis ranges; QEMU's virtio tablets scale
+        // every axis to this one.
+        let info = InputAbsInfo {
+            value: 0,
+            minimum: 0,
+            maximum: 0x7fff,
+            fuzz: 0,
+            flat: 0,
+            resolution: 0,
+        };
+        let out = UserPtr::<u8>::from(arg).get_as_mut_slice(size)?;
+        copy_bytes(info.as_bytes(), out);
+        Ok(0)
+    }
+}
+
 fn copy_bytes(src: &[u8], dst: &mut [u8]) -> usize {
     let len = src.len().min(dst.len());
     dst[..len].copy_from_slice(&src[..len]);
@@ -136,6 +177,17 @@ pub struct KernelTimeval {
     pub tv_usec: __kernel_suseconds_t,
 }
 
+#[repr(C)]
+#[derive(FromBytes, IntoBytes, Immutable)]
+struct InputAbsInfo {
+    value: i32,
+    minimum: i32,
+    maximum: i32,
+    fuzz: i32,
+    flat: i32,
+    resolution: i32,
+}
+
 #[repr(C)]
 #[derive(FromBytes, IntoBytes, Immutable)]
 struct InputEvent {
@@ -168,6 +220,10 @@ impl DeviceOps for EventDev {
             let Some((time, event)) = inner.read_ahead.take() else {
                 break;
             };
+            let time = match inner.clock {
+                CLOCK_REALTIME => timekeeping::realtime_at(time),
+                _ => time,
+            };
             let input_event = InputEvent {
                 time: KernelTimeval {
                     tv_sec: time.as_secs() as _,
@@ -233,7 +289,18 @@ impl DeviceOps for EventDev {
 
                 match dir {
                     // IOC_WRITE
-                    1 => return Err(AxError::InvalidInput),
+                    1 => {
+                        // EVIOCSCLOCKID
+                        if nr == 0xa0 {
+                            let clock = *UserPtr::<i32>::from(arg).get_as_mut()? as u32;
+                            if !matches!(clock, CLOCK_REALTIME | CLOCK_MONOTONIC | CLOCK_BOOTTIME) {
+                                return Err(AxError::InvalidInput);
+                            }
+                            self.inner.lock().clock = clock;
+                            return Ok(0);
+                        }
+                        return Err(AxError::InvalidInput);
+                    }
                     // IOC_READ
                     2 => {
                         #[allow(clippy::single_match)]
@@ -291,8 +358,8 @@ impl DeviceOps for EventDev {
                         }
                         const ABS_CNT: u8 = 0x40;
                         if nr & !(ABS_CNT - 1) == ABS_CNT {
-                            // TODO: abs info
-                            return Ok(0);
+                            // EVIOCGABS
+                            return self.get_abs_info(arg, size, nr & (ABS_CNT - 1));
                         }
                         return Err(AxError::InvalidInput);
                     }
@@ -314,34 +381,24 @@ impl Pollable for EventDev {
 
     fn register(&self, context: &mut Context<'_>, events: IoEvents) {
         if events.contains(IoEvents::IN) {
-            context.waker().wake_by_ref();
+            let waker = context.waker().clone();
+            hrtimer::start_after(POLL_INTERVAL, move || waker.wake());
         }
     }
 }
 
 pub fn input_devices(fs: Arc<SimpleFs>) -> DirMapping {
     let mut inputs = DirMapping::new();
-    let mut input_id = 0;
-    let input_devices = axinput::take_inputs();
-    let mut keys = [0; 0x300usize.div_ceil(8)];
-    for (i, mut device) in input_devices.into_iter().enumerate() {
-        assert!(device.get_event_bits(EventType::Key, &mut keys).unwrap());
-
+    // Every device speaks evdev, mice included; the Linux /dev/input/mice
+    // is a different protocol altogether.
+    for (i, device) in axinput::take_inputs().into_iter().enumerate() {
         let dev = Device::new(
             fs.clone(),
             NodeType::CharacterDevice,
-            DeviceId::new(13, (i + 1) as _),
+            DeviceId::new(13, (64 + i) as _),
             Arc::new(EventDev::new(device)),
         );
-
-        const BTN_MOUSE: usize = 0x110;
-        if keys[BTN_MOUSE / 8] & (1 << (BTN_MOUSE % 8)) != 0 {
-            // Mouse
-            inputs.add("mice", dev);
-        } else {
-            inputs.add(format!("event{input_id}"), dev);
-            input_id += 1;
-        }
+        inputs.add(format!("event{i}"), dev);
     }
     inputs
 }
@@ -1,18 +1,28 @@
-use core::{any::Any, slice};
+use core::{
+    any::Any,
+    slice,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
 
 #[allow(unused_imports)]
 use axdriver::prelude::DisplayDriverOps;
 use axerrno::AxError;
 use axfs_ng_vfs::{NodeFlags, VfsError, VfsResult};
 use axhal::mem::virt_to_phys;
+use bytemuck::AnyBitPattern;
 use memory_addr::{PhysAddrRange, VirtAddr};
-use starry_core::vfs::{DeviceMmap, DeviceOps};
-use starry_vm::VmMutPtr;
+use starry_core::{
+    task::processes,
+    vfs::{Device, DeviceMmap, DeviceOps},
+    workqueue::queue_delayed_work,
+};
+use starry_vm::{VmMutPtr, VmPtr};
 
 // Types from https://github.com/Tangzh33/asterinas
 
 #[repr(C)]
-#[derive(Default, Debug, Clone, Copy)]
+#[derive(Default, Debug, Clone, Copy, AnyBitPattern)]
 pub struct FrameBufferBitfield {
     /// The beginning of bitfield.
     offset: u32,
@@ -23,7 +33,7 @@ pub struct FrameBufferBitfield {
 }
 
 #[repr(C)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, AnyBitPattern)]
 struct VarScreenInfo {
     pub xres: u32, // Visible resolution
     pub yres: u32,
@@ -77,26 +87,111 @@ struct FixScreenInfo {
     pub reserved: [u16; 2], // Reserved for future compatibility
 }
 
-async fn refresh_task() {
-    let delay = core::time::Duration::from_secs_f32(1. / 60.);
-    loop {
-        if !axdisplay::framebuffer_flush() {
-            warn!("Failed to refresh framebuffer");
-        }
-        axtask::future::sleep(delay).await;
+/// Returns the mode of the display, which is the only one there is.
+fn var_screen_info() -> VarScreenInfo {
+    let info = axdisplay::framebuffer_info();
+    let line_length = (info.fb_size / info.height as usize) as u32;
+    let bpp = line_length / info.width;
+    VarScreenInfo {
+        xres: info.width,
+        yres: info.height,
+        xres_virtual: info.width,
+        yres_virtual: info.height,
+        xoffset: 0,
+        yoffset: 0,
+        bits_per_pixel: bpp * 8,
+        grayscale: 0,
+        red: FrameBufferBitfield {
+            offset: 16,
+            length: 8,
+            msb_right: 0,
+        },
+        green: FrameBufferBitfield {
+            offset: 8,
+            length: 8,
+            msb_right: 0,
+        },
+        blue: FrameBufferBitfield {
+            offset: 0,
+            length: 8,
+            msb_right: 0,
+        },
+        transp: FrameBufferBitfield {
+            offset: 24,
+            length: 8,
+            msb_right: 0,
+        },
+        nonstd: 0,
+        activate: 0,
+        height: 0,
+        width: 0,
+        accel_flags: 0,
+        pixclock: 10000000 / info.width * 1000 / info.height,
+        left_margin: (info.width / 8) & 0xf8,
+        right_margin: 32,
+        upper_margin: 16,
+        lower_margin: 4,
+        hsync_len: (info.width / 8) & 0xf8,
+        vsync_len: 4,
+        sync: 0,
+        vmode: 0,
+        rotate: 0,
+        colorspace: 0,
+        reserved: [0; 4],
+    }
+}
+
+/// Whether the framebuffer was written since the last refresh.
+static DIRTY: AtomicBool = AtomicBool::new(false);
+/// Whether a refresh is queued.
+static REFRESH_QUEUED: AtomicBool = AtomicBool::new(false);
+
+/// Queues a refresh for the next frame, unless one is queued already.
+fn queue_refresh() {
+    if !REFRESH_QUEUED.swap(true, Ordering::AcqRel) {
+        queue_delayed_work(Duration::from_secs_f32(1. / 60.), refresh);
+    }
+}
+
+/// Returns whether a process has the framebuffer mapped.
+fn is_mapped() -> bool {
+    processes().iter().any(|proc_data| {
+        proc_data.mapped_files.lock().files().any(|loc| {
+            loc.entry()
+                .downcast::<Device>()
+                .is_ok_and(|device| device.inner().as_any().is::<FrameBuffer>())
+        })
+    })
+}
+
+/// Flushes the framebuffer to the display if it was written or may have
+/// been through a mapping, and keeps doing so every frame while it is
+/// mapped.
+fn refresh() {
+    REFRESH_QUEUED.store(false, Ordering::Release);
+    let dirty = DIRTY.swap(false, Ordering::AcqRel);
+    let mapped = is_mapped();
+    if (dirty || mapped) && !axdisplay::framebuffer_flush() {
+        warn!("Failed to refresh framebuffer");
+    }
+    if mapped {
+        queue_refresh();
     }
 }
 
+/// Marks the framebuffer written, to be flushed on the next frame.
+fn mark_dirty() {
+    DIRTY.store(true, Ordering::Release);
+    queue_refresh();
+}
+
 pub struct FrameBuffer {
     base: VirtAddr,
     size: usize,
 }
 impl FrameBuffer {
     pub fn new() -> Self {
-        axtask::spawn_with_name(
-            || axtask::future::block_on(refresh_task()),
-            "fb-refresh".into(),
-        );
+        mark_dirty();
         let info = axdisplay::framebuffer_info();
         Self {
             base: VirtAddr::from(info.fb_base_vaddr),
@@ -112,10 +207,11 @@ impl FrameBuffer {
 impl DeviceOps for FrameBuffer {
     fn read_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<usize> {
         let slice = self.as_mut_slice();
-        let len = buf
-            .len()
-            .min((slice.len() as u64).saturating_sub(offset) as usize);
-        buf[..len].copy_from_slice(&slice[..len]);
+        let Some(src) = usize::try_from(offset).ok().and_then(|it| slice.get(it..)) else {
+            return Ok(0);
+        };
+        let len = buf.len().min(src.len());
+        buf[..len].copy_from_slice(&src[..len]);
         Ok(len)
     }
 
@@ -124,8 +220,10 @@ impl DeviceOps for FrameBuffer {
         if offset >= slice.len() as u64 {
             return Err(VfsError::StorageFull);
         }
-        let len = buf.len().min(slice.len() - offset as usize);
-        slice[..len].copy_from_slice(&buf[..len]);
+        let dst = &mut slice[offset as usize..];
+        let len = buf.len().min(dst.len());
+        dst[..len].copy_from_slice(&buf[..len]);
+        mark_dirty();
         Ok(len)
     }
 
@@ -133,66 +231,22 @@ impl DeviceOps for FrameBuffer {
         match cmd {
             // FBIOGET_VSCREENINFO
             0x4600 => {
-                let info = axdisplay::framebuffer_info();
-                let line_length = (info.fb_size / info.height as usize) as u32;
-                let bpp = line_length / info.width;
-                (arg as *mut VarScreenInfo).vm_write(VarScreenInfo {
-                    xres: info.width,
-                    yres: info.height,
-                    xres_virtual: info.width,
-                    yres_virtual: info.height,
-                    xoffset: 0,
-                    yoffset: 0,
-                    bits_per_pixel: bpp * 8,
-                    grayscale: 0,
-                    red: FrameBufferBitfield {
-                        offset: 16,
-                        length: 8,
-                        msb_right: 0,
-                    },
-                    green: FrameBufferBitfield {
-                        offset: 8,
-                        length: 8,
-                        msb_right: 0,
-                    },
-                    blue: FrameBufferBitfield {
-                        offset: 0,
-                        length: 8,
-                        msb_right: 0,
-                    },
-                    transp: FrameBufferBitfield {
-                        offset: 24,
-                        length: 8,
-                        msb_right: 0,
-                    },
-                    nonstd: 0,
-                    activate: 0,
-                    height: 0,
-                    width: 0,
-                    accel_flags: 0,
-                    pixclock: 10000000 / info.width * 1000 / info.height,
-                    left_margin: (info.width / 8) & 0xf8,
-                    right_margin: 32,
-                    upper_margin: 16,
-                    lower_margin: 4,
-                    hsync_len: (info.width / 8) & 0xf8,
-                    vsync_len: 4,
-                    sync: 0,
-                    vmode: 0,
-                    rotate: 0,
-                    colorspace: 0,
-                    reserved: [0; 4],
-                })?;
+                (arg as *mut VarScreenInfo).vm_write(var_screen_info())?;
                 Ok(0)
             }
             // FBIOPUT_VSCREENINFO
-            0x4601 => Ok(0),
+            0x4601 => {
+                // Like Linux drivers that cannot change modes, ignore the
+                // request and report the mode in use.
+                (arg as *mut VarScreenInfo).vm_write(var_screen_info())?;
+                Ok(0)
+            }
             // FBIOGET_FSCREENINFO
             0x4602 => {
                 let info = axdisplay::framebuffer_info();
                 (arg as *mut FixScreenInfo).vm_write(FixScreenInfo {
                     id: *b"Virtio Framebuf\0",
-                    smem_start: info.fb_base_vaddr as u64,
+                    smem_start: virt_to_phys(self.base).as_usize() as u64,
                     smem_len: info.fb_size as u32,
                     type_: 0,
                     type_aux: 0,
@@ -214,7 +268,15 @@ impl DeviceOps for FrameBuffer {
             // FBIOPUTCMAP
             0x4605 => Ok(0),
             // FBIOPAN_DISPLAY
-            0x4606 => Err(AxError::InvalidInput),
+            0x4606 => {
+                // The virtual resolution is the visible one, so panning can
+                // only stay put.
+                let var = (arg as *const VarScreenInfo).vm_read()?;
+                if var.xoffset != 0 || var.yoffset != 0 {
+                    return Err(AxError::InvalidInput);
+                }
+                Ok(0)
+            }
             // FBIOBLANK
             0x4611 => Err(AxError::InvalidInput),
             _ => Err(AxError::NotATty),
@@ -226,6 +288,9 @@ impl DeviceOps for FrameBuffer {
     }
 
     fn mmap(&self) -> DeviceMmap {
+        // Writes through the mapping go unnoticed, so refresh every frame
+        // until it is gone.
+        queue_refresh();
         DeviceMmap::Physical(PhysAddrRange::from_start_size(
             virt_to_phys(self.base),
             self.size,
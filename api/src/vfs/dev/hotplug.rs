@@ -0,0 +1,347 @@
+//! Devices that appear after boot.
+//!
+//! Bus code reports new devices with [`add_device`], and drivers register
+//! themselves with [`register_driver`]. A device is offered to every driver
+//! until one binds to it, which creates its node under /dev and emits an `add`
+//! uevent. Devices that no driver accepts yet, or whose driver asks to defer
+//! the probe, are probed again whenever another driver is registered. When a
+//! driver is unregistered, its devices lose their nodes and wait for another
+//! driver.
+
+use alloc::{
+    borrow::Cow,
+    boxed::Box,
+    collections::{btree_map::BTreeMap, vec_deque::VecDeque},
+    format,
+    string::String,
+    sync::Arc,
+    vec::Vec,
+};
+
+use axerrno::{AxError, AxResult};
+use axfs_ng_vfs::{DeviceId, NodeType, VfsError, VfsResult};
+use event_listener::Event;
+use spin::{Mutex, Once};
+use starry_core::{
+    vfs::{Device, DeviceOps, NodeOpsMux, SimpleDirOps, SimpleFs},
+    workqueue::queue_work,
+};
+
+/// Number of recent uevents kept for listeners that fall behind.
+const UEVENT_BACKLOG: usize = 64;
+
+/// A device reported by a bus, waiting to be bound to a driver.
+#[derive(Debug, Clone)]
+pub struct HotplugDevice {
+    /// The bus the device sits on, e.g. `pci` or `virtio`.
+    pub bus: String,
+    /// The name of the device on its bus, e.g. `0000:00:04.0`.
+    pub id: String,
+}
+
+impl HotplugDevice {
+    fn devpath(&self) -> String {
+        format!("/devices/{}/{}", self.bus, self.id)
+    }
+}
+
+/// The device node created for a device bound to a driver.
+pub struct DeviceNode {
+    /// The name of the node under /dev.
+    pub name: String,
+    /// The subsystem reported in uevents, e.g. `block` or `input`.
+    pub subsystem: &'static str,
+    /// Either [`NodeType::CharacterDevice`] or [`NodeType::BlockDevice`].
+    pub node_type: NodeType,
+    /// The device number of the node.
+    pub device_id: DeviceId,
+    /// The operations of the node.
+    pub ops: Arc<dyn DeviceOps>,
+}
+
+/// A driver for devices that may appear after boot.
+pub trait DeviceDriver: Send + Sync {
+    /// Returns the name of the driver.
+    fn name(&self) -> &str;
+
+    /// Tries to bind to `dev`.
+    ///
+    /// Returns [`AxError::NoSuchDevice`] if the device is not handled by this
+    /// driver, or [`AxError::WouldBlock`] if the driver depends on something
+    /// that is not available yet, in which case the probe is retried later.
+    fn probe(&self, dev: &HotplugDevice) -> AxResult<DeviceNode>;
+}
+
+struct BoundDevice {
+    dev: HotplugDevice,
+    driver: Arc<dyn DeviceDriver>,
+    subsystem: &'static str,
+    device_id: DeviceId,
+    node: Arc<Device>,
+}
+
+struct DeviceManager {
+    drivers: Vec<Arc<dyn DeviceDriver>>,
+    /// Devices waiting for a driver.
+    pending: Vec<HotplugDevice>,
+    /// Bound devices, keyed by their node name.
+    bound: BTreeMap<String, BoundDevice>,
+}
+
+static MANAGER: Mutex<DeviceManager> = Mutex::new(DeviceManager {
+    drivers: Vec::new(),
+    pending: Vec::new(),
+    bound: BTreeMap::new(),
+});
+
+/// The devfs instance that hotplugged nodes are created in.
+static DEVFS: Once<Arc<SimpleFs>> = Once::new();
+
+/// The kind of a uevent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UEventAction {
+    /// A device was added.
+    Add,
+    /// A device was removed.
+    Remove,
+}
+
+impl UEventAction {
+    fn as_str(self) -> &'static str {
+        match self {
+            UEventAction::Add => "add",
+            UEventAction::Remove => "remove",
+        }
+    }
+}
+
+/// A notification about a device being added or removed, in the format of
+/// `NETLINK_KOBJECT_UEVENT` messages.
+#[derive(Debug)]
+pub struct UEvent {
+    /// The sequence number of the event.
+    pub seqnum: u64,
+    /// The kind of the event.
+    pub action: UEventAction,
+    /// The sysfs-style path of the device.
+    pub devpath: String,
+    /// The subsystem of the device.
+    pub subsystem: &'static str,
+    /// The name of the device node under /dev.
+    pub devname: String,
+    /// The device number of the node.
+    pub device_id: DeviceId,
+}
+
+impl UEvent {
+    /// Encodes the event as a uevent message: an `action@devpath` header
+    /// followed by `KEY=value` pairs, each terminated by a NUL.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let action = self.action.as_str();
+        let fields = [
+            format!("{action}@{}", self.devpath),
+            format!("ACTION={action}"),
+            format!("DEVPATH={}", self.devpath),
+            format!("SUBSYSTEM={}", self.subsystem),
+            format!("DEVNAME={}", self.devname),
+            format!("MAJOR={}", self.device_id.major()),
+            format!("MINOR={}", self.device_id.minor()),
+            format!("SEQNUM={}", self.seqnum),
+        ];
+        let mut buf = Vec::new();
+        for field in fields {
+            buf.extend_from_slice(field.as_bytes());
+            buf.push(0);
+        }
+        buf
+    }
+}
+
+struct UEventLog {
+    next_seqnum: u64,
+    recent: VecDeque<Arc<UEvent>>,
+}
+
+static UEVENT_LOG: Mutex<UEventLog> = Mutex::new(UEventLog {
+    next_seqnum: 1,
+    recent: VecDeque::new(),
+});
+
+/// Notified whenever a uevent is emitted.
+pub static UEVENT_EVENT: Event = Event::new();
+
+fn emit_uevent(action: UEventAction, devname: &str, bound: &BoundDevice) {
+    let mut log = UEVENT_LOG.lock();
+    let event = Arc::new(UEvent {
+        seqnum: log.next_seqnum,
+        action,
+        devpath: bound.dev.devpath(),
+        subsystem: bound.subsystem,
+        devname: devname.into(),
+        device_id: bound.device_id,
+    });
+    debug!("uevent: {event:?}");
+    log.next_seqnum += 1;
+    if log.recent.len() == UEVENT_BACKLOG {
+        log.recent.pop_front();
+    }
+    log.recent.push_back(event);
+    drop(log);
+    UEVENT_EVENT.notify(usize::MAX);
+}
+
+/// Returns the uevents with a sequence number of at least `seqnum` that are
+/// still kept, oldest first.
+pub fn uevents_since(seqnum: u64) -> Vec<Arc<UEvent>> {
+    UEVENT_LOG
+        .lock()
+        .recent
+        .iter()
+        .filter(|it| it.seqnum >= seqnum)
+        .cloned()
+        .collect()
+}
+
+/// Returns the sequence number the next uevent will get.
+pub fn uevent_seqnum() -> u64 {
+    UEVENT_LOG.lock().next_seqnum
+}
+
+/// Offers `dev` to the registered drivers, and binds it to the first one that
+/// accepts it. Returns the device back if none did.
+fn probe(dev: HotplugDevice) -> Option<HotplugDevice> {
+    let Some(fs) = DEVFS.get() else {
+        return Some(dev);
+    };
+    let drivers = MANAGER.lock().drivers.clone();
+    for driver in drivers {
+        let node = match driver.probe(&dev) {
+            Ok(node) => node,
+            Err(AxError::NoSuchDevice) => continue,
+            Err(AxError::WouldBlock) => {
+                debug!("{}: probe of {} deferred", driver.name(), dev.devpath());
+                return Some(dev);
+            }
+            Err(err) => {
+                warn!("{}: probe of {} failed: {err:?}", driver.name(), dev.devpath());
+                continue;
+            }
+        };
+
+        let mut manager = MANAGER.lock();
+        if manager.bound.contains_key(&node.name) {
+            warn!("{}: /dev/{} already exists", driver.name(), node.name);
+            return None;
+        }
+        info!("{}: bound {} to /dev/{}", driver.name(), dev.devpath(), node.name);
+        let bound = BoundDevice {
+            dev,
+            driver,
+            subsystem: node.subsystem,
+            device_id: node.device_id,
+            node: Device::new(fs.clone(), node.node_type, node.device_id, node.ops),
+        };
+        emit_uevent(UEventAction::Add, &node.name, &bound);
+        manager.bound.insert(node.name, bound);
+        return None;
+    }
+    Some(dev)
+}
+
+/// Probes the devices that are still waiting for a driver.
+fn probe_pending() {
+    let pending = core::mem::take(&mut MANAGER.lock().pending);
+    let still_pending = pending.into_iter().filter_map(probe).collect::<Vec<_>>();
+    MANAGER.lock().pending.extend(still_pending);
+}
+
+/// Reports a device that appeared after boot.
+pub fn add_device(dev: HotplugDevice) {
+    if let Some(dev) = probe(dev) {
+        MANAGER.lock().pending.push(dev);
+    }
+}
+
+/// Reports that a device has been removed, removing its node under /dev.
+pub fn remove_device(bus: &str, id: &str) {
+    let mut manager = MANAGER.lock();
+    manager.pending.retain(|it| it.bus != bus || it.id != id);
+    let Some(name) = manager
+        .bound
+        .iter()
+        .find(|(_, it)| it.dev.bus == bus && it.dev.id == id)
+        .map(|(name, _)| name.clone())
+    else {
+        return;
+    };
+    let bound = manager.bound.remove(&name).unwrap();
+    drop(manager);
+    info!("{}: removed /dev/{name}", bound.driver.name());
+    emit_uevent(UEventAction::Remove, &name, &bound);
+}
+
+/// Registers a driver, and probes the devices waiting for one in the
+/// background.
+pub fn register_driver(driver: Arc<dyn DeviceDriver>) {
+    MANAGER.lock().drivers.push(driver);
+    queue_work(probe_pending);
+}
+
+/// Unregisters the driver named `name`, removing the nodes of the devices
+/// bound to it. The devices are offered to the remaining drivers in the
+/// background.
+pub fn unregister_driver(name: &str) -> AxResult<()> {
+    let mut manager = MANAGER.lock();
+    let index = manager
+        .drivers
+        .iter()
+        .position(|it| it.name() == name)
+        .ok_or(AxError::NotFound)?;
+    manager.drivers.remove(index);
+    let unbound = manager
+        .bound
+        .extract_if(.., |_, it| it.driver.name() == name)
+        .collect::<Vec<_>>();
+    for (devname, bound) in unbound {
+        info!("{name}: unbound /dev/{devname}");
+        emit_uevent(UEventAction::Remove, &devname, &bound);
+        manager.pending.push(bound.dev);
+    }
+    drop(manager);
+    queue_work(probe_pending);
+    Ok(())
+}
+
+/// The part of /dev that holds the nodes of hotplugged devices.
+pub(crate) struct HotplugDir;
+
+impl HotplugDir {
+    pub(crate) fn new(fs: Arc<SimpleFs>) -> Self {
+        DEVFS.call_once(|| fs);
+        // Devices may have been reported before devfs was mounted.
+        queue_work(probe_pending);
+        Self
+    }
+}
+
+impl SimpleDirOps for HotplugDir {
+    fn child_names<'a>(&'a self) -> Box<dyn Iterator<Item = Cow<'a, str>> + 'a> {
+        let names = MANAGER
+            .lock()
+            .bound
+            .keys()
+            .map(|name| Cow::Owned(name.clone()))
+            .collect::<Vec<_>>();
+        Box::new(names.into_iter())
+    }
+
+    fn lookup_child(&self, name: &str) -> VfsResult<NodeOpsMux> {
+        let manager = MANAGER.lock();
+        let bound = manager.bound.get(name).ok_or(VfsError::NotFound)?;
+        Ok(bound.node.clone().into())
+    }
+
+    fn is_cacheable(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,147 @@
+//! /dev/kmsg, giving access to the kernel log.
+
+use alloc::{borrow::Cow, string::String, vec};
+use core::{
+    any::Any,
+    sync::atomic::{AtomicBool, Ordering},
+    task::Context,
+};
+
+use axerrno::{AxError, AxResult};
+use axfs_ng_vfs::{NodeFlags, VfsResult};
+use axpoll::{IoEvents, Pollable};
+use axtask::future::{block_on, poll_io};
+use spin::Mutex;
+use starry_core::{
+    kmsg::{self, DEFAULT_MESSAGE_LOGLEVEL, LOG_KERN, LOG_USER},
+    vfs::DeviceOps,
+};
+
+use crate::file::{FileLike, IoDst, IoSrc};
+
+/// Logs a message written to /dev/kmsg, which may start with a `<N>`
+/// priority prefix.
+fn write_message(data: &[u8]) {
+    let text = String::from_utf8_lossy(data);
+    let (facility, level, text) = text
+        .strip_prefix('<')
+        .and_then(|it| it.split_once('>'))
+        .and_then(|(prio, text)| Some((prio.parse::<u32>().ok()?, text)))
+        .map_or(
+            (LOG_USER, DEFAULT_MESSAGE_LOGLEVEL, &*text),
+            |(prio, text)| ((prio >> 3) as u8, (prio & 7) as u8, text),
+        );
+    // User space may not pretend to be the kernel.
+    let facility = if facility == LOG_KERN {
+        LOG_USER
+    } else {
+        facility
+    };
+    kmsg::log(facility, level, text);
+}
+
+/// The /dev/kmsg device.
+///
+/// Opening it creates a [`KmsgFile`] instead, which keeps its own position
+/// in the log.
+pub struct Kmsg;
+
+impl DeviceOps for Kmsg {
+    fn read_at(&self, _buf: &mut [u8], _offset: u64) -> VfsResult<usize> {
+        Err(AxError::InvalidInput)
+    }
+
+    fn write_at(&self, buf: &[u8], _offset: u64) -> VfsResult<usize> {
+        write_message(buf);
+        Ok(buf.len())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn flags(&self) -> NodeFlags {
+        NodeFlags::NON_CACHEABLE | NodeFlags::STREAM
+    }
+}
+
+/// An open file on /dev/kmsg. Each read returns one record of the kernel
+/// log, starting from the oldest one still kept.
+pub struct KmsgFile {
+    seq: Mutex<u64>,
+    non_blocking: AtomicBool,
+}
+
+impl KmsgFile {
+    pub fn new() -> Self {
+        Self {
+            seq: Mutex::new(kmsg::first_seq()),
+            non_blocking: AtomicBool::new(false),
+        }
+    }
+}
+
+impl Default for KmsgFile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FileLike for KmsgFile {
+    fn read(&self, dst: &mut IoDst) -> AxResult<usize> {
+        block_on(poll_io(self, IoEvents::IN, self.nonblocking(), || {
+            let mut seq = self.seq.lock();
+            let record = match kmsg::read(*seq) {
+                Ok(Some(record)) => record,
+                Ok(None) => return Err(AxError::WouldBlock),
+                // Records were dropped before they could be read. The error
+                // is reported once, and reading resumes at the oldest record.
+                Err(first) => {
+                    *seq = first;
+                    return Err(AxError::BrokenPipe);
+                }
+            };
+            let line = record.to_kmsg();
+            if dst.remaining_mut() < line.len() {
+                return Err(AxError::InvalidInput);
+            }
+            dst.write(line.as_bytes())?;
+            *seq += 1;
+            Ok(line.len())
+        }))
+    }
+
+    fn write(&self, src: &mut IoSrc) -> AxResult<usize> {
+        let mut buf = vec![0; src.remaining()];
+        let len = src.read(&mut buf)?;
+        write_message(&buf[..len]);
+        Ok(len)
+    }
+
+    fn nonblocking(&self) -> bool {
+        self.non_blocking.load(Ordering::Acquire)
+    }
+
+    fn set_nonblocking(&self, non_blocking: bool) -> AxResult {
+        self.non_blocking.store(non_blocking, Ordering::Release);
+        Ok(())
+    }
+
+    fn path(&self) -> Cow<'_, str> {
+        "/dev/kmsg".into()
+    }
+}
+
+impl Pollable for KmsgFile {
+    fn poll(&self) -> IoEvents {
+        let mut events = IoEvents::OUT;
+        events.set(IoEvents::IN, *self.seq.lock() < kmsg::next_seq());
+        events
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        if events.contains(IoEvents::IN) {
+            kmsg::register_reader(context.waker());
+        }
+    }
+}
@@ -39,6 +39,22 @@ impl LoopDevice {
         }
     }
 
+    /// Returns the number of the device, as in its name `loopN`.
+    pub fn number(&self) -> u32 {
+        self.number
+    }
+
+    /// Returns the device number of the device.
+    pub fn device_id(&self) -> DeviceId {
+        self.dev_id
+    }
+
+    /// Returns the size of the backing file in bytes, or 0 if there is none.
+    pub fn size(&self) -> u64 {
+        let file = self.file.lock().clone();
+        file.and_then(|it| it.location().len().ok()).unwrap_or(0)
+    }
+
     /// Get information about the loop device.
     pub fn get_info(&self) -> AxResult<loop_info> {
         if self.file.lock().is_none() {
@@ -4,31 +4,51 @@ mod dice;
 #[cfg(feature = "input")]
 mod event;
 mod fb;
+pub mod hotplug;
+pub mod kmsg;
 #[cfg(feature = "dev-log")]
 mod log;
 mod r#loop;
 #[cfg(feature = "memtrack")]
 mod memtrack;
+mod random;
 mod rtc;
 pub mod tty;
+pub mod watchdog;
 
-use alloc::{format, sync::Arc};
+use alloc::{format, sync::Arc, vec::Vec};
 use core::any::Any;
 
 use axerrno::AxError;
 use axfs_ng_vfs::{DeviceId, Filesystem, NodeFlags, NodeType, VfsResult};
-use axsync::Mutex;
 #[cfg(feature = "dev-log")]
 pub use log::bind_dev_log;
-use rand::{RngCore, SeedableRng, rngs::SmallRng};
-use starry_core::vfs::{Device, DeviceOps, DirMaker, DirMapping, SimpleDir, SimpleFs};
-
-const RANDOM_SEED: &[u8; 32] = b"0123456789abcdef0123456789abcdef";
+pub use r#loop::LoopDevice;
+pub use random::{add_hwrng_randomness, add_timer_randomness, entropy_avail, fill_random_bytes};
+pub use rtc::rtc_time;
+use spin::Once;
+use starry_core::vfs::{
+    Device, DeviceOps, DirMaker, DirMapping, SimpleDir, SimpleDirOps, SimpleFs,
+};
 
 pub(crate) fn new_devfs() -> Filesystem {
     SimpleFs::new_with("devfs".into(), 0x01021994, builder)
 }
 
+/// The number of loop devices.
+const LOOP_DEVICES: u32 = 16;
+
+static LOOPS: Once<Vec<Arc<LoopDevice>>> = Once::new();
+
+/// Returns the loop devices, /dev/loop0 onwards.
+pub fn loop_devices() -> &'static [Arc<LoopDevice>] {
+    LOOPS.call_once(|| {
+        (0..LOOP_DEVICES)
+            .map(|i| Arc::new(LoopDevice::new(i, DeviceId::new(7, i))))
+            .collect()
+    })
+}
+
 struct Null;
 
 impl DeviceOps for Null {
@@ -70,37 +90,6 @@ impl DeviceOps for Zero {
     }
 }
 
-struct Random {
-    rng: Mutex<SmallRng>,
-}
-
-impl Random {
-    pub fn new() -> Self {
-        Self {
-            rng: Mutex::new(SmallRng::from_seed(*RANDOM_SEED)),
-        }
-    }
-}
-
-impl DeviceOps for Random {
-    fn read_at(&self, buf: &mut [u8], _offset: u64) -> VfsResult<usize> {
-        self.rng.lock().fill_bytes(buf);
-        Ok(buf.len())
-    }
-
-    fn write_at(&self, buf: &[u8], _offset: u64) -> VfsResult<usize> {
-        Ok(buf.len())
-    }
-
-    fn as_any(&self) -> &dyn Any {
-        self
-    }
-
-    fn flags(&self) -> NodeFlags {
-        NodeFlags::NON_CACHEABLE | NodeFlags::STREAM
-    }
-}
-
 struct Full;
 
 impl DeviceOps for Full {
@@ -177,7 +166,7 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
             fs.clone(),
             NodeType::CharacterDevice,
             DeviceId::new(1, 8),
-            Arc::new(Random::new()),
+            Arc::new(random::Random { blocking: true }),
         ),
     );
     root.add(
@@ -186,7 +175,25 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
             fs.clone(),
             NodeType::CharacterDevice,
             DeviceId::new(1, 9),
-            Arc::new(Random::new()),
+            Arc::new(random::Random { blocking: false }),
+        ),
+    );
+    root.add(
+        "kmsg",
+        Device::new(
+            fs.clone(),
+            NodeType::CharacterDevice,
+            DeviceId::new(1, 11),
+            Arc::new(kmsg::Kmsg),
+        ),
+    );
+    root.add(
+        "watchdog",
+        Device::new(
+            fs.clone(),
+            NodeType::CharacterDevice,
+            DeviceId::new(10, 130),
+            Arc::new(watchdog::Watchdog),
         ),
     );
     root.add(
@@ -195,7 +202,7 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
             fs.clone(),
             NodeType::CharacterDevice,
             rtc::RTC0_DEVICE_ID,
-            Arc::new(rtc::Rtc),
+            Arc::new(rtc::Rtc::default()),
         ),
     );
     if axdisplay::has_display() {
@@ -276,15 +283,14 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
     );
 
     // Loop devices
-    for i in 0..16 {
-        let dev_id = DeviceId::new(7, 0);
+    for dev in loop_devices() {
         root.add(
-            format!("loop{i}"),
+            format!("loop{}", dev.number()),
             Device::new(
                 fs.clone(),
                 NodeType::BlockDevice,
-                dev_id,
-                Arc::new(r#loop::LoopDevice::new(i, dev_id)),
+                dev.device_id(),
+                dev.clone(),
             ),
         );
     }
@@ -307,5 +313,8 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
         ),
     );
 
-    SimpleDir::new_maker(fs, Arc::new(root))
+    // Nodes of devices that appear after boot
+    let hotplug = hotplug::HotplugDir::new(fs.clone());
+
+    SimpleDir::new_maker(fs, Arc::new(root.chain(hotplug)))
 }
@@ -0,0 +1,45 @@
+//! /dev/random and /dev/urandom, backed by the kernel random number
+//! generator.
+
+use core::any::Any;
+
+use axfs_ng_vfs::{NodeFlags, VfsResult};
+pub use starry_core::random::{add_hwrng_randomness, add_timer_randomness, entropy_avail};
+use starry_core::{
+    random::{add_device_randomness, get_random_bytes, wait_for_crng},
+    vfs::DeviceOps,
+};
+
+/// Fills `buf` with random bytes from the kernel random number generator.
+pub fn fill_random_bytes(buf: &mut [u8]) {
+    get_random_bytes(buf);
+}
+
+/// A random device. /dev/random waits for the generator to be seeded before
+/// its first read, while /dev/urandom never blocks.
+pub struct Random {
+    pub blocking: bool,
+}
+
+impl DeviceOps for Random {
+    fn read_at(&self, buf: &mut [u8], _offset: u64) -> VfsResult<usize> {
+        if self.blocking {
+            wait_for_crng()?;
+        }
+        get_random_bytes(buf);
+        Ok(buf.len())
+    }
+
+    fn write_at(&self, buf: &[u8], _offset: u64) -> VfsResult<usize> {
+        add_device_randomness(buf);
+        Ok(buf.len())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn flags(&self) -> NodeFlags {
+        NodeFlags::NON_CACHEABLE | NodeFlags::STREAM
+    }
+}
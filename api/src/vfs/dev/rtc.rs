@@ -1,16 +1,35 @@
-use core::{any::Any, ffi::c_int};
+use core::{
+    any::Any,
+    ffi::{c_int, c_ulong},
+    sync::atomic::{AtomicI64, Ordering},
+    task::Context,
+    time::Duration,
+};
 
+use axerrno::AxError;
 use axfs_ng_vfs::{DeviceId, NodeFlags, VfsError, VfsResult};
-use chrono::{Datelike, Timelike};
-use linux_raw_sys::ioctl::RTC_RD_TIME;
-use starry_vm::VmMutPtr;
+use axhal::time::{TimeValue, wall_time_nanos};
+use axpoll::{IoEvents, Pollable};
+use chrono::{DateTime, Datelike, NaiveDate, Timelike};
+use linux_raw_sys::ioctl::{RTC_RD_TIME, RTC_SET_TIME, RTC_UIE_OFF, RTC_UIE_ON};
+use spin::Mutex;
+use starry_core::hrtimer;
+use starry_vm::{VmMutPtr, VmPtr};
 
 use crate::vfs::DeviceOps;
 
 /// The device ID for /dev/rtc0
 pub const RTC0_DEVICE_ID: DeviceId = DeviceId::new(250, 0);
 
+/// An interrupt occurred.
+const RTC_IRQF: c_ulong = 0x80;
+/// The interrupt was an update interrupt.
+const RTC_UF: c_ulong = 0x10;
+
+const NANOS_PER_SEC: i64 = 1_000_000_000;
+
 #[repr(C)]
+#[derive(Clone, Copy, bytemuck::AnyBitPattern)]
 #[allow(non_camel_case_types, dead_code)]
 struct rtc_time {
     tm_sec: c_int,
@@ -24,12 +43,90 @@ struct rtc_time {
     tm_isdst: c_int,
 }
 
+impl rtc_time {
+    fn from_nanos(nanos: i64) -> Self {
+        let time = DateTime::from_timestamp_nanos(nanos);
+        Self {
+            tm_sec: time.second() as _,
+            tm_min: time.minute() as _,
+            tm_hour: time.hour() as _,
+            tm_mday: time.day() as _,
+            tm_mon: time.month0() as _,
+            tm_year: (time.year() - 1900) as _,
+            tm_wday: time.weekday().num_days_from_sunday() as _,
+            tm_yday: time.ordinal0() as _,
+            tm_isdst: 0,
+        }
+    }
+
+    fn to_nanos(self) -> Option<i64> {
+        let date = NaiveDate::from_ymd_opt(
+            self.tm_year.checked_add(1900)?,
+            u32::try_from(self.tm_mon).ok()? + 1,
+            self.tm_mday.try_into().ok()?,
+        )?;
+        let time = date.and_hms_opt(
+            self.tm_hour.try_into().ok()?,
+            self.tm_min.try_into().ok()?,
+            self.tm_sec.try_into().ok()?,
+        )?;
+        time.and_utc().timestamp_nanos_opt()
+    }
+}
+
+/// How far the hardware clock has been set from the time the platform
+/// reported at boot, in nanoseconds.
+static RTC_OFFSET: AtomicI64 = AtomicI64::new(0);
+
+fn rtc_nanos() -> i64 {
+    (wall_time_nanos() as i64).saturating_add(RTC_OFFSET.load(Ordering::Acquire))
+}
+
+/// Returns the time of the hardware clock.
+pub fn rtc_time() -> TimeValue {
+    TimeValue::from_nanos(rtc_nanos().max(0) as u64)
+}
+
+#[derive(Default)]
+struct UpdateIrq {
+    enabled: bool,
+    /// The second of the last update reported by `read`.
+    last: i64,
+}
+
 /// RTC device
-pub struct Rtc;
+#[derive(Default)]
+pub struct Rtc {
+    uie: Mutex<UpdateIrq>,
+}
+
+impl Rtc {
+    /// Returns the number of update interrupts since the last `read`.
+    fn pending_updates(&self) -> i64 {
+        let uie = self.uie.lock();
+        if !uie.enabled {
+            return 0;
+        }
+        (rtc_nanos().div_euclid(NANOS_PER_SEC) - uie.last).max(0)
+    }
+}
 
 impl DeviceOps for Rtc {
-    fn read_at(&self, _buf: &mut [u8], _offset: u64) -> VfsResult<usize> {
-        Ok(0)
+    fn read_at(&self, buf: &mut [u8], _offset: u64) -> VfsResult<usize> {
+        // Each read reports the update interrupts since the previous one;
+        // hwclock waits on them to catch the start of a second.
+        const LEN: usize = size_of::<c_ulong>();
+        if buf.len() < LEN {
+            return Err(AxError::InvalidInput);
+        }
+        let count = self.pending_updates();
+        if count == 0 {
+            return Err(AxError::WouldBlock);
+        }
+        self.uie.lock().last += count;
+        let data = ((count as c_ulong) << 8) | RTC_UF | RTC_IRQF;
+        buf[..LEN].copy_from_slice(&data.to_ne_bytes());
+        Ok(LEN)
     }
 
     fn write_at(&self, _buf: &[u8], _offset: u64) -> VfsResult<usize> {
@@ -39,20 +136,22 @@ impl DeviceOps for Rtc {
     fn ioctl(&self, cmd: u32, arg: usize) -> VfsResult<usize> {
         match cmd {
             RTC_RD_TIME => {
-                let wall =
-                    chrono::DateTime::from_timestamp_nanos(axhal::time::wall_time_nanos() as _);
-                (arg as *mut rtc_time).vm_write(rtc_time {
-                    tm_sec: wall.second() as _,
-                    tm_min: wall.minute() as _,
-                    tm_hour: wall.hour() as _,
-                    tm_mday: wall.day() as _,
-                    tm_mon: wall.month0() as _,
-                    tm_year: (wall.year() - 1900) as _,
-                    tm_wday: 0,
-                    tm_yday: 0,
-                    tm_isdst: 0,
-                })?;
+                (arg as *mut rtc_time).vm_write(rtc_time::from_nanos(rtc_nanos()))?;
+            }
+            RTC_SET_TIME => {
+                let time = (arg as *const rtc_time).vm_read()?;
+                let nanos = time.to_nanos().ok_or(AxError::InvalidInput)?;
+                if nanos < 0 {
+                    return Err(AxError::InvalidInput);
+                }
+                RTC_OFFSET.store(nanos - wall_time_nanos() as i64, Ordering::Release);
+            }
+            RTC_UIE_ON => {
+                let mut uie = self.uie.lock();
+                uie.enabled = true;
+                uie.last = rtc_nanos().div_euclid(NANOS_PER_SEC);
             }
+            RTC_UIE_OFF => self.uie.lock().enabled = false,
             _ => return Err(VfsError::NotATty),
         }
         Ok(0)
@@ -62,7 +161,27 @@ impl DeviceOps for Rtc {
         self
     }
 
+    fn as_pollable(&self) -> Option<&dyn Pollable> {
+        Some(self)
+    }
+
     fn flags(&self) -> NodeFlags {
         NodeFlags::NON_CACHEABLE | NodeFlags::STREAM
     }
 }
+
+impl Pollable for Rtc {
+    fn poll(&self) -> IoEvents {
+        let mut events = IoEvents::empty();
+        events.set(IoEvents::IN, self.pending_updates() > 0);
+        events
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        if events.contains(IoEvents::IN) && self.uie.lock().enabled {
+            let next = NANOS_PER_SEC - rtc_nanos().rem_euclid(NANOS_PER_SEC);
+            let waker = context.waker().clone();
+            hrtimer::start_after(Duration::from_nanos(next as u64), move || waker.wake());
+        }
+    }
+}
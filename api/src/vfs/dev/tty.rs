@@ -3,14 +3,18 @@ use core::{any::Any, ops::Deref, sync::atomic::Ordering, task::Context};
 
 use axerrno::{AxError, AxResult};
 use axfs_ng_vfs::NodeFlags;
-use axpoll::{IoEvents, Pollable};
+use axpoll::{IoEvents, PollSet, Pollable};
 use axsync::Mutex;
-use axtask::{
-    current,
-    future::{block_on, poll_io},
+use axtask::current;
+use linux_raw_sys::general::{
+    TCIFLUSH, TCIOFF, TCIOFLUSH, TCION, TCOFLUSH, TCOOFF, TCOON, TOSTOP, VSTART, VSTOP,
 };
-use starry_core::{task::AsThread, vfs::SimpleFs};
-use starry_process::Process;
+use starry_core::{
+    task::{AsThread, get_process_group, send_signal_to_process_group},
+    vfs::SimpleFs,
+};
+use starry_process::{Pid, Process, Session};
+use starry_signal::{SignalInfo, Signo};
 use starry_vm::{VmMutPtr, VmPtr};
 
 use crate::{
@@ -29,12 +33,25 @@ mod pty;
 
 pub use ntty::{N_TTY, NTtyDriver};
 pub use ptm::Ptmx;
-pub use pts::PtsDir;
-pub use pty::PtyDriver;
+pub use pts::{PtsDir, new_devpts, open_slave};
+pub use pty::{PtyDriver, PtyHandle};
+
+/// Detaches the controlling terminal of `session`, if any, as when its
+/// leader exits.
+pub fn disassociate_ctty(session: &Session) {
+    let Some(term) = session.terminal() else {
+        return;
+    };
+    if let Some(tty) = term.downcast_ref::<NTtyDriver>() {
+        tty.disassociate(true);
+    } else if let Some(tty) = term.downcast_ref::<PtyDriver>() {
+        tty.disassociate(true);
+    }
+}
 
 pub fn create_pty_master(fs: Arc<SimpleFs>) -> AxResult<Arc<PtyDriver>> {
     let (master, slave) = pty::create_pty_pair();
-    pts::add_slave(fs, slave)?;
+    pts::add_slave(fs, slave, &master)?;
     Ok(master)
 }
 
@@ -43,6 +60,9 @@ pub struct Tty<R, W> {
     this: Weak<Self>,
     terminal: Arc<Terminal>,
     ldisc: Mutex<LineDiscipline<R, W>>,
+    /// Woken when input arrives, or when the other end of a pseudo-terminal
+    /// goes away.
+    rx_pollset: Option<Arc<PollSet>>,
     writer: W,
     is_ptm: bool,
 }
@@ -51,11 +71,13 @@ impl<R: TtyRead, W: TtyWrite + Clone> Tty<R, W> {
     fn new(terminal: Arc<Terminal>, config: TtyConfig<R, W>) -> Arc<Self> {
         let writer = config.writer.clone();
         let is_ptm = matches!(&config.process_mode, ProcessMode::None(_));
-        let ldisc = Mutex::new(LineDiscipline::new(terminal.clone(), config));
+        let ldisc = LineDiscipline::new(terminal.clone(), config);
+        let rx_pollset = ldisc.rx_pollset();
         Arc::new_cyclic(|this| Self {
             this: this.clone(),
             terminal,
-            ldisc,
+            ldisc: Mutex::new(ldisc),
+            rx_pollset,
             writer,
             is_ptm,
         })
@@ -63,48 +85,162 @@ impl<R: TtyRead, W: TtyWrite + Clone> Tty<R, W> {
 }
 
 impl<R: TtyRead, W: TtyWrite> Tty<R, W> {
-    pub fn bind_to(self: &Arc<Self>, proc: &Process) -> AxResult<()> {
+    /// Makes this the controlling terminal of the session led by `proc`.
+    ///
+    /// A terminal controlling another session is only taken over if `steal`
+    /// is set.
+    pub fn bind_to(self: &Arc<Self>, proc: &Process, steal: bool) -> AxResult<()> {
         let pg = proc.group();
-        if pg.session().sid() != proc.pid() {
+        let session = pg.session();
+        if session.sid() != proc.pid() {
             return Err(AxError::OperationNotPermitted);
         }
-        assert!(pg.session().set_terminal_with(|| {
-            self.terminal.job_control.set_session(&pg.session());
+        if session.terminal().is_some() {
+            return if self.is_controlling(&session) {
+                Ok(())
+            } else {
+                Err(AxError::OperationNotPermitted)
+            };
+        }
+        if let Some(old) = self.terminal.job_control.session() {
+            if !steal {
+                return Err(AxError::OperationNotPermitted);
+            }
+            old.unset_terminal(&(self.clone() as _));
+        }
+        if !session.set_terminal_with(|| {
+            self.terminal.job_control.set_session(&session);
             self.clone()
-        }));
+        }) {
+            return Err(AxError::OperationNotPermitted);
+        }
+
+        self.terminal.job_control.set_foreground(&pg)
+    }
+
+    /// Makes this the controlling terminal of the session led by `proc` if
+    /// neither has one yet, as opening a terminal without `O_NOCTTY` does.
+    pub fn acquire_on_open(&self, proc: &Process) {
+        if self.terminal.job_control.session().is_none()
+            && let Some(this) = self.this.upgrade()
+        {
+            let _ = this.bind_to(proc, false);
+        }
+    }
+
+    /// Detaches this from the session it controls, sending `SIGHUP` and
+    /// `SIGCONT` to the foreground process group if `to_foreground` is set,
+    /// or else to the session leader.
+    pub fn disassociate(&self, to_foreground: bool) {
+        if let Some(session) = self.terminal.job_control.session()
+            && let Some(this) = self.this.upgrade()
+        {
+            session.unset_terminal(&(this as _));
+        }
+        self.terminal.job_control.hang_up(to_foreground);
+        self.wake_readers();
+    }
 
-        self.terminal.job_control.set_foreground(&pg).unwrap();
-        Ok(())
+    /// Returns whether this is the controlling terminal of `session`.
+    fn is_controlling(&self, session: &Session) -> bool {
+        session
+            .terminal()
+            .is_some_and(|term| core::ptr::addr_eq(Arc::as_ptr(&term), self as *const Self))
+    }
+
+    /// Returns whether this is the controlling terminal of the current
+    /// process.
+    fn is_current_controlling(&self) -> bool {
+        let curr = current();
+        self.is_controlling(&curr.as_thread().proc_data.proc.group().session())
     }
 
     pub fn pty_number(&self) -> u32 {
         self.terminal.pty_number.load(Ordering::Acquire)
     }
+
+    /// Wakes up readers, so that they notice a hangup.
+    pub fn wake_readers(&self) {
+        if let Some(set) = &self.rx_pollset {
+            set.wake();
+        }
+    }
+
+    /// Returns whether there is room to write, and output is not suspended by
+    /// flow control, which holds back the slave side only.
+    fn writable(&self) -> bool {
+        (self.is_ptm || !self.terminal.output_stopped()) && self.writer.poll_write()
+    }
+
+    /// Returns whether the other end of a pseudo-terminal has gone away.
+    fn peer_closed(&self) -> bool {
+        if self.is_ptm {
+            self.terminal.slave_closed.load(Ordering::Acquire)
+        } else {
+            self.terminal.master_closed.load(Ordering::Acquire)
+        }
+    }
 }
 
 impl<R: TtyRead, W: TtyWrite> DeviceOps for Tty<R, W> {
     fn read_at(&self, buf: &mut [u8], _offset: u64) -> AxResult<usize> {
-        block_on(poll_io(
-            &self.terminal.job_control,
-            IoEvents::IN,
-            false,
-            || {
-                if self.is_ptm || self.terminal.job_control.current_in_foreground() {
-                    self.ldisc.lock().read(buf)
+        if !self.is_ptm {
+            self.terminal.job_control.wait_foreground(Signo::SIGTTIN)?;
+        }
+        match self.ldisc.lock().read(buf) {
+            // The master reads EIO once the slave is closed, and the slave
+            // reads end-of-file once the master is.
+            Err(AxError::WouldBlock) if self.peer_closed() => {
+                if self.is_ptm {
+                    Err(AxError::Io)
                 } else {
-                    Err(AxError::WouldBlock)
+                    Ok(0)
                 }
-            },
-        ))
+            }
+            result => result,
+        }
     }
 
     fn write_at(&self, buf: &[u8], _offset: u64) -> AxResult<usize> {
-        self.writer.write(buf);
-        Ok(buf.len())
+        if !self.is_ptm {
+            if self.peer_closed() {
+                return Err(AxError::Io);
+            }
+            if self.terminal.termios.lock().has_lflag(TOSTOP) {
+                self.terminal.job_control.wait_foreground(Signo::SIGTTOU)?;
+            }
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if !self.writable() {
+            return Err(AxError::WouldBlock);
+        }
+        match self.writer.write(buf) {
+            0 => Err(AxError::WouldBlock),
+            written => Ok(written),
+        }
     }
 
     fn ioctl(&self, cmd: u32, arg: usize) -> AxResult<usize> {
         use linux_raw_sys::ioctl::*;
+        // Background processes may not change their controlling terminal.
+        if !self.is_ptm
+            && matches!(
+                cmd,
+                TCSETS
+                    | TCSETSF
+                    | TCSETSW
+                    | TCSETS2
+                    | TCSETSF2
+                    | TCSETSW2
+                    | TIOCSPGRP
+                    | TCFLSH
+                    | TCXONC
+            )
+        {
+            self.terminal.job_control.wait_foreground(Signo::SIGTTOU)?;
+        }
         match cmd {
             TCGETS => {
                 (arg as *mut Termios).vm_write(*self.terminal.termios.lock().as_ref().deref())?;
@@ -128,6 +264,9 @@ impl<R: TtyRead, W: TtyWrite> DeviceOps for Tty<R, W> {
                 }
             }
             TIOCGPGRP => {
+                if !self.is_ptm && !self.is_current_controlling() {
+                    return Err(AxError::NotATty);
+                }
                 let foreground = self
                     .terminal
                     .job_control
@@ -136,18 +275,75 @@ impl<R: TtyRead, W: TtyWrite> DeviceOps for Tty<R, W> {
                 (arg as *mut u32).vm_write(foreground.pgid())?;
             }
             TIOCSPGRP => {
-                let curr = current();
+                if !self.is_current_controlling() {
+                    return Err(AxError::NotATty);
+                }
+                let pgid = (arg as *const i32).vm_read()?;
+                if pgid < 0 {
+                    return Err(AxError::InvalidInput);
+                }
                 self.terminal
                     .job_control
-                    .set_foreground(&curr.as_thread().proc_data.proc.group())?;
+                    .set_foreground(&get_process_group(pgid as Pid)?)?;
+            }
+            TIOCGSID => {
+                if !self.is_ptm && !self.is_current_controlling() {
+                    return Err(AxError::NotATty);
+                }
+                let session = self
+                    .terminal
+                    .job_control
+                    .session()
+                    .ok_or(AxError::NotATty)?;
+                (arg as *mut u32).vm_write(session.sid())?;
             }
             TIOCGWINSZ => {
                 (arg as *mut WindowSize).vm_write(*self.terminal.window_size.lock())?;
             }
             TIOCSWINSZ => {
-                *self.terminal.window_size.lock() = (arg as *const WindowSize).vm_read()?;
+                let size = (arg as *const WindowSize).vm_read()?;
+                let old = core::mem::replace(&mut *self.terminal.window_size.lock(), size);
+                if old != size
+                    && let Some(pg) = self.terminal.job_control.foreground()
+                {
+                    let sig = SignalInfo::new_kernel(Signo::SIGWINCH);
+                    send_signal_to_process_group(pg.pgid(), Some(sig))?;
+                }
+            }
+            TIOCSPTLCK => {
+                let lock = (arg as *const i32).vm_read()?;
+                self.terminal.pty_locked.store(lock != 0, Ordering::Release);
+            }
+            TIOCGPTLCK => {
+                let locked = self.terminal.pty_locked.load(Ordering::Acquire);
+                (arg as *mut i32).vm_write(locked as i32)?;
+            }
+            FIONREAD => {
+                let available = self.ldisc.lock().available();
+                (arg as *mut i32).vm_write(available as i32)?;
             }
-            TIOCSPTLCK => {}
+            TIOCOUTQ => {
+                (arg as *mut i32).vm_write(0)?;
+            }
+            TCFLSH => match arg as u32 {
+                TCIFLUSH | TCIOFLUSH => self.ldisc.lock().drain_input(),
+                TCOFLUSH => {}
+                _ => return Err(AxError::InvalidInput),
+            },
+            TCXONC => match arg as u32 {
+                TCOOFF => self.terminal.set_output_stopped(true),
+                TCOON => self.terminal.set_output_stopped(false),
+                TCIOFF | TCION => {
+                    let index = if arg as u32 == TCIOFF { VSTOP } else { VSTART };
+                    let ch = self.terminal.load_termios().special_char(index);
+                    if ch != 0 {
+                        self.writer.write(&[ch]);
+                    }
+                }
+                _ => return Err(AxError::InvalidInput),
+            },
+            // Output is never queued, so there is nothing to wait for.
+            TCSBRK | TCSBRKP => {}
             TIOCGPTN => {
                 (arg as *mut u32).vm_write(self.pty_number())?;
             }
@@ -155,23 +351,18 @@ impl<R: TtyRead, W: TtyWrite> DeviceOps for Tty<R, W> {
                 self.this
                     .upgrade()
                     .unwrap()
-                    .bind_to(&current().as_thread().proc_data.proc)?;
+                    .bind_to(&current().as_thread().proc_data.proc, arg == 1)?;
             }
             TIOCNOTTY => {
-                if current()
-                    .as_thread()
-                    .proc_data
-                    .proc
-                    .group()
-                    .session()
-                    .unset_terminal(&(self.this.upgrade().unwrap() as _))
-                {
-                    // TODO: If the process was session leader, send SIGHUP and
-                    // SIGCONT to the foreground process group and all processes
-                    // in the current session lose their
-                    // controlling terminal.
-                } else {
-                    warn!("Failed to unset terminal");
+                if !self.is_current_controlling() {
+                    return Err(AxError::NotATty);
+                }
+                // Only the session leader giving up the terminal detaches it
+                // from the session.
+                let curr = current();
+                let proc = &curr.as_thread().proc_data.proc;
+                if proc.group().session().sid() == proc.pid() {
+                    self.disassociate(true);
                 }
             }
             _ => return Err(AxError::NotATty),
@@ -195,10 +386,14 @@ impl<R: TtyRead, W: TtyWrite> DeviceOps for Tty<R, W> {
 
 impl<R: TtyRead, W: TtyWrite> Pollable for Tty<R, W> {
     fn poll(&self) -> IoEvents {
-        let mut events = IoEvents::OUT | self.terminal.job_control.poll();
+        let mut events = self.terminal.job_control.poll();
+        events.set(IoEvents::OUT, self.writable());
         if self.is_ptm || events.contains(IoEvents::IN) {
             events.set(IoEvents::IN, self.ldisc.lock().poll_read());
         }
+        if self.peer_closed() {
+            events |= IoEvents::IN | IoEvents::HUP;
+        }
         events
     }
 
@@ -209,6 +404,12 @@ impl<R: TtyRead, W: TtyWrite> Pollable for Tty<R, W> {
         if events.contains(IoEvents::IN) {
             self.ldisc.lock().register_rx_waker(context.waker());
         }
+        if events.contains(IoEvents::OUT) {
+            if !self.is_ptm {
+                self.terminal.output_started.register(context.waker());
+            }
+            self.writer.register_tx_waker(context.waker());
+        }
     }
 }
 
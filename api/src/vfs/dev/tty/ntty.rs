@@ -16,8 +16,9 @@ impl TtyRead for Console {
     }
 }
 impl TtyWrite for Console {
-    fn write(&self, buf: &[u8]) {
+    fn write(&self, buf: &[u8]) -> usize {
         axhal::console::write_bytes(buf);
+        buf.len()
     }
 }
 
@@ -9,13 +9,13 @@ pub struct Ptmx(pub Arc<SimpleFs>);
 impl Ptmx {
     pub fn create_pty(&self) -> AxResult<(Arc<Device>, u32)> {
         let (master, slave) = super::pty::create_pty_pair();
-        super::pts::add_slave(self.0.clone(), slave)?;
+        super::pts::add_slave(self.0.clone(), slave, &master)?;
         let pty_number = master.pty_number();
         let device = Device::new(
             self.0.clone(),
             NodeType::CharacterDevice,
             DeviceId::new(128, pty_number),
-            master,
+            Arc::new(super::PtyHandle::open_master(master)),
         );
         Ok((device, pty_number))
     }
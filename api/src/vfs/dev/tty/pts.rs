@@ -1,36 +1,92 @@
-use alloc::{borrow::Cow, boxed::Box, string::ToString, sync::Arc, vec::Vec};
+use alloc::{
+    borrow::Cow,
+    boxed::Box,
+    string::ToString,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
 use core::sync::atomic::Ordering;
 
 use axerrno::{AxError, AxResult};
-use axfs_ng_vfs::{DeviceId, NodeType, VfsResult};
+use axfs_ng_vfs::{DeviceId, Filesystem, NodeType, VfsResult};
 use flatten_objects::FlattenObjects;
 use kspin::SpinNoIrq;
-use starry_core::vfs::{Device, NodeOpsMux, SimpleDirOps, SimpleFs};
+use starry_core::vfs::{Device, NodeOpsMux, SimpleDir, SimpleDirOps, SimpleFs};
 
-use crate::vfs::dev::tty::pty::PtyDriver;
+use super::pty::{PtyDriver, PtyHandle};
 
-static PTS_TABLE: SpinNoIrq<FlattenObjects<Arc<Device>, 16>> =
+/// The most pseudo-terminals that can be open at once.
+const MAX_PTYS: usize = 256;
+
+struct PtsEntry {
+    fs: Arc<SimpleFs>,
+    slave: Arc<PtyDriver>,
+    device: Arc<Device>,
+    master: Weak<PtyDriver>,
+}
+
+static PTS_TABLE: SpinNoIrq<FlattenObjects<PtsEntry, MAX_PTYS>> =
     SpinNoIrq::new(FlattenObjects::new());
 
-pub fn add_slave(fs: Arc<SimpleFs>, pty: Arc<PtyDriver>) -> AxResult<u32> {
+pub fn add_slave(fs: Arc<SimpleFs>, pty: Arc<PtyDriver>, master: &Arc<PtyDriver>) -> AxResult<u32> {
     let terminal = pty.terminal.clone();
+    let device = Device::new(
+        fs.clone(),
+        NodeType::CharacterDevice,
+        DeviceId::default(),
+        pty.clone(),
+    );
     let mut table = PTS_TABLE.lock();
     let pty_number = table
-        .add(Device::new(
+        .add(PtsEntry {
             fs,
-            NodeType::CharacterDevice,
-            DeviceId::default(),
-            pty,
-        ))
+            slave: pty,
+            device,
+            master: Arc::downgrade(master),
+        })
         .map_err(|_| AxError::TooManyOpenFiles)? as u32;
     terminal.pty_number.store(pty_number, Ordering::Release);
     table
         .get(pty_number as usize)
         .unwrap()
+        .device
         .set_device_id(DeviceId::new(136, pty_number));
     Ok(pty_number)
 }
 
+/// Removes the slave of a pseudo-terminal whose master has been closed,
+/// returning it.
+pub(super) fn remove_slave(pty_number: u32) -> Option<Arc<PtyDriver>> {
+    PTS_TABLE
+        .lock()
+        .remove(pty_number as usize)
+        .map(|entry| entry.slave)
+}
+
+/// Returns the master of a pseudo-terminal, if it is still open.
+pub(super) fn master_of(pty_number: u32) -> Option<Arc<PtyDriver>> {
+    PTS_TABLE
+        .lock()
+        .get(pty_number as usize)
+        .and_then(|entry| entry.master.upgrade())
+}
+
+/// Opens the slave of a pseudo-terminal, returning a device for the new
+/// file.
+pub fn open_slave(pty_number: u32) -> AxResult<Arc<Device>> {
+    let (fs, slave) = PTS_TABLE
+        .lock()
+        .get(pty_number as usize)
+        .map(|entry| (entry.fs.clone(), entry.slave.clone()))
+        .ok_or(AxError::NotFound)?;
+    Ok(Device::new(
+        fs,
+        NodeType::CharacterDevice,
+        DeviceId::new(136, pty_number),
+        Arc::new(PtyHandle::open_slave(slave)?),
+    ))
+}
+
 /// /dev/pts directory
 pub struct PtsDir;
 
@@ -46,7 +102,19 @@ impl SimpleDirOps for PtsDir {
 
     fn lookup_child(&self, name: &str) -> VfsResult<NodeOpsMux> {
         let id = name.parse::<usize>().map_err(|_| AxError::InvalidData)?;
-        let pty = PTS_TABLE.lock().get(id).ok_or(AxError::NotFound)?.clone();
+        let pty = PTS_TABLE
+            .lock()
+            .get(id)
+            .ok_or(AxError::NotFound)?
+            .device
+            .clone();
         Ok(NodeOpsMux::File(pty))
     }
 }
+
+/// Creates a devpts filesystem, showing the slaves of all pseudo-terminals.
+pub fn new_devpts() -> Filesystem {
+    SimpleFs::new_with("devpts".into(), 0x1cd1, |fs| {
+        SimpleDir::new_maker(fs, Arc::new(PtsDir))
+    })
+}
@@ -1,16 +1,26 @@
 use alloc::{boxed::Box, sync::Arc};
+use core::{
+    any::Any,
+    sync::atomic::{AtomicBool, Ordering},
+    task::Waker,
+};
 
-use axpoll::PollSet;
+use axerrno::{AxError, AxResult};
+use axfs_ng_vfs::NodeFlags;
+use axpoll::{PollSet, Pollable};
 use kspin::SpinNoPreempt;
 use ringbuf::{
     Cons, HeapRb, Prod,
-    traits::{Consumer, Producer},
+    traits::{Consumer, Observer, Producer},
 };
 
-use super::Tty;
-use crate::terminal::{
-    Terminal,
-    ldisc::{ProcessMode, TtyConfig, TtyRead, TtyWrite},
+use super::{Tty, pts};
+use crate::{
+    terminal::{
+        Terminal,
+        ldisc::{ProcessMode, TtyConfig, TtyRead, TtyWrite},
+    },
+    vfs::DeviceOps,
 };
 
 const PTY_BUF_SIZE: usize = 4096;
@@ -19,36 +29,59 @@ pub type PtyDriver = Tty<PtyReader, PtyWriter>;
 
 type Buffer = Arc<HeapRb<u8>>;
 
-pub struct PtyReader(Cons<Buffer>);
+/// The reading end of a buffer between the two sides of a pseudo-terminal.
+///
+/// The [`PollSet`] is woken when room is made in the buffer.
+pub struct PtyReader(Cons<Buffer>, Arc<PollSet>);
 
 impl PtyReader {
-    pub fn new(buffer: Buffer) -> Self {
-        Self(Cons::new(buffer))
+    pub fn new(buffer: Buffer, poll_tx: Arc<PollSet>) -> Self {
+        Self(Cons::new(buffer), poll_tx)
     }
 }
 
 impl TtyRead for PtyReader {
     fn read(&mut self, buf: &mut [u8]) -> usize {
-        self.0.pop_slice(buf)
+        let read = self.0.pop_slice(buf);
+        if read > 0 {
+            self.1.wake();
+        }
+        read
     }
 }
 
+/// The writing end of a buffer between the two sides of a pseudo-terminal.
+///
+/// The first [`PollSet`] is woken when data is written, and the second is
+/// waited on for room in the buffer.
 #[derive(Clone)]
-pub struct PtyWriter(Arc<SpinNoPreempt<Prod<Buffer>>>, Arc<PollSet>);
+pub struct PtyWriter(Arc<SpinNoPreempt<Prod<Buffer>>>, Arc<PollSet>, Arc<PollSet>);
 
 impl PtyWriter {
-    pub fn new(buffer: Buffer, poll_rx: Arc<PollSet>) -> Self {
-        Self(Arc::new(SpinNoPreempt::new(Prod::new(buffer))), poll_rx)
+    pub fn new(buffer: Buffer, poll_rx: Arc<PollSet>, poll_tx: Arc<PollSet>) -> Self {
+        Self(
+            Arc::new(SpinNoPreempt::new(Prod::new(buffer))),
+            poll_rx,
+            poll_tx,
+        )
     }
 }
 
 impl TtyWrite for PtyWriter {
-    fn write(&self, buf: &[u8]) {
-        let read = self.0.lock().push_slice(buf);
-        self.1.wake();
-        if read < buf.len() {
-            warn!("Discarding {} bytes written to pty", buf.len() - read);
+    fn write(&self, buf: &[u8]) -> usize {
+        let written = self.0.lock().push_slice(buf);
+        if written > 0 {
+            self.1.wake();
         }
+        written
+    }
+
+    fn poll_write(&self) -> bool {
+        !self.0.lock().is_full()
+    }
+
+    fn register_tx_waker(&self, waker: &Waker) {
+        self.2.register(waker);
     }
 }
 
@@ -57,14 +90,24 @@ pub(crate) fn create_pty_pair() -> (Arc<PtyDriver>, Arc<PtyDriver>) {
     let slave_to_master = Arc::new(HeapRb::new(PTY_BUF_SIZE));
     let poll_rx_slave = Arc::new(PollSet::new());
     let poll_rx_master = Arc::new(PollSet::new());
+    let poll_tx_slave = Arc::new(PollSet::new());
+    let poll_tx_master = Arc::new(PollSet::new());
 
-    let terminal = Arc::new(Terminal::default());
+    // Like Linux, the slave starts out locked until `unlockpt`.
+    let terminal = Arc::new(Terminal {
+        pty_locked: AtomicBool::new(true),
+        ..Terminal::default()
+    });
 
     let master = Tty::new(
         terminal.clone(),
         TtyConfig {
-            reader: PtyReader::new(slave_to_master.clone()),
-            writer: PtyWriter::new(master_to_slave.clone(), poll_rx_slave.clone()),
+            reader: PtyReader::new(slave_to_master.clone(), poll_tx_slave.clone()),
+            writer: PtyWriter::new(
+                master_to_slave.clone(),
+                poll_rx_slave.clone(),
+                poll_tx_master.clone(),
+            ),
             process_mode: ProcessMode::None(poll_rx_master.clone()),
         },
     );
@@ -72,8 +115,8 @@ pub(crate) fn create_pty_pair() -> (Arc<PtyDriver>, Arc<PtyDriver>) {
     let slave = Tty::new(
         terminal,
         TtyConfig {
-            reader: PtyReader::new(master_to_slave),
-            writer: PtyWriter::new(slave_to_master, poll_rx_master),
+            reader: PtyReader::new(master_to_slave, poll_tx_master),
+            writer: PtyWriter::new(slave_to_master, poll_rx_master, poll_tx_slave),
             process_mode: ProcessMode::External(Box::new(move |waker| {
                 poll_rx_slave.register(&waker)
             })),
@@ -82,3 +125,81 @@ pub(crate) fn create_pty_pair() -> (Arc<PtyDriver>, Arc<PtyDriver>) {
 
     (master, slave)
 }
+
+/// An open file on the master or the slave of a pseudo-terminal.
+///
+/// The master is closed along with its only file, and the slave when the last
+/// of its files is closed, so each side can tell when the other hangs up.
+pub struct PtyHandle {
+    pty: Arc<PtyDriver>,
+    is_master: bool,
+}
+
+impl PtyHandle {
+    pub fn open_master(pty: Arc<PtyDriver>) -> Self {
+        Self {
+            pty,
+            is_master: true,
+        }
+    }
+
+    pub fn open_slave(pty: Arc<PtyDriver>) -> AxResult<Self> {
+        let terminal = &pty.terminal;
+        if terminal.pty_locked.load(Ordering::Acquire)
+            || terminal.master_closed.load(Ordering::Acquire)
+        {
+            return Err(AxError::Io);
+        }
+        terminal.slave_opens.fetch_add(1, Ordering::AcqRel);
+        terminal.slave_closed.store(false, Ordering::Release);
+        Ok(Self {
+            pty,
+            is_master: false,
+        })
+    }
+}
+
+impl Drop for PtyHandle {
+    fn drop(&mut self) {
+        let terminal = &self.pty.terminal;
+        let pty_number = self.pty.pty_number();
+        if self.is_master {
+            terminal.master_closed.store(true, Ordering::Release);
+            // Closing the master hangs up the slave.
+            if let Some(slave) = pts::remove_slave(pty_number) {
+                slave.disassociate(false);
+            }
+        } else if terminal.slave_opens.fetch_sub(1, Ordering::AcqRel) == 1 {
+            terminal.slave_closed.store(true, Ordering::Release);
+            if let Some(master) = pts::master_of(pty_number) {
+                master.wake_readers();
+            }
+        }
+    }
+}
+
+impl DeviceOps for PtyHandle {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> AxResult<usize> {
+        self.pty.read_at(buf, offset)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> AxResult<usize> {
+        self.pty.write_at(buf, offset)
+    }
+
+    fn ioctl(&self, cmd: u32, arg: usize) -> AxResult<usize> {
+        self.pty.ioctl(cmd, arg)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_pollable(&self) -> Option<&dyn Pollable> {
+        Some(&*self.pty)
+    }
+
+    fn flags(&self) -> NodeFlags {
+        self.pty.flags()
+    }
+}
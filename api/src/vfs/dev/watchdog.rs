@@ -0,0 +1,173 @@
+//! /dev/watchdog, the interface to the software watchdog.
+
+use alloc::{borrow::Cow, vec};
+use core::{
+    any::Any,
+    ffi::c_int,
+    sync::atomic::{AtomicBool, Ordering},
+    task::Context,
+};
+
+use axerrno::{AxError, AxResult};
+use axfs_ng_vfs::{NodeFlags, VfsResult};
+use axpoll::{IoEvents, Pollable};
+use starry_core::{kmsg, vfs::DeviceOps, watchdog};
+use starry_vm::{VmMutPtr, VmPtr};
+
+use crate::file::{FileLike, IoDst, IoSrc};
+
+const WDIOC_GETSUPPORT: u32 = 0x80285700;
+const WDIOC_GETSTATUS: u32 = 0x80045701;
+const WDIOC_GETBOOTSTATUS: u32 = 0x80045702;
+const WDIOC_SETOPTIONS: u32 = 0x80045704;
+const WDIOC_KEEPALIVE: u32 = 0x80045705;
+const WDIOC_SETTIMEOUT: u32 = 0xc0045706;
+const WDIOC_GETTIMEOUT: u32 = 0x80045707;
+const WDIOC_GETTIMELEFT: u32 = 0x8004570a;
+
+const WDIOF_SETTIMEOUT: u32 = 0x0080;
+const WDIOF_MAGICCLOSE: u32 = 0x0100;
+const WDIOF_KEEPALIVEPING: u32 = 0x8000;
+
+const WDIOS_DISABLECARD: c_int = 0x0001;
+const WDIOS_ENABLECARD: c_int = 0x0002;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+#[allow(non_camel_case_types)]
+struct watchdog_info {
+    options: u32,
+    firmware_version: u32,
+    identity: [u8; 32],
+}
+
+/// Whether /dev/watchdog is open; only one file may be open on it at a time.
+static OPEN: AtomicBool = AtomicBool::new(false);
+
+/// The /dev/watchdog device.
+///
+/// Opening it creates a [`WatchdogFile`] instead, which starts the watchdog.
+pub struct Watchdog;
+
+impl DeviceOps for Watchdog {
+    fn read_at(&self, _buf: &mut [u8], _offset: u64) -> VfsResult<usize> {
+        Err(AxError::InvalidInput)
+    }
+
+    fn write_at(&self, _buf: &[u8], _offset: u64) -> VfsResult<usize> {
+        Err(AxError::InvalidInput)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn flags(&self) -> NodeFlags {
+        NodeFlags::NON_CACHEABLE | NodeFlags::STREAM
+    }
+}
+
+/// An open file on /dev/watchdog.
+///
+/// Writing to it or `WDIOC_KEEPALIVE` pings the watchdog. Closing it stops
+/// the watchdog only if the magic character `V` was the last thing written,
+/// so that a daemon that dies leaves the watchdog running.
+pub struct WatchdogFile {
+    expect_close: AtomicBool,
+}
+
+impl WatchdogFile {
+    /// Opens the watchdog and starts it.
+    pub fn open() -> AxResult<Self> {
+        if OPEN.swap(true, Ordering::Acquire) {
+            return Err(AxError::ResourceBusy);
+        }
+        watchdog::start();
+        Ok(Self {
+            expect_close: AtomicBool::new(false),
+        })
+    }
+}
+
+impl Drop for WatchdogFile {
+    fn drop(&mut self) {
+        if self.expect_close.load(Ordering::Acquire) {
+            watchdog::stop();
+        } else if watchdog::is_active() {
+            kmsg::printk(2, format_args!("watchdog: unexpected close, not stopping"));
+        }
+        OPEN.store(false, Ordering::Release);
+    }
+}
+
+impl FileLike for WatchdogFile {
+    fn read(&self, _dst: &mut IoDst) -> AxResult<usize> {
+        Err(AxError::InvalidInput)
+    }
+
+    fn write(&self, src: &mut IoSrc) -> AxResult<usize> {
+        let mut buf = vec![0; src.remaining()];
+        let len = src.read(&mut buf)?;
+        if len > 0 {
+            self.expect_close
+                .store(buf[..len].contains(&b'V'), Ordering::Release);
+            watchdog::keepalive();
+        }
+        Ok(len)
+    }
+
+    fn path(&self) -> Cow<'_, str> {
+        "/dev/watchdog".into()
+    }
+
+    fn ioctl(&self, cmd: u32, arg: usize) -> AxResult<usize> {
+        match cmd {
+            WDIOC_GETSUPPORT => {
+                let mut info = watchdog_info {
+                    options: WDIOF_SETTIMEOUT | WDIOF_MAGICCLOSE | WDIOF_KEEPALIVEPING,
+                    firmware_version: 0,
+                    identity: [0; 32],
+                };
+                let identity = b"Software Watchdog";
+                info.identity[..identity.len()].copy_from_slice(identity);
+                (arg as *mut watchdog_info).vm_write(info)?;
+            }
+            WDIOC_GETSTATUS | WDIOC_GETBOOTSTATUS => {
+                (arg as *mut c_int).vm_write(0)?;
+            }
+            WDIOC_SETOPTIONS => {
+                let options = (arg as *const c_int).vm_read()?;
+                if options & WDIOS_DISABLECARD != 0 {
+                    watchdog::stop();
+                }
+                if options & WDIOS_ENABLECARD != 0 {
+                    watchdog::start();
+                }
+            }
+            WDIOC_KEEPALIVE => watchdog::keepalive(),
+            WDIOC_SETTIMEOUT => {
+                let timeout = (arg as *const c_int).vm_read()?;
+                let timeout = u32::try_from(timeout).map_err(|_| AxError::InvalidInput)?;
+                watchdog::set_timeout(timeout)?;
+                (arg as *mut c_int).vm_write(watchdog::timeout() as c_int)?;
+            }
+            WDIOC_GETTIMEOUT => {
+                (arg as *mut c_int).vm_write(watchdog::timeout() as c_int)?;
+            }
+            WDIOC_GETTIMELEFT => {
+                let left = watchdog::time_left().ok_or(AxError::InvalidInput)?;
+                (arg as *mut c_int).vm_write(left as c_int)?;
+            }
+            _ => return Err(AxError::NotATty),
+        }
+        Ok(0)
+    }
+}
+
+impl Pollable for WatchdogFile {
+    fn poll(&self) -> IoEvents {
+        IoEvents::OUT
+    }
+
+    fn register(&self, _context: &mut Context<'_>, _events: IoEvents) {}
+}
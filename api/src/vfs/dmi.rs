@@ -0,0 +1,271 @@
+//! SMBIOS/DMI system identification, exposed under /sys/class/dmi/id.
+
+use alloc::{
+    borrow::Cow,
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::fmt::Write;
+
+use axfs_ng_vfs::NodePermission;
+use memory_addr::PhysAddr;
+use spin::Once;
+#[cfg(target_arch = "aarch64")]
+use starry_core::firmware::fw_cfg;
+use starry_core::{
+    firmware::{checksum_ok, map_firmware, read_le},
+    vfs::{DirMaker, DirMapping, SimpleDir, SimpleFile, SimpleFs},
+};
+
+/// Identification fields found in the SMBIOS tables, named after their
+/// files in /sys/class/dmi/id.
+static DMI_FIELDS: Once<Vec<(&'static str, String)>> = Once::new();
+
+/// Scans the legacy BIOS area for the SMBIOS entry point structure, which is
+/// anchored on a 16-byte boundary.
+#[cfg(target_arch = "x86_64")]
+fn find_entry_point() -> Option<&'static [u8]> {
+    let area = map_firmware(PhysAddr::from_usize(0xf0000), 0x10000)?;
+    (0..area.len())
+        .step_by(16)
+        .map(|offset| &area[offset..])
+        .find(|it| it.starts_with(b"_SM3_") || it.starts_with(b"_SM_"))
+}
+
+/// Without a legacy BIOS area, the entry point can only be found through the
+/// EFI configuration table, which the platform layer doesn't pass on. QEMU
+/// passes the tables through fw_cfg instead, see [`fw_cfg_tables`].
+#[cfg(not(target_arch = "x86_64"))]
+fn find_entry_point() -> Option<&'static [u8]> {
+    None
+}
+
+/// Reads the SMBIOS tables that QEMU passes through fw_cfg, along with the
+/// SMBIOS version.
+///
+/// The entry point is not checked: QEMU leaves its checksums and the table
+/// address for the firmware to fill in.
+#[cfg(target_arch = "aarch64")]
+fn fw_cfg_tables() -> Option<(Cow<'static, [u8]>, (u8, u8))> {
+    let anchor = fw_cfg::read_file("etc/smbios/smbios-anchor")?;
+    let tables = fw_cfg::read_file("etc/smbios/smbios-tables")?;
+    let version = if anchor.starts_with(b"_SM3_") {
+        (*anchor.get(7)?, *anchor.get(8)?)
+    } else if anchor.starts_with(b"_SM_") {
+        (*anchor.get(6)?, *anchor.get(7)?)
+    } else {
+        return None;
+    };
+    Some((Cow::Owned(tables), version))
+}
+
+/// Finds the SMBIOS structure table, along with the SMBIOS version.
+fn find_tables() -> Option<(Cow<'static, [u8]>, (u8, u8))> {
+    #[cfg(target_arch = "aarch64")]
+    if let Some(tables) = fw_cfg_tables() {
+        return Some(tables);
+    }
+    let ep = find_entry_point().and_then(EntryPoint::parse)?;
+    let table = map_firmware(ep.table, ep.table_len)?;
+    Some((Cow::Borrowed(table), ep.version))
+}
+
+/// The location of the structure table and the SMBIOS version, as described
+/// by an entry point structure.
+struct EntryPoint {
+    table: PhysAddr,
+    table_len: usize,
+    version: (u8, u8),
+}
+
+impl EntryPoint {
+    fn parse(data: &[u8]) -> Option<Self> {
+        if data.starts_with(b"_SM3_") {
+            let data = data.get(..*data.get(6)? as usize)?;
+            if !checksum_ok(data) {
+                return None;
+            }
+            Some(Self {
+                table: PhysAddr::from_usize(u64::from_le_bytes(read_le(data, 0x10)?) as usize),
+                table_len: u32::from_le_bytes(read_le(data, 0x0c)?) as usize,
+                version: (data[7], data[8]),
+            })
+        } else if data.starts_with(b"_SM_") {
+            let data = data.get(..*data.get(5)? as usize)?;
+            // The 32-bit entry point embeds a legacy DMI entry point, which
+            // has a checksum of its own.
+            let dmi = data.get(0x10..0x1f)?;
+            if !checksum_ok(data) || !dmi.starts_with(b"_DMI_") || !checksum_ok(dmi) {
+                return None;
+            }
+            Some(Self {
+                table: PhysAddr::from_usize(u32::from_le_bytes(read_le(data, 0x18)?) as usize),
+                table_len: u16::from_le_bytes(read_le(data, 0x16)?) as usize,
+                version: (data[6], data[7]),
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// A structure in the SMBIOS table: its formatted area, followed by the
+/// strings it refers to.
+struct Structure<'a> {
+    formatted: &'a [u8],
+    strings: &'a [u8],
+}
+
+impl Structure<'_> {
+    /// Returns the string referenced by the byte at `offset`.
+    fn string(&self, offset: usize) -> Option<String> {
+        let index = *self.formatted.get(offset)? as usize;
+        if index == 0 {
+            return None;
+        }
+        let s = self.strings.split(|&b| b == 0).nth(index - 1)?;
+        Some(String::from_utf8_lossy(s).trim().into())
+    }
+
+    /// Returns the UUID at `offset`. Since SMBIOS 2.6, its first three fields
+    /// are stored in little-endian.
+    fn uuid(&self, offset: usize, little_endian: bool) -> Option<String> {
+        let mut b: [u8; 16] = read_le(self.formatted, offset)?;
+        if b.iter().all(|&it| it == 0) || b.iter().all(|&it| it == 0xff) {
+            return None;
+        }
+        if little_endian {
+            b[..4].reverse();
+            b[4..6].reverse();
+            b[6..8].reverse();
+        }
+        let mut uuid = String::new();
+        for (i, byte) in b.iter().enumerate() {
+            if matches!(i, 4 | 6 | 8 | 10) {
+                uuid.push('-');
+            }
+            write!(uuid, "{byte:02x}").unwrap();
+        }
+        Some(uuid)
+    }
+}
+
+fn parse_table(mut table: &[u8], version: (u8, u8)) -> Vec<(&'static str, String)> {
+    let mut fields = Vec::new();
+    let mut add = |name, value: Option<String>| {
+        if let Some(value) = value.filter(|it| !it.is_empty()) {
+            fields.push((name, value));
+        }
+    };
+
+    while table.len() >= 4 {
+        let (ty, len) = (table[0], table[1] as usize);
+        if len < 4 || len > table.len() {
+            break;
+        }
+        let (formatted, rest) = table.split_at(len);
+        // The string set is terminated by two NULs.
+        let strings_len = rest
+            .windows(2)
+            .position(|it| it == [0, 0])
+            .map_or(rest.len(), |pos| pos + 2);
+        let s = Structure {
+            formatted,
+            strings: &rest[..strings_len],
+        };
+        table = &rest[strings_len..];
+
+        match ty {
+            // BIOS information
+            0 => {
+                add("bios_vendor", s.string(4));
+                add("bios_version", s.string(5));
+                add("bios_date", s.string(8));
+            }
+            // System information
+            1 => {
+                add("sys_vendor", s.string(4));
+                add("product_name", s.string(5));
+                add("product_version", s.string(6));
+                add("product_serial", s.string(7));
+                add("product_uuid", s.uuid(8, version >= (2, 6)));
+            }
+            // Baseboard information
+            2 => {
+                add("board_vendor", s.string(4));
+                add("board_name", s.string(5));
+                add("board_version", s.string(6));
+                add("board_serial", s.string(7));
+                add("board_asset_tag", s.string(8));
+            }
+            // System enclosure
+            3 => {
+                add("chassis_vendor", s.string(4));
+                add("chassis_type", formatted.get(5).map(|it| (it & 0x7f).to_string()));
+                add("chassis_version", s.string(6));
+                add("chassis_serial", s.string(7));
+                add("chassis_asset_tag", s.string(8));
+            }
+            // End of table
+            127 => break,
+            _ => {}
+        }
+    }
+    fields
+}
+
+/// Returns the DMI field with the given name.
+fn dmi_field(name: &str) -> Option<&'static str> {
+    DMI_FIELDS
+        .get()?
+        .iter()
+        .find(|(it, _)| *it == name)
+        .map(|(_, value)| value.as_str())
+}
+
+/// Parses the SMBIOS tables and logs the board identification.
+pub fn init() {
+    let Some((table, version)) = find_tables() else {
+        info!("DMI not present or invalid");
+        return;
+    };
+    DMI_FIELDS.call_once(|| parse_table(&table, version));
+
+    let field = |name| dmi_field(name).unwrap_or_default();
+    info!(
+        "DMI: {} {}/{}, BIOS {} {}",
+        field("sys_vendor"),
+        field("product_name"),
+        field("board_name"),
+        field("bios_version"),
+        field("bios_date")
+    );
+}
+
+/// Returns whether SMBIOS tables were found at boot.
+pub fn available() -> bool {
+    DMI_FIELDS.get().is_some()
+}
+
+/// The fields that identify the machine, which only root may read.
+const PRIVATE_FIELDS: [&str; 4] = [
+    "product_serial",
+    "product_uuid",
+    "board_serial",
+    "chassis_serial",
+];
+
+/// Builds the /sys/class/dmi/id directory.
+pub(super) fn builder(fs: Arc<SimpleFs>) -> DirMaker {
+    let mut root = DirMapping::new();
+    for (name, value) in DMI_FIELDS.get().into_iter().flatten() {
+        let file = SimpleFile::new_regular(fs.clone(), move || Ok(format!("{value}\n")));
+        if PRIVATE_FIELDS.contains(name) {
+            file.set_permission(NodePermission::from_bits_truncate(0o400));
+        }
+        root.add(*name, file);
+    }
+    SimpleDir::new_maker(fs, Arc::new(root))
+}
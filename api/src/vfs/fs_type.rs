@@ -0,0 +1,53 @@
+//! Filesystem types registered at runtime, which `mount(2)` offers besides
+//! the built-in ones.
+
+use alloc::{sync::Arc, vec::Vec};
+
+use axerrno::{AxError, AxResult};
+use axfs_ng_vfs::Filesystem;
+use spin::Mutex;
+
+/// The filesystem types `mount(2)` handles itself, which can't be replaced.
+const BUILTIN_FS_TYPES: &[&str] = &["tmpfs", "devpts", "proc"];
+
+/// A type of filesystem that can be mounted.
+pub trait FilesystemType: Send + Sync {
+    /// Returns the name given to `mount(2)`, e.g. `ext4`.
+    fn name(&self) -> &str;
+
+    /// Creates a filesystem to mount from `source`, with the
+    /// filesystem-specific options `data`.
+    fn mount(&self, source: &str, data: &str) -> AxResult<Filesystem>;
+}
+
+static FS_TYPES: Mutex<Vec<Arc<dyn FilesystemType>>> = Mutex::new(Vec::new());
+
+/// Registers a filesystem type, failing with `EEXIST` if one of the same
+/// name exists.
+pub fn register_filesystem(fs_type: Arc<dyn FilesystemType>) -> AxResult<()> {
+    let mut fs_types = FS_TYPES.lock();
+    let name = fs_type.name();
+    if BUILTIN_FS_TYPES.contains(&name) || fs_types.iter().any(|it| it.name() == name) {
+        return Err(AxError::AlreadyExists);
+    }
+    info!("Registered filesystem type {name}");
+    fs_types.push(fs_type);
+    Ok(())
+}
+
+/// Unregisters a filesystem type. Filesystems already mounted stay so.
+pub fn unregister_filesystem(name: &str) -> AxResult<()> {
+    let mut fs_types = FS_TYPES.lock();
+    let index = fs_types
+        .iter()
+        .position(|it| it.name() == name)
+        .ok_or(AxError::InvalidInput)?;
+    fs_types.remove(index);
+    info!("Unregistered filesystem type {name}");
+    Ok(())
+}
+
+/// Looks up a registered filesystem type by name.
+pub fn find_filesystem(name: &str) -> Option<Arc<dyn FilesystemType>> {
+    FS_TYPES.lock().iter().find(|it| it.name() == name).cloned()
+}
@@ -1,25 +1,42 @@
 //! Virtual filesystems
 
+mod binfmt_misc;
 pub mod dev;
+pub mod dmi;
+pub mod fs_type;
+pub mod mounts;
 mod proc;
+mod sys;
 mod tmp;
+mod trace;
+mod vulnerabilities;
 
 use axerrno::LinuxResult;
 use axfs::{FS_CONTEXT, FsContext};
-use axfs_ng_vfs::{
-    Filesystem, NodePermission,
-    path::{Path, PathBuf},
-};
+use axfs_ng_vfs::{Filesystem, NodePermission};
+use mounts::MountFlags;
+pub use proc::{HidePid, ProcFsOptions, new_procfs};
 pub use starry_core::vfs::{Device, DeviceOps, DirMapping, SimpleFs};
 pub use tmp::MemoryFs;
 
 const DIR_PERMISSION: NodePermission = NodePermission::from_bits_truncate(0o755);
 
-fn mount_at(fs: &FsContext, path: &str, mount_fs: Filesystem) -> LinuxResult<()> {
+/// Flags of the pseudo filesystems that hold no programs or devices.
+const PSEUDO_FS_FLAGS: MountFlags = MountFlags::NOSUID
+    .union(MountFlags::NODEV)
+    .union(MountFlags::NOEXEC);
+
+fn mount_at(
+    fs: &FsContext,
+    path: &str,
+    mount_fs: Filesystem,
+    flags: MountFlags,
+) -> LinuxResult<()> {
     if fs.resolve(path).is_err() {
         fs.create_dir(path, DIR_PERMISSION)?;
     }
     fs.resolve(path)?.mount(&mount_fs)?;
+    mounts::add_mount(&fs.resolve(path)?, mount_fs.name(), flags, "")?;
     info!("Mounted {} at {}", mount_fs.name(), path);
     Ok(())
 }
@@ -27,21 +44,29 @@ fn mount_at(fs: &FsContext, path: &str, mount_fs: Filesystem) -> LinuxResult<()>
 /// Mount all filesystems
 pub fn mount_all() -> LinuxResult<()> {
     let fs = FS_CONTEXT.lock();
-    mount_at(&fs, "/dev", dev::new_devfs())?;
-    mount_at(&fs, "/dev/shm", tmp::MemoryFs::new())?;
-    mount_at(&fs, "/tmp", tmp::MemoryFs::new())?;
-    mount_at(&fs, "/proc", proc::new_procfs())?;
-
-    mount_at(&fs, "/sys", tmp::MemoryFs::new())?;
-    let mut path = PathBuf::new();
-    for comp in Path::new("/sys/class/graphics/fb0/device").components() {
-        path.push(comp.as_str());
-        if fs.resolve(&path).is_err() {
-            fs.create_dir(&path, DIR_PERMISSION)?;
-        }
-    }
-    path.push("subsystem");
-    fs.symlink("whatever", &path)?;
+    mounts::add_mount(&fs.resolve("/")?, "/dev/root", MountFlags::empty(), "")?;
+    mount_at(&fs, "/dev", dev::new_devfs(), MountFlags::NOSUID)?;
+    mount_at(
+        &fs,
+        "/dev/shm",
+        tmp::MemoryFs::new(),
+        MountFlags::NOSUID | MountFlags::NODEV,
+    )?;
+    mount_at(&fs, "/tmp", tmp::MemoryFs::new(), MountFlags::empty())?;
+    mount_at(
+        &fs,
+        "/proc",
+        new_procfs(ProcFsOptions::default()),
+        PSEUDO_FS_FLAGS,
+    )?;
+
+    mount_at(&fs, "/sys", sys::new_sysfs(), PSEUDO_FS_FLAGS)?;
+    mount_at(
+        &fs,
+        "/sys/kernel/tracing",
+        trace::new_tracefs(),
+        PSEUDO_FS_FLAGS,
+    )?;
     drop(fs);
 
     #[cfg(feature = "dev-log")]
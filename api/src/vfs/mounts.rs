@@ -0,0 +1,296 @@
+//! The table of mounted filesystems shown in /proc/[pid]/mounts and
+//! mountinfo.
+//!
+//! The VFS keeps mounts as a tree of mountpoints that can't be listed, so
+//! each mount is recorded here as well, in the order it was made.
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use axerrno::{AxError, AxResult};
+use axfs_ng_vfs::Location;
+use kspin::SpinNoIrq;
+use linux_raw_sys::general::{
+    MS_NOATIME, MS_NODEV, MS_NODIRATIME, MS_NOEXEC, MS_NOSUID, MS_RDONLY, MS_STRICTATIME,
+};
+
+/// The bits of `statfs.f_flags` that are valid.
+const ST_VALID: u32 = 0x20;
+
+bitflags::bitflags! {
+    /// Per-mount flags, as passed to `mount(2)`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct MountFlags: u32 {
+        const RDONLY = MS_RDONLY;
+        const NOSUID = MS_NOSUID;
+        const NODEV = MS_NODEV;
+        const NOEXEC = MS_NOEXEC;
+        const NOATIME = MS_NOATIME;
+        const NODIRATIME = MS_NODIRATIME;
+        const STRICTATIME = MS_STRICTATIME;
+    }
+}
+
+/// How mount and unmount events propagate to and from a mount.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Propagation {
+    Private,
+    /// A member of the given peer group.
+    Shared(u32),
+    /// Receives events from the given peer group.
+    Slave(u32),
+    Unbindable,
+}
+
+struct Mount {
+    id: u32,
+    parent: u32,
+    device: u64,
+    path: String,
+    fs_type: String,
+    source: String,
+    flags: MountFlags,
+    /// The filesystem-specific options it was mounted with.
+    data: String,
+    propagation: Propagation,
+}
+
+impl Mount {
+    fn options(&self) -> String {
+        let mut options = String::from(if self.flags.contains(MountFlags::RDONLY) {
+            "ro"
+        } else {
+            "rw"
+        });
+        for (flag, name) in [
+            (MountFlags::NOSUID, "nosuid"),
+            (MountFlags::NODEV, "nodev"),
+            (MountFlags::NOEXEC, "noexec"),
+            (MountFlags::NOATIME, "noatime"),
+            (MountFlags::NODIRATIME, "nodiratime"),
+        ] {
+            if self.flags.contains(flag) {
+                options += ",";
+                options += name;
+            }
+        }
+        if !self
+            .flags
+            .intersects(MountFlags::NOATIME | MountFlags::STRICTATIME)
+        {
+            options += ",relatime";
+        }
+        options
+    }
+
+    fn super_options(&self) -> String {
+        let mode = if self.flags.contains(MountFlags::RDONLY) {
+            "ro"
+        } else {
+            "rw"
+        };
+        if self.data.is_empty() {
+            mode.into()
+        } else {
+            format!("{mode},{}", self.data)
+        }
+    }
+}
+
+/// Whether `path` is at or below `base`.
+fn is_below(path: &str, base: &str) -> bool {
+    base == "/"
+        || path
+            .strip_prefix(base)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+struct MountTable {
+    mounts: Vec<Mount>,
+    next_id: u32,
+    next_group: u32,
+}
+
+static MOUNTS: SpinNoIrq<MountTable> = SpinNoIrq::new(MountTable {
+    mounts: Vec::new(),
+    next_id: 1,
+    next_group: 1,
+});
+
+/// Splits a device number encoded as by Linux's `new_encode_dev` into its
+/// major and minor numbers.
+pub fn split_device(device: u64) -> (u64, u64) {
+    let major = ((device >> 8) & 0xfff) | ((device >> 32) & !0xfff);
+    let minor = (device & 0xff) | ((device >> 12) & !0xff);
+    (major, minor)
+}
+
+fn path_of(loc: &Location) -> AxResult<String> {
+    Ok(loc.absolute_path()?.to_string())
+}
+
+/// Records that a filesystem has been mounted, `root` being the root of the
+/// new mount.
+pub fn add_mount(root: &Location, source: &str, flags: MountFlags, data: &str) -> AxResult<()> {
+    let path = path_of(root)?;
+    let mut table = MOUNTS.lock();
+    let id = table.next_id;
+    table.next_id += 1;
+    let parent = table
+        .mounts
+        .iter()
+        .rev()
+        .find(|it| is_below(&path, &it.path))
+        .map_or(id, |it| it.id);
+    table.mounts.push(Mount {
+        id,
+        parent,
+        device: root.mountpoint().device() as _,
+        path,
+        fs_type: root.filesystem().name().to_string(),
+        source: source.into(),
+        flags,
+        data: data.into(),
+        propagation: Propagation::Private,
+    });
+    Ok(())
+}
+
+/// Forgets the topmost mount at `path`, which has been unmounted.
+pub fn remove_mount(path: &str) {
+    let mut table = MOUNTS.lock();
+    if let Some(index) = table.mounts.iter().rposition(|it| it.path == path) {
+        table.mounts.remove(index);
+    }
+}
+
+/// Changes the flags and options of the mount at `root`, as for
+/// `MS_REMOUNT`.
+pub fn remount(root: &Location, flags: MountFlags, data: Option<&str>) -> AxResult<()> {
+    let path = path_of(root)?;
+    let mut table = MOUNTS.lock();
+    let mount = table
+        .mounts
+        .iter_mut()
+        .rev()
+        .find(|it| it.path == path)
+        .ok_or(AxError::InvalidInput)?;
+    mount.flags = flags;
+    if let Some(data) = data {
+        mount.data = data.into();
+    }
+    Ok(())
+}
+
+/// Changes the propagation type of the mount at `root`, and of all mounts
+/// below it if `recursive`.
+pub fn set_propagation(root: &Location, propagation: Propagation, recursive: bool) -> AxResult<()> {
+    let path = path_of(root)?;
+    let mut table = MOUNTS.lock();
+    let target = table
+        .mounts
+        .iter()
+        .rposition(|it| it.path == path)
+        .ok_or(AxError::InvalidInput)?;
+    let MountTable {
+        mounts, next_group, ..
+    } = &mut *table;
+    for (index, mount) in mounts.iter_mut().enumerate() {
+        let selected =
+            index == target || (recursive && index > target && is_below(&mount.path, &path));
+        if !selected {
+            continue;
+        }
+        mount.propagation = match (propagation, mount.propagation) {
+            (Propagation::Shared(_), Propagation::Shared(group)) => Propagation::Shared(group),
+            (Propagation::Shared(_), _) => {
+                *next_group += 1;
+                Propagation::Shared(*next_group - 1)
+            }
+            // A shared mount made a slave receives from its old peer group.
+            (Propagation::Slave(_), Propagation::Shared(group) | Propagation::Slave(group)) => {
+                Propagation::Slave(group)
+            }
+            (Propagation::Slave(_), _) => Propagation::Private,
+            (propagation, _) => propagation,
+        };
+    }
+    Ok(())
+}
+
+/// Returns the `statfs.f_flags` of the filesystem with the given device.
+pub fn statfs_flags(device: u64) -> u32 {
+    let table = MOUNTS.lock();
+    let flags = table
+        .mounts
+        .iter()
+        .rev()
+        .find(|it| it.device == device)
+        .map_or(MountFlags::empty(), |it| it.flags);
+    flags.bits() | ST_VALID
+}
+
+/// Escapes the characters that would break the fields of a mount table.
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            ' ' | '\t' | '\n' | '\\' => out += &format!("\\{:03o}", c as u32),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Formats the contents of /proc/[pid]/mounts.
+pub fn format_mounts() -> String {
+    MOUNTS
+        .lock()
+        .mounts
+        .iter()
+        .map(|it| {
+            let mut options = it.options();
+            if !it.data.is_empty() {
+                options += ",";
+                options += &it.data;
+            }
+            format!(
+                "{} {} {} {options} 0 0\n",
+                escape(&it.source),
+                escape(&it.path),
+                it.fs_type,
+            )
+        })
+        .collect()
+}
+
+/// Formats the contents of /proc/[pid]/mountinfo.
+pub fn format_mountinfo() -> String {
+    MOUNTS
+        .lock()
+        .mounts
+        .iter()
+        .map(|it| {
+            let (major, minor) = split_device(it.device);
+            let optional = match it.propagation {
+                Propagation::Private => String::new(),
+                Propagation::Shared(group) => format!("shared:{group} "),
+                Propagation::Slave(group) => format!("master:{group} "),
+                Propagation::Unbindable => "unbindable ".into(),
+            };
+            format!(
+                "{} {} {major}:{minor} / {} {} {optional}- {} {} {}\n",
+                it.id,
+                it.parent,
+                escape(&it.path),
+                it.options(),
+                it.fs_type,
+                escape(&it.source),
+                it.super_options(),
+            )
+        })
+        .collect()
+}
@@ -1,27 +1,49 @@
 use alloc::{
     borrow::Cow,
     boxed::Box,
+    collections::vec_deque::VecDeque,
     format,
     string::{String, ToString},
     sync::{Arc, Weak},
     vec,
     vec::Vec,
 };
-use core::{ffi::CStr, iter};
+#[cfg(feature = "time-warp")]
+use core::time::Duration;
+use core::{ffi::CStr, iter, sync::atomic::Ordering};
 
+use axconfig::plat::CPU_NUM;
 use axfs_ng_vfs::{Filesystem, NodeType, VfsError, VfsResult};
-use axtask::{AxTaskRef, WeakAxTaskRef, current};
+use axhal::paging::MappingFlags;
+use axmm::backend::Backend;
+use axtask::{AxCpuMask, AxTaskRef, WeakAxTaskRef, current};
 use indoc::indoc;
+use memory_addr::{PAGE_SIZE_4K, VirtAddr, VirtAddrRange};
 use starry_core::{
-    task::{AsThread, TaskStat, get_task, tasks},
+    config::{SIGNAL_TRAMPOLINE, USER_SPACE_BASE, USER_SPACE_SIZE},
+    cred::IdSet,
+    kmsg,
+    logfilter::{self, LevelFilter},
+    mlock::all_areas,
+    mm::{MMAP_MIN_ADDR, RANDOMIZE_VA_SPACE, resident_size},
+    sched,
+    task::{AsThread, PID_MAX, ProcessData, TaskStat, get_task, tasks},
+    time::TimeNsOffsets,
     vfs::{
         DirMaker, DirMapping, NodeOpsMux, RwFile, SimpleDir, SimpleDirOps, SimpleFile,
         SimpleFileOperation, SimpleFs,
     },
 };
+#[cfg(feature = "time-warp")]
+use starry_core::time::TimeNamespace;
 use starry_process::Process;
+use starry_signal::{SignalSet, Signo};
 
-use crate::file::FD_TABLE;
+use super::{
+    binfmt_misc::binfmt_misc_dir,
+    mounts::{format_mountinfo, format_mounts, split_device},
+};
+use crate::{file::FD_TABLE, task::PRINT_FATAL_SIGNALS};
 
 const DUMMY_MEMINFO: &str = indoc! {"
     MemTotal:       32536204 kB
@@ -83,13 +105,76 @@ const DUMMY_MEMINFO: &str = indoc! {"
     DirectMap1G:     1048576 kB
 "};
 
-pub fn new_procfs() -> Filesystem {
-    SimpleFs::new_with("proc".into(), 0x9fa0, builder)
+/// The `hidepid=` mount option of procfs.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HidePid {
+    /// Everybody may access all /proc/[pid] directories.
+    #[default]
+    Off,
+    /// Users may not access files inside /proc/[pid] directories other than
+    /// their own, but the directories themselves remain visible.
+    NoAccess,
+    /// Like [`HidePid::NoAccess`], but inaccessible /proc/[pid] directories
+    /// are hidden as well.
+    Invisible,
+}
+
+impl HidePid {
+    fn parse(value: &str) -> Option<Self> {
+        Some(match value {
+            "0" | "off" => Self::Off,
+            "1" | "noaccess" => Self::NoAccess,
+            "2" | "invisible" => Self::Invisible,
+            _ => return None,
+        })
+    }
+
+    /// Returns whether the option, if at least as strict as `level`, hides
+    /// /proc/[pid] of `task` from the current task: that is, if the current
+    /// task may not access `task`, as decided from their credentials by
+    /// [`may_access`].
+    fn hides(self, level: HidePid, task: &AxTaskRef) -> bool {
+        self >= level && !may_access(task)
+    }
+}
+
+/// Mount options of procfs.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ProcFsOptions {
+    /// Restricts access to /proc/[pid] directories of other processes.
+    pub hidepid: HidePid,
+}
+
+impl ProcFsOptions {
+    /// Parses the comma-separated `data` argument of `mount(2)`.
+    pub fn parse(data: &str) -> Option<Self> {
+        let mut options = Self::default();
+        for opt in data.split(',').filter(|it| !it.is_empty()) {
+            match opt.split_once('=') {
+                Some(("hidepid", value)) => options.hidepid = HidePid::parse(value)?,
+                _ => {
+                    warn!("Unsupported procfs mount option: {opt}");
+                }
+            }
+        }
+        Some(options)
+    }
+}
+
+pub fn new_procfs(options: ProcFsOptions) -> Filesystem {
+    SimpleFs::new_with("proc".into(), 0x9fa0, move |fs| builder(fs, options))
+}
+
+/// Checks whether the current task may read sensitive per-process state of
+/// `task`, such as its environment or memory layout.
+fn may_access(task: &AxTaskRef) -> bool {
+    crate::task::may_access(&task.as_thread().proc_data)
 }
 
 struct ProcessTaskDir {
     fs: Arc<SimpleFs>,
     process: Weak<Process>,
+    hidepid: HidePid,
 }
 
 impl SimpleDirOps for ProcessTaskDir {
@@ -118,6 +203,7 @@ impl SimpleDirOps for ProcessTaskDir {
             Arc::new(ThreadDir {
                 fs: self.fs.clone(),
                 task: Arc::downgrade(&task),
+                hidepid: self.hidepid,
             }),
         )))
     }
@@ -127,22 +213,329 @@ impl SimpleDirOps for ProcessTaskDir {
     }
 }
 
+/// Formats a CPU mask as hexadecimal, in comma-separated groups of 32 bits.
+fn format_cpumask(mask: AxCpuMask) -> String {
+    let mut groups = Vec::new();
+    for start in (0..CPU_NUM).step_by(32) {
+        let group = (start..CPU_NUM.min(start + 32))
+            .filter(|cpu| mask.get(*cpu))
+            .fold(0u32, |acc, cpu| acc | 1 << (cpu - start));
+        groups.push(group);
+    }
+    let mut groups = groups.into_iter().rev();
+    let mut out = format!("{:x}", groups.next().unwrap_or(0));
+    for group in groups {
+        out += &format!(",{group:08x}");
+    }
+    out
+}
+
+/// Formats a CPU mask as a list of ranges, like `0-2,4`.
+fn format_cpulist(mask: AxCpuMask) -> String {
+    let mut ranges = Vec::<(usize, usize)>::new();
+    for cpu in (0..CPU_NUM).filter(|cpu| mask.get(*cpu)) {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == cpu => *end = cpu,
+            _ => ranges.push((cpu, cpu)),
+        }
+    }
+    ranges
+        .into_iter()
+        .map(|(start, end)| {
+            if start == end {
+                format!("{start}")
+            } else {
+                format!("{start}-{end}")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Formats a signal set as the hex mask shown in /proc/[pid]/status.
+fn format_sigset(set: SignalSet) -> String {
+    let bits = (1..=64)
+        .filter_map(Signo::from_repr)
+        .filter(|&signo| set.has(signo))
+        .fold(0u64, |bits, signo| bits | 1 << (signo as u8 - 1));
+    format!("{bits:016x}")
+}
+
+fn state_name(state: char) -> &'static str {
+    match state {
+        'R' => "R (running)",
+        'S' => "S (sleeping)",
+        'D' => "D (disk sleep)",
+        'T' => "T (stopped)",
+        't' => "t (tracing stop)",
+        'Z' => "Z (zombie)",
+        _ => "X (dead)",
+    }
+}
+
+/// Formats the real, effective, saved and filesystem IDs, as in the `Uid:`
+/// and `Gid:` lines of /proc/[pid]/status. The filesystem ID always follows
+/// the effective one.
+fn format_ids(ids: IdSet) -> String {
+    format!(
+        "{}\t{}\t{}\t{}",
+        ids.real, ids.effective, ids.saved, ids.effective
+    )
+}
+
 #[rustfmt::skip]
 fn task_status(task: &AxTaskRef) -> String {
+    let thr = task.as_thread();
+    let proc = &thr.proc_data.proc;
+    let cpus = sched::affinity(task);
+    let cred = thr.proc_data.cred.read().clone();
+    let (size, rss) = map_entries(&thr.proc_data)
+        .iter()
+        .fold((0, 0), |(size, rss), it| (size + it.range.size(), rss + it.rss));
     format!(
-        "Tgid:\t{}\n\
+        "Name:\t{}\n\
+        State:\t{}\n\
+        Tgid:\t{}\n\
         Pid:\t{}\n\
-        Uid:\t0 0 0 0\n\
-        Gid:\t0 0 0 0\n\
-        Cpus_allowed:\t1\n\
-        Cpus_allowed_list:\t0\n\
+        PPid:\t{}\n\
+        TracerPid:\t{}\n\
+        Uid:\t{}\n\
+        Gid:\t{}\n\
+        VmSize:\t{} kB\n\
+        VmRSS:\t{} kB\n\
+        Threads:\t{}\n\
+        SigPnd:\t{}\n\
+        SigBlk:\t{}\n\
+        Cpus_allowed:\t{}\n\
+        Cpus_allowed_list:\t{}\n\
         Mems_allowed:\t1\n\
-        Mems_allowed_list:\t0",
-        task.as_thread().proc_data.proc.pid(),
-        task.id().as_u64()
+        Mems_allowed_list:\t0\n",
+        task.name(),
+        state_name(TaskStat::from_thread(task).map_or('R', |it| it.state)),
+        proc.pid(),
+        thr.tid(),
+        proc.parent().map_or(0, |it| it.pid()),
+        thr.trace.tracer().unwrap_or(0),
+        format_ids(cred.uid),
+        format_ids(cred.gid),
+        size / 1024,
+        rss / 1024,
+        proc.threads().len(),
+        format_sigset(thr.signal.pending()),
+        format_sigset(thr.signal.blocked()),
+        format_cpumask(cpus),
+        format_cpulist(cpus),
     )
 }
 
+/// A mapping of a process, as listed in /proc/[pid]/maps and smaps.
+struct MapEntry {
+    range: VirtAddrRange,
+    flags: MappingFlags,
+    shared: bool,
+    offset: u64,
+    device: u64,
+    inode: u64,
+    name: String,
+    /// How many bytes of the mapping are resident.
+    rss: usize,
+}
+
+impl MapEntry {
+    /// Formats the line describing the mapping in /proc/[pid]/maps.
+    fn header(&self) -> String {
+        let flag = |flag, c| if self.flags.contains(flag) { c } else { '-' };
+        let (major, minor) = split_device(self.device);
+        let mut line = format!(
+            "{:08x}-{:08x} {}{}{}{} {:08x} {major:02x}:{minor:02x} {}",
+            self.range.start,
+            self.range.end,
+            flag(MappingFlags::READ, 'r'),
+            flag(MappingFlags::WRITE, 'w'),
+            flag(MappingFlags::EXECUTE, 'x'),
+            if self.shared { 's' } else { 'p' },
+            self.offset,
+            self.inode,
+        );
+        if !self.name.is_empty() {
+            line = format!("{line:<72} {}", self.name);
+        }
+        line.push('\n');
+        line
+    }
+
+    /// Formats the entry of the mapping in /proc/[pid]/smaps.
+    fn smaps(&self) -> String {
+        let kb = |bytes: usize| bytes / 1024;
+        let anonymous = if self.inode == 0 && !self.shared {
+            self.rss
+        } else {
+            0
+        };
+        let (shared, private) = if self.shared {
+            (self.rss, 0)
+        } else {
+            (0, self.rss)
+        };
+        let mut out = self.header();
+        for (name, value) in [
+            ("Size", kb(self.range.size())),
+            ("KernelPageSize", 4),
+            ("MMUPageSize", 4),
+            ("Rss", kb(self.rss)),
+            ("Pss", kb(self.rss)),
+            ("Shared_Clean", 0),
+            ("Shared_Dirty", kb(shared)),
+            ("Private_Clean", 0),
+            ("Private_Dirty", kb(private)),
+            ("Referenced", kb(self.rss)),
+            ("Anonymous", kb(anonymous)),
+            ("Swap", 0),
+            ("SwapPss", 0),
+            ("Locked", 0),
+        ] {
+            out += &format!("{:<16}{value:>8} kB\n", format!("{name}:"));
+        }
+        let vm_flags = [
+            (self.flags.contains(MappingFlags::READ), "rd"),
+            (self.flags.contains(MappingFlags::WRITE), "wr"),
+            (self.flags.contains(MappingFlags::EXECUTE), "ex"),
+            (self.shared, "sh"),
+        ]
+        .into_iter()
+        .filter_map(|(set, name)| set.then_some(name))
+        .collect::<Vec<_>>();
+        out += &format!("VmFlags: {} \n", vm_flags.join(" "));
+        out
+    }
+}
+
+/// Lists the user mappings of a process in address order.
+fn map_entries(proc_data: &ProcessData) -> Vec<MapEntry> {
+    let aspace = proc_data.aspace.lock();
+    let files = proc_data.mapped_files.lock();
+    let heap = VirtAddr::from(proc_data.heap_base());
+    let stack = proc_data.stacks.lock().first().copied();
+    let trampoline = VirtAddr::from(SIGNAL_TRAMPOLINE);
+
+    all_areas(&aspace)
+        .into_iter()
+        .filter(|(_, flags)| flags.contains(MappingFlags::USER))
+        .map(|(range, flags)| {
+            let shared = aspace.find_area(range.start).is_some_and(|area| {
+                matches!(area.backend(), Backend::Shared(_) | Backend::File(_))
+            });
+            let (offset, device, inode, name) = match files.get(range.start) {
+                Some((loc, offset)) => {
+                    let (device, inode) = loc
+                        .metadata()
+                        .map_or((0, 0), |meta| (meta.device, meta.inode));
+                    let path = loc
+                        .absolute_path()
+                        .map_or_else(|_| "<error>".into(), |path| path.to_string());
+                    (offset, device, inode, path)
+                }
+                None => {
+                    let name = if range.contains(heap) {
+                        "[heap]"
+                    } else if stack.is_some_and(|stack| stack.contains(range.start)) {
+                        "[stack]"
+                    } else if range.contains(trampoline) {
+                        "[sigpage]"
+                    } else {
+                        ""
+                    };
+                    (0, 0, 0, name.into())
+                }
+            };
+            MapEntry {
+                range,
+                flags,
+                shared,
+                offset,
+                device,
+                inode,
+                name,
+                rss: resident_size(&aspace, range),
+            }
+        })
+        .collect()
+}
+
+const NANOS_PER_SEC: i64 = 1_000_000_000;
+
+/// Formats the contents of /proc/[pid]/timens_offsets.
+fn format_timens_offsets(offsets: TimeNsOffsets) -> String {
+    [("monotonic", offsets.monotonic), ("boottime", offsets.boottime)]
+        .into_iter()
+        .map(|(name, offset)| {
+            format!(
+                "{name:<10} {:>10} {:>9}\n",
+                offset.div_euclid(NANOS_PER_SEC),
+                offset.rem_euclid(NANOS_PER_SEC)
+            )
+        })
+        .collect()
+}
+
+/// Parses lines of the form `<clock> <secs> <nanosecs>` written to
+/// /proc/[pid]/timens_offsets, where the clock is given by name or by ID, and
+/// applies them to `offsets`.
+fn parse_timens_offsets(mut offsets: TimeNsOffsets, data: &[u8]) -> VfsResult<TimeNsOffsets> {
+    let data = str::from_utf8(data).map_err(|_| VfsError::InvalidInput)?;
+    for line in data.lines().filter(|it| !it.trim().is_empty()) {
+        let mut fields = line.split_whitespace();
+        let (Some(clock), Some(secs), Some(nsecs), None) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            return Err(VfsError::InvalidInput);
+        };
+        let secs = secs.parse::<i64>().map_err(|_| VfsError::InvalidInput)?;
+        let nsecs = nsecs
+            .parse::<i64>()
+            .ok()
+            .filter(|it| (0..NANOS_PER_SEC).contains(it))
+            .ok_or(VfsError::InvalidInput)?;
+        let offset = secs
+            .checked_mul(NANOS_PER_SEC)
+            .and_then(|it| it.checked_add(nsecs))
+            .ok_or(VfsError::InvalidInput)?;
+        match clock {
+            "monotonic" | "1" => offsets.monotonic = offset,
+            "boottime" | "7" => offsets.boottime = offset,
+            _ => return Err(VfsError::InvalidInput),
+        }
+    }
+    Ok(offsets)
+}
+
+/// Applies a command written to /proc/[pid]/time_warp, which controls the
+/// clocks of the process's time namespace for reproducible timing tests.
+/// These are `CLOCK_MONOTONIC` and `CLOCK_BOOTTIME`, along with the sleeps and
+/// timers on them; `CLOCK_REALTIME` stays the host's.
+///
+/// `freeze` stops the clocks, `resume` lets them run at the host's rate again,
+/// a plain number sets their rate in percent of the host's, and
+/// `+<nanoseconds>` moves them forward.
+#[cfg(feature = "time-warp")]
+fn apply_time_warp(time_ns: &TimeNamespace, data: &[u8]) -> VfsResult<()> {
+    let cmd = str::from_utf8(data).map_err(|_| VfsError::InvalidInput)?.trim();
+    match cmd {
+        "freeze" => time_ns.set_warp_rate(0),
+        "resume" => time_ns.set_warp_rate(100),
+        _ => {
+            if let Some(nanos) = cmd.strip_prefix('+') {
+                let nanos = nanos.parse().map_err(|_| VfsError::InvalidInput)?;
+                time_ns.warp_advance(Duration::from_nanos(nanos));
+            } else {
+                let rate = cmd.parse().map_err(|_| VfsError::InvalidInput)?;
+                time_ns.set_warp_rate(rate);
+            }
+        }
+    }
+    Ok(())
+}
+
 /// The /proc/[pid]/fd directory
 struct ThreadFdDir {
     fs: Arc<SimpleFs>,
@@ -187,6 +580,7 @@ impl SimpleDirOps for ThreadFdDir {
 struct ThreadDir {
     fs: Arc<SimpleFs>,
     task: WeakAxTaskRef,
+    hidepid: HidePid,
 }
 
 impl SimpleDirOps for ThreadDir {
@@ -198,13 +592,21 @@ impl SimpleDirOps for ThreadDir {
                 "oom_score_adj",
                 "task",
                 "maps",
+                "smaps",
                 "mounts",
+                "mountinfo",
                 "cmdline",
+                "environ",
+                "cgroup",
+                "timens_offsets",
                 "comm",
                 "exe",
                 "fd",
+                "syscall_trace",
+                "wchan",
             ]
             .into_iter()
+            .chain(cfg!(feature = "time-warp").then_some("time_warp"))
             .map(Cow::Borrowed),
         )
     }
@@ -212,6 +614,9 @@ impl SimpleDirOps for ThreadDir {
     fn lookup_child(&self, name: &str) -> VfsResult<NodeOpsMux> {
         let fs = self.fs.clone();
         let task = self.task.upgrade().ok_or(VfsError::NotFound)?;
+        if self.hidepid.hides(HidePid::NoAccess, &task) {
+            return Err(VfsError::PermissionDenied);
+        }
         Ok(match name {
             "stat" => SimpleFile::new_regular(fs, move || {
                 Ok(format!("{}", TaskStat::from_thread(&task)?).into_bytes())
@@ -242,22 +647,29 @@ impl SimpleDirOps for ThreadDir {
                 Arc::new(ProcessTaskDir {
                     fs,
                     process: Arc::downgrade(&task.as_thread().proc_data.proc),
+                    hidepid: self.hidepid,
                 }),
             )
             .into(),
-            "maps" => SimpleFile::new_regular(fs, move || {
-                Ok(indoc! {"
-                    7f000000-7f001000 r--p 00000000 00:00 0          [vdso]
-                    7f001000-7f003000 r-xp 00001000 00:00 0          [vdso]
-                    7f003000-7f005000 r--p 00003000 00:00 0          [vdso]
-                    7f005000-7f007000 rw-p 00005000 00:00 0          [vdso]
-                "})
-            })
-            .into(),
-            "mounts" => SimpleFile::new_regular(fs, move || {
-                Ok("proc /proc proc rw,nosuid,nodev,noexec,relatime 0 0\n")
-            })
-            .into(),
+            "maps" | "smaps" => {
+                let smaps = name == "smaps";
+                SimpleFile::new_regular(fs, move || {
+                    if !may_access(&task) {
+                        return Err(VfsError::PermissionDenied);
+                    }
+                    let entries = map_entries(&task.as_thread().proc_data);
+                    Ok(entries
+                        .iter()
+                        .map(|it| if smaps { it.smaps() } else { it.header() })
+                        .collect::<String>())
+                })
+                .into()
+            }
+            // Wait channels aren't tracked; Linux shows the same 0 when it
+            // hides kernel symbols.
+            "wchan" => SimpleFile::new_regular(fs, move || Ok("0")).into(),
+            "mounts" => SimpleFile::new_regular(fs, move || Ok(format_mounts())).into(),
+            "mountinfo" => SimpleFile::new_regular(fs, move || Ok(format_mountinfo())).into(),
             "cmdline" => SimpleFile::new_regular(fs, move || {
                 let cmdline = task.as_thread().proc_data.cmdline.read();
                 let mut buf = Vec::new();
@@ -268,6 +680,22 @@ impl SimpleDirOps for ThreadDir {
                 Ok(buf)
             })
             .into(),
+            "environ" => SimpleFile::new_regular(fs, move || {
+                if !may_access(&task) {
+                    return Err(VfsError::PermissionDenied);
+                }
+                let environ = task.as_thread().proc_data.environ.read();
+                let mut buf = Vec::new();
+                for env in environ.iter() {
+                    buf.extend_from_slice(env.as_bytes());
+                    buf.push(0);
+                }
+                Ok(buf)
+            })
+            .into(),
+            // There is no cgroup hierarchy to move processes in, so every
+            // process is in the root cgroup of the unified hierarchy.
+            "cgroup" => SimpleFile::new_regular(fs, move || Ok("0::/\n")).into(),
             "comm" => SimpleFile::new_regular(
                 fs,
                 RwFile::new(move |req| match req {
@@ -296,6 +724,49 @@ impl SimpleDirOps for ThreadDir {
                 }),
             )
             .into(),
+            "syscall_trace" => SimpleFile::new_regular(
+                fs,
+                RwFile::new(move |req| {
+                    if !may_access(&task) {
+                        return Err(VfsError::PermissionDenied);
+                    }
+                    let proc_data = &task.as_thread().proc_data;
+                    let mut log = proc_data.syscall_log.lock();
+                    match req {
+                        SimpleFileOperation::Read => Ok(Some(
+                            log.iter()
+                                .flatten()
+                                .flat_map(|line| [line.as_str(), "\n"])
+                                .collect::<String>()
+                                .into_bytes(),
+                        )),
+                        // Short writes are merged with the current content,
+                        // so only the first line counts.
+                        SimpleFileOperation::Write(data) => {
+                            let Some(line) = data.split(|&b| b == b'\n').next() else {
+                                return Ok(None);
+                            };
+                            if line.is_empty() {
+                                return Ok(None);
+                            }
+                            let value = str::from_utf8(line)
+                                .ok()
+                                .and_then(|it| it.trim().parse::<i32>().ok())
+                                .ok_or(VfsError::InvalidInput)?;
+                            if value == 0 {
+                                *log = None;
+                            } else if log.is_none() {
+                                *log = Some(VecDeque::new());
+                            }
+                            proc_data
+                                .syscall_trace
+                                .store(log.is_some(), Ordering::Relaxed);
+                            Ok(None)
+                        }
+                    }
+                }),
+            )
+            .into(),
             "exe" => SimpleFile::new(fs, NodeType::Symlink, move || {
                 Ok(task.as_thread().proc_data.exe_path.read().clone())
             })
@@ -308,6 +779,44 @@ impl SimpleDirOps for ThreadDir {
                 }),
             )
             .into(),
+            "timens_offsets" => SimpleFile::new_regular(
+                fs,
+                RwFile::new(move |req| {
+                    let time_ns = task.as_thread().proc_data.time_ns_for_children.read().clone();
+                    match req {
+                        SimpleFileOperation::Read => {
+                            Ok(Some(format_timens_offsets(time_ns.offsets()).into_bytes()))
+                        }
+                        SimpleFileOperation::Write(data) => {
+                            if !data.is_empty() {
+                                let offsets = parse_timens_offsets(time_ns.offsets(), data)?;
+                                time_ns.set_offsets(offsets)?;
+                            }
+                            Ok(None)
+                        }
+                    }
+                }),
+            )
+            .into(),
+            #[cfg(feature = "time-warp")]
+            "time_warp" => SimpleFile::new_regular(
+                fs,
+                RwFile::new(move |req| {
+                    let time_ns = task.as_thread().proc_data.time_ns.read().clone();
+                    match req {
+                        SimpleFileOperation::Read => {
+                            Ok(Some(format!("{}\n", time_ns.warp_rate()).into_bytes()))
+                        }
+                        SimpleFileOperation::Write(data) => {
+                            if !data.is_empty() {
+                                apply_time_warp(&time_ns, data)?;
+                            }
+                            Ok(None)
+                        }
+                    }
+                }),
+            )
+            .into(),
             _ => return Err(VfsError::NotFound),
         })
     }
@@ -318,14 +827,18 @@ impl SimpleDirOps for ThreadDir {
 }
 
 /// Handles /proc/[pid] & /proc/self
-struct ProcFsHandler(Arc<SimpleFs>);
+struct ProcFsHandler {
+    fs: Arc<SimpleFs>,
+    hidepid: HidePid,
+}
 
 impl SimpleDirOps for ProcFsHandler {
     fn child_names<'a>(&'a self) -> Box<dyn Iterator<Item = Cow<'a, str>> + 'a> {
         Box::new(
             tasks()
                 .into_iter()
-                .map(|task| task.id().as_u64().to_string().into())
+                .filter(|task| !self.hidepid.hides(HidePid::Invisible, task))
+                .map(|task| task.as_thread().tid().to_string().into())
                 .chain([Cow::Borrowed("self")]),
         )
     }
@@ -337,11 +850,15 @@ impl SimpleDirOps for ProcFsHandler {
             let tid = name.parse::<u32>().map_err(|_| VfsError::NotFound)?;
             get_task(tid).map_err(|_| VfsError::NotFound)?
         };
+        if self.hidepid.hides(HidePid::Invisible, &task) {
+            return Err(VfsError::NotFound);
+        }
         let node = NodeOpsMux::Dir(SimpleDir::new_maker(
-            self.0.clone(),
+            self.fs.clone(),
             Arc::new(ThreadDir {
-                fs: self.0.clone(),
+                fs: self.fs.clone(),
                 task: Arc::downgrade(&task),
+                hidepid: self.hidepid,
             }),
         ));
         Ok(node)
@@ -352,13 +869,56 @@ impl SimpleDirOps for ProcFsHandler {
     }
 }
 
-fn builder(fs: Arc<SimpleFs>) -> DirMaker {
+/// Lists the section boundaries of the kernel image defined by the linker
+/// script, in the format of /proc/kallsyms.
+///
+/// The kernel carries no symbol table of its own, so this is only enough for
+/// tools to tell kernel text from other addresses; finer symbolization has to
+/// use the kernel ELF file.
+fn kallsyms() -> String {
+    unsafe extern "C" {
+        fn _stext();
+        fn _etext();
+        fn _srodata();
+        fn _erodata();
+        fn _sdata();
+        fn _edata();
+        fn _sbss();
+        fn _ebss();
+    }
+    let symbols: [(usize, char, &str); 8] = [
+        (_stext as usize, 'T', "_stext"),
+        (_etext as usize, 'T', "_etext"),
+        (_srodata as usize, 'R', "_srodata"),
+        (_erodata as usize, 'R', "_erodata"),
+        (_sdata as usize, 'D', "_sdata"),
+        (_edata as usize, 'D', "_edata"),
+        (_sbss as usize, 'B', "_sbss"),
+        (_ebss as usize, 'B', "_ebss"),
+    ];
+    symbols
+        .iter()
+        .map(|(addr, ty, name)| format!("{addr:016x} {ty} {name}\n"))
+        .collect()
+}
+
+/// Maps a console log level to the most verbose kernel log level printed
+/// under it: `4` lets errors through, `8` everything but traces.
+fn console_log_filter(level: u8) -> LevelFilter {
+    match level {
+        0..=3 => LevelFilter::Off,
+        4 => LevelFilter::Error,
+        5 | 6 => LevelFilter::Warn,
+        7 => LevelFilter::Info,
+        _ => LevelFilter::Debug,
+    }
+}
+
+fn builder(fs: Arc<SimpleFs>, options: ProcFsOptions) -> DirMaker {
     let mut root = DirMapping::new();
     root.add(
         "mounts",
-        SimpleFile::new_regular(fs.clone(), || {
-            Ok("proc /proc proc rw,nosuid,nodev,noexec,relatime 0 0\n")
-        }),
+        SimpleFile::new_regular(fs.clone(), || Ok(format_mounts())),
     );
     root.add(
         "meminfo",
@@ -384,6 +944,10 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
             }
         }),
     );
+    root.add(
+        "kallsyms",
+        SimpleFile::new_regular(fs.clone(), || Ok(kallsyms())),
+    );
     root.add(
         "interrupts",
         SimpleFile::new_regular(fs.clone(), || Ok(format!("0: {}", crate::time::irq_cnt()))),
@@ -397,15 +961,154 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
 
             kernel.add(
                 "pid_max",
-                SimpleFile::new_regular(fs.clone(), || Ok("32768\n")),
+                SimpleFile::new_regular(fs.clone(), || Ok(format!("{PID_MAX}\n"))),
             );
 
+            kernel.add(
+                "print-fatal-signals",
+                SimpleFile::new_regular(
+                    fs.clone(),
+                    RwFile::new(|req| match req {
+                        SimpleFileOperation::Read => {
+                            let enabled = PRINT_FATAL_SIGNALS.load(Ordering::Relaxed);
+                            Ok(Some(format!("{}\n", enabled as u8).into_bytes()))
+                        }
+                        SimpleFileOperation::Write(data) => {
+                            if !data.is_empty() {
+                                let value = str::from_utf8(data)
+                                    .ok()
+                                    .and_then(|it| it.trim().parse::<i32>().ok())
+                                    .ok_or(VfsError::InvalidInput)?;
+                                PRINT_FATAL_SIGNALS.store(value != 0, Ordering::Relaxed);
+                            }
+                            Ok(None)
+                        }
+                    }),
+                ),
+            );
+
+            kernel.add(
+                "printk",
+                SimpleFile::new_regular(
+                    fs.clone(),
+                    RwFile::new(|req| match req {
+                        SimpleFileOperation::Read => Ok(Some(
+                            format!(
+                                "{}\t{}\t{}\t{}\n",
+                                kmsg::console_loglevel(),
+                                kmsg::DEFAULT_MESSAGE_LOGLEVEL,
+                                kmsg::MINIMUM_CONSOLE_LOGLEVEL,
+                                kmsg::DEFAULT_CONSOLE_LOGLEVEL
+                            )
+                            .into_bytes(),
+                        )),
+                        SimpleFileOperation::Write(data) => {
+                            // Only the console log level can be changed. It
+                            // sets the level of the kernel's own log output
+                            // too.
+                            if !data.is_empty() {
+                                let level = str::from_utf8(data)
+                                    .ok()
+                                    .and_then(|it| it.split_whitespace().next())
+                                    .and_then(|it| it.parse::<u8>().ok())
+                                    .filter(|it| *it <= 8)
+                                    .ok_or(VfsError::InvalidInput)?;
+                                kmsg::set_console_loglevel(level);
+                                logfilter::set_default_level(console_log_filter(level));
+                            }
+                            Ok(None)
+                        }
+                    }),
+                ),
+            );
+
+            kernel.add(
+                "randomize_va_space",
+                SimpleFile::new_regular(
+                    fs.clone(),
+                    RwFile::new(|req| match req {
+                        SimpleFileOperation::Read => Ok(Some(
+                            format!("{}\n", RANDOMIZE_VA_SPACE.load(Ordering::Relaxed))
+                                .into_bytes(),
+                        )),
+                        SimpleFileOperation::Write(data) => {
+                            if !data.is_empty() {
+                                let value = str::from_utf8(data)
+                                    .ok()
+                                    .and_then(|it| it.trim().parse::<u8>().ok())
+                                    .filter(|it| *it <= 2)
+                                    .ok_or(VfsError::InvalidInput)?;
+                                RANDOMIZE_VA_SPACE.store(value, Ordering::Relaxed);
+                            }
+                            Ok(None)
+                        }
+                    }),
+                ),
+            );
+
+            kernel.add("random", {
+                let mut random = DirMapping::new();
+                random.add(
+                    "entropy_avail",
+                    SimpleFile::new_regular(fs.clone(), || {
+                        Ok(format!("{}\n", crate::vfs::dev::entropy_avail()))
+                    }),
+                );
+                random.add(
+                    "poolsize",
+                    SimpleFile::new_regular(fs.clone(), || Ok("256\n")),
+                );
+                SimpleDir::new_maker(fs.clone(), Arc::new(random))
+            });
+
             SimpleDir::new_maker(fs.clone(), Arc::new(kernel))
         });
 
+        sys.add("fs", {
+            let mut fs_dir = DirMapping::new();
+            fs_dir.add("binfmt_misc", binfmt_misc_dir(fs.clone()));
+            SimpleDir::new_maker(fs.clone(), Arc::new(fs_dir))
+        });
+
+        sys.add("vm", {
+            let mut vm = DirMapping::new();
+
+            vm.add(
+                "mmap_min_addr",
+                SimpleFile::new_regular(
+                    fs.clone(),
+                    RwFile::new(|req| match req {
+                        SimpleFileOperation::Read => Ok(Some(
+                            format!("{}\n", MMAP_MIN_ADDR.load(Ordering::Relaxed)).into_bytes(),
+                        )),
+                        SimpleFileOperation::Write(data) => {
+                            if !data.is_empty() {
+                                let value = str::from_utf8(data)
+                                    .ok()
+                                    .and_then(|it| it.trim().parse::<usize>().ok())
+                                    .ok_or(VfsError::InvalidInput)?;
+                                if value % PAGE_SIZE_4K != 0
+                                    || value >= USER_SPACE_BASE + USER_SPACE_SIZE
+                                {
+                                    return Err(VfsError::InvalidInput);
+                                }
+                                MMAP_MIN_ADDR.store(value, Ordering::Relaxed);
+                            }
+                            Ok(None)
+                        }
+                    }),
+                ),
+            );
+
+            SimpleDir::new_maker(fs.clone(), Arc::new(vm))
+        });
+
         SimpleDir::new_maker(fs.clone(), Arc::new(sys))
     });
 
-    let proc_dir = ProcFsHandler(fs.clone());
+    let proc_dir = ProcFsHandler {
+        fs: fs.clone(),
+        hidepid: options.hidepid,
+    };
     SimpleDir::new_maker(fs, Arc::new(proc_dir.chain(root)))
 }
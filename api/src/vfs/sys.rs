@@ -0,0 +1,300 @@
+//! sysfs, describing the CPUs, block devices and network interfaces of the
+//! system, mounted at /sys.
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::sync::atomic::Ordering;
+
+use axconfig::plat::CPU_NUM;
+use axfs_ng_vfs::{Filesystem, NodeType, VfsError};
+use starry_core::{
+    firmware::acpi,
+    logfilter,
+    vfs::{DirMaker, DirMapping, RwFile, SimpleDir, SimpleFile, SimpleFileOperation, SimpleFs},
+};
+
+use super::{
+    dev::{LoopDevice, loop_devices},
+    dmi, vulnerabilities,
+};
+
+const SYSFS_MAGIC: u32 = 0x62656572;
+
+/// Sector size of the block devices, in bytes.
+const SECTOR_SIZE: u64 = 512;
+
+fn dir(fs: &Arc<SimpleFs>, mapping: DirMapping) -> DirMaker {
+    SimpleDir::new_maker(fs.clone(), Arc::new(mapping))
+}
+
+/// Creates a read-only attribute with a fixed value.
+fn attr(fs: &Arc<SimpleFs>, value: impl ToString) -> Arc<SimpleFile> {
+    let value = format!("{}\n", value.to_string());
+    SimpleFile::new_regular(fs.clone(), move || Ok(value.clone()))
+}
+
+/// Formats the list of all CPUs, e.g. `0-3`.
+fn cpu_list() -> String {
+    if CPU_NUM == 1 {
+        "0".into()
+    } else {
+        format!("0-{}", CPU_NUM - 1)
+    }
+}
+
+/// Builds /sys/devices/system/cpu.
+fn cpu_dir(fs: &Arc<SimpleFs>) -> DirMaker {
+    let mut cpu = DirMapping::new();
+    for name in ["online", "possible", "present"] {
+        cpu.add(name, attr(fs, cpu_list()));
+    }
+    cpu.add("offline", attr(fs, ""));
+    cpu.add("kernel_max", attr(fs, CPU_NUM - 1));
+    for i in 0..CPU_NUM {
+        let mut topology = DirMapping::new();
+        topology.add("physical_package_id", attr(fs, 0));
+        topology.add("core_id", attr(fs, i));
+        topology.add("thread_siblings_list", attr(fs, i));
+        topology.add("core_siblings_list", attr(fs, cpu_list()));
+        topology.add("package_cpus_list", attr(fs, cpu_list()));
+
+        let mut entry = DirMapping::new();
+        // As on Linux, the boot CPU can't be taken offline and so has no
+        // `online` file.
+        if i != 0 {
+            entry.add("online", attr(fs, 1));
+        }
+        entry.add("topology", dir(fs, topology));
+        cpu.add(format!("cpu{i}"), dir(fs, entry));
+    }
+    cpu.add("vulnerabilities", vulnerabilities::builder(fs.clone()));
+    dir(fs, cpu)
+}
+
+/// Builds /sys/block/loopN.
+fn loop_dir(fs: &Arc<SimpleFs>, dev: &Arc<LoopDevice>) -> DirMaker {
+    let mut queue = DirMapping::new();
+    for name in [
+        "logical_block_size",
+        "physical_block_size",
+        "hw_sector_size",
+        "minimum_io_size",
+    ] {
+        queue.add(name, attr(fs, SECTOR_SIZE));
+    }
+    queue.add("rotational", attr(fs, 0));
+    queue.add("max_sectors_kb", attr(fs, 1280));
+    queue.add("nr_requests", attr(fs, 128));
+    queue.add("scheduler", attr(fs, "[none]"));
+    queue.add("read_ahead_kb", {
+        let dev = dev.clone();
+        SimpleFile::new_regular(fs.clone(), move || {
+            Ok(format!("{}\n", dev.ra.load(Ordering::Relaxed) / 1024))
+        })
+    });
+
+    let mut entry = DirMapping::new();
+    let id = dev.device_id();
+    entry.add("dev", attr(fs, format!("{}:{}", id.major(), id.minor())));
+    entry.add("size", {
+        let dev = dev.clone();
+        SimpleFile::new_regular(fs.clone(), move || {
+            Ok(format!("{}\n", dev.size() / SECTOR_SIZE))
+        })
+    });
+    entry.add("ro", {
+        let dev = dev.clone();
+        SimpleFile::new_regular(fs.clone(), move || {
+            Ok(format!("{}\n", dev.ro.load(Ordering::Relaxed) as u8))
+        })
+    });
+    entry.add("removable", attr(fs, 0));
+    entry.add("queue", dir(fs, queue));
+    dir(fs, entry)
+}
+
+/// A network interface described under /sys/class/net.
+struct NetInterface {
+    name: &'static str,
+    ifindex: u32,
+    address: [u8; 6],
+    mtu: u32,
+    /// The `ARPHRD_*` hardware type.
+    ty: u16,
+    /// The `IFF_*` flags.
+    flags: u32,
+}
+
+/// axnet doesn't list its interfaces, so only the loopback interface, which
+/// always exists, is described.
+const NET_INTERFACES: &[NetInterface] = &[NetInterface {
+    name: "lo",
+    ifindex: 1,
+    address: [0; 6],
+    mtu: 65536,
+    ty: 772,
+    // IFF_UP | IFF_LOOPBACK
+    flags: 0x9,
+}];
+
+fn net_dir(fs: &Arc<SimpleFs>, iface: &NetInterface) -> DirMaker {
+    let address = iface
+        .address
+        .iter()
+        .map(|it| format!("{it:02x}"))
+        .collect::<Vec<_>>()
+        .join(":");
+
+    // Traffic isn't counted per interface.
+    let mut statistics = DirMapping::new();
+    for name in [
+        "rx_bytes",
+        "rx_packets",
+        "rx_errors",
+        "rx_dropped",
+        "tx_bytes",
+        "tx_packets",
+        "tx_errors",
+        "tx_dropped",
+    ] {
+        statistics.add(name, attr(fs, 0));
+    }
+
+    let mut entry = DirMapping::new();
+    entry.add("address", attr(fs, &address));
+    entry.add("broadcast", attr(fs, &address));
+    entry.add("addr_len", attr(fs, iface.address.len()));
+    entry.add("ifindex", attr(fs, iface.ifindex));
+    entry.add("iflink", attr(fs, iface.ifindex));
+    entry.add("mtu", attr(fs, iface.mtu));
+    entry.add("type", attr(fs, iface.ty));
+    entry.add("flags", attr(fs, format!("{:#x}", iface.flags)));
+    entry.add("operstate", attr(fs, "unknown"));
+    entry.add("carrier", attr(fs, 1));
+    entry.add("tx_queue_len", attr(fs, 1000));
+    entry.add("statistics", dir(fs, statistics));
+    dir(fs, entry)
+}
+
+/// Builds /sys/firmware/acpi/tables, holding the raw ACPI tables. Where
+/// several tables share a signature, they are numbered from 1, as on Linux.
+fn acpi_tables_dir(fs: &Arc<SimpleFs>) -> DirMaker {
+    let tables = acpi::tables();
+    let mut dir_mapping = DirMapping::new();
+    for (i, table) in tables.iter().enumerate() {
+        let name = String::from_utf8_lossy(&table.signature).into_owned();
+        let same = |it: &&acpi::AcpiTable| it.signature == table.signature;
+        let name = if tables.iter().filter(same).count() > 1 {
+            format!("{name}{}", tables[..=i].iter().filter(same).count())
+        } else {
+            name
+        };
+        let data = table.data;
+        dir_mapping.add(name, SimpleFile::new_regular(fs.clone(), move || Ok(data)));
+    }
+    dir(fs, dir_mapping)
+}
+
+fn builder(fs: Arc<SimpleFs>) -> DirMaker {
+    let fs = &fs;
+    let mut root = DirMapping::new();
+
+    root.add("devices", {
+        let mut system = DirMapping::new();
+        system.add("cpu", cpu_dir(fs));
+        let mut devices = DirMapping::new();
+        devices.add("system", dir(fs, system));
+        dir(fs, devices)
+    });
+
+    root.add("block", {
+        let mut block = DirMapping::new();
+        for dev in loop_devices() {
+            block.add(format!("loop{}", dev.number()), loop_dir(fs, dev));
+        }
+        dir(fs, block)
+    });
+
+    root.add("class", {
+        let mut class = DirMapping::new();
+        class.add("net", {
+            let mut net = DirMapping::new();
+            for iface in NET_INTERFACES {
+                net.add(iface.name, net_dir(fs, iface));
+            }
+            dir(fs, net)
+        });
+        if axdisplay::has_display() {
+            let mut device = DirMapping::new();
+            device.add(
+                "subsystem",
+                SimpleFile::new(fs.clone(), NodeType::Symlink, || {
+                    Ok("../../../../class/graphics")
+                }),
+            );
+            let mut fb0 = DirMapping::new();
+            fb0.add("device", dir(fs, device));
+            let mut graphics = DirMapping::new();
+            graphics.add("fb0", dir(fs, fb0));
+            class.add("graphics", dir(fs, graphics));
+        }
+        if dmi::available() {
+            let mut dmi_dir = DirMapping::new();
+            dmi_dir.add("id", dmi::builder(fs.clone()));
+            class.add("dmi", dir(fs, dmi_dir));
+        }
+        dir(fs, class)
+    });
+
+    if acpi::available() {
+        root.add("firmware", {
+            let mut acpi_dir = DirMapping::new();
+            acpi_dir.add("tables", acpi_tables_dir(fs));
+            let mut firmware = DirMapping::new();
+            firmware.add("acpi", dir(fs, acpi_dir));
+            dir(fs, firmware)
+        });
+    }
+
+    // tracefs is mounted on /sys/kernel/tracing.
+    root.add("kernel", {
+        let mut kernel = DirMapping::new();
+        kernel.add("tracing", dir(fs, DirMapping::new()));
+        // Stands in for debugfs, which isn't implemented.
+        kernel.add("debug", {
+            let mut debug = DirMapping::new();
+            debug.add(
+                "log_filter",
+                SimpleFile::new_regular(
+                    fs.clone(),
+                    RwFile::new(|req| match req {
+                        SimpleFileOperation::Read => {
+                            Ok(Some(format!("{}\n", logfilter::filter()).into_bytes()))
+                        }
+                        SimpleFileOperation::Write(data) => {
+                            if !data.is_empty() {
+                                let spec =
+                                    str::from_utf8(data).map_err(|_| VfsError::InvalidInput)?;
+                                logfilter::set_filter(spec)?;
+                            }
+                            Ok(None)
+                        }
+                    }),
+                ),
+            );
+            dir(fs, debug)
+        });
+        dir(fs, kernel)
+    });
+
+    dir(fs, root)
+}
+
+/// Creates a sysfs instance.
+pub(crate) fn new_sysfs() -> Filesystem {
+    SimpleFs::new_with("sysfs".into(), SYSFS_MAGIC, builder)
+}
@@ -1,16 +1,19 @@
 use alloc::{borrow::ToOwned, string::String, sync::Arc};
 use core::{any::Any, borrow::Borrow, cmp::Ordering, task::Context, time::Duration};
 
+use axconfig::plat::PHYS_MEMORY_SIZE;
 use axfs_ng_vfs::{
     DeviceId, DirEntry, DirEntrySink, DirNode, DirNodeOps, FileNode, FileNodeOps, Filesystem,
     FilesystemOps, Metadata, MetadataUpdate, NodeFlags, NodeOps, NodePermission, NodeType,
-    Reference, StatFs, VfsError, VfsResult, WeakDirEntry,
+    Reference, StatFs, VfsError, VfsResult, WeakDirEntry, path::MAX_NAME_LEN,
 };
 use axpoll::{IoEvents, Pollable};
 use axsync::Mutex;
 use hashbrown::HashMap;
 use slab::Slab;
-use starry_core::vfs::dummy_stat_fs;
+use memory_addr::PAGE_SIZE_4K;
+
+const TMPFS_MAGIC: u32 = 0x01021994;
 
 #[derive(PartialEq, Eq, Hash, Clone)]
 struct FileName(String);
@@ -91,7 +94,27 @@ impl FilesystemOps for MemoryFs {
     }
 
     fn stat(&self) -> VfsResult<StatFs> {
-        Ok(dummy_stat_fs(0x01021994))
+        // As on Linux, a tmpfs may grow to half of RAM, with an inode for
+        // every page of it.
+        let limit = (PHYS_MEMORY_SIZE / 2 / PAGE_SIZE_4K) as u64;
+        let inodes = self.inodes.lock();
+        let used = inodes
+            .iter()
+            .filter_map(|(_, inode)| inode.as_file().ok())
+            .map(|file| file.length.lock().div_ceil(PAGE_SIZE_4K as u64))
+            .sum::<u64>();
+        Ok(StatFs {
+            fs_type: TMPFS_MAGIC,
+            block_size: PAGE_SIZE_4K as _,
+            blocks: limit,
+            blocks_free: limit.saturating_sub(used),
+            blocks_available: limit.saturating_sub(used),
+            file_count: limit,
+            free_file_count: limit.saturating_sub(inodes.len() as u64),
+            name_length: MAX_NAME_LEN as _,
+            fragment_size: PAGE_SIZE_4K as _,
+            mount_flags: 0,
+        })
     }
 }
 
@@ -0,0 +1,235 @@
+//! The tracing control interface, mounted at /sys/kernel/tracing.
+
+use alloc::{collections::btree_map::BTreeMap, format, string::String, sync::Arc, vec::Vec};
+use core::{any::Any, task::Context};
+
+use axerrno::AxError;
+use axfs_ng_vfs::{DeviceId, Filesystem, NodeFlags, NodeType, VfsError, VfsResult};
+use axpoll::{IoEvents, Pollable};
+use spin::Mutex;
+use starry_core::{
+    trace::{self, TracePoint},
+    vfs::{
+        Device, DeviceOps, DirMaker, DirMapping, RwFile, SimpleDir, SimpleFile,
+        SimpleFileOperation, SimpleFs,
+    },
+};
+
+fn parse_value<T: core::str::FromStr>(data: &[u8]) -> VfsResult<T> {
+    str::from_utf8(data)
+        .ok()
+        .and_then(|it| it.trim().parse().ok())
+        .ok_or(VfsError::InvalidInput)
+}
+
+/// Creates a file reading as `0` or `1`. Writing an empty string, as done
+/// when the file is truncated, leaves the value as it is.
+fn flag_file(
+    fs: &Arc<SimpleFs>,
+    get: impl Fn() -> bool + Send + Sync + 'static,
+    set: impl Fn(bool) + Send + Sync + 'static,
+) -> Arc<SimpleFile> {
+    SimpleFile::new_regular(
+        fs.clone(),
+        RwFile::new(move |req| match req {
+            SimpleFileOperation::Read => Ok(Some(format!("{}\n", get() as u8).into_bytes())),
+            SimpleFileOperation::Write(data) => {
+                if !data.is_empty() {
+                    set(parse_value::<u32>(data)? != 0);
+                }
+                Ok(None)
+            }
+        }),
+    )
+}
+
+/// Handles a write to `set_event`, which replaces the enabled events with
+/// those listed. Names prefixed with `!` are disabled instead.
+fn set_events(data: &[u8]) -> VfsResult<()> {
+    let data = str::from_utf8(data).map_err(|_| VfsError::InvalidInput)?;
+    for point in TracePoint::ALL {
+        trace::set_enabled(point, false);
+    }
+    for name in data.split_whitespace() {
+        let (name, enable) = match name.strip_prefix('!') {
+            Some(name) => (name, false),
+            None => (name, true),
+        };
+        let point = TracePoint::from_name(name).ok_or(VfsError::InvalidInput)?;
+        trace::set_enabled(point, enable);
+    }
+    Ok(())
+}
+
+fn format_trace() -> String {
+    let mut out = format!(
+        "# tracer: nop\n#\n# overrun: {}\n#\n#             TID   CPU      TIMESTAMP  EVENT\n",
+        trace::overrun()
+    );
+    out.push_str(&trace::format_records());
+    out
+}
+
+/// `trace_pipe`: a consuming, blocking read of the records.
+#[derive(Default)]
+struct TracePipe {
+    /// Formatted records not read yet.
+    pending: Mutex<Vec<u8>>,
+}
+
+impl DeviceOps for TracePipe {
+    fn read_at(&self, buf: &mut [u8], _offset: u64) -> VfsResult<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut pending = self.pending.lock();
+        while pending.len() < buf.len() {
+            let Some(record) = trace::pop_record() else {
+                break;
+            };
+            pending.extend_from_slice(format!("{record}\n").as_bytes());
+        }
+        if pending.is_empty() {
+            return Err(AxError::WouldBlock);
+        }
+        let read = pending.len().min(buf.len());
+        buf[..read].copy_from_slice(&pending[..read]);
+        pending.drain(..read);
+        Ok(read)
+    }
+
+    fn write_at(&self, _buf: &[u8], _offset: u64) -> VfsResult<usize> {
+        Err(AxError::InvalidInput)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_pollable(&self) -> Option<&dyn Pollable> {
+        Some(self)
+    }
+
+    fn flags(&self) -> NodeFlags {
+        NodeFlags::NON_CACHEABLE | NodeFlags::STREAM
+    }
+}
+
+impl Pollable for TracePipe {
+    fn poll(&self) -> IoEvents {
+        if !self.pending.lock().is_empty() || trace::has_records() {
+            IoEvents::IN
+        } else {
+            IoEvents::empty()
+        }
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        if events.contains(IoEvents::IN) {
+            trace::register_reader(context.waker());
+        }
+    }
+}
+
+fn builder(fs: Arc<SimpleFs>) -> DirMaker {
+    let mut root = DirMapping::new();
+    root.add(
+        "tracing_on",
+        flag_file(&fs, trace::tracing_on, trace::set_tracing_on),
+    );
+    root.add(
+        "buffer_size_kb",
+        SimpleFile::new_regular(
+            fs.clone(),
+            RwFile::new(|req| match req {
+                SimpleFileOperation::Read => {
+                    Ok(Some(format!("{}\n", trace::buffer_size_kb()).into_bytes()))
+                }
+                SimpleFileOperation::Write(data) => {
+                    if !data.is_empty() {
+                        trace::set_buffer_size_kb(parse_value(data)?)?;
+                    }
+                    Ok(None)
+                }
+            }),
+        ),
+    );
+    root.add(
+        "available_events",
+        SimpleFile::new_regular(fs.clone(), || {
+            Ok(TracePoint::ALL
+                .iter()
+                .map(|it| format!("{}:{}\n", it.subsystem(), it.name()))
+                .collect::<String>())
+        }),
+    );
+    root.add(
+        "set_event",
+        SimpleFile::new_regular(
+            fs.clone(),
+            RwFile::new(|req| match req {
+                SimpleFileOperation::Read => Ok(Some(
+                    TracePoint::ALL
+                        .iter()
+                        .filter(|it| trace::is_enabled(**it))
+                        .map(|it| format!("{}:{}\n", it.subsystem(), it.name()))
+                        .collect::<String>()
+                        .into_bytes(),
+                )),
+                SimpleFileOperation::Write(data) => set_events(data).map(|_| None),
+            }),
+        ),
+    );
+    root.add(
+        "trace",
+        SimpleFile::new_regular(
+            fs.clone(),
+            RwFile::new(|req| match req {
+                SimpleFileOperation::Read => Ok(Some(format_trace().into_bytes())),
+                // Any write, including truncation, clears the buffers.
+                SimpleFileOperation::Write(_) => {
+                    trace::clear();
+                    Ok(None)
+                }
+            }),
+        ),
+    );
+    root.add(
+        "trace_pipe",
+        Device::new(
+            fs.clone(),
+            NodeType::RegularFile,
+            DeviceId::default(),
+            Arc::new(TracePipe::default()),
+        ),
+    );
+
+    let mut subsystems = BTreeMap::<_, DirMapping>::new();
+    for point in TracePoint::ALL {
+        let mut event = DirMapping::new();
+        event.add(
+            "enable",
+            flag_file(
+                &fs,
+                move || trace::is_enabled(point),
+                move |enabled| trace::set_enabled(point, enabled),
+            ),
+        );
+        subsystems.entry(point.subsystem()).or_default().add(
+            point.name(),
+            SimpleDir::new_maker(fs.clone(), Arc::new(event)),
+        );
+    }
+    let mut events = DirMapping::new();
+    for (name, subsystem) in subsystems {
+        events.add(name, SimpleDir::new_maker(fs.clone(), Arc::new(subsystem)));
+    }
+    root.add("events", SimpleDir::new_maker(fs.clone(), Arc::new(events)));
+
+    SimpleDir::new_maker(fs, Arc::new(root))
+}
+
+/// Creates the filesystem mounted at /sys/kernel/tracing.
+pub fn new_tracefs() -> Filesystem {
+    SimpleFs::new_with("tracefs".into(), 0x74726163, builder)
+}
@@ -0,0 +1,21 @@
+//! CPU vulnerability status, exposed under
+//! /sys/devices/system/cpu/vulnerabilities.
+
+use alloc::{format, sync::Arc};
+
+use starry_core::{
+    mitigations::vulnerabilities,
+    vfs::{DirMaker, DirMapping, SimpleDir, SimpleFile, SimpleFs},
+};
+
+/// Builds the /sys/devices/system/cpu/vulnerabilities directory.
+pub(super) fn builder(fs: Arc<SimpleFs>) -> DirMaker {
+    let mut root = DirMapping::new();
+    for (name, status) in vulnerabilities() {
+        root.add(
+            name,
+            SimpleFile::new_regular(fs.clone(), move || Ok(format!("{status}\n"))),
+        );
+    }
+    SimpleDir::new_maker(fs, Arc::new(root))
+}
@@ -0,0 +1,305 @@
+//! Interpreters for foreign binary formats, in the manner of Linux's
+//! binfmt_misc.
+//!
+//! Each entry matches executables either by a magic byte sequence at some
+//! offset, or by their file name extension. When an entry matches, `execve`
+//! runs its interpreter instead, with the path of the executable inserted
+//! into the arguments. Entries are registered through
+//! /proc/sys/fs/binfmt_misc/register with lines of the form
+//! `:name:type:offset:magic:mask:interpreter:flags`.
+
+use alloc::{
+    borrow::ToOwned,
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use axerrno::{AxError, AxResult};
+use axfs::FS_CONTEXT;
+use spin::RwLock;
+
+/// The number of bytes at the start of an executable that magic numbers are
+/// matched against.
+pub const BINFMT_BUF_SIZE: usize = 128;
+
+/// How an entry recognizes executables.
+#[derive(Debug)]
+pub enum BinfmtMatch {
+    /// Bytes at a given offset, compared under an optional mask.
+    Magic {
+        /// The offset of the magic number in the file.
+        offset: usize,
+        /// The magic number.
+        magic: Vec<u8>,
+        /// The bits of the file compared against the magic number; all of
+        /// them if not given.
+        mask: Option<Vec<u8>>,
+    },
+    /// A file name extension, without the leading dot.
+    Extension(String),
+}
+
+/// A registered binary format.
+#[derive(Debug)]
+pub struct BinfmtEntry {
+    /// The name of the entry, which is also its file name in binfmt_misc.
+    pub name: String,
+    /// How executables are recognized.
+    pub matcher: BinfmtMatch,
+    /// The path of the interpreter.
+    pub interpreter: String,
+    /// The flags the entry was registered with.
+    pub flags: String,
+    enabled: AtomicBool,
+}
+
+/// Decodes the `\xHH` escapes in `s`.
+fn unescape(s: &str) -> AxResult<Vec<u8>> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        if b != b'\\' {
+            out.push(b);
+            continue;
+        }
+        match bytes.next() {
+            Some(b'\\') => out.push(b'\\'),
+            Some(b'x') => {
+                let hex = [
+                    bytes.next().ok_or(AxError::InvalidInput)?,
+                    bytes.next().ok_or(AxError::InvalidInput)?,
+                ];
+                let hex = str::from_utf8(&hex).map_err(|_| AxError::InvalidInput)?;
+                out.push(u8::from_str_radix(hex, 16).map_err(|_| AxError::InvalidInput)?);
+            }
+            _ => return Err(AxError::InvalidInput),
+        }
+    }
+    Ok(out)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut out, b| {
+        write!(out, "{b:02x}").unwrap();
+        out
+    })
+}
+
+impl BinfmtEntry {
+    /// Parses a registration line.
+    fn parse(line: &str) -> AxResult<Self> {
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        let mut chars = line.chars();
+        let delim = chars.next().ok_or(AxError::InvalidInput)?;
+        let fields = chars.as_str().split(delim).collect::<Vec<_>>();
+        let [name, kind, offset, magic, mask, interpreter, rest @ ..] = fields.as_slice() else {
+            return Err(AxError::InvalidInput);
+        };
+        let flags = match rest {
+            [] => "",
+            [flags] | [flags, ""] => *flags,
+            _ => return Err(AxError::InvalidInput),
+        };
+
+        if name.is_empty()
+            || name.contains('/')
+            || matches!(*name, "." | ".." | "register" | "status")
+        {
+            return Err(AxError::InvalidInput);
+        }
+        if interpreter.is_empty() || flags.chars().any(|c| !"POCF".contains(c)) {
+            return Err(AxError::InvalidInput);
+        }
+
+        let matcher = match *kind {
+            "M" => {
+                let offset = if offset.is_empty() {
+                    0
+                } else {
+                    offset.parse().map_err(|_| AxError::InvalidInput)?
+                };
+                let magic = unescape(magic)?;
+                let mask = (!mask.is_empty()).then(|| unescape(mask)).transpose()?;
+                if magic.is_empty()
+                    || mask.as_ref().is_some_and(|it| it.len() != magic.len())
+                    || offset
+                        .checked_add(magic.len())
+                        .is_none_or(|end| end > BINFMT_BUF_SIZE)
+                {
+                    return Err(AxError::InvalidInput);
+                }
+                BinfmtMatch::Magic {
+                    offset,
+                    magic,
+                    mask,
+                }
+            }
+            "E" => {
+                if !offset.is_empty() || !mask.is_empty() || magic.is_empty() || magic.contains('/')
+                {
+                    return Err(AxError::InvalidInput);
+                }
+                BinfmtMatch::Extension(magic.to_string())
+            }
+            _ => return Err(AxError::InvalidInput),
+        };
+
+        // With `F`, the interpreter is looked up once, now, rather than on
+        // every `execve`.
+        let interpreter = if flags.contains('F') {
+            FS_CONTEXT
+                .lock()
+                .resolve(*interpreter)?
+                .absolute_path()?
+                .to_string()
+        } else {
+            interpreter.to_string()
+        };
+
+        Ok(Self {
+            name: name.to_string(),
+            matcher,
+            interpreter,
+            flags: flags.to_owned(),
+            enabled: AtomicBool::new(true),
+        })
+    }
+
+    fn matches(&self, path: &str, head: &[u8]) -> bool {
+        match &self.matcher {
+            BinfmtMatch::Magic {
+                offset,
+                magic,
+                mask,
+            } => {
+                let Some(data) = head.get(*offset..*offset + magic.len()) else {
+                    return false;
+                };
+                match mask {
+                    Some(mask) => data
+                        .iter()
+                        .zip(mask)
+                        .zip(magic)
+                        .all(|((d, m), x)| d & m == x & m),
+                    None => data == magic.as_slice(),
+                }
+            }
+            BinfmtMatch::Extension(ext) => path
+                .rsplit_once('.')
+                .is_some_and(|(_, it)| it == ext.as_str()),
+        }
+    }
+
+    /// Returns whether the entry is enabled.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Enables or disables the entry.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Returns whether the original `argv[0]` is passed on to the
+    /// interpreter, instead of being replaced by the path of the executable.
+    pub fn preserve_argv0(&self) -> bool {
+        self.flags.contains('P')
+    }
+
+    /// Describes the entry, as read from its file in binfmt_misc.
+    pub fn describe(&self) -> String {
+        let mut out = format!(
+            "{}\ninterpreter {}\nflags: {}\n",
+            if self.is_enabled() {
+                "enabled"
+            } else {
+                "disabled"
+            },
+            self.interpreter,
+            self.flags
+        );
+        match &self.matcher {
+            BinfmtMatch::Magic {
+                offset,
+                magic,
+                mask,
+            } => {
+                writeln!(out, "offset {offset}\nmagic {}", hex(magic)).unwrap();
+                if let Some(mask) = mask {
+                    writeln!(out, "mask {}", hex(mask)).unwrap();
+                }
+            }
+            BinfmtMatch::Extension(ext) => writeln!(out, "extension .{ext}").unwrap(),
+        }
+        out
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+static ENTRIES: RwLock<Vec<Arc<BinfmtEntry>>> = RwLock::new(Vec::new());
+
+/// Returns whether binfmt_misc as a whole is enabled.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Enables or disables binfmt_misc as a whole.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Registers an entry from a line written to the `register` file.
+pub fn register(line: &str) -> AxResult<()> {
+    let entry = BinfmtEntry::parse(line)?;
+    let mut entries = ENTRIES.write();
+    if entries.iter().any(|it| it.name == entry.name) {
+        return Err(AxError::AlreadyExists);
+    }
+    entries.push(Arc::new(entry));
+    Ok(())
+}
+
+/// Removes the entry named `name`.
+pub fn unregister(name: &str) {
+    ENTRIES.write().retain(|it| it.name != name);
+}
+
+/// Removes all entries.
+pub fn clear() {
+    ENTRIES.write().clear();
+}
+
+/// Returns the entry named `name`.
+pub fn get(name: &str) -> Option<Arc<BinfmtEntry>> {
+    ENTRIES.read().iter().find(|it| it.name == name).cloned()
+}
+
+/// Returns all entries, in the order they were registered.
+pub fn entries() -> Vec<Arc<BinfmtEntry>> {
+    ENTRIES.read().clone()
+}
+
+/// Returns whether any entry could match, so callers can skip reading the
+/// executable otherwise.
+pub(crate) fn is_active() -> bool {
+    is_enabled() && !ENTRIES.read().is_empty()
+}
+
+/// Finds the first enabled entry matching the executable at `path`, whose
+/// first bytes are `head`.
+pub(crate) fn lookup(path: &str, head: &[u8]) -> Option<Arc<BinfmtEntry>> {
+    if !is_enabled() {
+        return None;
+    }
+    ENTRIES
+        .read()
+        .iter()
+        .find(|it| it.is_enabled() && it.matches(path, head))
+        .cloned()
+}
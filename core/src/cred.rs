@@ -0,0 +1,125 @@
+//! The user and group IDs a process runs as.
+//!
+//! A process with an effective user ID of 0 is privileged, and has every
+//! capability: there is no finer-grained capability model.
+
+use alloc::vec::Vec;
+
+use axerrno::{AxError, AxResult};
+
+/// The most supplementary groups a process may have.
+pub const NGROUPS_MAX: usize = 65536;
+
+/// The real, effective and saved IDs of either a user or a group.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct IdSet {
+    /// The real ID, of whoever started the process.
+    pub real: u32,
+    /// The effective ID, used in permission checks.
+    pub effective: u32,
+    /// The saved ID, which an unprivileged process may switch back to.
+    pub saved: u32,
+}
+
+impl IdSet {
+    fn contains(&self, id: u32) -> bool {
+        id == self.real || id == self.effective || id == self.saved
+    }
+
+    /// Changes the IDs as `setuid(2)` does: a privileged process sets all
+    /// of them, others may only set the effective ID, to the real or saved
+    /// one.
+    pub fn set(&mut self, id: u32, privileged: bool) -> AxResult<()> {
+        if privileged {
+            *self = Self {
+                real: id,
+                effective: id,
+                saved: id,
+            };
+        } else if id == self.real || id == self.saved {
+            self.effective = id;
+        } else {
+            return Err(AxError::OperationNotPermitted);
+        }
+        Ok(())
+    }
+
+    /// Changes the real and effective IDs as `setreuid(2)` does, leaving
+    /// those that are `None` alone.
+    ///
+    /// The saved ID follows the effective one if the real ID is set, or if
+    /// the effective one is set to something other than the real one.
+    pub fn set_re(
+        &mut self,
+        real: Option<u32>,
+        effective: Option<u32>,
+        privileged: bool,
+    ) -> AxResult<()> {
+        if !privileged
+            && (real.is_some_and(|id| id != self.real && id != self.effective)
+                || effective.is_some_and(|id| !self.contains(id)))
+        {
+            return Err(AxError::OperationNotPermitted);
+        }
+        let old_real = self.real;
+        if let Some(id) = real {
+            self.real = id;
+        }
+        if let Some(id) = effective {
+            self.effective = id;
+        }
+        if real.is_some() || effective.is_some_and(|id| id != old_real) {
+            self.saved = self.effective;
+        }
+        Ok(())
+    }
+
+    /// Changes the IDs as `setresuid(2)` does, leaving those that are `None`
+    /// alone. An unprivileged process may only set each to one of its
+    /// current IDs.
+    pub fn set_res(
+        &mut self,
+        real: Option<u32>,
+        effective: Option<u32>,
+        saved: Option<u32>,
+        privileged: bool,
+    ) -> AxResult<()> {
+        if !privileged
+            && [real, effective, saved]
+                .into_iter()
+                .flatten()
+                .any(|id| !self.contains(id))
+        {
+            return Err(AxError::OperationNotPermitted);
+        }
+        if let Some(id) = real {
+            self.real = id;
+        }
+        if let Some(id) = effective {
+            self.effective = id;
+        }
+        if let Some(id) = saved {
+            self.saved = id;
+        }
+        Ok(())
+    }
+}
+
+/// The credentials of a process.
+#[derive(Debug, Default, Clone)]
+pub struct Credentials {
+    /// The user IDs.
+    pub uid: IdSet,
+    /// The group IDs.
+    pub gid: IdSet,
+    /// The supplementary group IDs.
+    pub groups: Vec<u32>,
+}
+
+impl Credentials {
+    /// Returns whether the credentials are those of root, which is exempt
+    /// from permission checks and resource limits.
+    pub fn is_privileged(&self) -> bool {
+        self.uid.effective == 0
+    }
+}
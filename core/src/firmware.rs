@@ -0,0 +1,74 @@
+//! Tables the firmware leaves in memory for the kernel to read.
+
+pub mod acpi;
+#[cfg(target_arch = "aarch64")]
+pub mod fw_cfg;
+
+use core::slice;
+
+use axerrno::AxError;
+use axhal::{mem::phys_to_virt, paging::MappingFlags};
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, PhysAddr, VirtAddr};
+
+/// The largest area of firmware memory mapped at once. No table comes close,
+/// so a larger size means the firmware, or our reading of it, is broken.
+const MAX_FIRMWARE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Maps `size` bytes of firmware memory at `paddr` into the kernel address
+/// space.
+///
+/// Pages are mapped one at a time, since tables may share pages with others
+/// mapped before.
+pub fn map_firmware(paddr: PhysAddr, size: usize) -> Option<&'static [u8]> {
+    map_pages(paddr, size, MappingFlags::READ)?;
+    Some(unsafe { slice::from_raw_parts(phys_to_virt(paddr).as_ptr(), size) })
+}
+
+/// Maps `size` bytes of device registers at `paddr` into the kernel address
+/// space, uncached.
+pub fn map_mmio(paddr: PhysAddr, size: usize) -> Option<VirtAddr> {
+    map_pages(
+        paddr,
+        size,
+        MappingFlags::READ | MappingFlags::WRITE | MappingFlags::DEVICE,
+    )?;
+    Some(phys_to_virt(paddr))
+}
+
+fn map_pages(paddr: PhysAddr, size: usize, flags: MappingFlags) -> Option<()> {
+    let Some(end) = paddr
+        .as_usize()
+        .checked_add(size)
+        .filter(|_| size <= MAX_FIRMWARE_SIZE)
+        .and_then(|end| end.checked_next_multiple_of(PAGE_SIZE_4K))
+    else {
+        warn!("bad firmware memory area at {paddr:?}, size {size:#x}");
+        return None;
+    };
+    let end = PhysAddr::from_usize(end);
+    let mut aspace = axmm::kernel_aspace().lock();
+    let mut page = paddr.align_down_4k();
+    while page < end {
+        match aspace.map_linear(phys_to_virt(page), page, PAGE_SIZE_4K, flags) {
+            Ok(()) | Err(AxError::AlreadyExists) => {}
+            Err(err) => {
+                warn!("failed to map firmware memory at {page:?}: {err:?}");
+                return None;
+            }
+        }
+        page += PAGE_SIZE_4K;
+    }
+    Some(())
+}
+
+/// Reads `N` bytes at `offset` of `data`, to be decoded as a little-endian
+/// integer.
+pub fn read_le<const N: usize>(data: &[u8], offset: usize) -> Option<[u8; N]> {
+    data.get(offset..offset.checked_add(N)?)?.try_into().ok()
+}
+
+/// Returns whether the bytes of `data` add up to zero, as the checksums of
+/// firmware tables make them.
+pub fn checksum_ok(data: &[u8]) -> bool {
+    data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0
+}
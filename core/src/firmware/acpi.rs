@@ -0,0 +1,387 @@
+//! ACPI tables: the CPUs and interrupt controllers described by the MADT, the
+//! PCIe configuration spaces described by the MCFG, and the reset register
+//! described by the FADT.
+//!
+//! The tables are only found on x86_64, by scanning the BIOS memory for the
+//! RSDP. Elsewhere, the RSDP is only known through the EFI configuration
+//! table, which the platform layer doesn't pass on. CPUs and PCI buses are
+//! brought up by the platform layer before the tables are read, so what they
+//! describe is reported rather than acted upon, except for the reset
+//! register.
+
+use alloc::vec::Vec;
+#[cfg(target_arch = "x86_64")]
+use core::arch::asm;
+
+use memory_addr::PhysAddr;
+use spin::Once;
+
+use super::{checksum_ok, map_firmware, read_le};
+
+/// The length of the header common to all tables but the FACS.
+const HEADER_LEN: usize = 36;
+
+/// An ACPI table, as found in memory.
+pub struct AcpiTable {
+    /// The signature of the table, e.g. `APIC` for the MADT.
+    pub signature: [u8; 4],
+    /// The whole table, header included.
+    pub data: &'static [u8],
+}
+
+/// A CPU described by the MADT.
+#[derive(Debug, Clone, Copy)]
+pub struct AcpiCpu {
+    /// The ACPI processor UID.
+    pub uid: u32,
+    /// The hardware ID of the CPU: the local APIC ID on x86_64, the MPIDR on
+    /// aarch64.
+    pub hw_id: u64,
+    /// Whether the CPU is usable. CPUs that aren't may be hot-added later.
+    pub enabled: bool,
+}
+
+/// An interrupt controller described by the MADT, other than those of the
+/// CPUs.
+#[derive(Debug, Clone, Copy)]
+pub enum InterruptController {
+    /// An I/O APIC.
+    IoApic {
+        /// The ID of the I/O APIC.
+        id: u8,
+        /// The physical address of its registers.
+        address: PhysAddr,
+        /// The first global system interrupt it handles.
+        gsi_base: u32,
+    },
+    /// A GIC distributor.
+    GicDistributor {
+        /// The physical address of its registers.
+        address: PhysAddr,
+        /// The GIC version, or 0 if the firmware leaves it to be probed.
+        version: u8,
+    },
+}
+
+/// A range of PCI buses whose configuration space is memory-mapped, as
+/// described by the MCFG.
+#[derive(Debug, Clone, Copy)]
+pub struct PciSegment {
+    /// The PCI segment group number.
+    pub segment: u16,
+    /// The physical address of the ECAM area.
+    pub ecam: PhysAddr,
+    /// The first bus decoded.
+    pub bus_start: u8,
+    /// The last bus decoded.
+    pub bus_end: u8,
+}
+
+/// A register described by a generic address structure.
+#[derive(Debug, Clone, Copy)]
+struct GenericAddress {
+    space: u8,
+    address: u64,
+}
+
+impl GenericAddress {
+    fn parse(data: &[u8]) -> Option<Self> {
+        Some(Self {
+            space: *data.first()?,
+            address: u64::from_le_bytes(read_le(data, 4)?),
+        })
+    }
+
+    /// Writes a byte to the register. Only registers in the system I/O
+    /// space are supported, which is where x86_64 firmware puts the reset
+    /// register.
+    fn write_u8(&self, value: u8) {
+        #[cfg(target_arch = "x86_64")]
+        {
+            const SYSTEM_IO: u8 = 1;
+            if self.space == SYSTEM_IO {
+                let port = self.address as u16;
+                unsafe {
+                    asm!("out dx, al", in("dx") port, in("al") value, options(nomem, nostack))
+                };
+                return;
+            }
+        }
+        let _ = value;
+        warn!(
+            "ACPI: unsupported address space {} of the reset register",
+            self.space
+        );
+    }
+}
+
+struct Acpi {
+    tables: Vec<AcpiTable>,
+    cpus: Vec<AcpiCpu>,
+    controllers: Vec<InterruptController>,
+    pci_segments: Vec<PciSegment>,
+    /// The reset register and the value to write to it.
+    reset: Option<(GenericAddress, u8)>,
+}
+
+static ACPI: Once<Acpi> = Once::new();
+
+/// Scans the first KiB of the extended BIOS data area and the BIOS area for
+/// the RSDP, which is anchored on a 16-byte boundary.
+#[cfg(target_arch = "x86_64")]
+fn find_rsdp() -> Option<&'static [u8]> {
+    let ebda = map_firmware(PhysAddr::from_usize(0x40e), 2)
+        .map(|it| (u16::from_le_bytes([it[0], it[1]]) as usize) << 4)
+        .filter(|&it| it != 0)
+        .and_then(|it| map_firmware(PhysAddr::from_usize(it), 1024));
+    let bios = map_firmware(PhysAddr::from_usize(0xe0000), 0x20000);
+    ebda.into_iter().chain(bios).find_map(|area| {
+        (0..area.len())
+            .step_by(16)
+            .map(|offset| &area[offset..])
+            .find(|it| it.starts_with(b"RSD PTR "))
+    })
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn find_rsdp() -> Option<&'static [u8]> {
+    None
+}
+
+/// Returns the physical addresses of the tables listed by the RSDP: from the
+/// XSDT if there is one, from the RSDT otherwise.
+fn table_addresses(rsdp: &[u8]) -> Option<Vec<PhysAddr>> {
+    if !checksum_ok(rsdp.get(..20)?) {
+        return None;
+    }
+    let (root, entry_len) = if rsdp[15] >= 2
+        && let Some(ext) = rsdp.get(..u32::from_le_bytes(read_le(rsdp, 20)?) as usize)
+        && checksum_ok(ext)
+    {
+        (u64::from_le_bytes(read_le(rsdp, 24)?) as usize, 8)
+    } else {
+        (u32::from_le_bytes(read_le(rsdp, 16)?) as usize, 4)
+    };
+    let root = map_table(PhysAddr::from_usize(root))?;
+    Some(
+        root[HEADER_LEN..]
+            .chunks_exact(entry_len)
+            .map(|entry| {
+                let mut addr = [0; 8];
+                addr[..entry_len].copy_from_slice(entry);
+                PhysAddr::from_usize(u64::from_le_bytes(addr) as usize)
+            })
+            .collect(),
+    )
+}
+
+/// Maps the table at `paddr`, checking its length and checksum.
+fn map_table(paddr: PhysAddr) -> Option<&'static [u8]> {
+    if paddr.as_usize() == 0 {
+        return None;
+    }
+    let header = map_firmware(paddr, HEADER_LEN)?;
+    let len = u32::from_le_bytes(read_le(header, 4)?) as usize;
+    if len < HEADER_LEN {
+        return None;
+    }
+    let table = map_firmware(paddr, len)?;
+    if !checksum_ok(table) {
+        warn!(
+            "ACPI: bad checksum of {} at {paddr:?}",
+            str::from_utf8(&table[..4]).unwrap_or("????")
+        );
+        return None;
+    }
+    Some(table)
+}
+
+/// Parses the entries of the MADT.
+fn parse_madt(madt: &[u8], acpi: &mut Acpi) {
+    let mut entries = madt.get(44..).unwrap_or_default();
+    while let &[ty, len, ..] = entries {
+        let len = len as usize;
+        if len < 2 || len > entries.len() {
+            break;
+        }
+        let (entry, rest) = entries.split_at(len);
+        entries = rest;
+
+        let u32_at = |offset| read_le(entry, offset).map(u32::from_le_bytes);
+        let u64_at = |offset| read_le(entry, offset).map(u64::from_le_bytes);
+        match ty {
+            // Processor local APIC
+            0 => {
+                if let (Some(&uid), Some(&id), Some(flags)) =
+                    (entry.get(2), entry.get(3), u32_at(4))
+                {
+                    acpi.cpus.push(AcpiCpu {
+                        uid: uid as u32,
+                        hw_id: id as u64,
+                        enabled: flags & 1 != 0,
+                    });
+                }
+            }
+            // I/O APIC
+            1 => {
+                if let (Some(&id), Some(address), Some(gsi_base)) =
+                    (entry.get(2), u32_at(4), u32_at(8))
+                {
+                    acpi.controllers.push(InterruptController::IoApic {
+                        id,
+                        address: PhysAddr::from_usize(address as usize),
+                        gsi_base,
+                    });
+                }
+            }
+            // Processor local x2APIC
+            9 => {
+                if let (Some(id), Some(flags), Some(uid)) = (u32_at(4), u32_at(8), u32_at(12)) {
+                    acpi.cpus.push(AcpiCpu {
+                        uid,
+                        hw_id: id as u64,
+                        enabled: flags & 1 != 0,
+                    });
+                }
+            }
+            // GIC CPU interface
+            0xb => {
+                if let (Some(uid), Some(flags), Some(mpidr)) = (u32_at(8), u32_at(12), u64_at(68)) {
+                    acpi.cpus.push(AcpiCpu {
+                        uid,
+                        hw_id: mpidr,
+                        enabled: flags & 1 != 0,
+                    });
+                }
+            }
+            // GIC distributor
+            0xc => {
+                if let Some(address) = u64_at(8) {
+                    acpi.controllers.push(InterruptController::GicDistributor {
+                        address: PhysAddr::from_usize(address as usize),
+                        version: entry.get(20).copied().unwrap_or(0),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Parses the configuration space entries of the MCFG.
+fn parse_mcfg(mcfg: &[u8], acpi: &mut Acpi) {
+    for entry in mcfg.get(44..).unwrap_or_default().chunks_exact(16) {
+        acpi.pci_segments.push(PciSegment {
+            segment: u16::from_le_bytes([entry[8], entry[9]]),
+            ecam: PhysAddr::from_usize(u64::from_le_bytes(read_le(entry, 0).unwrap()) as usize),
+            bus_start: entry[10],
+            bus_end: entry[11],
+        });
+    }
+}
+
+/// Parses the reset register of the FADT, returning the address of the DSDT.
+fn parse_fadt(fadt: &[u8], acpi: &mut Acpi) -> Option<PhysAddr> {
+    /// Whether the reset register is supported.
+    const RESET_REG_SUP: u32 = 1 << 10;
+
+    let flags = u32::from_le_bytes(read_le(fadt, 112)?);
+    if flags & RESET_REG_SUP != 0
+        && let Some(reg) = fadt.get(116..128).and_then(GenericAddress::parse)
+        && let Some(&value) = fadt.get(128)
+    {
+        acpi.reset = Some((reg, value));
+    }
+
+    // The 64-bit address takes precedence where present.
+    let dsdt = read_le(fadt, 140)
+        .map(u64::from_le_bytes)
+        .filter(|&it| it != 0)
+        .or_else(|| read_le(fadt, 40).map(|it| u32::from_le_bytes(it) as u64))?;
+    Some(PhysAddr::from_usize(dsdt as usize))
+}
+
+/// Finds and parses the ACPI tables.
+pub fn init() {
+    let Some(addresses) = find_rsdp().and_then(table_addresses) else {
+        info!("ACPI not present or invalid");
+        return;
+    };
+
+    let mut acpi = Acpi {
+        tables: Vec::new(),
+        cpus: Vec::new(),
+        controllers: Vec::new(),
+        pci_segments: Vec::new(),
+        reset: None,
+    };
+    let mut dsdt = None;
+    for table in addresses.into_iter().filter_map(map_table) {
+        let signature: [u8; 4] = table[..4].try_into().unwrap();
+        match &signature {
+            b"APIC" => parse_madt(table, &mut acpi),
+            b"MCFG" => parse_mcfg(table, &mut acpi),
+            b"FACP" => dsdt = parse_fadt(table, &mut acpi),
+            _ => {}
+        }
+        acpi.tables.push(AcpiTable {
+            signature,
+            data: table,
+        });
+    }
+    if let Some(table) = dsdt.and_then(map_table) {
+        acpi.tables.push(AcpiTable {
+            signature: *b"DSDT",
+            data: table,
+        });
+    }
+
+    info!(
+        "ACPI: {} tables, {} CPUs ({} enabled), {} PCI segments",
+        acpi.tables.len(),
+        acpi.cpus.len(),
+        acpi.cpus.iter().filter(|it| it.enabled).count(),
+        acpi.pci_segments.len()
+    );
+    for segment in &acpi.pci_segments {
+        info!(
+            "ACPI: PCI segment {} buses {:02x}-{:02x} at {:?}",
+            segment.segment, segment.bus_start, segment.bus_end, segment.ecam
+        );
+    }
+    ACPI.call_once(|| acpi);
+}
+
+/// Returns whether ACPI tables were found at boot.
+pub fn available() -> bool {
+    ACPI.get().is_some()
+}
+
+/// Returns the ACPI tables found at boot, in the order the firmware lists
+/// them, followed by the DSDT.
+pub fn tables() -> &'static [AcpiTable] {
+    ACPI.get().map_or(&[], |it| &it.tables)
+}
+
+/// Returns the CPUs described by the MADT.
+pub fn cpus() -> &'static [AcpiCpu] {
+    ACPI.get().map_or(&[], |it| &it.cpus)
+}
+
+/// Returns the interrupt controllers described by the MADT.
+pub fn interrupt_controllers() -> &'static [InterruptController] {
+    ACPI.get().map_or(&[], |it| &it.controllers)
+}
+
+/// Returns the PCI segments described by the MCFG.
+pub fn pci_segments() -> &'static [PciSegment] {
+    ACPI.get().map_or(&[], |it| &it.pci_segments)
+}
+
+/// Resets the machine through the reset register of the FADT, if it has one.
+/// Only returns if there is none or the reset failed.
+pub fn reset() {
+    if let Some((reg, value)) = ACPI.get().and_then(|it| it.reset) {
+        reg.write_u8(value);
+    }
+}
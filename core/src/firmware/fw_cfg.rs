@@ -0,0 +1,81 @@
+//! The QEMU firmware configuration device, through which QEMU passes files
+//! such as the SMBIOS tables to the guest.
+//!
+//! Only the MMIO interface of the `virt` machine is supported, at the address
+//! that machine puts it at.
+
+use alloc::{vec, vec::Vec};
+use core::ptr;
+
+use memory_addr::{PhysAddr, VirtAddr};
+
+use super::map_mmio;
+
+/// The physical address of the registers on the `virt` machine.
+const FW_CFG_BASE: usize = 0x0902_0000;
+/// The size of the registers: data, selector and DMA address.
+const FW_CFG_SIZE: usize = 0x18;
+
+/// The item holding the "QEMU" signature.
+const FW_CFG_SIGNATURE: u16 = 0x00;
+/// The item listing the files.
+const FW_CFG_FILE_DIR: u16 = 0x19;
+
+/// The length of a file directory entry: size, item, reserved and name.
+const FILE_ENTRY_LEN: usize = 64;
+
+struct FwCfg {
+    base: VirtAddr,
+}
+
+impl FwCfg {
+    /// Finds the device, if the kernel runs on a QEMU `virt` machine.
+    fn probe() -> Option<Self> {
+        if axconfig::PLATFORM != "aarch64-qemu-virt" {
+            return None;
+        }
+        let base = map_mmio(PhysAddr::from_usize(FW_CFG_BASE), FW_CFG_SIZE)?;
+        let dev = Self { base };
+        let mut signature = [0; 4];
+        dev.select(FW_CFG_SIGNATURE);
+        dev.read(&mut signature);
+        (signature == *b"QEMU").then_some(dev)
+    }
+
+    /// Selects the item to read, from its start. The selector is big-endian.
+    fn select(&self, item: u16) {
+        let selector = (self.base + 8).as_mut_ptr_of::<u16>();
+        unsafe { ptr::write_volatile(selector, item.to_be()) };
+    }
+
+    /// Reads the next bytes of the selected item.
+    fn read(&self, buf: &mut [u8]) {
+        let data = self.base.as_ptr();
+        for byte in buf {
+            *byte = unsafe { ptr::read_volatile(data) };
+        }
+    }
+}
+
+/// Reads the file called `name`, if QEMU passes one.
+pub fn read_file(name: &str) -> Option<Vec<u8>> {
+    let dev = FwCfg::probe()?;
+    let mut count = [0; 4];
+    dev.select(FW_CFG_FILE_DIR);
+    dev.read(&mut count);
+    for _ in 0..u32::from_be_bytes(count) {
+        let mut entry = [0; FILE_ENTRY_LEN];
+        dev.read(&mut entry);
+        let entry_name = entry[8..].split(|&b| b == 0).next().unwrap_or_default();
+        if entry_name != name.as_bytes() {
+            continue;
+        }
+        let size = u32::from_be_bytes(entry[..4].try_into().unwrap());
+        let item = u16::from_be_bytes([entry[4], entry[5]]);
+        let mut data = vec![0; size as usize];
+        dev.select(item);
+        dev.read(&mut data);
+        return Some(data);
+    }
+    None
+}
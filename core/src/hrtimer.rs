@@ -0,0 +1,145 @@
+//! High-resolution timers, which run a callback at a given deadline.
+//!
+//! Unlike the interval timers in [`time`](crate::time), which are polled per
+//! task, each timer here keeps its exact deadline, and a single kernel task
+//! runs the callbacks as they come due. Deadlines are on the wall clock that
+//! axtask timeouts use, so they can be converted from any clock by adding the
+//! remaining time to [`wall_time`].
+//!
+//! axtask only checks timeouts when the timer interrupt fires, which is
+//! otherwise once per periodic tick. So that callbacks don't wait for the
+//! tick after their deadline, the task programs the interrupt for the
+//! earliest deadline when that comes before the next tick.
+
+use alloc::{borrow::ToOwned, boxed::Box, collections::btree_map::BTreeMap};
+use core::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use axconfig::TICKS_PER_SEC;
+use axhal::time::{
+    NANOS_PER_SEC, epochoffset_nanos, set_oneshot_timer, wall_time, wall_time_nanos,
+};
+use axsync::spin::SpinNoIrq;
+use axtask::future::{block_on, timeout_at};
+use event_listener::{Event, listener};
+use lazy_static::lazy_static;
+
+type Callback = Box<dyn FnOnce() + Send>;
+
+/// The pending timers, by deadline and then by a sequence number, so that
+/// equal deadlines fire in arming order.
+static QUEUE: SpinNoIrq<BTreeMap<(Duration, u64), Callback>> = SpinNoIrq::new(BTreeMap::new());
+static NEXT_SEQ: AtomicU64 = AtomicU64::new(0);
+
+lazy_static! {
+    static ref EVENT_NEW_TIMER: Event = Event::new();
+}
+
+/// A timer started by [`start`], which can be cancelled until it fires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerHandle {
+    deadline: Duration,
+    seq: u64,
+}
+
+impl TimerHandle {
+    /// Cancels the timer, returning whether it was still pending.
+    ///
+    /// A callback already taken off the queue may still be running or about
+    /// to run; owners that re-arm should tell stale callbacks apart, such as
+    /// with a generation counter.
+    pub fn cancel(self) -> bool {
+        let callback = QUEUE.lock().remove(&(self.deadline, self.seq));
+        // Dropped outside the lock, as it may own anything.
+        callback.is_some()
+    }
+}
+
+/// Runs `callback` once [`wall_time`] reaches `deadline`, returning a handle
+/// to cancel it.
+pub fn start(deadline: Duration, callback: impl FnOnce() + Send + 'static) -> TimerHandle {
+    let handle = TimerHandle {
+        deadline,
+        seq: NEXT_SEQ.fetch_add(1, Ordering::Relaxed),
+    };
+    let mut queue = QUEUE.lock();
+    let should_wake = queue
+        .first_key_value()
+        .is_none_or(|((it, _), _)| *it > deadline);
+    queue.insert((handle.deadline, handle.seq), Box::new(callback));
+    drop(queue);
+    if should_wake {
+        EVENT_NEW_TIMER.notify(1);
+    }
+    handle
+}
+
+/// Runs `callback` after `delay`, returning a handle to cancel it.
+pub fn start_after(delay: Duration, callback: impl FnOnce() + Send + 'static) -> TimerHandle {
+    start(wall_time() + delay, callback)
+}
+
+/// Returns the deadline of the earliest pending timer.
+fn next_deadline(queue: &BTreeMap<(Duration, u64), Callback>) -> Option<Duration> {
+    queue.first_key_value().map(|((deadline, _), _)| *deadline)
+}
+
+/// Programs the timer interrupt of the current CPU for `deadline`, if that
+/// is sooner than the next periodic tick can be.
+///
+/// The tick handler programs the next tick again when the interrupt fires,
+/// at most one period later, and expires the axtask timeout the hrtimer task
+/// waits on.
+fn program_interrupt(deadline: Duration) {
+    const TICK_NANOS: u64 = NANOS_PER_SEC / TICKS_PER_SEC as u64;
+    let deadline = deadline.as_nanos().min(u64::MAX as u128) as u64;
+    if deadline < wall_time_nanos().saturating_add(TICK_NANOS) {
+        // The interrupt is programmed on the monotonic clock.
+        set_oneshot_timer(deadline.saturating_sub(epochoffset_nanos()));
+    }
+}
+
+async fn hrtimer_task() {
+    loop {
+        let mut queue = QUEUE.lock();
+        let Some(next) = next_deadline(&queue) else {
+            drop(queue);
+            listener!(EVENT_NEW_TIMER => listener);
+            if !QUEUE.lock().is_empty() {
+                continue;
+            }
+            listener.await;
+            continue;
+        };
+
+        if next <= wall_time() {
+            let (_, callback) = queue.pop_first().unwrap();
+            drop(queue);
+            callback();
+            continue;
+        }
+
+        drop(queue);
+        listener!(EVENT_NEW_TIMER => listener);
+        let queue = QUEUE.lock();
+        if next_deadline(&queue) != Some(next) {
+            continue;
+        }
+        // The timeout below waits on the timer list of this CPU, which is
+        // the one the interrupt is programmed on.
+        program_interrupt(next);
+        drop(queue);
+        let _ = timeout_at(Some(next), listener).await;
+    }
+}
+
+/// Spawns the task that runs timer callbacks.
+pub fn spawn_hrtimer_task() {
+    axtask::spawn_raw(
+        || block_on(hrtimer_task()),
+        "hrtimer".to_owned(),
+        axconfig::TASK_STACK_SIZE,
+    );
+}
@@ -0,0 +1,252 @@
+//! Kernel key retention, as used through `add_key`, `request_key` and
+//! `keyctl`.
+//!
+//! Only the `user`, `logon` and `keyring` key types exist, and keys can't be
+//! instantiated by an upcall, so `request_key` only finds keys already
+//! added. Keys are owned by root and don't expire.
+
+use alloc::{
+    collections::btree_map::BTreeMap,
+    format,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicI32, Ordering};
+
+use axerrno::{AxError, AxResult, LinuxError};
+use kspin::SpinNoIrq;
+
+/// The largest payload of a `user` or `logon` key, in bytes.
+pub const MAX_PAYLOAD: usize = 32767;
+
+/// The permissions given to new keys: everything for possessors, and view
+/// for the owner.
+const DEFAULT_PERM: u32 = 0x3f01_0000;
+
+/// The type of a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyType {
+    /// A blob of data that user space can read back.
+    User,
+    /// Like [`KeyType::User`], but unreadable from user space.
+    Logon,
+    /// A set of links to other keys.
+    Keyring,
+}
+
+impl KeyType {
+    /// Looks up a key type by name, failing with `ENODEV` if it is unknown.
+    pub fn from_name(name: &str) -> AxResult<Self> {
+        match name {
+            "user" => Ok(KeyType::User),
+            "logon" => Ok(KeyType::Logon),
+            "keyring" => Ok(KeyType::Keyring),
+            _ => Err(AxError::NoSuchDevice),
+        }
+    }
+
+    /// Returns the name of the key type.
+    pub fn name(&self) -> &'static str {
+        match self {
+            KeyType::User => "user",
+            KeyType::Logon => "logon",
+            KeyType::Keyring => "keyring",
+        }
+    }
+}
+
+enum Payload {
+    Data(Vec<u8>),
+    Keyring(Vec<Arc<Key>>),
+    Revoked,
+}
+
+/// A key.
+pub struct Key {
+    serial: i32,
+    key_type: KeyType,
+    description: String,
+    perm: u32,
+    payload: SpinNoIrq<Payload>,
+}
+
+/// The keys alive, by serial number.
+static KEYS: SpinNoIrq<BTreeMap<i32, Weak<Key>>> = SpinNoIrq::new(BTreeMap::new());
+static NEXT_SERIAL: AtomicI32 = AtomicI32::new(1);
+
+/// The user keyring and user session keyring of root, the only user.
+static USER_KEYRINGS: SpinNoIrq<Option<(Arc<Key>, Arc<Key>)>> = SpinNoIrq::new(None);
+
+impl Key {
+    fn new(key_type: KeyType, description: String, payload: Payload) -> Arc<Self> {
+        let key = Arc::new(Self {
+            serial: NEXT_SERIAL.fetch_add(1, Ordering::Relaxed),
+            key_type,
+            description,
+            perm: DEFAULT_PERM,
+            payload: SpinNoIrq::new(payload),
+        });
+        let mut keys = KEYS.lock();
+        keys.retain(|_, key| key.strong_count() > 0);
+        keys.insert(key.serial, Arc::downgrade(&key));
+        key
+    }
+
+    /// Creates an empty keyring.
+    pub fn new_keyring(description: String) -> Arc<Self> {
+        Self::new(KeyType::Keyring, description, Payload::Keyring(Vec::new()))
+    }
+
+    /// Looks up a key by serial number.
+    pub fn get(serial: i32) -> AxResult<Arc<Self>> {
+        KEYS.lock()
+            .get(&serial)
+            .and_then(Weak::upgrade)
+            .ok_or(AxError::from(LinuxError::ENOKEY))
+    }
+
+    /// Returns the serial number of the key.
+    pub fn serial(&self) -> i32 {
+        self.serial
+    }
+
+    /// Returns the type of the key.
+    pub fn key_type(&self) -> KeyType {
+        self.key_type
+    }
+
+    /// Returns whether the key was revoked.
+    pub fn is_revoked(&self) -> bool {
+        matches!(*self.payload.lock(), Payload::Revoked)
+    }
+
+    /// Revokes the key, dropping its payload.
+    pub fn revoke(&self) {
+        *self.payload.lock() = Payload::Revoked;
+    }
+
+    /// Returns the description of the key as `KEYCTL_DESCRIBE` does:
+    /// `type;uid;gid;perm;description`.
+    pub fn describe(&self) -> AxResult<String> {
+        if self.is_revoked() {
+            return Err(AxError::from(LinuxError::EKEYREVOKED));
+        }
+        Ok(format!(
+            "{};0;0;{:08x};{}",
+            self.key_type.name(),
+            self.perm,
+            self.description
+        ))
+    }
+
+    /// Returns the payload as `KEYCTL_READ` does: the data of a `user` key,
+    /// or the serial numbers of the keys linked to a keyring.
+    pub fn read(&self) -> AxResult<Vec<u8>> {
+        match &*self.payload.lock() {
+            Payload::Revoked => Err(AxError::from(LinuxError::EKEYREVOKED)),
+            Payload::Data(_) if self.key_type == KeyType::Logon => {
+                Err(AxError::OperationNotSupported)
+            }
+            Payload::Data(data) => Ok(data.clone()),
+            Payload::Keyring(links) => Ok(links
+                .iter()
+                .flat_map(|key| key.serial.to_ne_bytes())
+                .collect()),
+        }
+    }
+
+    fn links(&self) -> AxResult<Vec<Arc<Key>>> {
+        match &*self.payload.lock() {
+            Payload::Keyring(links) => Ok(links.clone()),
+            Payload::Revoked => Err(AxError::from(LinuxError::EKEYREVOKED)),
+            Payload::Data(_) => Err(AxError::NotADirectory),
+        }
+    }
+
+    /// Links `key` into the keyring, replacing any key of the same type and
+    /// description.
+    pub fn link(&self, key: Arc<Key>) -> AxResult<()> {
+        match &mut *self.payload.lock() {
+            Payload::Keyring(links) => {
+                links.retain(|it| it.key_type != key.key_type || it.description != key.description);
+                links.push(key);
+                Ok(())
+            }
+            Payload::Revoked => Err(AxError::from(LinuxError::EKEYREVOKED)),
+            Payload::Data(_) => Err(AxError::NotADirectory),
+        }
+    }
+
+    /// Adds a key to the keyring, as `add_key` does, returning it along with
+    /// whether it was already linked.
+    ///
+    /// A key of the same type and description already linked is updated in
+    /// place, except for keyrings, which are replaced by an empty one.
+    pub fn add(
+        &self,
+        key_type: KeyType,
+        description: String,
+        data: Vec<u8>,
+    ) -> AxResult<(Arc<Key>, bool)> {
+        if key_type != KeyType::Keyring
+            && let Some(key) = self.find_linked(key_type, &description)?
+        {
+            *key.payload.lock() = Payload::Data(data);
+            return Ok((key, true));
+        }
+        let payload = match key_type {
+            KeyType::Keyring => Payload::Keyring(Vec::new()),
+            _ => Payload::Data(data),
+        };
+        let key = Key::new(key_type, description, payload);
+        self.link(key.clone())?;
+        Ok((key, false))
+    }
+
+    fn find_linked(&self, key_type: KeyType, description: &str) -> AxResult<Option<Arc<Key>>> {
+        Ok(self.links()?.into_iter().find(|key| {
+            key.key_type == key_type && key.description == description && !key.is_revoked()
+        }))
+    }
+
+    /// Searches the keyring and the keyrings linked to it, depth first, for
+    /// a live key of the given type and description.
+    pub fn search(&self, key_type: KeyType, description: &str) -> Option<Arc<Key>> {
+        self.search_inner(key_type, description, 0)
+    }
+
+    fn search_inner(&self, key_type: KeyType, description: &str, depth: usize) -> Option<Arc<Key>> {
+        // Linux limits the nesting of keyrings to six levels.
+        if depth >= 6 {
+            return None;
+        }
+        let links = self.links().ok()?;
+        links
+            .iter()
+            .find(|key| {
+                key.key_type == key_type && key.description == description && !key.is_revoked()
+            })
+            .cloned()
+            .or_else(|| {
+                links
+                    .iter()
+                    .filter(|key| key.key_type == KeyType::Keyring)
+                    .find_map(|key| key.search_inner(key_type, description, depth + 1))
+            })
+    }
+}
+
+/// Returns the user keyring and the user session keyring, creating them on
+/// first use.
+pub fn user_keyrings() -> (Arc<Key>, Arc<Key>) {
+    USER_KEYRINGS
+        .lock()
+        .get_or_insert_with(|| {
+            let user = Key::new_keyring("_uid.0".into());
+            let session = Key::new_keyring("_uid_ses.0".into());
+            let _ = session.link(user.clone());
+            (user, session)
+        })
+        .clone()
+}
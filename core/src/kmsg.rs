@@ -0,0 +1,276 @@
+//! The kernel log, a ring buffer of messages read through /dev/kmsg and
+//! `syslog(2)`.
+//!
+//! Every message is a record with a sequence number, a timestamp and a
+//! syslog priority. When the buffer is full, the oldest records are dropped;
+//! readers that fall behind notice this from the gap in sequence numbers.
+//! Messages more important than the console log level are also printed on
+//! the console.
+
+use alloc::{
+    collections::vec_deque::VecDeque,
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{
+    fmt,
+    sync::atomic::{AtomicU8, Ordering},
+    task::Waker,
+    time::Duration,
+};
+
+use axhal::time::monotonic_time;
+use axpoll::PollSet;
+use kspin::SpinNoIrq;
+use lazy_static::lazy_static;
+
+/// The most bytes of message text kept in the buffer.
+pub const LOG_BUF_LEN: usize = 128 * 1024;
+
+/// The log level of messages that don't give one.
+pub const DEFAULT_MESSAGE_LOGLEVEL: u8 = 4;
+/// The lowest console log level that can be set.
+pub const MINIMUM_CONSOLE_LOGLEVEL: u8 = 1;
+/// The console log level at boot.
+pub const DEFAULT_CONSOLE_LOGLEVEL: u8 = 7;
+
+/// Facility of messages from the kernel itself.
+pub const LOG_KERN: u8 = 0;
+/// Facility of messages written by user space.
+pub const LOG_USER: u8 = 1;
+
+/// Messages with a level below this are printed on the console.
+static CONSOLE_LOGLEVEL: AtomicU8 = AtomicU8::new(DEFAULT_CONSOLE_LOGLEVEL);
+/// The console log level to restore when the console is turned back on, or 0
+/// if it is on.
+static SAVED_CONSOLE_LOGLEVEL: AtomicU8 = AtomicU8::new(0);
+
+/// A message in the kernel log.
+#[derive(Debug)]
+pub struct Record {
+    /// The sequence number of the record.
+    pub seq: u64,
+    /// The syslog facility, e.g. [`LOG_KERN`].
+    pub facility: u8,
+    /// The log level, from 0 (emergency) to 7 (debug).
+    pub level: u8,
+    /// The time since boot at which the message was logged.
+    pub timestamp: Duration,
+    /// The message, without a trailing newline.
+    pub text: String,
+}
+
+impl Record {
+    /// Formats the record as read from /dev/kmsg.
+    pub fn to_kmsg(&self) -> String {
+        format!(
+            "{},{},{},-;{}\n",
+            ((self.facility as u32) << 3) | self.level as u32,
+            self.seq,
+            self.timestamp.as_micros(),
+            self.text
+        )
+    }
+
+    /// Formats the record as read through `syslog(2)`.
+    pub fn to_syslog(&self) -> String {
+        format!("<{}>{}\n", self.level, self.console_line())
+    }
+
+    fn console_line(&self) -> String {
+        format!(
+            "[{:5}.{:06}] {}",
+            self.timestamp.as_secs(),
+            self.timestamp.subsec_micros(),
+            self.text
+        )
+    }
+}
+
+struct LogBuffer {
+    records: VecDeque<Arc<Record>>,
+    /// The bytes of text held.
+    size: usize,
+    next_seq: u64,
+    /// The next record `syslog(2)` reads destructively.
+    syslog_seq: u64,
+    /// The first record not cleared by `syslog(2)`.
+    clear_seq: u64,
+}
+
+impl LogBuffer {
+    fn first_seq(&self) -> u64 {
+        self.records.front().map_or(self.next_seq, |it| it.seq)
+    }
+
+    fn get(&self, seq: u64) -> Option<&Arc<Record>> {
+        let index = seq.checked_sub(self.first_seq())?;
+        self.records.get(index as usize)
+    }
+
+    fn since(&self, seq: u64) -> impl Iterator<Item = &Arc<Record>> {
+        let skip = seq.saturating_sub(self.first_seq());
+        self.records.iter().skip(skip as usize)
+    }
+}
+
+static LOG: SpinNoIrq<LogBuffer> = SpinNoIrq::new(LogBuffer {
+    records: VecDeque::new(),
+    size: 0,
+    next_seq: 0,
+    syslog_seq: 0,
+    clear_seq: 0,
+});
+
+lazy_static! {
+    /// Readers waiting for new records.
+    static ref READERS: PollSet = PollSet::new();
+}
+
+/// Adds a message to the kernel log.
+pub fn log(facility: u8, level: u8, text: &str) {
+    let level = level & 7;
+    let text = text.trim_end_matches('\n');
+    let mut log = LOG.lock();
+    let record = Arc::new(Record {
+        seq: log.next_seq,
+        facility,
+        level,
+        timestamp: monotonic_time(),
+        text: text.to_string(),
+    });
+    log.next_seq += 1;
+    log.size += text.len();
+    log.records.push_back(record.clone());
+    while log.size > LOG_BUF_LEN
+        && let Some(oldest) = log.records.pop_front()
+    {
+        log.size -= oldest.text.len();
+    }
+    drop(log);
+
+    if level < CONSOLE_LOGLEVEL.load(Ordering::Relaxed) {
+        let mut line = record.console_line();
+        line.push('\n');
+        axhal::console::write_bytes(line.as_bytes());
+    }
+    READERS.wake();
+}
+
+/// Adds a formatted message from the kernel to the kernel log.
+pub fn printk(level: u8, args: fmt::Arguments) {
+    log(LOG_KERN, level, &args.to_string());
+}
+
+/// Returns the sequence number of the oldest record still kept.
+pub fn first_seq() -> u64 {
+    LOG.lock().first_seq()
+}
+
+/// Returns the sequence number the next record will get.
+pub fn next_seq() -> u64 {
+    LOG.lock().next_seq
+}
+
+/// Returns the record with sequence number `seq`.
+///
+/// Fails with the sequence number of the oldest record if `seq` has already
+/// been dropped, and returns `None` if it hasn't been logged yet.
+pub fn read(seq: u64) -> Result<Option<Arc<Record>>, u64> {
+    let log = LOG.lock();
+    if seq < log.first_seq() {
+        return Err(log.first_seq());
+    }
+    Ok(log.get(seq).cloned())
+}
+
+/// Registers a waker to be woken when a record is logged.
+pub fn register_reader(waker: &Waker) {
+    READERS.register(waker);
+}
+
+/// Reads records not yet read by this function, up to `len` bytes in the
+/// `syslog(2)` format, as for `SYSLOG_ACTION_READ`.
+pub fn syslog_read(len: usize) -> String {
+    let mut log = LOG.lock();
+    let mut out = String::new();
+    let mut seq = log.syslog_seq.max(log.first_seq());
+    while let Some(record) = log.get(seq) {
+        let line = record.to_syslog();
+        if out.len() + line.len() > len {
+            break;
+        }
+        out += &line;
+        seq += 1;
+    }
+    log.syslog_seq = seq;
+    out
+}
+
+/// Returns whether there are records not yet read by [`syslog_read`].
+pub fn syslog_pending() -> bool {
+    let log = LOG.lock();
+    log.syslog_seq < log.next_seq
+}
+
+/// Returns the size of the records not yet read by [`syslog_read`], as for
+/// `SYSLOG_ACTION_SIZE_UNREAD`.
+pub fn syslog_unread_size() -> usize {
+    let log = LOG.lock();
+    log.since(log.syslog_seq)
+        .map(|it| it.to_syslog().len())
+        .sum()
+}
+
+/// Returns the most recent records that fit in `len` bytes in the
+/// `syslog(2)` format, leaving out those cleared by [`clear`].
+pub fn syslog_read_all(len: usize) -> String {
+    let log = LOG.lock();
+    let lines = log
+        .since(log.clear_seq)
+        .map(|it| it.to_syslog())
+        .collect::<Vec<_>>();
+    let mut size = lines.iter().map(String::len).sum::<usize>();
+    let mut lines = lines.into_iter();
+    while size > len
+        && let Some(line) = lines.next()
+    {
+        size -= line.len();
+    }
+    lines.collect()
+}
+
+/// Hides the records logged so far from [`syslog_read_all`].
+pub fn clear() {
+    let mut log = LOG.lock();
+    log.clear_seq = log.next_seq;
+}
+
+/// Returns the console log level.
+pub fn console_loglevel() -> u8 {
+    CONSOLE_LOGLEVEL.load(Ordering::Relaxed)
+}
+
+/// Sets the console log level, turning the console back on if it was off.
+pub fn set_console_loglevel(level: u8) {
+    CONSOLE_LOGLEVEL.store(level.max(MINIMUM_CONSOLE_LOGLEVEL), Ordering::Relaxed);
+    SAVED_CONSOLE_LOGLEVEL.store(0, Ordering::Relaxed);
+}
+
+/// Stops printing messages other than emergencies on the console.
+pub fn console_off() {
+    let level = CONSOLE_LOGLEVEL.swap(MINIMUM_CONSOLE_LOGLEVEL, Ordering::Relaxed);
+    if SAVED_CONSOLE_LOGLEVEL.load(Ordering::Relaxed) == 0 {
+        SAVED_CONSOLE_LOGLEVEL.store(level, Ordering::Relaxed);
+    }
+}
+
+/// Restores the console log level from before [`console_off`].
+pub fn console_on() {
+    let saved = SAVED_CONSOLE_LOGLEVEL.swap(0, Ordering::Relaxed);
+    if saved != 0 {
+        CONSOLE_LOGLEVEL.store(saved, Ordering::Relaxed);
+    }
+}
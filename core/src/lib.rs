@@ -7,15 +7,34 @@
 
 extern crate alloc;
 
+// Declared first so that its logging macros are in scope everywhere else.
 #[macro_use]
-extern crate axlog;
+pub mod logfilter;
 
+pub mod binfmt;
 pub mod config;
+pub mod cred;
+pub mod firmware;
 pub mod futex;
+pub mod hrtimer;
+pub mod keys;
+pub mod kmsg;
 mod lrucache;
+pub mod mempolicy;
+pub mod mitigations;
+pub mod mlock;
 pub mod mm;
+pub mod posix_timer;
+pub mod power;
+pub mod random;
 pub mod resources;
+pub mod rusage;
+pub mod sched;
 pub mod shm;
 pub mod task;
 pub mod time;
+pub mod timekeeping;
+pub mod trace;
 pub mod vfs;
+pub mod watchdog;
+pub mod workqueue;
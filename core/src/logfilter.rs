@@ -0,0 +1,180 @@
+//! Runtime control of log output.
+//!
+//! The global log level can be changed at any time, and rules can give
+//! individual modules a level of their own, e.g. `starry_api::syscall=off`.
+//!
+//! Rules are applied by the logging macros exported from this crate, which
+//! the code in this tree uses instead of axlog's. Crates outside this tree
+//! log through axlog directly and only follow the global level; since that
+//! level is raised to let the most verbose rule through, a rule above the
+//! global level lets their messages through as well.
+
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{
+    cmp::Reverse,
+    str::FromStr,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use axerrno::{AxError, AxResult};
+use kspin::SpinNoIrq;
+#[doc(hidden)]
+pub use log;
+pub use log::{Level, LevelFilter};
+
+const LEVELS: [LevelFilter; 6] = [
+    LevelFilter::Off,
+    LevelFilter::Error,
+    LevelFilter::Warn,
+    LevelFilter::Info,
+    LevelFilter::Debug,
+    LevelFilter::Trace,
+];
+
+/// The level of modules without a rule of their own, as an index into
+/// [`LEVELS`].
+static DEFAULT_LEVEL: AtomicUsize = AtomicUsize::new(LevelFilter::Warn as usize);
+/// Whether [`RULES`] is empty, so that logging needn't take its lock.
+static HAS_RULES: AtomicBool = AtomicBool::new(false);
+/// Per-module levels, most specific module first.
+static RULES: SpinNoIrq<Vec<(String, LevelFilter)>> = SpinNoIrq::new(Vec::new());
+
+/// Whether the module at `target` is `module` or inside it.
+fn is_within(target: &str, module: &str) -> bool {
+    target
+        .strip_prefix(module)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+}
+
+/// Tells the logger the most verbose level anything may be logged at.
+fn update_max_level(rules: &[(String, LevelFilter)]) {
+    let max = rules
+        .iter()
+        .map(|(_, level)| *level)
+        .fold(default_level(), LevelFilter::max);
+    log::set_max_level(max);
+}
+
+/// Takes the current global level from the logger at boot.
+pub fn init() {
+    DEFAULT_LEVEL.store(log::max_level() as usize, Ordering::Relaxed);
+}
+
+/// Returns the global log level.
+pub fn default_level() -> LevelFilter {
+    LEVELS[DEFAULT_LEVEL.load(Ordering::Relaxed)]
+}
+
+/// Sets the global log level.
+pub fn set_default_level(level: LevelFilter) {
+    DEFAULT_LEVEL.store(level as usize, Ordering::Relaxed);
+    update_max_level(&RULES.lock());
+}
+
+/// Returns whether a message at `level` from the module at `target` is
+/// logged.
+pub fn enabled(target: &str, level: Level) -> bool {
+    if level > log::max_level() {
+        return false;
+    }
+    if !HAS_RULES.load(Ordering::Relaxed) {
+        return level <= default_level();
+    }
+    let rules = RULES.lock();
+    let filter = rules
+        .iter()
+        .find(|(module, _)| is_within(target, module))
+        .map_or_else(default_level, |(_, level)| *level);
+    level <= filter
+}
+
+/// Parses and applies a filter in the `RUST_LOG` syntax: a list of
+/// directives separated by commas or whitespace, each either a level, which
+/// becomes the global level, or `module=level`. The rules replace all
+/// previous ones.
+pub fn set_filter(spec: &str) -> AxResult<()> {
+    let parse = |level: &str| LevelFilter::from_str(level).map_err(|_| AxError::InvalidInput);
+    let mut default = None;
+    let mut rules = Vec::new();
+    for directive in spec
+        .split([',', ' ', '\t', '\n'])
+        .filter(|it| !it.is_empty())
+    {
+        match directive.split_once('=') {
+            Some((module, level)) => rules.push((module.to_string(), parse(level)?)),
+            None => default = Some(parse(directive)?),
+        }
+    }
+    // Longer module paths are more specific.
+    rules.sort_by_key(|(module, _)| Reverse(module.len()));
+
+    if let Some(default) = default {
+        DEFAULT_LEVEL.store(default as usize, Ordering::Relaxed);
+    }
+    let mut current = RULES.lock();
+    *current = rules;
+    HAS_RULES.store(!current.is_empty(), Ordering::Relaxed);
+    update_max_level(&current);
+    Ok(())
+}
+
+/// Formats the global level and the rules in the syntax [`set_filter`]
+/// takes.
+pub fn filter() -> String {
+    let mut out = default_level().as_str().to_ascii_lowercase();
+    for (module, level) in RULES.lock().iter() {
+        out += ",";
+        out += module;
+        out += "=";
+        out += &level.as_str().to_ascii_lowercase();
+    }
+    out
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __log_filtered {
+    ($level:ident, $($arg:tt)+) => {
+        if $crate::logfilter::enabled(module_path!(), $crate::logfilter::Level::$level) {
+            $crate::logfilter::log::log!($crate::logfilter::Level::$level, $($arg)+);
+        }
+    };
+}
+
+/// Logs a message at the error level, subject to the filter of the calling
+/// module.
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)+) => { $crate::__log_filtered!(Error, $($arg)+) };
+}
+
+/// Logs a message at the warn level, subject to the filter of the calling
+/// module.
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)+) => { $crate::__log_filtered!(Warn, $($arg)+) };
+}
+
+/// Logs a message at the info level, subject to the filter of the calling
+/// module.
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)+) => { $crate::__log_filtered!(Info, $($arg)+) };
+}
+
+/// Logs a message at the debug level, subject to the filter of the calling
+/// module.
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)+) => { $crate::__log_filtered!(Debug, $($arg)+) };
+}
+
+/// Logs a message at the trace level, subject to the filter of the calling
+/// module.
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)+) => { $crate::__log_filtered!(Trace, $($arg)+) };
+}
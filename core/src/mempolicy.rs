@@ -0,0 +1,182 @@
+//! NUMA memory policies, as set by `set_mempolicy` and `mbind`.
+//!
+//! The platform has a single memory node, so the policies are validated and
+//! recorded but have no effect yet. [`alloc_node`] is what frame allocation
+//! should consult once there are more nodes.
+
+use alloc::{collections::btree_map::BTreeMap, vec::Vec};
+
+use axerrno::{AxError, AxResult};
+use memory_addr::{VirtAddr, VirtAddrRange};
+
+use crate::task::{AsThread, Thread};
+
+/// The number of memory nodes.
+pub const NR_NODES: usize = 1;
+
+/// The default policy: allocate on the local node, or defer to the thread
+/// policy for address ranges.
+pub const MPOL_DEFAULT: u32 = 0;
+/// Allocate on the given node, falling back to others.
+pub const MPOL_PREFERRED: u32 = 1;
+/// Allocate only on the given nodes.
+pub const MPOL_BIND: u32 = 2;
+/// Spread allocations across the given nodes.
+pub const MPOL_INTERLEAVE: u32 = 3;
+/// Allocate on the node of the CPU doing the allocation.
+pub const MPOL_LOCAL: u32 = 4;
+/// Allocate on any of the given nodes, falling back to others.
+pub const MPOL_PREFERRED_MANY: u32 = 5;
+
+/// Node IDs are not remapped when the allowed nodes change.
+pub const MPOL_F_STATIC_NODES: u32 = 1 << 15;
+/// Node IDs are relative to the allowed nodes.
+pub const MPOL_F_RELATIVE_NODES: u32 = 1 << 14;
+
+/// Returns the mask of online memory nodes.
+pub fn online_nodes() -> u64 {
+    (1 << NR_NODES) - 1
+}
+
+/// A memory policy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemPolicy {
+    /// The policy mode, one of the `MPOL_*` constants.
+    pub mode: u32,
+    /// The mode flags, `MPOL_F_STATIC_NODES` or `MPOL_F_RELATIVE_NODES`.
+    pub flags: u32,
+    /// The nodes the policy applies to.
+    pub nodes: u64,
+}
+
+impl MemPolicy {
+    /// Creates a policy from a mode with flags, as passed to
+    /// `set_mempolicy`, and a node mask.
+    ///
+    /// Offline nodes are dropped from the mask, and it is an error if that
+    /// leaves none where some are needed.
+    pub fn new(mode: u32, nodes: u64) -> AxResult<Self> {
+        let flags = mode & (MPOL_F_STATIC_NODES | MPOL_F_RELATIVE_NODES);
+        let mode = mode & !flags;
+        if flags == MPOL_F_STATIC_NODES | MPOL_F_RELATIVE_NODES {
+            return Err(AxError::InvalidInput);
+        }
+        let online = nodes & online_nodes();
+        match mode {
+            MPOL_DEFAULT | MPOL_LOCAL => {
+                if nodes != 0 || flags != 0 {
+                    return Err(AxError::InvalidInput);
+                }
+            }
+            // An empty mask means local allocation.
+            MPOL_PREFERRED if nodes == 0 => {
+                if flags != 0 {
+                    return Err(AxError::InvalidInput);
+                }
+                return Ok(Self {
+                    mode: MPOL_LOCAL,
+                    ..Self::default()
+                });
+            }
+            MPOL_PREFERRED | MPOL_BIND | MPOL_INTERLEAVE | MPOL_PREFERRED_MANY => {
+                if online == 0 {
+                    return Err(AxError::InvalidInput);
+                }
+            }
+            _ => return Err(AxError::InvalidInput),
+        }
+        Ok(Self {
+            mode,
+            flags,
+            nodes: match mode {
+                MPOL_PREFERRED => 1 << online.trailing_zeros(),
+                _ => online,
+            },
+        })
+    }
+
+    /// Returns the node to allocate on under this policy, or `None` to
+    /// defer to a less specific one.
+    fn node(&self) -> Option<usize> {
+        match self.mode {
+            MPOL_DEFAULT => None,
+            MPOL_LOCAL => Some(0),
+            // Interleaving would need a per-thread cursor, which is pointless
+            // with one node.
+            _ => Some(self.nodes.trailing_zeros() as usize),
+        }
+    }
+}
+
+/// The memory policies of the address ranges of a process, as set by
+/// `mbind`.
+#[derive(Default, Clone)]
+pub struct RangePolicies {
+    /// The policies by start address, with their end addresses. The ranges
+    /// never overlap.
+    ranges: BTreeMap<VirtAddr, (VirtAddr, MemPolicy)>,
+}
+
+impl RangePolicies {
+    /// Returns the policy of the range containing `addr`.
+    pub fn get(&self, addr: VirtAddr) -> Option<MemPolicy> {
+        self.ranges
+            .range(..=addr)
+            .next_back()
+            .filter(|(_, (end, _))| addr < *end)
+            .map(|(_, (_, policy))| *policy)
+    }
+
+    /// Sets the policy of `range`, or removes it for `MPOL_DEFAULT`.
+    pub fn set(&mut self, range: VirtAddrRange, policy: MemPolicy) {
+        self.remove(range);
+        if policy.mode != MPOL_DEFAULT {
+            self.ranges.insert(range.start, (range.end, policy));
+        }
+    }
+
+    /// Removes the policies of `range`, as when it is unmapped.
+    pub fn remove(&mut self, range: VirtAddrRange) {
+        let overlapping = self
+            .ranges
+            .range(..range.end)
+            .filter(|(_, (end, _))| *end > range.start)
+            .map(|(start, (end, policy))| (*start, *end, *policy))
+            .collect::<Vec<_>>();
+        for (start, end, policy) in overlapping {
+            self.ranges.remove(&start);
+            if start < range.start {
+                self.ranges.insert(start, (range.start, policy));
+            }
+            if end > range.end {
+                self.ranges.insert(range.end, (end, policy));
+            }
+        }
+    }
+
+    /// Removes all policies.
+    pub fn clear(&mut self) {
+        self.ranges.clear();
+    }
+}
+
+/// Returns the node a page at `addr` in the address space of `thread`
+/// should be allocated on.
+pub fn alloc_node(thread: &Thread, addr: VirtAddr) -> usize {
+    thread
+        .proc_data
+        .mempolicy
+        .lock()
+        .get(addr)
+        .and_then(|it| it.node())
+        .or_else(|| thread.mempolicy().node())
+        .unwrap_or(0)
+}
+
+/// Returns the node a page at `addr` should be allocated on for the current
+/// task, if it is a user thread.
+pub fn current_alloc_node(addr: VirtAddr) -> usize {
+    axtask::current()
+        .try_as_thread()
+        .map_or(0, |thr| alloc_node(thr, addr))
+}
@@ -0,0 +1,194 @@
+//! Detection of speculative execution vulnerabilities, and the mitigations
+//! applied against them.
+//!
+//! The CPU is probed once at boot by [`init`]. The only mitigation applied so
+//! far is a barrier on the indirect branch predictor (IBPB) when switching
+//! between processes on x86_64, which keeps one process from steering the
+//! indirect branches of another.
+
+use alloc::{string::String, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axconfig::plat::CPU_NUM;
+use spin::Once;
+
+/// What was found out about the CPU.
+#[derive(Debug, Default)]
+struct CpuInfo {
+    /// Whether kernel memory can be read speculatively from user space
+    /// (Meltdown).
+    meltdown: Option<bool>,
+    /// Whether bounds checks can be bypassed speculatively (Spectre v1).
+    spectre_v1: Option<bool>,
+    /// Whether branch target injection is possible (Spectre v2).
+    spectre_v2: Option<bool>,
+    /// Whether loads may bypass older stores speculatively.
+    spec_store_bypass: Option<bool>,
+    /// Whether microarchitectural buffers leak data (MDS).
+    mds: Option<bool>,
+    /// Whether the CPU supports an indirect branch prediction barrier.
+    ibpb: bool,
+}
+
+static CPU_INFO: Once<CpuInfo> = Once::new();
+
+/// The process last switched in on each CPU, by address.
+static LAST_PROCESS: [AtomicUsize; CPU_NUM] = [const { AtomicUsize::new(0) }; CPU_NUM];
+
+#[cfg(target_arch = "x86_64")]
+mod arch {
+    use core::arch::{asm, x86_64::__cpuid_count};
+
+    use super::CpuInfo;
+
+    const MSR_IA32_PRED_CMD: u32 = 0x49;
+    const MSR_IA32_ARCH_CAPABILITIES: u32 = 0x10a;
+
+    const ARCH_CAP_RDCL_NO: u64 = 1 << 0;
+    const ARCH_CAP_SSB_NO: u64 = 1 << 4;
+    const ARCH_CAP_MDS_NO: u64 = 1 << 5;
+
+    #[allow(unused_unsafe)]
+    fn cpuid(leaf: u32, subleaf: u32) -> (u32, u32, u32, u32) {
+        let r = unsafe { __cpuid_count(leaf, subleaf) };
+        (r.eax, r.ebx, r.ecx, r.edx)
+    }
+
+    fn rdmsr(msr: u32) -> u64 {
+        let (lo, hi): (u32, u32);
+        unsafe {
+            asm!("rdmsr", in("ecx") msr, out("eax") lo, out("edx") hi, options(nomem, nostack));
+        }
+        ((hi as u64) << 32) | lo as u64
+    }
+
+    pub fn probe() -> CpuInfo {
+        let (max_leaf, ebx, ecx, edx) = cpuid(0, 0);
+        let amd = [ebx, edx, ecx] == [0x6874_7541, 0x6974_6e65, 0x444d_4163]
+            || [ebx, edx, ecx] == [0x6f67_7948, 0x6e65_476e, 0x656e_6975];
+        let (_, _, _, feature_edx) = if max_leaf >= 7 {
+            cpuid(7, 0)
+        } else {
+            (0, 0, 0, 0)
+        };
+        let arch_caps = if feature_edx & (1 << 29) != 0 {
+            rdmsr(MSR_IA32_ARCH_CAPABILITIES)
+        } else {
+            0
+        };
+
+        // AMD and Hygon parts are not affected by Meltdown and MDS, and
+        // report SSB_NO and IBPB through an extended leaf instead.
+        let amd_ext_ebx = if amd && cpuid(0x8000_0000, 0).0 >= 0x8000_0008 {
+            cpuid(0x8000_0008, 0).1
+        } else {
+            0
+        };
+        let amd_ssb_no = amd_ext_ebx & (1 << 26) != 0;
+        CpuInfo {
+            meltdown: Some(!amd && arch_caps & ARCH_CAP_RDCL_NO == 0),
+            spectre_v1: Some(true),
+            spectre_v2: Some(true),
+            spec_store_bypass: Some(!amd_ssb_no && arch_caps & ARCH_CAP_SSB_NO == 0),
+            mds: Some(!amd && arch_caps & ARCH_CAP_MDS_NO == 0),
+            ibpb: feature_edx & (1 << 26) != 0 || amd_ext_ebx & (1 << 12) != 0,
+        }
+    }
+
+    pub fn ibpb() {
+        unsafe {
+            asm!(
+                "wrmsr",
+                in("ecx") MSR_IA32_PRED_CMD,
+                in("eax") 1,
+                in("edx") 0,
+                options(nostack),
+            );
+        }
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod arch {
+    use core::arch::asm;
+
+    use super::CpuInfo;
+
+    pub fn probe() -> CpuInfo {
+        let pfr0: u64;
+        unsafe { asm!("mrs {}, ID_AA64PFR0_EL1", out(reg) pfr0, options(nomem, nostack)) };
+        // CSV2: branch targets trained in one context can't be used in
+        // another. CSV3: faulting loads don't leak data speculatively.
+        let csv2 = (pfr0 >> 56) & 0xf != 0;
+        let csv3 = (pfr0 >> 60) & 0xf != 0;
+        CpuInfo {
+            meltdown: csv3.then_some(false),
+            spectre_v1: Some(true),
+            spectre_v2: csv2.then_some(false),
+            ..Default::default()
+        }
+    }
+
+    pub fn ibpb() {}
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod arch {
+    use super::CpuInfo;
+
+    pub fn probe() -> CpuInfo {
+        CpuInfo::default()
+    }
+
+    pub fn ibpb() {}
+}
+
+/// Probes the CPU, and logs the vulnerabilities found.
+pub fn init() {
+    let info = CPU_INFO.call_once(arch::probe);
+    debug!("CPU vulnerabilities: {info:?}");
+    for (name, status) in vulnerabilities() {
+        if status.starts_with("Vulnerable") {
+            info!("{name}: {status}");
+        }
+    }
+}
+
+/// Called when a thread of the process at `process` is switched in, to
+/// flush the branch predictor if the previous process on the CPU differs.
+#[inline]
+pub fn on_switch_in(process: usize) {
+    let last = &LAST_PROCESS[axhal::percpu::this_cpu_id()];
+    if last.swap(process, Ordering::Relaxed) != process && CPU_INFO.get().is_some_and(|it| it.ibpb)
+    {
+        arch::ibpb();
+    }
+}
+
+fn status(affected: Option<bool>) -> &'static str {
+    match affected {
+        Some(false) => "Not affected",
+        Some(true) => "Vulnerable",
+        None => "Unknown: no detection on this CPU",
+    }
+}
+
+/// Returns the status of each vulnerability, as reported in
+/// /sys/devices/system/cpu/vulnerabilities.
+pub fn vulnerabilities() -> Vec<(&'static str, String)> {
+    let Some(info) = CPU_INFO.get() else {
+        return Vec::new();
+    };
+    let spectre_v2 = match (info.spectre_v2, info.ibpb) {
+        (Some(true), true) => "Vulnerable; IBPB: conditional".into(),
+        (affected, _) => status(affected).into(),
+    };
+    alloc::vec![
+        ("meltdown", status(info.meltdown).into()),
+        // User pointers are not sanitized against speculation.
+        ("spectre_v1", status(info.spectre_v1).into()),
+        ("spectre_v2", spectre_v2),
+        ("spec_store_bypass", status(info.spec_store_bypass).into()),
+        ("mds", status(info.mds).into()),
+    ]
+}
@@ -0,0 +1,206 @@
+//! Memory locking, as done by `mlock` and `mlockall`.
+//!
+//! Pages are never reclaimed or swapped out, so locking them only means
+//! populating them up front and accounting for them against
+//! `RLIMIT_MEMLOCK`.
+
+use alloc::{collections::btree_map::BTreeMap, vec::Vec};
+
+use axerrno::{AxError, AxResult};
+use axhal::paging::MappingFlags;
+use axmm::AddrSpace;
+use linux_raw_sys::general::RLIMIT_MEMLOCK;
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr, VirtAddrRange};
+
+use crate::task::ProcessData;
+
+/// The locked memory of a process.
+#[derive(Default)]
+pub struct MemoryLocks {
+    /// Locked ranges, by start address, which never overlap or touch.
+    ranges: BTreeMap<VirtAddr, VirtAddr>,
+    /// Whether mappings created from now on are locked, as set by
+    /// `mlockall(MCL_FUTURE)`.
+    pub future: bool,
+    /// Whether future mappings are populated on fault rather than up front.
+    pub future_on_fault: bool,
+}
+
+impl MemoryLocks {
+    /// Returns the number of bytes locked.
+    pub fn locked_bytes(&self) -> usize {
+        self.ranges.iter().map(|(start, end)| *end - *start).sum()
+    }
+
+    /// Returns whether any part of `range` is locked.
+    pub fn is_locked(&self, range: VirtAddrRange) -> bool {
+        self.overlap(range) > 0
+    }
+
+    /// Returns the number of bytes of `range` that are already locked.
+    fn overlap(&self, range: VirtAddrRange) -> usize {
+        self.ranges
+            .range(..range.end)
+            .map(|(start, end)| {
+                let start = (*start).max(range.start);
+                let end = (*end).min(range.end);
+                end.as_usize().saturating_sub(start.as_usize())
+            })
+            .sum()
+    }
+
+    /// Locks `range`, failing if that would take the locked bytes past
+    /// `limit`.
+    ///
+    /// As for unprivileged processes on Linux, a limit of 0 forbids locking
+    /// altogether.
+    pub fn lock(&mut self, range: VirtAddrRange, limit: usize) -> AxResult<()> {
+        if limit == 0 {
+            return Err(AxError::OperationNotPermitted);
+        }
+        let added = range.size() - self.overlap(range);
+        if self.locked_bytes() + added > limit {
+            return Err(AxError::NoMemory);
+        }
+        let mut start = range.start;
+        let mut end = range.end;
+        let merged = self
+            .ranges
+            .range(..=range.end)
+            .filter(|(_, e)| **e >= range.start)
+            .map(|(s, e)| (*s, *e))
+            .collect::<Vec<_>>();
+        for (s, e) in merged {
+            self.ranges.remove(&s);
+            start = start.min(s);
+            end = end.max(e);
+        }
+        self.ranges.insert(start, end);
+        Ok(())
+    }
+
+    /// Unlocks `range`.
+    pub fn unlock(&mut self, range: VirtAddrRange) {
+        let overlapping = self
+            .ranges
+            .range(..range.end)
+            .filter(|(_, e)| **e > range.start)
+            .map(|(s, e)| (*s, *e))
+            .collect::<Vec<_>>();
+        for (s, e) in overlapping {
+            self.ranges.remove(&s);
+            if s < range.start {
+                self.ranges.insert(s, range.start);
+            }
+            if e > range.end {
+                self.ranges.insert(range.end, e);
+            }
+        }
+    }
+
+    /// Unlocks everything, including future mappings.
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Returns the mapped parts of `range`, along with their flags, failing
+/// with `ENOMEM` if any part of it is unmapped.
+pub fn mapped_areas(
+    aspace: &AddrSpace,
+    range: VirtAddrRange,
+) -> AxResult<Vec<(VirtAddrRange, MappingFlags)>> {
+    let mut areas = Vec::new();
+    let mut addr = range.start;
+    while addr < range.end {
+        let area = aspace.find_area(addr).ok_or(AxError::NoMemory)?;
+        let end = area.end().min(range.end);
+        areas.push((VirtAddrRange::new(addr, end), area.flags()));
+        addr = end;
+    }
+    Ok(areas)
+}
+
+/// Returns all mapped areas of `aspace`, along with their flags.
+pub fn all_areas(aspace: &AddrSpace) -> Vec<(VirtAddrRange, MappingFlags)> {
+    areas_in(aspace, VirtAddrRange::new(aspace.base(), aspace.end()))
+}
+
+/// Returns the mapped parts of `range`, along with their flags, skipping
+/// the parts that are unmapped.
+pub fn areas_in(aspace: &AddrSpace, range: VirtAddrRange) -> Vec<(VirtAddrRange, MappingFlags)> {
+    // `AddrSpace` can't list its areas, so the gaps between them are
+    // skipped by searching for their ends.
+    let is_free = |start: VirtAddr, size: usize| {
+        aspace.find_free_area(start, size, range, PAGE_SIZE_4K) == Some(start)
+    };
+
+    let mut areas = Vec::new();
+    let mut addr = range.start;
+    while addr < range.end {
+        if let Some(area) = aspace.find_area(addr) {
+            let end = area.end().min(range.end);
+            areas.push((VirtAddrRange::new(addr, end), area.flags()));
+            addr = end;
+            continue;
+        }
+        // Find the largest free size at `addr`, a page at a time.
+        let max = range.end - addr;
+        let mut free = PAGE_SIZE_4K;
+        while free < max && is_free(addr, (free * 2).min(max)) {
+            free = (free * 2).min(max);
+        }
+        let mut step = free / 2;
+        while step >= PAGE_SIZE_4K {
+            if free + step <= max && is_free(addr, free + step) {
+                free += step;
+            }
+            step /= 2;
+        }
+        addr += free.align_down_4k();
+    }
+    areas
+}
+
+/// Populates the accessible pages of `areas`, as locking them requires.
+pub fn populate(aspace: &mut AddrSpace, areas: &[(VirtAddrRange, MappingFlags)]) -> AxResult<()> {
+    for (range, flags) in areas {
+        let access = *flags & (MappingFlags::READ | MappingFlags::WRITE);
+        if !access.is_empty() {
+            aspace.populate_area(range.start, range.size(), access)?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns how many bytes the process may lock: its `RLIMIT_MEMLOCK` soft
+/// limit, or no limit if it is privileged, as with `CAP_IPC_LOCK` on Linux.
+pub fn memlock_limit(proc_data: &ProcessData) -> usize {
+    if proc_data.is_privileged() {
+        return usize::MAX;
+    }
+    proc_data.rlim.read()[RLIMIT_MEMLOCK]
+        .current
+        .try_into()
+        .unwrap_or(usize::MAX)
+}
+
+/// Locks the mapping just created at `range` if `mlockall(MCL_FUTURE)` is in
+/// effect, failing with `EAGAIN` if `RLIMIT_MEMLOCK` doesn't allow it.
+pub fn lock_new_mapping(
+    proc_data: &ProcessData,
+    aspace: &mut AddrSpace,
+    range: VirtAddrRange,
+) -> AxResult<()> {
+    let mut locks = proc_data.mlock.lock();
+    if !locks.future {
+        return Ok(());
+    }
+    locks
+        .lock(range, memlock_limit(proc_data))
+        .map_err(|_| AxError::WouldBlock)?;
+    if !locks.future_on_fault {
+        populate(aspace, &mapped_areas(aspace, range)?)?;
+    }
+    Ok(())
+}
@@ -1,7 +1,13 @@
 //! User address space management.
 
-use alloc::{borrow::ToOwned, string::String, vec, vec::Vec};
-use core::{ffi::CStr, hint::unlikely, iter, mem::MaybeUninit};
+use alloc::{borrow::ToOwned, collections::btree_map::BTreeMap, string::String, vec, vec::Vec};
+use core::{
+    ffi::CStr,
+    hint::unlikely,
+    iter,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicU8, AtomicUsize, Ordering},
+};
 
 use axerrno::{AxError, AxResult};
 use axfs::{CachedFile, FS_CONTEXT, FileBackend};
@@ -17,16 +23,365 @@ use axtask::current;
 use extern_trait::extern_trait;
 use kernel_elf_parser::{AuxEntry, ELFHeaders, ELFHeadersBuilder, ELFParser, app_stack_region};
 use kernel_guard::IrqSave;
-use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr};
+use linux_raw_sys::general::{RLIMIT_AS, RLIMIT_STACK};
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr, VirtAddrRange};
 use ouroboros::self_referencing;
 use starry_vm::{VmError, VmIo, VmResult};
 
 use crate::{
+    binfmt::{self, BINFMT_BUF_SIZE},
     config::{USER_SPACE_BASE, USER_SPACE_SIZE},
     lrucache::LruCache,
-    task::AsThread,
+    mlock,
+    task::{AsThread, ProcessData},
 };
 
+/// The lowest address a mapping may be placed at by `mmap`, as set through
+/// /proc/sys/vm/mmap_min_addr.
+///
+/// Leaving the lowest pages unmapped makes sure a kernel NULL pointer
+/// dereference faults instead of reaching memory controlled by user space.
+pub static MMAP_MIN_ADDR: AtomicUsize = AtomicUsize::new(PAGE_SIZE_4K);
+
+/// How much of the address space layout is randomized, as set through
+/// /proc/sys/kernel/randomize_va_space: 0 for nothing, 1 for the stack,
+/// `mmap` and position-independent executables, 2 for the heap as well.
+pub static RANDOMIZE_VA_SPACE: AtomicU8 = AtomicU8::new(2);
+
+/// The `personality` flag that turns off address space randomization.
+pub const ADDR_NO_RANDOMIZE: u32 = 0x0040000;
+/// The `personality` flag that selects the legacy layout, where `mmap`
+/// places mappings bottom-up instead of top-down below the stack.
+pub const ADDR_COMPAT_LAYOUT: u32 = 0x0200000;
+
+/// The gap kept between the stack top and the top-down `mmap` area, leaving
+/// the stack room to grow.
+const MMAP_STACK_GAP: usize = 0x800_0000;
+
+/// The range a position-independent executable's base is moved within.
+const EXE_RND_SIZE: usize = 0x100_0000;
+/// The range the dynamic linker's base is moved within.
+const INTERP_RND_SIZE: usize = 0x1000_0000;
+/// The range the stack top is moved down within.
+const STACK_RND_SIZE: usize = 0x1000_0000;
+/// The range the heap start is moved up within.
+const HEAP_RND_SIZE: usize = 0x200_0000;
+/// The range the default `mmap` search start is moved up within.
+const MMAP_RND_SIZE: usize = 0x1000_0000;
+
+/// Where the parts of a program are placed in its address space.
+#[derive(Debug, Clone, Copy)]
+pub struct UserLayout {
+    /// The base of a position-independent executable.
+    pub exe_base: usize,
+    /// The base of the dynamic linker.
+    pub interp_base: usize,
+    /// The top of the stack.
+    pub stack_top: usize,
+    /// The start of the heap grown by `brk`.
+    pub heap_base: usize,
+    /// Where `mmap` starts looking for free space when given no hint: the
+    /// top of the search if [`mmap_top_down`](Self::mmap_top_down), else its
+    /// bottom, with 0 for the lowest address allowed.
+    pub mmap_base: usize,
+    /// Whether `mmap` places mappings given no hint top-down from
+    /// [`mmap_base`](Self::mmap_base) instead of bottom-up.
+    pub mmap_top_down: bool,
+}
+
+impl Default for UserLayout {
+    fn default() -> Self {
+        Self {
+            exe_base: crate::config::USER_SPACE_BASE,
+            interp_base: crate::config::USER_INTERP_BASE,
+            stack_top: crate::config::USER_STACK_TOP,
+            heap_base: crate::config::USER_HEAP_BASE,
+            mmap_base: 0,
+            mmap_top_down: false,
+        }
+    }
+}
+
+impl UserLayout {
+    /// Returns the layout of a new program, randomized as configured by
+    /// [`RANDOMIZE_VA_SPACE`] unless `personality` has [`ADDR_NO_RANDOMIZE`].
+    ///
+    /// `mmap` places mappings top-down below the stack, unless `personality`
+    /// has [`ADDR_COMPAT_LAYOUT`].
+    pub fn new(personality: u32) -> Self {
+        let mut layout = Self::default();
+        layout.mmap_top_down = personality & ADDR_COMPAT_LAYOUT == 0;
+        if layout.mmap_top_down {
+            layout.mmap_base = layout.stack_top.saturating_sub(MMAP_STACK_GAP);
+        }
+        let level = RANDOMIZE_VA_SPACE.load(Ordering::Relaxed);
+        if personality & ADDR_NO_RANDOMIZE != 0 || level == 0 {
+            return layout;
+        }
+
+        // A single seed covers all offsets, each of which needs far fewer
+        // than 32 bits.
+        let mut seed = [0; 32];
+        crate::random::get_random_bytes(&mut seed);
+        let word = |i: usize| u64::from_le_bytes(seed[i * 8..i * 8 + 8].try_into().unwrap());
+        let offset = |rnd: u64, size: usize| (rnd as usize % (size / PAGE_SIZE_4K)) * PAGE_SIZE_4K;
+
+        layout.exe_base += offset(word(0), EXE_RND_SIZE);
+        layout.interp_base += offset(word(1), INTERP_RND_SIZE);
+        let stack_offset = offset(word(2), STACK_RND_SIZE);
+        layout.stack_top -= stack_offset;
+        let mmap_offset = offset(word(3), MMAP_RND_SIZE);
+        layout.mmap_base = if layout.mmap_top_down {
+            layout.mmap_base.saturating_sub(stack_offset + mmap_offset)
+        } else {
+            // The sysctl only takes page-aligned values.
+            MMAP_MIN_ADDR
+                .load(Ordering::Relaxed)
+                .checked_add(mmap_offset)
+                .unwrap_or(0)
+        };
+        if level >= 2 {
+            layout.heap_base += offset(word(0) >> 32, HEAP_RND_SIZE);
+        }
+        layout
+    }
+
+    /// Returns the range of the main thread stack, as mapped initially.
+    pub fn stack(&self) -> VirtAddrRange {
+        let size = crate::config::USER_STACK_SIZE;
+        VirtAddrRange::from_start_size((self.stack_top - size).into(), size)
+    }
+}
+
+/// The gap kept free below a stack, which it may not grow into, as
+/// `stack_guard_gap` in Linux.
+pub const STACK_GUARD_GAP: usize = 256 * PAGE_SIZE_4K;
+
+/// Checks that mapping `size` more bytes keeps the address space of the
+/// process within `RLIMIT_AS`, failing with `ENOMEM` otherwise.
+pub fn check_as_limit(proc_data: &ProcessData, size: usize) -> AxResult<()> {
+    let limit = proc_data.rlim.read()[RLIMIT_AS].current;
+    let used = proc_data.vm_size.load(Ordering::Relaxed);
+    if used.saturating_add(size) as u64 > limit {
+        return Err(AxError::NoMemory);
+    }
+    Ok(())
+}
+
+/// Returns how many bytes of `range` are mapped in `aspace`.
+pub fn mapped_size(aspace: &AddrSpace, range: VirtAddrRange) -> usize {
+    mlock::areas_in(aspace, range)
+        .iter()
+        .map(|(range, _)| range.size())
+        .sum()
+}
+
+/// Returns the total size of the mappings in `aspace`, walking all of it.
+///
+/// Only needed once a program is loaded: after that,
+/// [`ProcessData::vm_size`] is kept up to date by [`map`] and [`unmap`].
+pub fn vm_size(aspace: &AddrSpace) -> usize {
+    mapped_size(aspace, VirtAddrRange::new(aspace.base(), aspace.end()))
+}
+
+/// Maps `size` bytes at `start` as [`AddrSpace::map`] does, counting them
+/// in the mapped size of the process.
+pub fn map(
+    proc_data: &ProcessData,
+    aspace: &mut AddrSpace,
+    start: VirtAddr,
+    size: usize,
+    flags: MappingFlags,
+    populate: bool,
+    backend: Backend,
+) -> AxResult<()> {
+    aspace.map(start, size, flags, populate, backend)?;
+    proc_data.vm_size.fetch_add(size, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Unmaps `size` bytes at `start` as [`AddrSpace::unmap`] does, no longer
+/// counting what was mapped there in the mapped size of the process.
+pub fn unmap(
+    proc_data: &ProcessData,
+    aspace: &mut AddrSpace,
+    start: VirtAddr,
+    size: usize,
+) -> AxResult<()> {
+    let mapped = mapped_size(aspace, VirtAddrRange::from_start_size(start, size));
+    aspace.unmap(start, size)?;
+    proc_data.vm_size.fetch_sub(mapped, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Finds the highest free area of `size` bytes aligned to `align` that ends
+/// at or below `top` and lies within `limit`, as `mmap` does for a top-down
+/// layout.
+pub fn find_free_area_top_down(
+    aspace: &AddrSpace,
+    top: VirtAddr,
+    size: usize,
+    limit: VirtAddrRange,
+    align: usize,
+) -> Option<VirtAddr> {
+    // The highest aligned start in the gap, if the gap is large enough.
+    let fit = |gap_start: VirtAddr, gap_end: VirtAddr| {
+        let start = gap_end.as_usize().checked_sub(size)?.align_down(align);
+        (start >= gap_start.as_usize()).then_some(VirtAddr::from(start))
+    };
+
+    let mut gap_end = top.min(limit.end);
+    for (range, _) in mlock::all_areas(aspace).iter().rev() {
+        if range.start >= gap_end {
+            continue;
+        }
+        if range.end < gap_end
+            && let Some(start) = fit(range.end.max(limit.start), gap_end)
+        {
+            return Some(start);
+        }
+        gap_end = range.start;
+        if gap_end <= limit.start {
+            return None;
+        }
+    }
+    fit(limit.start, gap_end)
+}
+
+/// Returns how many bytes of `range` are backed by resident pages.
+pub fn resident_size(aspace: &AddrSpace, range: VirtAddrRange) -> usize {
+    let mut rss = 0;
+    let mut addr = range.start;
+    while addr < range.end {
+        match aspace.page_table().query(addr) {
+            Ok((_, _, size)) => {
+                let size = size as usize;
+                let next = (addr.align_down(size) + size).min(range.end);
+                rss += next - addr;
+                addr = next;
+            }
+            Err(_) => addr += PAGE_SIZE_4K,
+        }
+    }
+    rss
+}
+
+/// Returns how many bytes of the address space are backed by resident
+/// pages.
+pub fn resident_set_size(aspace: &AddrSpace) -> usize {
+    mlock::all_areas(aspace)
+        .into_iter()
+        .map(|(range, _)| resident_size(aspace, range))
+        .sum()
+}
+
+/// Grows a stack of the current process down to cover `addr`, after a fault
+/// there found no mapping. Returns whether the stack was grown.
+///
+/// Only the stack right above `addr` is grown, as far as `RLIMIT_STACK`
+/// allows and as long as [`STACK_GUARD_GAP`] stays free below it.
+pub fn grow_stack(proc_data: &ProcessData, aspace: &mut AddrSpace, addr: VirtAddr) -> bool {
+    let limit = proc_data.rlim.read()[RLIMIT_STACK].current as usize;
+    let mut stacks = proc_data.stacks.lock();
+    // Forget stacks that have been unmapped.
+    stacks.retain(|it| aspace.find_area(it.start).is_some());
+    let Some(stack) = stacks
+        .iter_mut()
+        .filter(|it| it.start > addr)
+        .min_by_key(|it| it.start)
+    else {
+        return false;
+    };
+    let flags = aspace.find_area(stack.start).unwrap().flags();
+
+    let new_start = addr.align_down_4k();
+    if stack.end - new_start > limit || check_as_limit(proc_data, stack.start - new_start).is_err()
+    {
+        return false;
+    }
+    let gap_start = (new_start.as_usize().saturating_sub(STACK_GUARD_GAP))
+        .max(aspace.base().as_usize())
+        .into();
+    let gap = VirtAddrRange::new(gap_start, stack.start);
+    if aspace.find_free_area(gap_start, gap.size(), gap, PAGE_SIZE_4K) != Some(gap_start) {
+        return false;
+    }
+
+    let size = stack.start - new_start;
+    if map(
+        proc_data,
+        aspace,
+        new_start,
+        size,
+        flags,
+        false,
+        Backend::new_alloc(new_start, PageSize::Size4K),
+    )
+    .is_err()
+    {
+        return false;
+    }
+    debug!("Grew stack down to {new_start:#x}, {size:#x} bytes");
+    stack.start = new_start;
+    true
+}
+
+/// The files that mappings were made from, as listed in /proc/[pid]/maps.
+#[derive(Default, Clone)]
+pub struct MappedFiles {
+    /// The files by start address, with their end addresses and the offset
+    /// into the file at the start. The ranges never overlap.
+    ranges: BTreeMap<VirtAddr, (VirtAddr, Location, u64)>,
+}
+
+impl MappedFiles {
+    /// Returns the file mapped at `addr`, along with the offset into it.
+    pub fn get(&self, addr: VirtAddr) -> Option<(&Location, u64)> {
+        self.ranges
+            .range(..=addr)
+            .next_back()
+            .filter(|(_, (end, ..))| addr < *end)
+            .map(|(start, (_, loc, offset))| (loc, offset + (addr - *start) as u64))
+    }
+
+    /// Records that `range` maps `loc` from `offset` on.
+    pub fn insert(&mut self, range: VirtAddrRange, loc: Location, offset: u64) {
+        self.remove(range);
+        self.ranges.insert(range.start, (range.end, loc, offset));
+    }
+
+    /// Forgets the files mapped in `range`, as when it is unmapped.
+    pub fn remove(&mut self, range: VirtAddrRange) {
+        let overlapping = self
+            .ranges
+            .range(..range.end)
+            .filter(|(_, (end, ..))| *end > range.start)
+            .map(|(start, _)| *start)
+            .collect::<Vec<_>>();
+        for start in overlapping {
+            let (end, loc, offset) = self.ranges.remove(&start).unwrap();
+            if start < range.start {
+                self.ranges
+                    .insert(start, (range.start, loc.clone(), offset));
+            }
+            if end > range.end {
+                let offset = offset + (range.end - start) as u64;
+                self.ranges.insert(range.end, (end, loc, offset));
+            }
+        }
+    }
+
+    /// Returns the files mapped, once for every mapping of them.
+    pub fn files(&self) -> impl Iterator<Item = &Location> {
+        self.ranges.values().map(|(_, loc, _)| loc)
+    }
+
+    /// Forgets all files.
+    pub fn clear(&mut self) {
+        self.ranges.clear();
+    }
+}
+
 /// Creates a new empty user address space.
 pub fn new_user_aspace_empty() -> AxResult<AddrSpace> {
     AddrSpace::new_empty(
@@ -75,19 +430,44 @@ fn mapping_flags(flags: xmas_elf::program::Flags) -> MappingFlags {
     mapping_flags
 }
 
+/// Returns the alignment a position-independent image must be loaded at.
+///
+/// This is the largest alignment of its loadable and TLS segments, so that
+/// segments aligned beyond a page, such as the TLS segment of some
+/// static-PIE executables, keep their alignment once relocated.
+fn load_align(elf: &ELFHeaders) -> AxResult<usize> {
+    let mut align = PAGE_SIZE_4K;
+    for ph in elf.ph.iter().filter(|ph| {
+        matches!(
+            ph.get_type(),
+            Ok(xmas_elf::program::Type::Load | xmas_elf::program::Type::Tls)
+        )
+    }) {
+        let ph_align = ph.align as usize;
+        if ph_align > 1 && !ph_align.is_power_of_two() {
+            return Err(AxError::InvalidExecutable);
+        }
+        align = align.max(ph_align);
+    }
+    Ok(align)
+}
+
 /// Map the elf file to the user address space.
 ///
 /// # Arguments
 /// - `uspace`: The address space of the user app.
+/// - `files`: Where the mapped file is recorded.
 /// - `elf`: The elf file.
 ///
 /// # Returns
 /// - The entry point of the user app.
 fn map_elf<'a>(
     uspace: &mut AddrSpace,
+    files: &mut MappedFiles,
     base: usize,
     entry: &'a ElfCacheEntry,
 ) -> AxResult<ELFParser<'a>> {
+    let base = base.align_up(load_align(entry.borrow_elf())?);
     let elf_parser = ELFParser::new(entry.borrow_elf(), base).map_err(|_| AxError::InvalidData)?;
     let cache = entry.borrow_cache();
 
@@ -105,7 +485,9 @@ fn map_elf<'a>(
             ph.flags
         );
         let seg_pad = vaddr.align_offset_4k();
-        assert_eq!(seg_pad, ph.offset as usize % PAGE_SIZE_4K);
+        if seg_pad != ph.offset as usize % PAGE_SIZE_4K {
+            return Err(AxError::InvalidExecutable);
+        }
 
         let seg_align_size =
             (ph.mem_size as usize + seg_pad + PAGE_SIZE_4K - 1) & !(PAGE_SIZE_4K - 1);
@@ -127,6 +509,11 @@ fn map_elf<'a>(
             false,
             backend,
         )?;
+        files.insert(
+            VirtAddrRange::from_start_size(seg_start.align_down_4k(), seg_align_size),
+            cache.location().clone(),
+            ph.offset - seg_pad as u64,
+        );
 
         // TDOO: flush the I-cache
     }
@@ -182,7 +569,13 @@ impl ElfLoader {
         Self(LruCache::new())
     }
 
-    fn load(&mut self, uspace: &mut AddrSpace, path: &str) -> AxResult<LoadResult> {
+    fn load(
+        &mut self,
+        uspace: &mut AddrSpace,
+        files: &mut MappedFiles,
+        path: &str,
+        layout: &UserLayout,
+    ) -> AxResult<LoadResult> {
         let loc = FS_CONTEXT.lock().resolve(path)?;
 
         if !self.0.access(|e| e.borrow_cache().location().ptr_eq(&loc)) {
@@ -197,6 +590,7 @@ impl ElfLoader {
         }
 
         uspace.clear();
+        files.clear();
         map_trampoline(uspace)?;
 
         let entry = self.0.peek_mru().unwrap();
@@ -236,9 +630,9 @@ impl ElfLoader {
             (entry, None)
         };
 
-        let elf = map_elf(uspace, crate::config::USER_SPACE_BASE, elf)?;
+        let elf = map_elf(uspace, files, layout.exe_base, elf)?;
         let ldso = ldso
-            .map(|elf| map_elf(uspace, crate::config::USER_INTERP_BASE, elf))
+            .map(|elf| map_elf(uspace, files, layout.interp_base, elf))
             .transpose()?;
 
         let entry = VirtAddr::from_usize(
@@ -262,22 +656,80 @@ pub fn clear_elf_cache() {
     ELF_LOADER.lock().0.flush();
 }
 
+/// The number of bytes of a script searched for its `#!` line, as
+/// `BINPRM_BUF_SIZE` in Linux.
+const SHEBANG_BUF_SIZE: usize = 256;
+
+/// How many interpreter scripts may be chained, as `BINPRM_MAX_RECURSION`
+/// in Linux.
+const MAX_INTERP_DEPTH: usize = 4;
+
+/// Parses the `#!` line at the start of `data`, returning the interpreter
+/// and its optional argument.
+///
+/// Everything following the interpreter name, with surrounding blanks
+/// removed, is passed as a single argument. A line that doesn't fit in
+/// [`SHEBANG_BUF_SIZE`] is cut short, unless that would cut the interpreter
+/// name itself.
+fn parse_shebang(data: &[u8]) -> AxResult<(String, Option<String>)> {
+    let head = &data[2..data.len().min(SHEBANG_BUF_SIZE)];
+    let (line, complete) = match head.iter().position(|c| *c == b'\n') {
+        Some(pos) => (&head[..pos], true),
+        None => (head, data.len() <= SHEBANG_BUF_SIZE),
+    };
+    let line = str::from_utf8(line)
+        .map_err(|_| AxError::InvalidExecutable)?
+        .trim_matches([' ', '\t']);
+    let (interp, arg) = match line.split_once([' ', '\t']) {
+        Some((interp, arg)) => (interp, Some(arg.trim_matches([' ', '\t']))),
+        None if complete => (line, None),
+        None => return Err(AxError::InvalidExecutable),
+    };
+    if interp.is_empty() {
+        return Err(AxError::InvalidExecutable);
+    }
+    Ok((
+        interp.to_owned(),
+        arg.filter(|it| !it.is_empty()).map(str::to_owned),
+    ))
+}
+
 /// Load the user app to the user address space.
 ///
+/// Executables matching a [`binfmt`] entry, and scripts starting with `#!`,
+/// are run by their interpreter, with their path inserted into the
+/// arguments.
+///
 /// # Arguments
 /// - `uspace`: The address space of the user app.
+/// - `files`: Where the files mapped for the user app are recorded.
 /// - `args`: The arguments of the user app. The first argument is the path of
 ///   the user app.
 /// - `envs`: The environment variables of the user app.
+/// - `layout`: Where the parts of the user app are placed.
 ///
 /// # Returns
 /// - The entry point of the user app.
 /// - The stack pointer of the user app.
 pub fn load_user_app(
     uspace: &mut AddrSpace,
+    files: &mut MappedFiles,
     path: Option<&str>,
     args: &[String],
     envs: &[String],
+    layout: &UserLayout,
+) -> AxResult<(VirtAddr, VirtAddr)> {
+    load_user_app_at_depth(uspace, files, path, args, envs, layout, 0)
+}
+
+fn load_user_app_at_depth(
+    uspace: &mut AddrSpace,
+    files: &mut MappedFiles,
+    path: Option<&str>,
+    args: &[String],
+    envs: &[String],
+    layout: &UserLayout,
+    depth: usize,
 ) -> AxResult<(VirtAddr, VirtAddr)> {
     let path = path
         .or_else(|| args.first().map(String::as_str))
@@ -288,33 +740,62 @@ pub fn load_user_app(
         let new_args: Vec<String> = iter::once("/bin/sh".to_owned())
             .chain(args.iter().cloned())
             .collect();
-        return load_user_app(uspace, None, &new_args, envs);
+        return load_user_app_at_depth(uspace, files, None, &new_args, envs, layout, depth + 1);
     }
 
-    let (entry, auxv) = match { ELF_LOADER.lock().load(uspace, path)? } {
+    if binfmt::is_active() {
+        let loc = FS_CONTEXT.lock().resolve(path)?;
+        let mut head = vec![0; BINFMT_BUF_SIZE];
+        let read = CachedFile::get_or_create(loc).read_at(&mut head[..], 0)?;
+        if let Some(entry) = binfmt::lookup(path, &head[..read]) {
+            if depth >= MAX_INTERP_DEPTH {
+                return Err(AxError::FilesystemLoop);
+            }
+            debug!(
+                "Running {path} with interpreter {} ({})",
+                entry.interpreter, entry.name
+            );
+
+            let skip = if entry.preserve_argv0() { 0 } else { 1 };
+            let new_args: Vec<String> = iter::once(entry.interpreter.clone())
+                .chain(iter::once(path.to_owned()))
+                .chain(args.iter().skip(skip).cloned())
+                .collect();
+            return load_user_app_at_depth(uspace, files, None, &new_args, envs, layout, depth + 1);
+        }
+    }
+
+    let (entry, auxv) = match { ELF_LOADER.lock().load(uspace, files, path, layout)? } {
         Ok((entry, auxv)) => (entry, auxv),
         Err(data) => {
             if data.starts_with(b"#!") {
-                let head = &data[2..data.len().min(256)];
-                let pos = head.iter().position(|c| *c == b'\n').unwrap_or(head.len());
-                let line = core::str::from_utf8(&head[..pos]).map_err(|_| AxError::InvalidInput)?;
-
-                let new_args: Vec<String> = line
-                    .trim()
-                    .splitn(2, |c: char| c.is_ascii_whitespace())
-                    .map(|s| s.trim_ascii().to_owned())
+                if depth >= MAX_INTERP_DEPTH {
+                    return Err(AxError::FilesystemLoop);
+                }
+                let (interp, arg) = parse_shebang(&data)?;
+                debug!("Running script {path} with interpreter {interp} {arg:?}");
+
+                let new_args: Vec<String> = iter::once(interp)
+                    .chain(arg)
                     .chain(iter::once(path.to_owned()))
                     .chain(args.iter().skip(1).cloned())
                     .collect();
-                return load_user_app(uspace, None, &new_args, envs);
+                return load_user_app_at_depth(
+                    uspace,
+                    files,
+                    None,
+                    &new_args,
+                    envs,
+                    layout,
+                    depth + 1,
+                );
             }
             return Err(AxError::InvalidExecutable);
         }
     };
 
-    let ustack_top = VirtAddr::from_usize(crate::config::USER_STACK_TOP);
-    let ustack_size = crate::config::USER_STACK_SIZE;
-    let ustack_start = ustack_top - ustack_size;
+    let stack = layout.stack();
+    let (ustack_start, ustack_top, ustack_size) = (stack.start, stack.end, stack.size());
     debug!("Mapping user stack: {ustack_start:#x?} -> {ustack_top:#x?}");
 
     uspace.map(
@@ -335,7 +816,7 @@ pub fn load_user_app(
     )?;
     uspace.write(user_sp, stack_data.as_slice())?;
 
-    let heap_start = VirtAddr::from_usize(crate::config::USER_HEAP_BASE);
+    let heap_start = VirtAddr::from_usize(layout.heap_base);
     let heap_size = crate::config::USER_HEAP_SIZE;
     uspace.map(
         heap_start,
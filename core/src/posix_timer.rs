@@ -0,0 +1,360 @@
+//! POSIX per-process timers, as created by `timer_create`.
+
+use alloc::{
+    collections::btree_map::BTreeMap,
+    sync::{Arc, Weak},
+};
+use core::{mem, time::Duration};
+
+use axerrno::{AxError, AxResult};
+use axhal::time::{TimeValue, wall_time};
+use axsync::spin::SpinNoIrq;
+use linux_raw_sys::general::{CLOCK_BOOTTIME, CLOCK_MONOTONIC, CLOCK_REALTIME, SI_TIMER};
+use starry_process::Pid;
+use starry_signal::{SignalInfo, Signo};
+
+use crate::{
+    hrtimer,
+    task::{AsThread, get_task, raw_signal_info, send_signal_to_process, send_signal_to_thread},
+    time::TimeNamespace,
+};
+
+/// The most timers a process may have at once.
+pub const MAX_TIMERS: usize = 4096;
+
+/// How a timer notifies its process when it expires.
+#[derive(Debug, Clone, Copy)]
+pub enum TimerNotify {
+    /// Not at all; the timer can only be polled with `timer_gettime`.
+    None,
+    /// With a signal to the process.
+    Signal(Signo),
+    /// With a signal to a thread of the process.
+    Thread(Signo, Pid),
+}
+
+struct TimerState {
+    /// Bumped whenever the timer is set, so that callbacks armed earlier know
+    /// they are stale.
+    generation: u64,
+    /// Bumped whenever an [`hrtimer`] is started for the timer, so that only
+    /// the callback of the latest one expires it.
+    armed: u64,
+    /// The next expiration, on the clock of [`PosixTimer::now`].
+    deadline: Option<Duration>,
+    /// The pending [`hrtimer`] entry for `deadline`, cancelled whenever the
+    /// timer is set again or deleted.
+    handle: Option<hrtimer::TimerHandle>,
+    interval: Duration,
+    /// The expirations missed before the last notification, and since, while
+    /// its signal is still queued.
+    overrun: u32,
+    /// Whether a signal was sent for the last notification. Until the target
+    /// takes it, later expirations only count as overruns.
+    queued: bool,
+}
+
+impl TimerState {
+    /// Cancels the pending expiration, if any, and makes callbacks that
+    /// already left the queue stale.
+    fn disarm(&mut self) {
+        self.generation += 1;
+        self.deadline = None;
+        if let Some(handle) = self.handle.take() {
+            handle.cancel();
+        }
+    }
+}
+
+/// A POSIX timer.
+pub struct PosixTimer {
+    id: i32,
+    pid: Pid,
+    clock: u32,
+    time_ns: Arc<TimeNamespace>,
+    notify: TimerNotify,
+    /// The value passed along with the signal.
+    value: usize,
+    state: SpinNoIrq<TimerState>,
+}
+
+/// Returns the current time of `clock` as seen in `time_ns`.
+fn clock_now(clock: u32, time_ns: &TimeNamespace) -> TimeValue {
+    match clock {
+        CLOCK_REALTIME => time_ns.wall_time(),
+        CLOCK_BOOTTIME => time_ns.boottime(),
+        _ => time_ns.monotonic_time(),
+    }
+}
+
+impl PosixTimer {
+    /// Returns the time that deadlines are kept on: the wall time for
+    /// `CLOCK_REALTIME`, and the monotonic time of the namespace otherwise,
+    /// so that its warp applies.
+    fn now(&self) -> Duration {
+        match self.clock {
+            CLOCK_REALTIME => wall_time(),
+            _ => self.time_ns.monotonic_time(),
+        }
+    }
+
+    /// Returns the [`hrtimer`] deadline for `deadline`, or `None` if the
+    /// clock is frozen.
+    fn hrtimer_deadline(&self, deadline: Duration) -> Option<Duration> {
+        match self.clock {
+            CLOCK_REALTIME => Some(deadline),
+            _ => self.time_ns.wall_deadline(deadline),
+        }
+    }
+
+    /// Returns whether timers can be created on `clock`.
+    pub fn is_supported_clock(clock: u32) -> bool {
+        matches!(clock, CLOCK_REALTIME | CLOCK_MONOTONIC | CLOCK_BOOTTIME)
+    }
+
+    /// Returns the ID of the timer.
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+
+    /// Returns the interval and the time left until the next expiration,
+    /// both zero if the timer is disarmed.
+    pub fn get(&self) -> (Duration, Duration) {
+        let state = self.state.lock();
+        let remaining = state
+            .deadline
+            .map_or(Duration::ZERO, |it| it.saturating_sub(self.now()));
+        (state.interval, remaining)
+    }
+
+    /// Arms the timer to expire at `value` and then every `interval`, or
+    /// disarms it if `value` is zero. `value` is an absolute time on the
+    /// timer's clock if `absolute` is set, and relative to now otherwise.
+    ///
+    /// Returns the old interval and time left, as [`get`](Self::get) does.
+    pub fn set(
+        self: &Arc<Self>,
+        value: Duration,
+        interval: Duration,
+        absolute: bool,
+    ) -> (Duration, Duration) {
+        let old = self.get();
+        let mut state = self.state.lock();
+        state.disarm();
+        state.interval = interval;
+        state.overrun = 0;
+        state.deadline = if value.is_zero() {
+            None
+        } else {
+            let delay = if absolute {
+                value.saturating_sub(clock_now(self.clock, &self.time_ns))
+            } else {
+                value
+            };
+            Some(self.now() + delay)
+        };
+        self.arm(&mut state);
+        old
+    }
+
+    /// Arms the timer again for the same deadline, as the time it comes at
+    /// changes with the warp of the namespace.
+    #[cfg(feature = "time-warp")]
+    pub(crate) fn rearm(self: &Arc<Self>) {
+        let mut state = self.state.lock();
+        if let Some(handle) = state.handle.take() {
+            handle.cancel();
+        }
+        self.arm(&mut state);
+    }
+
+    /// Returns the number of expirations missed before the last
+    /// notification.
+    pub fn overrun(&self) -> u32 {
+        self.state.lock().overrun
+    }
+
+    /// Starts an [`hrtimer`] for the deadline in `state`, if there is one
+    /// and the clock is not frozen.
+    fn arm(self: &Arc<Self>, state: &mut TimerState) {
+        state.armed += 1;
+        let (generation, armed) = (state.generation, state.armed);
+        let timer = Arc::downgrade(self);
+        state.handle = state
+            .deadline
+            .and_then(|deadline| self.hrtimer_deadline(deadline))
+            .map(|deadline| {
+                hrtimer::start(deadline, move || Self::expire(timer, generation, armed))
+            });
+    }
+
+    fn expire(timer: Weak<Self>, generation: u64, armed: u64) {
+        let Some(timer) = timer.upgrade() else {
+            return;
+        };
+        let mut state = timer.state.lock();
+        if state.generation != generation || state.armed != armed {
+            return;
+        }
+        let Some(deadline) = state.deadline else {
+            return;
+        };
+        let now = timer.now();
+        // The hrtimer came early, as when the clock was slowed down after it
+        // was started.
+        if now < deadline {
+            timer.arm(&mut state);
+            return;
+        }
+
+        // Expirations that passed while the timer waited to run count as
+        // overruns of this one, which stands in for them.
+        let late = now.saturating_sub(deadline);
+        let missed = if state.interval.is_zero() {
+            0
+        } else {
+            late.as_nanos() / state.interval.as_nanos()
+        };
+        state.deadline = (!state.interval.is_zero()).then(|| {
+            let next = state.interval.as_nanos() * (missed + 1);
+            deadline + Duration::from_nanos(next.min(u64::MAX as u128) as u64)
+        });
+        timer.arm(&mut state);
+        let missed = missed.min(i32::MAX as u128) as u32;
+        let queued = state.queued;
+        drop(state);
+
+        // As on Linux, a signal still queued from an earlier expiration
+        // stands in for this one as well, rather than another being sent.
+        let coalesce = queued && timer.signal_queued();
+        let mut state = timer.state.lock();
+        // The timer was set again meanwhile.
+        if state.generation != generation {
+            return;
+        }
+        if coalesce {
+            state.overrun = state
+                .overrun
+                .saturating_add(missed + 1)
+                .min(i32::MAX as u32);
+            return;
+        }
+        state.overrun = missed;
+        state.queued = !matches!(timer.notify, TimerNotify::None);
+        drop(state);
+        timer.notify(missed);
+    }
+
+    /// Returns whether the signal of the timer is still pending for its
+    /// target, that is, it has been neither delivered nor waited for.
+    fn signal_queued(&self) -> bool {
+        let (signo, tid) = match self.notify {
+            TimerNotify::None => return false,
+            TimerNotify::Signal(signo) => (signo, self.pid),
+            TimerNotify::Thread(signo, tid) => (signo, tid),
+        };
+        // The pending set of a thread includes that of its process.
+        get_task(tid)
+            .ok()
+            .and_then(|task| {
+                task.try_as_thread()
+                    .map(|thr| thr.signal.pending().has(signo))
+            })
+            .unwrap_or(false)
+    }
+
+    fn notify(&self, overrun: u32) {
+        let (signo, tid) = match self.notify {
+            TimerNotify::None => return,
+            TimerNotify::Signal(signo) => (signo, None),
+            TimerNotify::Thread(signo, tid) => (signo, Some(tid)),
+        };
+        let sig = self.signal_info(signo, overrun);
+        // The process or thread may be gone already.
+        let _ = match tid {
+            Some(tid) => send_signal_to_thread(Some(self.pid), tid, Some(sig)),
+            None => send_signal_to_process(self.pid, Some(sig)),
+        };
+    }
+
+    fn signal_info(&self, signo: Signo, overrun: u32) -> SignalInfo {
+        // The `_timer` member of the `siginfo_t` union: the timer ID, the
+        // overrun count and the value.
+        let mut fields = [0u8; 16];
+        fields[0..4].copy_from_slice(&self.id.to_ne_bytes());
+        fields[4..8].copy_from_slice(&(overrun as i32).to_ne_bytes());
+        fields[8..16].copy_from_slice(&(self.value as u64).to_ne_bytes());
+        raw_signal_info(signo, SI_TIMER as i32, &fields)
+    }
+}
+
+/// The POSIX timers of a process.
+#[derive(Default)]
+pub struct PosixTimers {
+    timers: BTreeMap<i32, Arc<PosixTimer>>,
+}
+
+impl PosixTimers {
+    /// Creates a timer, returning its ID.
+    ///
+    /// `value` is passed along with the signal; if `None`, it is the ID of the
+    /// timer.
+    pub fn create(
+        &mut self,
+        pid: Pid,
+        clock: u32,
+        time_ns: Arc<TimeNamespace>,
+        notify: TimerNotify,
+        value: Option<usize>,
+    ) -> AxResult<i32> {
+        if !PosixTimer::is_supported_clock(clock) {
+            return Err(AxError::InvalidInput);
+        }
+        if self.timers.len() >= MAX_TIMERS {
+            return Err(AxError::WouldBlock);
+        }
+        let id = (0..)
+            .find(|id| !self.timers.contains_key(id))
+            .ok_or(AxError::WouldBlock)?;
+        let timer = Arc::new(PosixTimer {
+            id,
+            pid,
+            clock,
+            time_ns,
+            notify,
+            value: value.unwrap_or(id as usize),
+            state: SpinNoIrq::new(TimerState {
+                generation: 0,
+                armed: 0,
+                deadline: None,
+                handle: None,
+                interval: Duration::ZERO,
+                overrun: 0,
+                queued: false,
+            }),
+        });
+        #[cfg(feature = "time-warp")]
+        timer.time_ns.add_timer(&timer);
+        self.timers.insert(id, timer);
+        Ok(id)
+    }
+
+    /// Returns the timer with the given ID.
+    pub fn get(&self, id: i32) -> AxResult<Arc<PosixTimer>> {
+        self.timers.get(&id).cloned().ok_or(AxError::InvalidInput)
+    }
+
+    /// Deletes the timer with the given ID, disarming it.
+    pub fn delete(&mut self, id: i32) -> AxResult<()> {
+        let timer = self.timers.remove(&id).ok_or(AxError::InvalidInput)?;
+        timer.state.lock().disarm();
+        Ok(())
+    }
+
+    /// Deletes all timers, as on `execve`.
+    pub fn clear(&mut self) {
+        for timer in mem::take(&mut self.timers).into_values() {
+            timer.state.lock().disarm();
+        }
+    }
+}
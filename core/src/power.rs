@@ -0,0 +1,136 @@
+//! Resetting and powering off the machine.
+//!
+//! Resets go straight to the firmware: PSCI on aarch64, the SBI system reset
+//! extension on riscv64, and the reset control register of the chipset on
+//! x86_64. A cold reset tries the reset register described by ACPI first,
+//! where there is one. A warm reset keeps the contents of memory where the firmware
+//! allows it, a cold one power-cycles the machine.
+
+use axerrno::{AxError, AxResult};
+
+/// The kind of reset to perform.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetKind {
+    /// A full reset, as after power-on.
+    Cold,
+    /// A reset that leaves memory alone, falling back to a cold reset where
+    /// that isn't supported.
+    Warm,
+}
+
+#[cfg(target_arch = "aarch64")]
+mod arch {
+    use core::arch::asm;
+
+    use super::ResetKind;
+
+    const PSCI_SYSTEM_RESET: u32 = 0x8400_0009;
+    const PSCI_FEATURES: u32 = 0x8400_000a;
+    const PSCI_SYSTEM_RESET2: u32 = 0xc400_0012;
+    const PSCI_SYSTEM_WARM_RESET: usize = 0;
+
+    /// Calls into the PSCI firmware. The platforms supported all take PSCI
+    /// calls through `hvc`.
+    fn psci_call(func: u32, arg0: usize, arg1: usize) -> isize {
+        let ret: isize;
+        unsafe {
+            asm!(
+                "hvc #0",
+                inlateout("x0") func as usize => ret,
+                in("x1") arg0,
+                in("x2") arg1,
+                options(nostack),
+            );
+        }
+        ret
+    }
+
+    pub fn reset(kind: ResetKind) {
+        if kind == ResetKind::Warm && psci_call(PSCI_FEATURES, PSCI_SYSTEM_RESET2 as _, 0) >= 0 {
+            psci_call(PSCI_SYSTEM_RESET2, PSCI_SYSTEM_WARM_RESET, 0);
+        }
+        psci_call(PSCI_SYSTEM_RESET, 0, 0);
+    }
+}
+
+#[cfg(target_arch = "riscv64")]
+mod arch {
+    use core::arch::asm;
+
+    use super::ResetKind;
+
+    const SBI_EXT_SRST: usize = 0x5352_5354;
+    const SBI_SRST_COLD_REBOOT: usize = 1;
+    const SBI_SRST_WARM_REBOOT: usize = 2;
+
+    pub fn reset(kind: ResetKind) {
+        let ty = match kind {
+            ResetKind::Cold => SBI_SRST_COLD_REBOOT,
+            ResetKind::Warm => SBI_SRST_WARM_REBOOT,
+        };
+        unsafe {
+            asm!(
+                "ecall",
+                inlateout("a0") ty => _,
+                inlateout("a1") 0 => _,
+                in("a6") 0,
+                in("a7") SBI_EXT_SRST,
+                options(nostack),
+            );
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod arch {
+    use core::arch::asm;
+
+    use super::ResetKind;
+
+    const RESET_CONTROL: u16 = 0xcf9;
+    /// Resets the system, without cycling power.
+    const SYS_RST: u8 = 0x02;
+    const RST_CPU: u8 = 0x04;
+    /// Cycles power as part of the reset.
+    const FULL_RST: u8 = 0x08;
+
+    fn outb(port: u16, value: u8) {
+        unsafe { asm!("out dx, al", in("dx") port, in("al") value, options(nomem, nostack)) };
+    }
+
+    pub fn reset(kind: ResetKind) {
+        let value = match kind {
+            ResetKind::Cold => SYS_RST | RST_CPU | FULL_RST,
+            ResetKind::Warm => SYS_RST | RST_CPU,
+        };
+        outb(RESET_CONTROL, SYS_RST);
+        outb(RESET_CONTROL, value);
+    }
+}
+
+#[cfg(not(any(
+    target_arch = "aarch64",
+    target_arch = "riscv64",
+    target_arch = "x86_64"
+)))]
+mod arch {
+    use super::ResetKind;
+
+    pub fn reset(_kind: ResetKind) {}
+}
+
+/// Resets the machine. Only returns if the reset failed.
+pub fn reset(kind: ResetKind) -> AxResult<()> {
+    info!("Resetting the system ({kind:?})");
+    if kind == ResetKind::Cold {
+        crate::firmware::acpi::reset();
+    }
+    arch::reset(kind);
+    Err(AxError::Unsupported)
+}
+
+/// Powers the machine off.
+pub fn power_off() -> ! {
+    info!("Powering off the system");
+    axhal::power::system_off()
+}
@@ -0,0 +1,348 @@
+//! The kernel entropy pool and the random number generator built on it.
+//!
+//! The pool accumulates unpredictable inputs and derives seeds from them.
+//! Random bytes come from a ChaCha20-based generator, which is seeded from
+//! the pool once it has collected enough entropy and reseeded from it every
+//! minute after that. Before then, the generator only has the uncredited
+//! boot inputs to go on; `getrandom` waits for it to be seeded unless
+//! `GRND_INSECURE` is given, collecting timing jitter while it waits.
+
+use core::{
+    future::poll_fn,
+    sync::atomic::{AtomicBool, Ordering},
+    task::Poll,
+};
+
+use axconfig::TICKS_PER_SEC;
+use axerrno::AxResult;
+use axhal::time::{NANOS_PER_SEC, monotonic_time_nanos, wall_time_nanos};
+use axpoll::PollSet;
+use axtask::future::{block_on, interruptible};
+use kspin::SpinNoIrq;
+use lazy_static::lazy_static;
+
+/// Upper bound of the entropy estimate, in bits.
+const POOL_BITS: u32 = 256;
+/// The entropy the pool must have collected for the generator to be
+/// considered seeded, in bits.
+const CRNG_INIT_BITS: u32 = 256;
+/// How often a seeded generator takes a new seed from the pool.
+const CRNG_RESEED_INTERVAL_NS: u64 = 60_000_000_000;
+
+/// An entropy pool that accumulates unpredictable inputs, such as interrupt
+/// timings or bytes from a hardware random number generator, and derives seeds
+/// from them.
+struct EntropyPool {
+    state: [u64; 4],
+    /// Number of mixed-in inputs, folded into every extraction.
+    counter: u64,
+    /// Estimated entropy of the pool, in bits.
+    entropy: u32,
+}
+
+impl EntropyPool {
+    const fn new() -> Self {
+        Self {
+            // Fractional parts of sqrt(2), sqrt(3), sqrt(5) and sqrt(7)
+            state: [
+                0x6a09e667f3bcc908,
+                0xbb67ae8584caa73b,
+                0x3c6ef372fe94f82b,
+                0xa54ff53a5f1d36f1,
+            ],
+            counter: 0,
+            entropy: 0,
+        }
+    }
+
+    fn mix(&mut self, word: u64) {
+        self.counter = self.counter.wrapping_add(1);
+        let i = self.counter as usize % self.state.len();
+        self.state[i] ^= word;
+        self.stir();
+    }
+
+    /// A few ARX rounds over the whole state, so every input affects every
+    /// word.
+    fn stir(&mut self) {
+        let [a, b, c, d] = &mut self.state;
+        for _ in 0..2 {
+            *a = a.wrapping_add(*b);
+            *d = (*d ^ *a).rotate_left(32);
+            *c = c.wrapping_add(*d);
+            *b = (*b ^ *c).rotate_left(24);
+            *a = a.wrapping_add(*b);
+            *d = (*d ^ *a).rotate_left(16);
+            *c = c.wrapping_add(*d);
+            *b = (*b ^ *c).rotate_left(63);
+        }
+    }
+
+    fn mix_bytes(&mut self, data: &[u8]) {
+        for chunk in data.chunks(8) {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.mix(u64::from_le_bytes(word));
+        }
+    }
+
+    fn credit(&mut self, bits: u32) {
+        self.entropy = (self.entropy + bits).min(POOL_BITS);
+    }
+
+    /// Derives a seed from the pool.
+    ///
+    /// The state is run through the ChaCha20 block function, whose
+    /// feed-forward makes it one-way: half of the block replaces the state
+    /// and the other half is the seed, so neither a seed nor the new state
+    /// gives away the state that earlier seeds came from.
+    fn extract(&mut self) -> [u8; 32] {
+        self.mix(monotonic_time_nanos() ^ self.counter);
+        let mut bytes = [0; 32];
+        for (chunk, word) in bytes.chunks_exact_mut(8).zip(self.state) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        let block = chacha20_block(&key_from_bytes(&bytes), self.counter);
+        for (word, chunk) in self.state.iter_mut().zip(block[..32].chunks_exact(8)) {
+            *word = u64::from_le_bytes(chunk.try_into().unwrap());
+        }
+        self.entropy = self.entropy.saturating_sub(POOL_BITS / 2);
+        block[32..].try_into().unwrap()
+    }
+}
+
+static POOL: SpinNoIrq<EntropyPool> = SpinNoIrq::new(EntropyPool::new());
+
+fn quarter_round(x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    x[a] = x[a].wrapping_add(x[b]);
+    x[d] = (x[d] ^ x[a]).rotate_left(16);
+    x[c] = x[c].wrapping_add(x[d]);
+    x[b] = (x[b] ^ x[c]).rotate_left(12);
+    x[a] = x[a].wrapping_add(x[b]);
+    x[d] = (x[d] ^ x[a]).rotate_left(8);
+    x[c] = x[c].wrapping_add(x[d]);
+    x[b] = (x[b] ^ x[c]).rotate_left(7);
+}
+
+/// The ChaCha20 block function: 64 bytes of keystream for `key` at block
+/// `counter`, with a zero nonce.
+fn chacha20_block(key: &[u32; 8], counter: u64) -> [u8; 64] {
+    let mut init = [0; 16];
+    // "expand 32-byte k"
+    init[..4].copy_from_slice(&[0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574]);
+    init[4..12].copy_from_slice(key);
+    init[12] = counter as u32;
+    init[13] = (counter >> 32) as u32;
+
+    let mut x = init;
+    for _ in 0..10 {
+        quarter_round(&mut x, 0, 4, 8, 12);
+        quarter_round(&mut x, 1, 5, 9, 13);
+        quarter_round(&mut x, 2, 6, 10, 14);
+        quarter_round(&mut x, 3, 7, 11, 15);
+        quarter_round(&mut x, 0, 5, 10, 15);
+        quarter_round(&mut x, 1, 6, 11, 12);
+        quarter_round(&mut x, 2, 7, 8, 13);
+        quarter_round(&mut x, 3, 4, 9, 14);
+    }
+
+    let mut out = [0; 64];
+    for (chunk, (word, init)) in out.chunks_exact_mut(4).zip(x.iter().zip(init)) {
+        chunk.copy_from_slice(&word.wrapping_add(init).to_le_bytes());
+    }
+    out
+}
+
+fn key_from_bytes(bytes: &[u8]) -> [u32; 8] {
+    let mut key = [0; 8];
+    for (word, chunk) in key.iter_mut().zip(bytes.chunks_exact(4)) {
+        *word = u32::from_le_bytes(chunk.try_into().unwrap());
+    }
+    key
+}
+
+/// The state of the random number generator.
+struct Crng {
+    key: [u32; 8],
+    counter: u64,
+    /// When the generator was last seeded from the pool.
+    reseeded_at: u64,
+}
+
+impl Crng {
+    fn reseed(&mut self, seed: [u8; 32]) {
+        for (word, new) in self.key.iter_mut().zip(key_from_bytes(&seed)) {
+            *word ^= new;
+        }
+        self.reseeded_at = monotonic_time_nanos();
+    }
+
+    /// Returns a key for a single request, replacing the generator key with
+    /// fresh keystream so that the output can't be recovered from the state
+    /// later on.
+    fn next_key(&mut self) -> [u32; 8] {
+        let block = chacha20_block(&self.key, self.counter);
+        self.counter = self.counter.wrapping_add(1);
+        self.key = key_from_bytes(&block[..32]);
+        key_from_bytes(&block[32..])
+    }
+}
+
+static CRNG: SpinNoIrq<Crng> = SpinNoIrq::new(Crng {
+    key: [0; 8],
+    counter: 0,
+    reseeded_at: 0,
+});
+/// Whether the generator was seeded with enough entropy.
+static CRNG_READY: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    /// Woken when the generator is seeded.
+    static ref CRNG_READY_EVENT: PollSet = PollSet::new();
+}
+
+/// Credits entropy to the pool, seeding the generator once there is enough.
+///
+/// The pool must not be locked.
+fn credit_entropy(bits: u32) {
+    let enough = {
+        let mut pool = POOL.lock();
+        pool.credit(bits);
+        pool.entropy >= CRNG_INIT_BITS
+    };
+    if enough && !CRNG_READY.load(Ordering::Acquire) {
+        CRNG.lock().reseed(extract_seed());
+        if !CRNG_READY.swap(true, Ordering::AcqRel) {
+            CRNG_READY_EVENT.wake();
+        }
+    }
+}
+
+/// Mixes the boot-time inputs into the pool and seeds the generator from
+/// them, so that its output differs from boot to boot even before it is
+/// properly seeded.
+///
+/// None of this is credited as entropy.
+pub fn init() {
+    {
+        let mut pool = POOL.lock();
+        pool.mix(wall_time_nanos());
+        pool.mix(monotonic_time_nanos());
+    }
+    CRNG.lock().reseed(extract_seed());
+}
+
+/// Mixes the timing of the current event into the entropy pool.
+///
+/// This is called from the timer interrupt. Its arrival time jitters
+/// slightly, but the tick is periodic, so that is not credited as entropy.
+pub fn add_timer_randomness() {
+    POOL.lock().mix(monotonic_time_nanos());
+}
+
+/// Collects entropy from the jitter between the CPU and the timer
+/// interrupt, as Linux does in `try_to_generate_entropy`.
+///
+/// The monotonic clock is sampled in a loop that yields the CPU in between,
+/// and how many samples fit in a timer tick depends on everything else that
+/// runs. Only a bit is credited per tick in which more than one sample was
+/// taken.
+#[derive(Default)]
+struct Jitter {
+    /// The timer tick of the last sample.
+    tick: u64,
+    /// The samples taken in that tick.
+    samples: u32,
+}
+
+impl Jitter {
+    fn sample(&mut self) {
+        const TICK_NANOS: u64 = NANOS_PER_SEC / TICKS_PER_SEC as u64;
+
+        let now = monotonic_time_nanos();
+        POOL.lock().mix(now);
+        let tick = now / TICK_NANOS;
+        if tick == self.tick {
+            self.samples += 1;
+            return;
+        }
+        if self.samples > 1 {
+            credit_entropy(1);
+        }
+        self.tick = tick;
+        self.samples = 1;
+    }
+}
+
+/// Mixes bytes from a hardware random number generator into the entropy pool,
+/// crediting them with full entropy.
+///
+/// A driver for such a generator, like virtio-rng, feeds it through here.
+pub fn add_hwrng_randomness(data: &[u8]) {
+    POOL.lock().mix_bytes(data);
+    credit_entropy(data.len().saturating_mul(8).min(POOL_BITS as usize) as u32);
+}
+
+/// Mixes data into the entropy pool without crediting any entropy, as writes
+/// to /dev/random do.
+pub fn add_device_randomness(data: &[u8]) {
+    POOL.lock().mix_bytes(data);
+}
+
+/// Returns the estimated entropy of the pool, in bits.
+pub fn entropy_avail() -> u32 {
+    POOL.lock().entropy
+}
+
+/// Derives a fresh 32-byte seed from the entropy pool.
+pub fn extract_seed() -> [u8; 32] {
+    POOL.lock().extract()
+}
+
+/// Returns whether the generator was seeded with enough entropy.
+pub fn crng_ready() -> bool {
+    CRNG_READY.load(Ordering::Acquire)
+}
+
+/// Waits until the generator is seeded, collecting timing jitter in the
+/// meantime. Fails with `EINTR` if a signal arrives first.
+pub fn wait_for_crng() -> AxResult<()> {
+    if crng_ready() {
+        return Ok(());
+    }
+    let mut jitter = Jitter::default();
+    block_on(interruptible(poll_fn(|cx| {
+        CRNG_READY_EVENT.register(cx.waker());
+        if crng_ready() {
+            return Poll::Ready(());
+        }
+        jitter.sample();
+        axtask::yield_now();
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    })))
+}
+
+/// Fills `buf` with random bytes from the generator, whether or not it is
+/// seeded.
+pub fn get_random_bytes(buf: &mut [u8]) {
+    let key = {
+        let mut crng = CRNG.lock();
+        if crng_ready()
+            && monotonic_time_nanos().saturating_sub(crng.reseeded_at) >= CRNG_RESEED_INTERVAL_NS
+        {
+            crng.reseed(extract_seed());
+        }
+        crng.next_key()
+    };
+    for (counter, chunk) in buf.chunks_mut(64).enumerate() {
+        chunk.copy_from_slice(&chacha20_block(&key, counter as u64)[..chunk.len()]);
+    }
+}
+
+/// Returns a random `u64` from the generator.
+pub fn get_random_u64() -> u64 {
+    let mut bytes = [0; 8];
+    get_random_bytes(&mut bytes);
+    u64::from_le_bytes(bytes)
+}
@@ -2,13 +2,28 @@
 
 use core::ops::{Index, IndexMut};
 
-use linux_raw_sys::general::{RLIM_NLIMITS, RLIMIT_NOFILE, RLIMIT_STACK};
+use linux_raw_sys::general::{
+    RLIM_NLIMITS, RLIMIT_CORE, RLIMIT_MEMLOCK, RLIMIT_MSGQUEUE, RLIMIT_NICE, RLIMIT_NOFILE,
+    RLIMIT_RTPRIO, RLIMIT_STACK,
+};
+
+/// The value of an unlimited resource, `RLIM64_INFINITY`
+pub const RLIM_INFINITY: u64 = u64::MAX;
 
 /// The maximum number of open files
 pub const AX_FILE_LIMIT: usize = 1024;
 
+/// The default size limit of the main thread stack, which grows on demand
+pub const DEFAULT_STACK_LIMIT: u64 = 8 * 1024 * 1024;
+
+/// The default soft limit of memory locked by `mlock`, as on Linux
+pub const DEFAULT_MEMLOCK_LIMIT: u64 = 8 * 1024 * 1024;
+
+/// The default limit of bytes in POSIX message queues, as on Linux
+pub const DEFAULT_MSGQUEUE_LIMIT: u64 = 819200;
+
 /// The limit for a specific resource
-#[derive(Default)]
+#[derive(Clone)]
 pub struct Rlimit {
     /// The current limit for the resource (soft)
     pub current: u64,
@@ -26,6 +41,12 @@ impl Rlimit {
     }
 }
 
+impl Default for Rlimit {
+    fn default() -> Self {
+        RLIM_INFINITY.into()
+    }
+}
+
 impl From<u64> for Rlimit {
     fn from(value: u64) -> Self {
         Self {
@@ -36,13 +57,20 @@ impl From<u64> for Rlimit {
 }
 
 /// Process resource limits
+#[derive(Clone)]
 pub struct Rlimits([Rlimit; RLIM_NLIMITS as usize]);
 
 impl Default for Rlimits {
     fn default() -> Self {
+        // Limits not set here are unlimited.
         let mut result = Self(Default::default());
-        result[RLIMIT_STACK] = (crate::config::USER_STACK_SIZE as u64).into();
+        result[RLIMIT_CORE] = Rlimit::new(0, RLIM_INFINITY);
+        result[RLIMIT_STACK] = Rlimit::new(DEFAULT_STACK_LIMIT, RLIM_INFINITY);
+        result[RLIMIT_MEMLOCK] = Rlimit::new(DEFAULT_MEMLOCK_LIMIT, RLIM_INFINITY);
         result[RLIMIT_NOFILE] = (AX_FILE_LIMIT as u64).into();
+        result[RLIMIT_MSGQUEUE] = DEFAULT_MSGQUEUE_LIMIT.into();
+        result[RLIMIT_NICE] = 0.into();
+        result[RLIMIT_RTPRIO] = 0.into();
         result
     }
 }
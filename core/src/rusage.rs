@@ -0,0 +1,191 @@
+//! Resource usage accounting, as reported by `getrusage`, `wait4` and
+//! /proc/[pid]/stat.
+//!
+//! Times and counters are kept per thread. When a thread exits, its usage is
+//! added to its process; when a process exits, its usage is kept until its
+//! parent waits for it, and is then added to the children usage of the
+//! parent.
+
+use alloc::collections::btree_map::BTreeMap;
+use core::{
+    ptr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use axhal::time::TimeValue;
+use axtask::current;
+use kspin::SpinNoIrq;
+use memory_addr::VirtAddr;
+use starry_process::Pid;
+
+use crate::{
+    mm::resident_set_size,
+    task::{AsThread, ProcessData, Thread, get_task},
+};
+
+/// The unit in which block I/O is counted, in bytes.
+const BLOCK_SIZE: usize = 512;
+
+/// Resource usage of a thread, a process or the children of a process.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Rusage {
+    /// Time spent in user mode.
+    pub utime: TimeValue,
+    /// Time spent in the kernel.
+    pub stime: TimeValue,
+    /// The peak resident set size, in bytes.
+    pub maxrss: usize,
+    /// Page faults served from memory.
+    pub minflt: u64,
+    /// Page faults on file mappings.
+    pub majflt: u64,
+    /// Blocks read from files.
+    pub inblock: u64,
+    /// Blocks written to files.
+    pub oublock: u64,
+}
+
+impl Rusage {
+    /// Adds `other` to the usage. The peak resident set size is the larger
+    /// of the two, as on Linux.
+    pub fn add(&mut self, other: &Rusage) {
+        self.utime += other.utime;
+        self.stime += other.stime;
+        self.maxrss = self.maxrss.max(other.maxrss);
+        self.minflt += other.minflt;
+        self.majflt += other.majflt;
+        self.inblock += other.inblock;
+        self.oublock += other.oublock;
+    }
+}
+
+/// The events counted for a thread.
+#[derive(Default)]
+pub struct UsageCounters {
+    minflt: AtomicU64,
+    majflt: AtomicU64,
+    inblock: AtomicU64,
+    oublock: AtomicU64,
+}
+
+impl UsageCounters {
+    /// Counts a page fault that was handled.
+    pub fn page_fault(&self, major: bool) {
+        let counter = if major { &self.majflt } else { &self.minflt };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts `len` bytes read from a file.
+    pub fn read(&self, len: usize) {
+        self.inblock
+            .fetch_add(len.div_ceil(BLOCK_SIZE) as u64, Ordering::Relaxed);
+    }
+
+    /// Counts `len` bytes written to a file.
+    pub fn write(&self, len: usize) {
+        self.oublock
+            .fetch_add(len.div_ceil(BLOCK_SIZE) as u64, Ordering::Relaxed);
+    }
+}
+
+/// Counts a page fault of `thr` at `addr` that was handled.
+///
+/// Whether a page had to be read from storage isn't known, so faults on
+/// file mappings count as major and all others as minor.
+pub fn page_fault(thr: &Thread, addr: VirtAddr) {
+    let major = thr.proc_data.mapped_files.lock().get(addr).is_some();
+    thr.usage.page_fault(major);
+}
+
+/// Returns the user and system time of a thread.
+///
+/// The time manager of a thread can only be borrowed by the thread itself, so
+/// the time of other threads is as they last published it.
+pub fn thread_cpu_time(thr: &Thread) -> (TimeValue, TimeValue) {
+    let is_current = current()
+        .try_as_thread()
+        .is_some_and(|curr| ptr::eq(curr, thr));
+    if is_current && let Ok(time) = thr.time.try_borrow() {
+        return time.output();
+    }
+    thr.cpu_time.load()
+}
+
+/// Returns the usage of a thread.
+///
+/// The peak resident set size is that of the whole process, as last sampled.
+pub fn thread_usage(thr: &Thread) -> Rusage {
+    let (utime, stime) = thread_cpu_time(thr);
+    let counters = &thr.usage;
+    Rusage {
+        utime,
+        stime,
+        maxrss: thr.proc_data.maxrss.load(Ordering::Relaxed) as usize,
+        minflt: counters.minflt.load(Ordering::Relaxed),
+        majflt: counters.majflt.load(Ordering::Relaxed),
+        inblock: counters.inblock.load(Ordering::Relaxed),
+        oublock: counters.oublock.load(Ordering::Relaxed),
+    }
+}
+
+/// Samples the resident set size of a process, updating its peak, and
+/// returns it in bytes.
+///
+/// Walking the page table is costly, so this is only done when the usage is
+/// asked for, and before the address space is dropped on `execve` and exit.
+pub fn sample_rss(proc_data: &ProcessData) -> usize {
+    let rss = resident_set_size(&proc_data.aspace.lock());
+    proc_data.maxrss.fetch_max(rss as u64, Ordering::Relaxed);
+    rss
+}
+
+/// Returns the usage of all threads of a process, live and exited.
+///
+/// The peak resident set size is as last sampled by [`sample_rss`].
+pub fn process_usage(proc_data: &ProcessData) -> Rusage {
+    let mut usage = *proc_data.exited_threads_usage.lock();
+    for tid in proc_data.proc.threads() {
+        if let Ok(task) = get_task(tid)
+            && let Some(thr) = task.try_as_thread()
+        {
+            usage.add(&thread_usage(thr));
+        }
+    }
+    usage
+}
+
+/// Adds the usage of an exiting thread to its process.
+pub fn thread_exited(thr: &Thread) {
+    thr.proc_data
+        .exited_threads_usage
+        .lock()
+        .add(&thread_usage(thr));
+}
+
+/// The usage of processes that have exited but haven't been waited for, by
+/// PID. This outlives their [`ProcessData`].
+static ZOMBIES: SpinNoIrq<BTreeMap<Pid, Rusage>> = SpinNoIrq::new(BTreeMap::new());
+
+/// Keeps the usage of an exiting process and its waited-for children until
+/// its parent waits for it.
+///
+/// Called once the last thread has exited.
+pub fn process_exited(proc_data: &ProcessData) {
+    sample_rss(proc_data);
+    let mut usage = process_usage(proc_data);
+    usage.add(&proc_data.children_usage.lock());
+    ZOMBIES.lock().insert(proc_data.proc.pid(), usage);
+}
+
+/// Adds the usage of the exited child `pid` to the children usage of its
+/// parent, as it's waited for, and returns it.
+pub fn reap(parent: &ProcessData, pid: Pid) -> Rusage {
+    let usage = ZOMBIES.lock().remove(&pid).unwrap_or_default();
+    parent.children_usage.lock().add(&usage);
+    usage
+}
+
+/// Returns the usage of an exited child that hasn't been waited for yet.
+pub fn zombie_usage(pid: Pid) -> Rusage {
+    ZOMBIES.lock().get(&pid).copied().unwrap_or_default()
+}
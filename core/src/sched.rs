@@ -0,0 +1,110 @@
+//! Scheduling helpers.
+
+#[cfg(feature = "resched-debug")]
+use core::panic::Location;
+use core::time::Duration;
+
+use axerrno::{AxError, AxResult};
+use axhal::time::monotonic_time;
+use axtask::{AxCpuMask, TaskInner};
+
+use crate::task::AsThread;
+
+/// The nice value of the highest priority.
+pub const MIN_NICE: i32 = -20;
+/// The nice value of the lowest priority.
+pub const MAX_NICE: i32 = 19;
+
+/// How long a kernel loop may run before it voluntarily yields the CPU.
+const RESCHED_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Gaps between two rescheduling points longer than this are reported when the
+/// `resched-debug` feature is enabled.
+#[cfg(feature = "resched-debug")]
+const LATENCY_BUDGET: Duration = Duration::from_millis(50);
+
+/// Voluntary rescheduling points for long-running kernel loops.
+///
+/// Kernel code is not preempted, so a loop that copies a lot of data or scans
+/// a large structure keeps other tasks on the same CPU waiting. Such loops
+/// should create a [`CondResched`] before starting and call
+/// [`CondResched::cond_resched`] on every iteration, which yields once the loop
+/// has been running for longer than a time slice.
+pub struct CondResched {
+    last: Duration,
+}
+
+impl CondResched {
+    /// Starts tracking the running time of the current loop.
+    pub fn new() -> Self {
+        Self {
+            last: monotonic_time(),
+        }
+    }
+
+    /// Yields the CPU if the loop has been running for too long since the last
+    /// rescheduling point.
+    #[cfg_attr(feature = "resched-debug", track_caller)]
+    pub fn cond_resched(&mut self) {
+        let elapsed = monotonic_time().saturating_sub(self.last);
+        if elapsed < RESCHED_INTERVAL {
+            return;
+        }
+        #[cfg(feature = "resched-debug")]
+        if elapsed > LATENCY_BUDGET {
+            warn!(
+                "{}: ran for {elapsed:?} without rescheduling",
+                Location::caller()
+            );
+        }
+        axtask::yield_now();
+        self.last = monotonic_time();
+    }
+}
+
+impl Default for CondResched {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Passes scheduling changes made to the current thread by other threads on
+/// to the scheduler: a new nice value or CPU affinity.
+///
+/// axtask can only reweight or migrate the task that is running, so such
+/// changes wait until the thread calls this on its way back to user space.
+pub fn apply_pending() {
+    let curr = axtask::current();
+    let Some(thr) = curr.try_as_thread() else {
+        return;
+    };
+    if let Some(nice) = thr.take_nice_change() {
+        axtask::set_priority(nice as isize);
+    }
+    if let Some(mask) = thr.take_pending_affinity() {
+        axtask::set_current_affinity(mask);
+    }
+}
+
+/// Returns the CPU affinity of `task`, including a change not yet applied.
+pub fn affinity(task: &TaskInner) -> AxCpuMask {
+    task.try_as_thread()
+        .and_then(|thr| thr.pending_affinity())
+        .unwrap_or_else(|| task.cpumask())
+}
+
+/// Sets the CPU affinity of `task`, migrating it right away if it is the
+/// current task.
+pub fn set_affinity(task: &TaskInner, mask: AxCpuMask) -> AxResult<()> {
+    if mask.is_empty() {
+        return Err(AxError::InvalidInput);
+    }
+    let is_current = task.id() == axtask::current().id();
+    if let Some(thr) = task.try_as_thread() {
+        thr.set_pending_affinity((!is_current).then_some(mask));
+    }
+    if is_current {
+        axtask::set_current_affinity(mask);
+    }
+    Ok(())
+}
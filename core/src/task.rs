@@ -1,9 +1,11 @@
 //! User task management.
 
+mod ptrace;
 mod stat;
 
 use alloc::{
     boxed::Box,
+    collections::vec_deque::VecDeque,
     string::String,
     sync::{Arc, Weak},
     vec::Vec,
@@ -13,17 +15,20 @@ use core::any::Any;
 use core::{
     cell::RefCell,
     ops::Deref,
-    sync::atomic::{AtomicBool, AtomicI32, AtomicU32, AtomicUsize, Ordering},
+    sync::atomic::{AtomicBool, AtomicI32, AtomicU8, AtomicU32, AtomicU64, AtomicUsize, Ordering},
 };
 
 use axerrno::{AxError, AxResult};
+use axhal::time::TimeValue;
 use axmm::AddrSpace;
 use axpoll::PollSet;
 use axsync::{Mutex, spin::SpinNoIrq};
-use axtask::{AxTaskRef, TaskExt, TaskInner, WeakAxTaskRef, current};
+use axtask::{AxCpuMask, AxTaskRef, TaskExt, TaskInner, WeakAxTaskRef, current};
 use extern_trait::extern_trait;
 use hashbrown::HashMap;
 use lazy_static::lazy_static;
+use linux_raw_sys::general::RLIMIT_CPU;
+use memory_addr::VirtAddrRange;
 use scope_local::{ActiveScope, Scope};
 use spin::RwLock;
 use starry_process::{Pid, Process, ProcessGroup, Session};
@@ -33,11 +38,21 @@ use starry_signal::{
 };
 use weak_map::WeakMap;
 
-pub use self::stat::TaskStat;
+pub use self::{
+    ptrace::{TraceStop, Tracee, detach_all, notify_tracer, tracees},
+    stat::TaskStat,
+};
 use crate::{
+    cred::Credentials,
     futex::{FutexKey, FutexTable},
+    keys::Key,
+    mempolicy::{MemPolicy, RangePolicies},
+    mlock::MemoryLocks,
+    mm::{MappedFiles, UserLayout},
+    posix_timer::PosixTimers,
     resources::Rlimits,
-    time::{TimeManager, TimerState},
+    rusage::{Rusage, UsageCounters},
+    time::{CpuTime, TimeManager, TimeNamespace, TimerState},
 };
 
 ///  A wrapper type that assumes the inner type is `Sync`.
@@ -65,6 +80,12 @@ pub trait TeeSessionCtxTrait {
 
 /// The inner data of a thread.
 pub struct Thread {
+    /// The thread ID.
+    ///
+    /// This is the ID of the task, unless the thread took over the PID of
+    /// the process as it called `execve`.
+    tid: AtomicU32,
+
     /// The process data shared by all threads in the process.
     pub proc_data: Arc<ProcessData>,
 
@@ -85,12 +106,32 @@ pub struct Thread {
     /// Time manager
     ///
     /// This is assumed to be `Sync` because it's only borrowed mutably during
-    /// context switches, which is exclusive to the current thread.
+    /// context switches, which is exclusive to the current thread. Other
+    /// threads must not borrow it, and read [`Thread::cpu_time`] instead.
     pub time: AssumeSync<RefCell<TimeManager>>,
+    /// The CPU time of the thread, published by its time manager for other
+    /// threads to read.
+    pub cpu_time: CpuTime,
+    /// The page faults and file I/O of the thread.
+    pub usage: UsageCounters,
+    /// The tracing state of the thread.
+    pub trace: Tracee,
 
     /// The OOM score adjustment value.
     oom_score_adj: AtomicI32,
 
+    /// The memory policy, as set by `set_mempolicy`
+    mempolicy: SpinNoIrq<MemPolicy>,
+
+    /// The nice value
+    nice: AtomicI32,
+    /// Whether the nice value changed since it was last given to the
+    /// scheduler
+    nice_changed: AtomicBool,
+    /// The CPU affinity set by another thread, not yet given to the
+    /// scheduler
+    pending_affinity: SpinNoIrq<Option<AxCpuMask>>,
+
     /// Ready to exit
     exit: AtomicBool,
 
@@ -106,12 +147,20 @@ impl Thread {
     /// Create a new [`Thread`].
     pub fn new(tid: u32, proc_data: Arc<ProcessData>) -> Box<Self> {
         Box::new(Thread {
+            tid: AtomicU32::new(tid),
             signal: ThreadSignalManager::new(tid, proc_data.signal.clone()),
             proc_data,
             clear_child_tid: AtomicUsize::new(0),
             robust_list_head: AtomicUsize::new(0),
             time: AssumeSync(RefCell::new(TimeManager::new())),
+            cpu_time: CpuTime::default(),
+            usage: UsageCounters::default(),
+            trace: Tracee::default(),
             oom_score_adj: AtomicI32::new(200),
+            mempolicy: SpinNoIrq::new(MemPolicy::default()),
+            nice: AtomicI32::new(0),
+            nice_changed: AtomicBool::new(false),
+            pending_affinity: SpinNoIrq::new(None),
             exit: AtomicBool::new(false),
             accessing_user_memory: AtomicBool::new(false),
             #[cfg(feature = "tee")]
@@ -119,6 +168,11 @@ impl Thread {
         })
     }
 
+    /// Get the thread ID.
+    pub fn tid(&self) -> Pid {
+        self.tid.load(Ordering::Acquire)
+    }
+
     /// Get the clear child tid field.
     pub fn clear_child_tid(&self) -> usize {
         self.clear_child_tid.load(Ordering::Relaxed)
@@ -151,6 +205,51 @@ impl Thread {
         self.oom_score_adj.store(value, Ordering::SeqCst);
     }
 
+    /// Get the memory policy.
+    pub fn mempolicy(&self) -> MemPolicy {
+        *self.mempolicy.lock()
+    }
+
+    /// Set the memory policy.
+    pub fn set_mempolicy(&self, policy: MemPolicy) {
+        *self.mempolicy.lock() = policy;
+    }
+
+    /// Get the nice value.
+    pub fn nice(&self) -> i32 {
+        self.nice.load(Ordering::SeqCst)
+    }
+
+    /// Set the nice value. It takes effect once the thread next returns to
+    /// user space; see [`apply_pending`](crate::sched::apply_pending).
+    pub fn set_nice(&self, nice: i32) {
+        self.nice.store(nice, Ordering::SeqCst);
+        self.nice_changed.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns the nice value if it changed since the last call.
+    pub fn take_nice_change(&self) -> Option<i32> {
+        self.nice_changed
+            .swap(false, Ordering::SeqCst)
+            .then(|| self.nice())
+    }
+
+    /// Get the CPU affinity that is waiting to be applied, if any.
+    pub fn pending_affinity(&self) -> Option<AxCpuMask> {
+        *self.pending_affinity.lock()
+    }
+
+    /// Set the CPU affinity of the thread from another one. Like the nice
+    /// value, it takes effect once the thread next returns to user space.
+    pub fn set_pending_affinity(&self, mask: Option<AxCpuMask>) {
+        *self.pending_affinity.lock() = mask;
+    }
+
+    /// Returns the CPU affinity waiting to be applied, clearing it.
+    pub fn take_pending_affinity(&self) -> Option<AxCpuMask> {
+        self.pending_affinity.lock().take()
+    }
+
     /// Check if the thread is ready to exit.
     pub fn pending_exit(&self) -> bool {
         self.exit.load(Ordering::Acquire)
@@ -185,12 +284,22 @@ impl Thread {
 #[extern_trait]
 unsafe impl TaskExt for Box<Thread> {
     fn on_enter(&self) {
+        crate::trace::trace_switch_in(self.tid);
+        if let Ok(mut time) = self.time.try_borrow_mut() {
+            time.switch_in();
+        }
+        crate::mitigations::on_switch_in(Arc::as_ptr(&self.proc_data) as usize);
         let scope = self.proc_data.scope.read();
         unsafe { ActiveScope::set(&scope) };
         core::mem::forget(scope);
     }
 
     fn on_leave(&self) {
+        crate::trace::trace_switch_out(self.tid);
+        if let Ok(mut time) = self.time.try_borrow_mut() {
+            time.switch_out();
+            time.publish(&self.cpu_time);
+        }
         ActiveScope::set_global();
         unsafe { self.proc_data.scope.force_read_decrement() };
     }
@@ -214,40 +323,119 @@ impl AsThread for TaskInner {
     }
 }
 
+/// A change in the job control state of a process, for its parent to collect
+/// through `waitpid`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReport {
+    /// The process was stopped by the signal.
+    Stopped(Signo),
+    /// The process was continued by `SIGCONT`.
+    Continued,
+}
+
 /// [`Process`]-shared data.
 pub struct ProcessData {
     /// The process.
     pub proc: Arc<Process>,
     /// The executable path
     pub exe_path: RwLock<String>,
+    /// The user and group IDs the process runs as.
+    pub cred: RwLock<Credentials>,
     /// The command line arguments
     pub cmdline: RwLock<Arc<Vec<String>>>,
+    /// The environment variables passed on the last `execve`
+    pub environ: RwLock<Arc<Vec<String>>>,
+    /// The time namespace.
+    pub time_ns: RwLock<Arc<TimeNamespace>>,
+    /// The time namespace that children will be created in, and that the
+    /// process enters on `execve`.
+    pub time_ns_for_children: RwLock<Arc<TimeNamespace>>,
     /// The virtual memory address space.
     // TODO: scopify
     pub aspace: Arc<Mutex<AddrSpace>>,
+    /// The total size of the mappings in [`aspace`](Self::aspace), which
+    /// `RLIMIT_AS` limits. Shared along with it.
+    pub vm_size: Arc<AtomicUsize>,
     /// The resource scope
     pub scope: RwLock<Scope>,
     /// The user heap top
     heap_top: AtomicUsize,
+    /// The mappings that grow down on faults below them, such as the main
+    /// thread stack. See [`grow_stack`](crate::mm::grow_stack).
+    pub stacks: Mutex<Vec<VirtAddrRange>>,
+    /// The lowest address of the user heap
+    heap_base: AtomicUsize,
+    /// Where `mmap` starts looking for free space when given no hint
+    mmap_base: AtomicUsize,
+    /// Whether `mmap` searches down from `mmap_base` instead of up
+    mmap_top_down: AtomicBool,
+    /// The execution domain, as set by `personality`
+    personality: AtomicU32,
+    /// The memory locked by `mlock` and `mlockall`
+    pub mlock: Mutex<MemoryLocks>,
+    /// The memory policies of address ranges, as set by `mbind`
+    pub mempolicy: Mutex<RangePolicies>,
+    /// The files that mappings were made from
+    pub mapped_files: Mutex<MappedFiles>,
+    /// The POSIX timers
+    pub posix_timers: Mutex<PosixTimers>,
 
     /// The resource limits
     pub rlim: RwLock<Rlimits>,
+    /// The CPU time in seconds up to which `SIGXCPU` has been sent for
+    /// exceeding the soft `RLIMIT_CPU`.
+    cpu_limit_warned: AtomicU64,
+
+    /// The peak resident set size in bytes, as last sampled.
+    pub maxrss: AtomicU64,
+    /// The usage of the threads of the process that have exited.
+    pub exited_threads_usage: SpinNoIrq<Rusage>,
+    /// The usage of the children that have been waited for, and of their
+    /// own children.
+    pub children_usage: SpinNoIrq<Rusage>,
 
     /// The child exit wait event
     pub child_exit_event: Arc<PollSet>,
     /// Self exit event
     pub exit_event: Arc<PollSet>,
+    /// Woken when a thread of the process exits.
+    pub thread_exit_event: Arc<PollSet>,
+    /// Whether a thread is killing the other threads, to exit the whole
+    /// process or to run a new program.
+    group_exiting: AtomicBool,
+    /// Held while a thread leaves the process, so that threads leaving
+    /// concurrently with a group exit don't override its exit code.
+    exit_lock: SpinNoIrq<()>,
     /// The exit signal of the thread
     pub exit_signal: Option<Signo>,
 
     /// The process signal manager
     pub signal: Arc<ProcessSignalManager>,
+    /// The job control signal the process is stopped by, or 0 if it's
+    /// running.
+    stopped: AtomicU8,
+    /// Woken when the process is continued.
+    pub continue_event: Arc<PollSet>,
+    /// The stop or continue not yet reported to the parent.
+    stop_report: SpinNoIrq<Option<StopReport>>,
 
     /// The futex table.
     futex_table: Arc<FutexTable>,
 
     /// The default mask for file permissions.
     umask: AtomicU32,
+
+    /// The session keyring, shared with the children.
+    pub session_keyring: SpinNoIrq<Option<Arc<Key>>>,
+    /// The process keyring, created on first use.
+    pub process_keyring: SpinNoIrq<Option<Arc<Key>>>,
+
+    /// The most recent syscalls of the process, decoded, if they are being
+    /// logged.
+    pub syscall_log: Mutex<Option<VecDeque<String>>>,
+    /// Whether [`syscall_log`](Self::syscall_log) is on, so that syscalls can
+    /// check without taking its lock.
+    pub syscall_trace: AtomicBool,
 }
 
 impl ProcessData {
@@ -257,34 +445,151 @@ impl ProcessData {
         exe_path: String,
         cmdline: Arc<Vec<String>>,
         aspace: Arc<Mutex<AddrSpace>>,
+        vm_size: Arc<AtomicUsize>,
         signal_actions: Arc<SpinNoIrq<SignalActions>>,
         exit_signal: Option<Signo>,
     ) -> Arc<Self> {
         Arc::new(Self {
             proc,
             exe_path: RwLock::new(exe_path),
+            cred: RwLock::default(),
             cmdline: RwLock::new(cmdline),
+            environ: RwLock::default(),
+            time_ns: RwLock::new(TimeNamespace::root().clone()),
+            time_ns_for_children: RwLock::new(TimeNamespace::root().clone()),
             aspace,
+            vm_size,
             scope: RwLock::new(Scope::new()),
             heap_top: AtomicUsize::new(crate::config::USER_HEAP_BASE),
+            stacks: Mutex::new(Vec::new()),
+            heap_base: AtomicUsize::new(crate::config::USER_HEAP_BASE),
+            mmap_base: AtomicUsize::new(0),
+            mmap_top_down: AtomicBool::new(false),
+            personality: AtomicU32::new(0),
+            mlock: Mutex::new(MemoryLocks::default()),
+            mempolicy: Mutex::new(RangePolicies::default()),
+            mapped_files: Mutex::new(MappedFiles::default()),
+            posix_timers: Mutex::new(PosixTimers::default()),
 
             rlim: RwLock::default(),
+            cpu_limit_warned: AtomicU64::new(0),
+
+            maxrss: AtomicU64::new(0),
+            exited_threads_usage: SpinNoIrq::new(Rusage::default()),
+            children_usage: SpinNoIrq::new(Rusage::default()),
 
             child_exit_event: Arc::default(),
             exit_event: Arc::default(),
+            thread_exit_event: Arc::default(),
+            group_exiting: AtomicBool::new(false),
+            exit_lock: SpinNoIrq::new(()),
             exit_signal,
 
             signal: Arc::new(ProcessSignalManager::new(
                 signal_actions,
                 crate::config::SIGNAL_TRAMPOLINE,
             )),
+            stopped: AtomicU8::new(0),
+            continue_event: Arc::default(),
+            stop_report: SpinNoIrq::new(None),
 
             futex_table: Arc::new(FutexTable::new()),
 
             umask: AtomicU32::new(0o022),
+
+            session_keyring: SpinNoIrq::new(None),
+            process_keyring: SpinNoIrq::new(None),
+
+            syscall_log: Mutex::new(None),
+            syscall_trace: AtomicBool::new(false),
         })
     }
 
+    /// Returns whether the process is stopped by a job control signal.
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Acquire) != 0
+    }
+
+    /// Returns the job control signal the process is stopped by.
+    pub fn stop_signal(&self) -> Option<Signo> {
+        Signo::from_repr(self.stopped.load(Ordering::Acquire))
+    }
+
+    /// Marks the process stopped by `signo`, and tells the parent.
+    ///
+    /// The threads themselves wait in the signal handling code until the
+    /// process is continued.
+    pub fn stop(&self, signo: Signo) {
+        if self.stopped.swap(signo as u8, Ordering::AcqRel) == 0 {
+            *self.stop_report.lock() = Some(StopReport::Stopped(signo));
+            self.notify_parent();
+        }
+    }
+
+    /// Continues the process if it is stopped, telling the parent if
+    /// `report` is set.
+    pub fn resume(&self, report: bool) {
+        if self.stopped.swap(0, Ordering::AcqRel) != 0 {
+            if report {
+                *self.stop_report.lock() = Some(StopReport::Continued);
+                self.notify_parent();
+            } else {
+                *self.stop_report.lock() = None;
+            }
+            self.continue_event.wake();
+        }
+    }
+
+    /// Starts killing the other threads of the process, returning `false` if
+    /// another thread is already doing so.
+    pub fn begin_group_exit(&self) -> bool {
+        !self.group_exiting.swap(true, Ordering::AcqRel)
+    }
+
+    /// Lets threads start a group exit again, once `execve` has got rid of
+    /// the other threads.
+    pub fn end_group_exit(&self) {
+        self.group_exiting.store(false, Ordering::Release);
+    }
+
+    /// Returns whether a thread is killing the other threads of the process.
+    pub fn is_group_exiting(&self) -> bool {
+        self.group_exiting.load(Ordering::Acquire)
+    }
+
+    /// Removes the thread `tid` from the process, returning whether it was
+    /// the last one.
+    ///
+    /// With `group_exit`, `exit_code` becomes the exit code of the process,
+    /// which the threads that leave afterwards don't change.
+    pub fn exit_thread(&self, tid: Pid, exit_code: i32, group_exit: bool) -> bool {
+        let guard = self.exit_lock.lock();
+        let last = self.proc.exit_thread(tid, exit_code);
+        if group_exit {
+            self.proc.group_exit();
+        }
+        drop(guard);
+        self.thread_exit_event.wake();
+        last
+    }
+
+    /// Returns the stop or continue not yet reported to the parent, taking it
+    /// unless `peek` is set.
+    pub fn take_stop_report(&self, peek: bool) -> Option<StopReport> {
+        let mut report = self.stop_report.lock();
+        if peek { *report } else { report.take() }
+    }
+
+    fn notify_parent(&self) {
+        let Some(parent) = self.proc.parent() else {
+            return;
+        };
+        let _ = send_signal_to_process(parent.pid(), Some(SignalInfo::new_kernel(Signo::SIGCHLD)));
+        if let Ok(data) = get_process_data(parent.pid()) {
+            data.child_exit_event.wake();
+        }
+    }
+
     /// Get the top address of the user heap.
     pub fn get_heap_top(&self) -> usize {
         self.heap_top.load(Ordering::Acquire)
@@ -295,6 +600,65 @@ impl ProcessData {
         self.heap_top.store(top, Ordering::Release)
     }
 
+    /// Get the lowest address of the user heap.
+    pub fn heap_base(&self) -> usize {
+        self.heap_base.load(Ordering::Acquire)
+    }
+
+    /// Get where `mmap` starts looking for free space when given no hint: the
+    /// top of the search if [`mmap_top_down`](Self::mmap_top_down), else its
+    /// bottom, with 0 for the lowest address allowed.
+    pub fn mmap_base(&self) -> usize {
+        self.mmap_base.load(Ordering::Acquire)
+    }
+
+    /// Get whether `mmap` places mappings given no hint top-down.
+    pub fn mmap_top_down(&self) -> bool {
+        self.mmap_top_down.load(Ordering::Acquire)
+    }
+
+    /// Places the heap, the `mmap` area and the stack as in `layout`,
+    /// resetting the heap and forgetting any other stacks.
+    pub fn set_layout(&self, layout: &UserLayout) {
+        self.heap_base.store(layout.heap_base, Ordering::Release);
+        self.heap_top.store(layout.heap_base, Ordering::Release);
+        self.mmap_base.store(layout.mmap_base, Ordering::Release);
+        self.mmap_top_down
+            .store(layout.mmap_top_down, Ordering::Release);
+        *self.stacks.lock() = alloc::vec![layout.stack()];
+    }
+
+    /// Copies the heap, `mmap` and stack placement of `other`, as on `fork`.
+    pub fn inherit_layout(&self, other: &ProcessData) {
+        self.heap_base.store(other.heap_base(), Ordering::Release);
+        self.heap_top.store(other.get_heap_top(), Ordering::Release);
+        self.mmap_base.store(other.mmap_base(), Ordering::Release);
+        self.mmap_top_down
+            .store(other.mmap_top_down(), Ordering::Release);
+        *self.stacks.lock() = other.stacks.lock().clone();
+    }
+
+    /// Get the execution domain.
+    pub fn personality(&self) -> u32 {
+        self.personality.load(Ordering::SeqCst)
+    }
+
+    /// Set the execution domain.
+    pub fn set_personality(&self, personality: u32) {
+        self.personality.store(personality, Ordering::SeqCst);
+    }
+
+    /// Get the effective user ID.
+    pub fn euid(&self) -> u32 {
+        self.cred.read().uid.effective
+    }
+
+    /// Returns whether the process has every capability, as root does, and
+    /// so is exempt from checks such as resource limits on locked memory.
+    pub fn is_privileged(&self) -> bool {
+        self.cred.read().is_privileged()
+    }
+
     /// Linux manual: A "clone" child is one which delivers no signal, or a
     /// signal other than SIGCHLD to its parent upon termination.
     pub fn is_clone_child(&self) -> bool {
@@ -412,6 +776,28 @@ pub fn add_task_to_table(task: &AxTaskRef) {
     session_table.insert(session.sid(), &session);
 }
 
+/// Makes the current thread, the only one left in its process, take over
+/// the PID of the process as its TID, as `execve` does when called from a
+/// thread other than the leader.
+pub fn take_over_leadership(task: &AxTaskRef) {
+    let thr = task.as_thread();
+    let proc = &thr.proc_data.proc;
+    let (tid, pid) = (thr.tid(), proc.pid());
+    if tid == pid {
+        return;
+    }
+    info!("Thread {tid} takes over the leadership of process {pid}");
+
+    let _guard = thr.proc_data.exit_lock.lock();
+    proc.add_thread(pid);
+    proc.exit_thread(tid, 0);
+    thr.tid.store(pid, Ordering::Release);
+
+    let mut task_table = TASK_TABLE.write();
+    task_table.remove(&tid);
+    task_table.insert(pid, task);
+}
+
 /// Lists all tasks.
 pub fn tasks() -> Vec<AxTaskRef> {
     TASK_TABLE.read().values().collect()
@@ -430,6 +816,10 @@ pub fn processes() -> Vec<Arc<ProcessData>> {
     PROCESS_TABLE.read().values().collect()
 }
 
+/// The limit on PIDs reported in /proc/sys/kernel/pid_max, which PIDs asked
+/// for by user space must stay below.
+pub const PID_MAX: u32 = 32768;
+
 /// Finds the process with the given PID.
 pub fn get_process_data(pid: Pid) -> AxResult<Arc<ProcessData>> {
     if pid == 0 {
@@ -463,6 +853,7 @@ pub fn poll_timer(task: &TaskInner) {
     time.poll(|signo| {
         send_signal_thread_inner(task, thr, SignalInfo::new_kernel(signo));
     });
+    time.publish(&thr.cpu_time);
 }
 
 /// Sets the timer state.
@@ -477,25 +868,100 @@ pub fn set_timer_state(task: &TaskInner, state: TimerState) {
     time.poll(|signo| {
         send_signal_thread_inner(task, thr, SignalInfo::new_kernel(signo));
     });
+    time.publish(&thr.cpu_time);
     time.set_state(state);
 }
 
+/// Returns the CPU time used by all threads of a process, live and exited.
+///
+/// Threads other than the current one may be running elsewhere, so their
+/// time is as they last published it.
+pub fn process_cpu_time(proc_data: &ProcessData) -> TimeValue {
+    let exited = *proc_data.exited_threads_usage.lock();
+    let mut total = exited.utime + exited.stime;
+    for tid in proc_data.proc.threads() {
+        if let Ok(task) = get_task(tid)
+            && let Some(thr) = task.try_as_thread()
+        {
+            let (utime, stime) = thr.cpu_time.load();
+            total += utime + stime;
+        }
+    }
+    total
+}
+
+/// Enforces `RLIMIT_CPU` on the process of `thr`: `SIGXCPU` is sent when the
+/// soft limit is reached and every second past it, `SIGKILL` when the hard
+/// limit is reached.
+pub fn check_cpu_limit(thr: &Thread) {
+    let proc_data = &thr.proc_data;
+    let (soft, hard) = {
+        let rlim = proc_data.rlim.read();
+        (rlim[RLIMIT_CPU].current, rlim[RLIMIT_CPU].max)
+    };
+    if soft == u64::MAX && hard == u64::MAX {
+        return;
+    }
+
+    // As on Linux, a limit of 0 acts as 1 second.
+    let secs = process_cpu_time(proc_data).as_secs();
+    let pid = proc_data.proc.pid();
+    if secs >= hard.max(1) {
+        let _ = send_signal_to_process(pid, Some(SignalInfo::new_kernel(Signo::SIGKILL)));
+    } else if secs >= soft.max(1)
+        && proc_data
+            .cpu_limit_warned
+            .fetch_max(secs + 1, Ordering::AcqRel)
+            <= secs
+    {
+        let _ = send_signal_to_process(pid, Some(SignalInfo::new_kernel(Signo::SIGXCPU)));
+    }
+}
+
+/// Builds a `siginfo_t` from its common header and `fields`, the bytes of
+/// the union member that follows the header.
+pub fn raw_signal_info(signo: Signo, code: i32, fields: &[u8]) -> SignalInfo {
+    let mut raw = [0u8; 128];
+    raw[0..4].copy_from_slice(&(signo as i32).to_ne_bytes());
+    raw[8..12].copy_from_slice(&code.to_ne_bytes());
+    raw[16..16 + fields.len()].copy_from_slice(fields);
+    // SAFETY: `SignalInfo` is a plain `siginfo_t`.
+    unsafe { core::mem::transmute::<[u8; 128], SignalInfo>(raw) }
+}
+
 fn send_signal_thread_inner(task: &TaskInner, thr: &Thread, sig: SignalInfo) {
+    let kill = sig.signo() == Signo::SIGKILL;
     if thr.signal.send_signal(sig) {
         task.interrupt();
     }
+    if kill {
+        thr.trace.wake();
+    }
+}
+
+/// Continues a stopped process on `SIGCONT` or `SIGKILL`, which take effect
+/// on the whole process even if blocked or ignored, and even if sent to a
+/// single thread.
+fn resume_for_signal(proc_data: &ProcessData, signo: Signo) {
+    match signo {
+        Signo::SIGCONT => proc_data.resume(true),
+        Signo::SIGKILL => proc_data.resume(false),
+        _ => {}
+    }
 }
 
 /// Sends a signal to a thread.
 pub fn send_signal_to_thread(tgid: Option<Pid>, tid: Pid, sig: Option<SignalInfo>) -> AxResult<()> {
     let task = get_task(tid)?;
     let thread = task.try_as_thread().ok_or(AxError::OperationNotPermitted)?;
-    if tgid.is_some_and(|tgid| thread.proc_data.proc.pid() != tgid) {
+    // The task may linger in the table for a while after the thread exits.
+    if thread.pending_exit() || tgid.is_some_and(|tgid| thread.proc_data.proc.pid() != tgid) {
         return Err(AxError::NoSuchProcess);
     }
 
     if let Some(sig) = sig {
         info!("Send signal {:?} to thread {}", sig.signo(), tid);
+        resume_for_signal(&thread.proc_data, sig.signo());
         send_signal_thread_inner(&task, thread, sig);
     }
 
@@ -509,8 +975,11 @@ pub fn send_signal_to_process(pid: Pid, sig: Option<SignalInfo>) -> AxResult<()>
     if let Some(sig) = sig {
         let signo = sig.signo();
         info!("Send signal {signo:?} to process {pid}");
+        resume_for_signal(&proc_data, signo);
+        // The signal manager of a thread that took over the leadership
+        // still knows it by its old TID.
         if let Some(tid) = proc_data.signal.send_signal(sig)
-            && let Ok(task) = get_task(tid)
+            && let Ok(task) = get_task(tid).or_else(|_| get_task(pid))
         {
             task.interrupt();
         }
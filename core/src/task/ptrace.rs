@@ -0,0 +1,270 @@
+//! Process tracing, limited to what freezing a process for inspection needs:
+//! `PTRACE_SEIZE`, `PTRACE_INTERRUPT`, `PTRACE_LISTEN`, `PTRACE_CONT` and
+//! `PTRACE_DETACH`.
+//!
+//! Tracees are threads. A seized thread runs on until it is interrupted or
+//! takes part in a group stop; it then sits in a ptrace stop, which its
+//! tracer collects through `waitpid` and ends. Events other than these stops
+//! aren't reported.
+
+use alloc::vec::Vec;
+use core::task::Waker;
+
+use axerrno::{AxError, AxResult};
+use axpoll::PollSet;
+use axtask::AxTaskRef;
+use kspin::SpinNoIrq;
+use starry_process::Pid;
+use starry_signal::{SignalInfo, Signo};
+
+use super::{AsThread, ProcessData, get_process_data, send_signal_to_process, tasks};
+
+/// Why a tracee is in a ptrace stop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceStop {
+    /// The tracer asked for the stop with `PTRACE_INTERRUPT`.
+    Interrupt,
+    /// The process was stopped by the signal.
+    Group(Signo),
+}
+
+impl TraceStop {
+    /// Returns the signal reported along with `PTRACE_EVENT_STOP`.
+    pub fn signo(&self) -> Signo {
+        match self {
+            TraceStop::Interrupt => Signo::SIGTRAP,
+            TraceStop::Group(signo) => *signo,
+        }
+    }
+}
+
+struct TraceState {
+    /// The PID of the tracing process.
+    tracer: Pid,
+    /// The `PTRACE_O_*` options given on seizing.
+    options: u32,
+    /// Whether `PTRACE_INTERRUPT` is waiting to be acted upon.
+    interrupt: bool,
+    /// The ptrace stop the thread is in.
+    stop: Option<TraceStop>,
+    /// Whether the stop has been collected by the tracer.
+    reported: bool,
+    /// Whether the tracer let the thread wait for the group stop to end,
+    /// through `PTRACE_LISTEN`.
+    listening: bool,
+    /// Whether the current group stop was reported already.
+    group_stop_seen: bool,
+}
+
+/// The tracing state of a thread.
+#[derive(Default)]
+pub struct Tracee {
+    state: SpinNoIrq<Option<TraceState>>,
+    /// Woken when the tracer ends a ptrace stop.
+    resume_event: PollSet,
+}
+
+impl Tracee {
+    /// Returns the PID of the tracing process, if any.
+    pub fn tracer(&self) -> Option<Pid> {
+        self.state.lock().as_ref().map(|it| it.tracer)
+    }
+
+    /// Returns whether the thread is traced.
+    pub fn is_traced(&self) -> bool {
+        self.state.lock().is_some()
+    }
+
+    /// Returns whether the thread is in a ptrace stop.
+    pub fn is_stopped(&self) -> bool {
+        self.state
+            .lock()
+            .as_ref()
+            .is_some_and(|it| it.stop.is_some())
+    }
+
+    /// Starts tracing by `tracer`, without stopping the thread.
+    pub fn seize(&self, tracer: Pid, options: u32) -> AxResult<()> {
+        let mut state = self.state.lock();
+        if state.is_some() {
+            return Err(AxError::OperationNotPermitted);
+        }
+        *state = Some(TraceState {
+            tracer,
+            options,
+            interrupt: false,
+            stop: None,
+            reported: false,
+            listening: false,
+            group_stop_seen: false,
+        });
+        Ok(())
+    }
+
+    /// Stops tracing, letting the thread run again.
+    pub fn detach(&self) {
+        *self.state.lock() = None;
+        self.resume_event.wake();
+    }
+
+    /// Asks the thread to enter a ptrace stop, returning whether it must be
+    /// interrupted to notice.
+    ///
+    /// A thread already stopped stays so; if it was listening, the stop is
+    /// reported again as an interrupt.
+    pub fn interrupt(&self) -> bool {
+        let mut state = self.state.lock();
+        let Some(state) = state.as_mut() else {
+            return false;
+        };
+        match state.stop {
+            Some(_) if state.listening => {
+                state.stop = Some(TraceStop::Interrupt);
+                state.listening = false;
+                state.reported = false;
+                false
+            }
+            Some(_) => false,
+            None => {
+                state.interrupt = true;
+                true
+            }
+        }
+    }
+
+    /// Takes the request to stop made by `PTRACE_INTERRUPT`.
+    pub fn take_interrupt(&self) -> bool {
+        self.state
+            .lock()
+            .as_mut()
+            .is_some_and(|it| core::mem::take(&mut it.interrupt))
+    }
+
+    /// Records that the thread takes part in the current group stop,
+    /// returning `false` if it did already.
+    pub fn begin_group_stop(&self) -> bool {
+        self.state
+            .lock()
+            .as_mut()
+            .is_some_and(|it| !core::mem::replace(&mut it.group_stop_seen, true))
+    }
+
+    /// Records that the process is no longer stopped.
+    pub fn end_group_stop(&self) {
+        if let Some(state) = self.state.lock().as_mut() {
+            state.group_stop_seen = false;
+        }
+    }
+
+    /// Enters a ptrace stop, returning the PID of the tracer to notify.
+    pub fn enter_stop(&self, stop: TraceStop) -> Option<Pid> {
+        let mut state = self.state.lock();
+        let state = state.as_mut()?;
+        state.stop = Some(stop);
+        state.reported = false;
+        state.listening = false;
+        Some(state.tracer)
+    }
+
+    /// Returns whether the ptrace stop is over: the tracer continued or
+    /// detached the thread, or let it listen and the process was continued.
+    pub fn stop_ended(&self, proc_data: &ProcessData) -> bool {
+        self.state
+            .lock()
+            .as_ref()
+            .is_none_or(|it| it.stop.is_none() || (it.listening && !proc_data.is_stopped()))
+    }
+
+    /// Leaves the ptrace stop.
+    pub fn leave_stop(&self) {
+        if let Some(state) = self.state.lock().as_mut() {
+            state.stop = None;
+            state.listening = false;
+        }
+    }
+
+    /// Wakes the thread from a ptrace stop to notice `SIGKILL`, which ends
+    /// any stop.
+    pub fn wake(&self) {
+        self.resume_event.wake();
+    }
+
+    /// Registers a waker for the end of a ptrace stop.
+    pub fn register(&self, waker: &Waker) {
+        self.resume_event.register(waker);
+    }
+
+    /// Returns the ptrace stop not yet collected by the tracer, marking it
+    /// collected unless `peek` is set.
+    pub fn take_report(&self, peek: bool) -> Option<TraceStop> {
+        let mut state = self.state.lock();
+        let state = state.as_mut()?;
+        if state.reported || state.listening {
+            return None;
+        }
+        let stop = state.stop?;
+        if !peek {
+            state.reported = true;
+        }
+        Some(stop)
+    }
+
+    /// Ends the ptrace stop as `PTRACE_CONT` does.
+    pub fn resume(&self) -> AxResult<()> {
+        let mut guard = self.state.lock();
+        let state = guard.as_mut().ok_or(AxError::NoSuchProcess)?;
+        if state.stop.take().is_none() {
+            return Err(AxError::NoSuchProcess);
+        }
+        state.listening = false;
+        drop(guard);
+        self.resume_event.wake();
+        Ok(())
+    }
+
+    /// Lets the thread wait for the group stop to end as `PTRACE_LISTEN`
+    /// does, without the tracer seeing it stopped any more.
+    pub fn listen(&self) -> AxResult<()> {
+        let mut guard = self.state.lock();
+        let state = guard.as_mut().ok_or(AxError::NoSuchProcess)?;
+        if state.stop.is_none() {
+            return Err(AxError::NoSuchProcess);
+        }
+        state.listening = true;
+        drop(guard);
+        self.resume_event.wake();
+        Ok(())
+    }
+
+    /// Returns the `PTRACE_O_*` options given on seizing.
+    pub fn options(&self) -> u32 {
+        self.state.lock().as_ref().map_or(0, |it| it.options)
+    }
+}
+
+/// Lists the threads traced by the process `tracer`.
+pub fn tracees(tracer: Pid) -> Vec<AxTaskRef> {
+    tasks()
+        .into_iter()
+        .filter(|task| {
+            task.try_as_thread()
+                .is_some_and(|thr| !thr.pending_exit() && thr.trace.tracer() == Some(tracer))
+        })
+        .collect()
+}
+
+/// Tells the tracer `tracer` that a tracee entered a ptrace stop, as a
+/// parent is told of a stopped child.
+pub fn notify_tracer(tracer: Pid) {
+    let _ = send_signal_to_process(tracer, Some(SignalInfo::new_kernel(Signo::SIGCHLD)));
+    if let Ok(data) = get_process_data(tracer) {
+        data.child_exit_event.wake();
+    }
+}
+
+/// Detaches all threads traced by the process `tracer`, as it exits.
+pub fn detach_all(tracer: Pid) {
+    for task in tracees(tracer) {
+        task.as_thread().trace.detach();
+    }
+}
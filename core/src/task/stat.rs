@@ -2,9 +2,15 @@ use alloc::{borrow::ToOwned, fmt, string::String};
 
 use axerrno::AxResult;
 use axtask::{TaskInner, TaskState};
+use linux_raw_sys::general::RLIMIT_RSS;
+use memory_addr::PAGE_SIZE_4K;
 use starry_signal::Signo;
 
-use crate::task::AsThread;
+use crate::{
+    rusage::{process_usage, sample_rss},
+    task::AsThread,
+    time::clock_ticks,
+};
 
 /// Represents the `/proc/[pid]/stat` file.
 ///
@@ -29,8 +35,8 @@ pub struct TaskStat {
     pub stime: u64,
     pub cutime: u64,
     pub cstime: u64,
-    pub priority: u32,
-    pub nice: u32,
+    pub priority: i32,
+    pub nice: i32,
     pub num_threads: u32,
     pub itrealvalue: u32,
     pub starttime: u64,
@@ -77,6 +83,8 @@ impl TaskStat {
         let comm = task.name();
         let comm = comm[..comm.len().min(16)].to_owned();
         let state = match task.state() {
+            _ if thread.trace.is_stopped() => 't',
+            _ if proc_data.is_stopped() => 'T',
             TaskState::Running | TaskState::Ready => 'R',
             TaskState::Blocked => 'S',
             TaskState::Exited => 'Z',
@@ -84,6 +92,9 @@ impl TaskStat {
         let ppid = proc.parent().map_or(0, |p| p.pid());
         let pgrp = proc.group().pgid();
         let session = proc.group().session().sid();
+        let rss = sample_rss(proc_data);
+        let usage = process_usage(proc_data);
+        let children = *proc_data.children_usage.lock();
         Ok(Self {
             pid,
             comm: comm.to_owned(),
@@ -91,7 +102,19 @@ impl TaskStat {
             ppid,
             pgrp,
             session,
+            minflt: usage.minflt,
+            cminflt: children.minflt,
+            majflt: usage.majflt,
+            cmajflt: children.majflt,
+            utime: clock_ticks(usage.utime),
+            stime: clock_ticks(usage.stime),
+            cutime: clock_ticks(children.utime),
+            cstime: clock_ticks(children.stime),
+            priority: 20 + thread.nice(),
+            nice: thread.nice(),
             num_threads: proc.threads().len() as u32,
+            rss: (rss / PAGE_SIZE_4K) as i64,
+            rsslim: proc_data.rlim.read()[RLIMIT_RSS].current,
             exit_signal: proc_data.exit_signal.unwrap_or(Signo::SIGCHLD) as u8,
             exit_code: proc.exit_code(),
             ..Default::default()
@@ -1,9 +1,16 @@
 //! Time management module.
 
 use alloc::{borrow::ToOwned, collections::binary_heap::BinaryHeap, sync::Arc};
-use core::{mem, time::Duration};
+#[cfg(feature = "time-warp")]
+use alloc::{sync::Weak, vec::Vec};
+use core::{
+    mem,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
 
-use axhal::time::{NANOS_PER_SEC, TimeValue, monotonic_time_nanos, wall_time};
+use axerrno::{AxError, AxResult};
+use axhal::time::{NANOS_PER_SEC, TimeValue, monotonic_time, monotonic_time_nanos, wall_time};
 use axtask::{
     WeakAxTaskRef, current,
     future::{block_on, timeout_at},
@@ -14,7 +21,18 @@ use spin::Mutex;
 use starry_signal::Signo;
 use strum::FromRepr;
 
-use crate::task::poll_timer;
+#[cfg(feature = "time-warp")]
+use crate::posix_timer::PosixTimer;
+use crate::{task::poll_timer, timekeeping};
+
+/// The frequency of the clock ticks that user space counts in, as reported
+/// by `sysconf(_SC_CLK_TCK)`.
+pub const USER_HZ: u64 = 100;
+
+/// Converts a time to clock ticks, at [`USER_HZ`].
+pub fn clock_ticks(time: TimeValue) -> u64 {
+    (time.as_nanos() / (NANOS_PER_SEC / USER_HZ) as u128) as u64
+}
 
 fn time_value_from_nanos(nanos: usize) -> TimeValue {
     let secs = nanos as u64 / NANOS_PER_SEC;
@@ -44,10 +62,233 @@ impl Ord for Entry {
 }
 
 lazy_static! {
+    static ref ROOT_TIME_NS: Arc<TimeNamespace> = {
+        let time_ns = TimeNamespace::new();
+        time_ns.enter();
+        Arc::new(time_ns)
+    };
     static ref ALARM_LIST: Mutex<BinaryHeap<Entry>> = Mutex::new(BinaryHeap::new());
     static ref EVENT_NEW_TIMER: Event = Event::new();
 }
 
+/// Speed control of the clocks in a time namespace, for making timing
+/// dependent tests reproducible.
+#[cfg(feature = "time-warp")]
+struct TimeWarp {
+    /// Host monotonic time at which the current rate took effect.
+    host_base: Duration,
+    /// Namespace monotonic time at `host_base`.
+    base: Duration,
+    /// Rate at which the namespace clocks advance, in percent of the host's.
+    rate: u32,
+}
+
+#[cfg(feature = "time-warp")]
+impl TimeWarp {
+    fn at(&self, host: Duration) -> Duration {
+        self.base + host.saturating_sub(self.host_base) * self.rate / 100
+    }
+}
+
+/// Offsets of the clocks in a time namespace from the host's, in nanoseconds.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TimeNsOffsets {
+    /// Offset of `CLOCK_MONOTONIC`.
+    pub monotonic: i64,
+    /// Offset of `CLOCK_BOOTTIME`.
+    pub boottime: i64,
+}
+
+fn apply_offset(time: TimeValue, offset: i64) -> TimeValue {
+    if offset >= 0 {
+        time.saturating_add(Duration::from_nanos(offset as u64))
+    } else {
+        time.saturating_sub(Duration::from_nanos(offset.unsigned_abs()))
+    }
+}
+
+/// A time namespace, which virtualizes the clocks seen by its processes.
+pub struct TimeNamespace {
+    offsets: Mutex<TimeNsOffsets>,
+    /// Whether any process has entered the namespace, after which its offsets
+    /// can no longer be changed.
+    entered: AtomicBool,
+    #[cfg(feature = "time-warp")]
+    warp: Mutex<TimeWarp>,
+    /// Notified when the warp changes, for sleepers to recompute their
+    /// deadlines.
+    #[cfg(feature = "time-warp")]
+    warp_changed: Event,
+    /// The POSIX timers on the clocks of the namespace, to re-arm when the
+    /// warp changes.
+    #[cfg(feature = "time-warp")]
+    timers: Mutex<Vec<Weak<PosixTimer>>>,
+}
+
+impl TimeNamespace {
+    /// Creates a time namespace whose clocks match the host's.
+    pub fn new() -> Self {
+        Self {
+            offsets: Mutex::new(TimeNsOffsets::default()),
+            entered: AtomicBool::new(false),
+            #[cfg(feature = "time-warp")]
+            warp: Mutex::new(TimeWarp {
+                host_base: Duration::ZERO,
+                base: Duration::ZERO,
+                rate: 100,
+            }),
+            #[cfg(feature = "time-warp")]
+            warp_changed: Event::new(),
+            #[cfg(feature = "time-warp")]
+            timers: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Returns the initial time namespace.
+    pub fn root() -> &'static Arc<TimeNamespace> {
+        &ROOT_TIME_NS
+    }
+
+    /// Marks that a process has entered the namespace.
+    pub fn enter(&self) {
+        self.entered.store(true, Ordering::Release);
+    }
+
+    /// Returns the clock offsets of the namespace.
+    pub fn offsets(&self) -> TimeNsOffsets {
+        *self.offsets.lock()
+    }
+
+    /// Sets the clock offsets of the namespace.
+    ///
+    /// This is only allowed before any process has entered the namespace, and
+    /// the offsets must neither overflow nor make any clock negative.
+    pub fn set_offsets(&self, offsets: TimeNsOffsets) -> AxResult {
+        if self.entered.load(Ordering::Acquire) {
+            return Err(AxError::PermissionDenied);
+        }
+        let now = self.virtualize(monotonic_time()).as_nanos() as i64;
+        let valid = |offset: i64| now.checked_add(offset).is_some_and(|it| it >= 0);
+        if !valid(offsets.monotonic) || !valid(offsets.boottime) {
+            return Err(AxError::InvalidInput);
+        }
+        *self.offsets.lock() = offsets;
+        Ok(())
+    }
+
+    /// Returns the value of `CLOCK_MONOTONIC` in this namespace.
+    pub fn monotonic_time(&self) -> TimeValue {
+        apply_offset(self.virtualize(monotonic_time()), self.offsets.lock().monotonic)
+    }
+
+    /// Returns the value of `CLOCK_BOOTTIME` in this namespace.
+    pub fn boottime(&self) -> TimeValue {
+        apply_offset(self.virtualize(monotonic_time()), self.offsets.lock().boottime)
+    }
+
+    /// Returns the value of `CLOCK_REALTIME` in this namespace, which is the
+    /// host's: neither offsets nor the warp apply to it.
+    pub fn wall_time(&self) -> TimeValue {
+        timekeeping::realtime_at(monotonic_time())
+    }
+
+    /// Returns the [`wall_time`] at which the monotonic clock of this
+    /// namespace will reach `deadline`, at its current rate, or `None` if
+    /// the clock is frozen.
+    pub fn wall_deadline(&self, deadline: TimeValue) -> Option<TimeValue> {
+        let left = deadline.saturating_sub(self.monotonic_time());
+        #[cfg(feature = "time-warp")]
+        let left = match self.warp.lock().rate {
+            0 => return None,
+            rate => left.checked_mul(100).map_or(Duration::MAX, |it| it / rate),
+        };
+        Some(wall_time().saturating_add(left))
+    }
+
+    /// Sleeps until the monotonic clock of this namespace reaches
+    /// `deadline`, following changes of the warp meanwhile.
+    pub async fn sleep_until(&self, deadline: TimeValue) {
+        loop {
+            #[cfg(feature = "time-warp")]
+            listener!(self.warp_changed => listener);
+            if self.monotonic_time() >= deadline {
+                return;
+            }
+            #[cfg(feature = "time-warp")]
+            let _ = timeout_at(self.wall_deadline(deadline), listener).await;
+            #[cfg(not(feature = "time-warp"))]
+            let _ = timeout_at(self.wall_deadline(deadline), core::future::pending::<()>()).await;
+        }
+    }
+
+    /// Converts a host monotonic time to the monotonic time of this namespace.
+    fn virtualize(&self, host: TimeValue) -> TimeValue {
+        #[cfg(feature = "time-warp")]
+        return self.warp.lock().at(host);
+        #[cfg(not(feature = "time-warp"))]
+        host
+    }
+
+    /// Returns the rate at which the clocks of this namespace advance, in
+    /// percent of the host's.
+    #[cfg(feature = "time-warp")]
+    pub fn warp_rate(&self) -> u32 {
+        self.warp.lock().rate
+    }
+
+    /// Changes the rate at which the clocks of this namespace advance, in
+    /// percent of the host's. A rate of 0 freezes them.
+    #[cfg(feature = "time-warp")]
+    pub fn set_warp_rate(&self, rate: u32) {
+        let host = monotonic_time();
+        let mut warp = self.warp.lock();
+        warp.base = warp.at(host);
+        warp.host_base = host;
+        warp.rate = rate;
+        drop(warp);
+        self.on_warp_change();
+    }
+
+    /// Moves the clocks of this namespace forward by `delta`.
+    #[cfg(feature = "time-warp")]
+    pub fn warp_advance(&self, delta: Duration) {
+        let mut warp = self.warp.lock();
+        warp.base += delta;
+        drop(warp);
+        self.on_warp_change();
+    }
+
+    /// Registers a POSIX timer on the clocks of this namespace, to be
+    /// re-armed when the warp changes.
+    #[cfg(feature = "time-warp")]
+    pub(crate) fn add_timer(&self, timer: &Arc<PosixTimer>) {
+        let mut timers = self.timers.lock();
+        timers.retain(|it| it.strong_count() > 0);
+        timers.push(Arc::downgrade(timer));
+    }
+
+    /// Moves the deadlines of sleepers and timers to match a new warp.
+    #[cfg(feature = "time-warp")]
+    fn on_warp_change(&self) {
+        self.warp_changed.notify(usize::MAX);
+        let timers = self
+            .timers
+            .lock()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .collect::<Vec<_>>();
+        for timer in timers {
+            timer.rearm();
+        }
+    }
+}
+
+impl Default for TimeNamespace {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// The type of interval timer.
 #[repr(i32)]
 #[allow(non_camel_case_types)]
@@ -130,11 +371,39 @@ pub enum TimerState {
     Kernel,
 }
 
-// TODO(mivik): preempting does not change the timer state currently
+/// The CPU time of a thread, as last accrued by its [`TimeManager`].
+///
+/// The time manager may only be borrowed by its own thread, so other threads
+/// read this instead. While the thread runs, it lags behind by at most a
+/// timer tick.
+#[derive(Default)]
+pub struct CpuTime {
+    utime_ns: AtomicU64,
+    stime_ns: AtomicU64,
+}
+
+impl CpuTime {
+    /// Returns the user time and system time.
+    pub fn load(&self) -> (TimeValue, TimeValue) {
+        (
+            time_value_from_nanos(self.utime_ns.load(Ordering::Relaxed) as usize),
+            time_value_from_nanos(self.stime_ns.load(Ordering::Relaxed) as usize),
+        )
+    }
+}
+
 /// A manager for time-related operations.
+///
+/// CPU time is only accrued while the thread is on a CPU, between
+/// [`switch_in`](Self::switch_in) and [`switch_out`](Self::switch_out).
 pub struct TimeManager {
     utime_ns: usize,
     stime_ns: usize,
+    /// When CPU time was last accrued, if the thread is on a CPU.
+    cpu_since_ns: Option<usize>,
+    /// The user and system time when the interval timers were last updated.
+    polled_utime_ns: usize,
+    polled_stime_ns: usize,
     last_wall_ns: usize,
     state: TimerState,
     itimers: [ITimer; 3],
@@ -151,37 +420,78 @@ impl TimeManager {
         Self {
             utime_ns: 0,
             stime_ns: 0,
+            cpu_since_ns: None,
+            polled_utime_ns: 0,
+            polled_stime_ns: 0,
             last_wall_ns: 0,
             state: TimerState::None,
             itimers: Default::default(),
         }
     }
 
+    /// Returns the CPU time spent on the CPU since it was last accrued.
+    fn unaccrued(&self, now_ns: usize) -> (usize, usize) {
+        let delta = self
+            .cpu_since_ns
+            .map_or(0, |since| now_ns.saturating_sub(since));
+        match self.state {
+            TimerState::User => (delta, 0),
+            TimerState::Kernel => (0, delta),
+            TimerState::None => (0, 0),
+        }
+    }
+
+    /// Adds the CPU time spent since it was last accrued.
+    fn accrue(&mut self, now_ns: usize) {
+        let (user, system) = self.unaccrued(now_ns);
+        self.utime_ns += user;
+        self.stime_ns += system;
+        if self.cpu_since_ns.is_some() {
+            self.cpu_since_ns = Some(now_ns);
+        }
+    }
+
     /// Returns the current user time and system time as a tuple of `TimeValue`.
     pub fn output(&self) -> (TimeValue, TimeValue) {
-        let utime = time_value_from_nanos(self.utime_ns);
-        let stime = time_value_from_nanos(self.stime_ns);
+        let (user, system) = self.unaccrued(monotonic_time_nanos() as usize);
+        let utime = time_value_from_nanos(self.utime_ns + user);
+        let stime = time_value_from_nanos(self.stime_ns + system);
         (utime, stime)
     }
 
+    /// Publishes the CPU time accrued so far to `cpu_time`.
+    pub fn publish(&self, cpu_time: &CpuTime) {
+        cpu_time
+            .utime_ns
+            .store(self.utime_ns as u64, Ordering::Relaxed);
+        cpu_time
+            .stime_ns
+            .store(self.stime_ns as u64, Ordering::Relaxed);
+    }
+
+    /// Starts accruing CPU time, as the thread is switched in.
+    pub fn switch_in(&mut self) {
+        self.cpu_since_ns = Some(monotonic_time_nanos() as usize);
+    }
+
+    /// Stops accruing CPU time, as the thread is switched out.
+    pub fn switch_out(&mut self) {
+        self.accrue(monotonic_time_nanos() as usize);
+        self.cpu_since_ns = None;
+    }
+
     /// Polls the time manager to update the timers and emit signals if
     /// necessary.
     pub fn poll(&mut self, emitter: impl Fn(Signo)) {
         let now_ns = monotonic_time_nanos() as usize;
-        let delta = now_ns - self.last_wall_ns;
-        match self.state {
-            TimerState::User => {
-                self.utime_ns += delta;
-                self.update_itimer(ITimerType::Virtual, delta, &emitter);
-                self.update_itimer(ITimerType::Prof, delta, &emitter);
-            }
-            TimerState::Kernel => {
-                self.stime_ns += delta;
-                self.update_itimer(ITimerType::Prof, delta, &emitter);
-            }
-            TimerState::None => {}
-        }
-        self.update_itimer(ITimerType::Real, delta, &emitter);
+        self.accrue(now_ns);
+        let user = self.utime_ns - self.polled_utime_ns;
+        let system = self.stime_ns - self.polled_stime_ns;
+        self.polled_utime_ns = self.utime_ns;
+        self.polled_stime_ns = self.stime_ns;
+        self.update_itimer(ITimerType::Virtual, user, &emitter);
+        self.update_itimer(ITimerType::Prof, user + system, &emitter);
+        self.update_itimer(ITimerType::Real, now_ns - self.last_wall_ns, &emitter);
         self.last_wall_ns = now_ns;
     }
 
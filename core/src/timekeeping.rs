@@ -0,0 +1,368 @@
+//! Discipline of `CLOCK_REALTIME`: stepping, slewing, frequency correction
+//! and leap seconds, as controlled by `clock_settime` and `adjtimex`.
+//!
+//! The realtime clock is derived from the host monotonic clock piecewise
+//! linearly. Whenever its parameters change, the current segment is closed at
+//! the current time and a new one starts there, so adjustments never make the
+//! clock jump unless asked to.
+
+use core::time::Duration;
+
+use axerrno::{AxError, AxResult};
+use axhal::time::{TimeValue, monotonic_time};
+use axsync::spin::SpinNoIrq;
+
+use crate::hrtimer;
+
+const NSEC_PER_SEC: i128 = 1_000_000_000;
+const NSEC_PER_USEC: i64 = 1_000;
+const SECS_PER_DAY: i128 = 86400;
+
+/// The largest frequency correction, 500 ppm, in ppm with a 16-bit fraction.
+pub const MAXFREQ_SCALED: i64 = 500 << 16;
+/// The rate at which offsets are slewed away, in ppm.
+const SLEW_PPM: i128 = 500;
+/// The largest offset `ADJ_OFFSET` accepts in PLL mode, in nanoseconds.
+const MAXPHASE: i64 = 500_000_000;
+/// The nominal length of a tick in microseconds, at `USER_HZ`.
+const TICK_USEC: i64 = 10_000;
+/// The initial maximum and estimated errors, in microseconds.
+const NTP_PHASE_LIMIT: i64 = 16_000_000;
+
+/// Slew the clock by `offset`.
+pub const ADJ_OFFSET: u32 = 0x0001;
+/// Set the frequency correction.
+pub const ADJ_FREQUENCY: u32 = 0x0002;
+/// Set the maximum error.
+pub const ADJ_MAXERROR: u32 = 0x0004;
+/// Set the estimated error.
+pub const ADJ_ESTERROR: u32 = 0x0008;
+/// Set the status bits.
+pub const ADJ_STATUS: u32 = 0x0010;
+/// Set the PLL time constant.
+pub const ADJ_TIMECONST: u32 = 0x0020;
+/// Set the TAI offset, passed in `constant`.
+pub const ADJ_TAI: u32 = 0x0080;
+/// Step the clock by the time in `time_sec` and `time_usec`.
+pub const ADJ_SETOFFSET: u32 = 0x0100;
+/// Use microseconds for offsets.
+pub const ADJ_MICRO: u32 = 0x1000;
+/// Use nanoseconds for offsets.
+pub const ADJ_NANO: u32 = 0x2000;
+/// Set the tick length.
+pub const ADJ_TICK: u32 = 0x4000;
+/// Slew by `offset` microseconds, as `adjtime` does.
+pub const ADJ_OFFSET_SINGLESHOT: u32 = 0x8001;
+/// Read the offset left from `ADJ_OFFSET_SINGLESHOT`.
+pub const ADJ_OFFSET_SS_READ: u32 = 0xa001;
+
+/// Phase-locked loop updates are enabled.
+pub const STA_PLL: i32 = 0x0001;
+/// Insert a leap second at the end of the day.
+pub const STA_INS: i32 = 0x0010;
+/// Delete a leap second at the end of the day.
+pub const STA_DEL: i32 = 0x0020;
+/// The clock is not synchronized.
+pub const STA_UNSYNC: i32 = 0x0040;
+/// Offsets are in nanoseconds.
+pub const STA_NANO: i32 = 0x2000;
+/// The status bits only the kernel sets.
+pub const STA_RONLY: i32 = 0x0100 | 0x0200 | 0x0400 | 0x0800 | 0x1000 | STA_NANO | 0x4000 | 0x8000;
+
+/// The clock is synchronized, with no leap second pending.
+pub const TIME_OK: i32 = 0;
+/// A leap second will be inserted.
+pub const TIME_INS: i32 = 1;
+/// A leap second will be deleted.
+pub const TIME_DEL: i32 = 2;
+/// A leap second has just happened.
+pub const TIME_WAIT: i32 = 4;
+/// The clock is not synchronized.
+pub const TIME_ERROR: i32 = 5;
+
+/// `struct timex`, as passed to `adjtimex` and `clock_adjtime`.
+#[allow(missing_docs)]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::AnyBitPattern)]
+pub struct Timex {
+    pub modes: u32,
+    _pad0: i32,
+    pub offset: i64,
+    pub freq: i64,
+    pub maxerror: i64,
+    pub esterror: i64,
+    pub status: i32,
+    _pad1: i32,
+    pub constant: i64,
+    pub precision: i64,
+    pub tolerance: i64,
+    pub time_sec: i64,
+    pub time_usec: i64,
+    pub tick: i64,
+    pub ppsfreq: i64,
+    pub jitter: i64,
+    pub shift: i32,
+    _pad2: i32,
+    pub stabil: i64,
+    pub jitcnt: i64,
+    pub calcnt: i64,
+    pub errcnt: i64,
+    pub stbcnt: i64,
+    pub tai: i32,
+    _reserved: [i32; 11],
+}
+
+struct Clock {
+    /// Host monotonic time at the start of the current segment.
+    host_base: i128,
+    /// Realtime at `host_base`.
+    base: i128,
+    /// Frequency correction, in ppm with a 16-bit fraction.
+    freq: i64,
+    /// Tick length, in microseconds.
+    tick: i64,
+    /// The part of the offset still to be slewed away at `host_base`.
+    offset_left: i64,
+    status: i32,
+    state: i32,
+    tai: i32,
+    maxerror: i64,
+    esterror: i64,
+    constant: i64,
+    /// Bumped whenever the pending leap second changes, so that a timer
+    /// that fires as it is cancelled does nothing.
+    leap_generation: u64,
+    /// The timer for the pending leap second, cancelled whenever it changes.
+    leap_timer: Option<hrtimer::TimerHandle>,
+}
+
+impl Clock {
+    /// The rate correction, in parts per billion.
+    fn ppb(&self) -> i128 {
+        (self.freq as i128 * 1000 >> 16) + (self.tick - TICK_USEC) as i128 * 100_000
+    }
+
+    /// Returns the realtime at `host`, and how much of the offset has been
+    /// slewed away by then.
+    fn at(&self, host: i128) -> (i128, i64) {
+        let elapsed = (host - self.host_base).max(0);
+        let max_slew = elapsed * SLEW_PPM / 1_000_000;
+        let slewed = (self.offset_left as i128).clamp(-max_slew, max_slew);
+        let time = self.base + elapsed + elapsed * self.ppb() / NSEC_PER_SEC + slewed;
+        (time, slewed as i64)
+    }
+
+    /// Starts a new segment at `host`.
+    fn rebase(&mut self, host: i128) {
+        let (time, slewed) = self.at(host);
+        self.base = time;
+        self.host_base = host;
+        self.offset_left -= slewed;
+    }
+
+    fn time_state(&self) -> i32 {
+        if self.status & STA_UNSYNC != 0 {
+            TIME_ERROR
+        } else {
+            self.state
+        }
+    }
+}
+
+static CLOCK: SpinNoIrq<Clock> = SpinNoIrq::new(Clock {
+    host_base: 0,
+    base: 0,
+    freq: 0,
+    tick: TICK_USEC,
+    offset_left: 0,
+    status: STA_UNSYNC,
+    state: TIME_OK,
+    tai: 0,
+    maxerror: NTP_PHASE_LIMIT,
+    esterror: NTP_PHASE_LIMIT,
+    constant: 2,
+    leap_generation: 0,
+    leap_timer: None,
+});
+
+fn host_now() -> i128 {
+    monotonic_time().as_nanos() as i128
+}
+
+fn to_time_value(nanos: i128) -> TimeValue {
+    Duration::from_nanos(nanos.clamp(0, u64::MAX as i128) as u64)
+}
+
+/// Starts `CLOCK_REALTIME` at `now`, as read from the hardware clock at
+/// boot. Until then it counts from the epoch.
+pub fn init(now: TimeValue) {
+    set_realtime(now);
+}
+
+/// Returns the realtime at the host monotonic time `host`.
+pub fn realtime_at(host: TimeValue) -> TimeValue {
+    to_time_value(CLOCK.lock().at(host.as_nanos() as i128).0)
+}
+
+/// Returns the current value of `CLOCK_REALTIME`.
+pub fn realtime() -> TimeValue {
+    realtime_at(monotonic_time())
+}
+
+/// Returns the offset of `CLOCK_TAI` from `CLOCK_REALTIME`, in seconds.
+pub fn tai_offset() -> i32 {
+    CLOCK.lock().tai
+}
+
+/// Sets `CLOCK_REALTIME`, dropping any offset still being slewed.
+pub fn set_realtime(time: TimeValue) {
+    let mut clock = CLOCK.lock();
+    let host = host_now();
+    clock.rebase(host);
+    clock.base = time.as_nanos() as i128;
+    clock.offset_left = 0;
+    arm_leap(&mut clock);
+}
+
+/// Steps `CLOCK_REALTIME` by `delta` nanoseconds.
+fn step(clock: &mut Clock, delta: i128) {
+    clock.rebase(host_now());
+    clock.base += delta;
+}
+
+/// Schedules the leap second pending in the status, if any, for the next
+/// midnight UTC.
+fn arm_leap(clock: &mut Clock) {
+    clock.leap_generation += 1;
+    if let Some(timer) = clock.leap_timer.take() {
+        timer.cancel();
+    }
+    let insert = match clock.state {
+        TIME_INS => true,
+        TIME_DEL => false,
+        _ => return,
+    };
+    let (now, _) = clock.at(host_now());
+    let day = SECS_PER_DAY * NSEC_PER_SEC;
+    // A deleted second is skipped by stepping from 23:59:59 straight to
+    // midnight.
+    let mut leap = (now / day + 1) * day;
+    if !insert {
+        leap -= NSEC_PER_SEC;
+    }
+    let generation = clock.leap_generation;
+    let timer = hrtimer::start_after(to_time_value(leap - now), move || {
+        let mut clock = CLOCK.lock();
+        if clock.leap_generation != generation {
+            return;
+        }
+        clock.leap_timer = None;
+        if insert {
+            step(&mut clock, -NSEC_PER_SEC);
+            clock.tai += 1;
+        } else {
+            step(&mut clock, NSEC_PER_SEC);
+            clock.tai -= 1;
+        }
+        clock.state = TIME_WAIT;
+    });
+    clock.leap_timer = Some(timer);
+}
+
+/// Reads and adjusts the clock discipline, as `adjtimex` does. The current
+/// state is written back to `tx`, and the clock state returned.
+pub fn adjtimex(tx: &mut Timex) -> AxResult<i32> {
+    let modes = tx.modes;
+    let mut clock = CLOCK.lock();
+    clock.rebase(host_now());
+
+    if modes == ADJ_OFFSET_SINGLESHOT || modes == ADJ_OFFSET_SS_READ {
+        // The old `adjtime` interface: slew by an offset in microseconds and
+        // report what was left of the previous one.
+        let left = clock.offset_left / NSEC_PER_USEC;
+        if modes == ADJ_OFFSET_SINGLESHOT {
+            clock.offset_left = tx.offset.saturating_mul(NSEC_PER_USEC);
+        }
+        tx.offset = left;
+        return Ok(clock.time_state());
+    }
+
+    if modes & ADJ_TICK != 0 && !(TICK_USEC * 9 / 10..=TICK_USEC * 11 / 10).contains(&tx.tick) {
+        return Err(AxError::InvalidInput);
+    }
+    if modes & ADJ_SETOFFSET != 0 {
+        let frac = if modes & ADJ_NANO != 0 {
+            tx.time_usec
+        } else {
+            tx.time_usec.saturating_mul(NSEC_PER_USEC)
+        };
+        if !(0..NSEC_PER_SEC as i64).contains(&frac) {
+            return Err(AxError::InvalidInput);
+        }
+        step(
+            &mut clock,
+            tx.time_sec as i128 * NSEC_PER_SEC + frac as i128,
+        );
+    }
+
+    if modes & ADJ_NANO != 0 {
+        clock.status |= STA_NANO;
+    }
+    if modes & ADJ_MICRO != 0 {
+        clock.status &= !STA_NANO;
+    }
+    let nano = clock.status & STA_NANO != 0;
+    let unit = if nano { 1 } else { NSEC_PER_USEC };
+
+    if modes & ADJ_STATUS != 0 {
+        clock.status = (clock.status & STA_RONLY) | (tx.status & !STA_RONLY);
+        clock.state = match clock.state {
+            TIME_OK | TIME_WAIT if clock.status & STA_INS != 0 => TIME_INS,
+            TIME_OK | TIME_WAIT if clock.status & STA_DEL != 0 => TIME_DEL,
+            TIME_INS if clock.status & STA_INS == 0 => TIME_OK,
+            TIME_DEL if clock.status & STA_DEL == 0 => TIME_OK,
+            TIME_WAIT => TIME_OK,
+            state => state,
+        };
+        arm_leap(&mut clock);
+    }
+    if modes & ADJ_FREQUENCY != 0 {
+        clock.freq = tx.freq.clamp(-MAXFREQ_SCALED, MAXFREQ_SCALED);
+    }
+    if modes & ADJ_MAXERROR != 0 {
+        clock.maxerror = tx.maxerror.clamp(0, NTP_PHASE_LIMIT);
+    }
+    if modes & ADJ_ESTERROR != 0 {
+        clock.esterror = tx.esterror.clamp(0, NTP_PHASE_LIMIT);
+    }
+    if modes & ADJ_TIMECONST != 0 {
+        clock.constant = tx.constant.clamp(0, 10);
+    }
+    if modes & ADJ_TAI != 0 && tx.constant >= 0 {
+        clock.tai = tx.constant as i32;
+    }
+    if modes & ADJ_OFFSET != 0 {
+        // Rather than feeding a PLL, the offset is slewed away at a fixed
+        // rate, which is what the daemons rely on: the clock is corrected
+        // without ever going backwards.
+        clock.offset_left = tx.offset.saturating_mul(unit).clamp(-MAXPHASE, MAXPHASE);
+    }
+    if modes & ADJ_TICK != 0 {
+        clock.tick = tx.tick;
+    }
+
+    let (now, _) = clock.at(host_now());
+    tx.offset = clock.offset_left / unit;
+    tx.freq = clock.freq;
+    tx.maxerror = clock.maxerror;
+    tx.esterror = clock.esterror;
+    tx.status = clock.status;
+    tx.constant = clock.constant;
+    tx.precision = 1;
+    tx.tolerance = MAXFREQ_SCALED;
+    tx.time_sec = (now / NSEC_PER_SEC) as i64;
+    tx.time_usec = (now % NSEC_PER_SEC) as i64 / unit;
+    tx.tick = clock.tick;
+    tx.tai = clock.tai;
+    Ok(clock.time_state())
+}
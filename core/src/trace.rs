@@ -0,0 +1,385 @@
+//! Static tracepoints, recorded into per-CPU ring buffers.
+//!
+//! Instrumented code calls [`trace`] with a [`TracePoint`] and up to three
+//! raw arguments. Nothing is recorded unless tracing is on and the point is
+//! enabled, and both are checked with a single atomic load each, so disabled
+//! points cost next to nothing. Records are fixed-size and written without
+//! allocating, so points may sit in interrupt handlers and in the scheduler.
+//!
+//! Each CPU has a buffer of its own. When it is full, the oldest records are
+//! overwritten.
+
+use alloc::{string::String, vec::Vec};
+use core::{
+    fmt::{self, Write},
+    sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+    task::Waker,
+};
+
+use axconfig::plat::CPU_NUM;
+use axerrno::{AxError, AxResult};
+use axhal::{percpu::this_cpu_id, time::monotonic_time_nanos};
+use axpoll::PollSet;
+use kspin::SpinNoIrq;
+use lazy_static::lazy_static;
+
+/// A place in the kernel that can emit trace records.
+#[repr(u16)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TracePoint {
+    /// A user thread was switched in. Arguments: the previous user thread on
+    /// the CPU, and the new one.
+    #[default]
+    SchedSwitch,
+    /// A syscall was entered. Arguments: the syscall number, and its first
+    /// two arguments.
+    SysEnter,
+    /// A syscall returned. Arguments: the syscall number, and the return
+    /// value.
+    SysExit,
+    /// User space faulted on a page. Arguments: the address, the instruction
+    /// pointer, and the access flags.
+    PageFaultUser,
+    /// The kernel faulted on a page while accessing user memory. Arguments:
+    /// the address, and the access flags.
+    PageFaultKernel,
+    /// A timer interrupt was taken.
+    IrqEntry,
+}
+
+impl TracePoint {
+    /// All tracepoints.
+    pub const ALL: [TracePoint; 6] = [
+        TracePoint::SchedSwitch,
+        TracePoint::SysEnter,
+        TracePoint::SysExit,
+        TracePoint::PageFaultUser,
+        TracePoint::PageFaultKernel,
+        TracePoint::IrqEntry,
+    ];
+
+    /// Returns the name of the tracepoint.
+    pub fn name(self) -> &'static str {
+        match self {
+            TracePoint::SchedSwitch => "sched_switch",
+            TracePoint::SysEnter => "sys_enter",
+            TracePoint::SysExit => "sys_exit",
+            TracePoint::PageFaultUser => "page_fault_user",
+            TracePoint::PageFaultKernel => "page_fault_kernel",
+            TracePoint::IrqEntry => "irq_handler_entry",
+        }
+    }
+
+    /// Returns the subsystem the tracepoint belongs to.
+    pub fn subsystem(self) -> &'static str {
+        match self {
+            TracePoint::SchedSwitch => "sched",
+            TracePoint::SysEnter | TracePoint::SysExit => "raw_syscalls",
+            TracePoint::PageFaultUser | TracePoint::PageFaultKernel => "exceptions",
+            TracePoint::IrqEntry => "irq",
+        }
+    }
+
+    /// Finds a tracepoint by its name, optionally prefixed with its
+    /// subsystem as in `sched:sched_switch`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let (subsystem, name) = match name.split_once(':') {
+            Some((subsystem, name)) => (Some(subsystem), name),
+            None => (None, name),
+        };
+        Self::ALL
+            .into_iter()
+            .find(|it| it.name() == name && subsystem.is_none_or(|s| s == it.subsystem()))
+    }
+
+    fn bit(self) -> u32 {
+        1 << self as u16
+    }
+}
+
+/// A trace record.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TraceRecord {
+    /// The monotonic time of the record, in nanoseconds.
+    pub timestamp: u64,
+    /// The thread that was running, or 0 for kernel tasks.
+    pub tid: u32,
+    /// The CPU the record was taken on.
+    pub cpu: u16,
+    /// The tracepoint that emitted the record.
+    pub point: TracePoint,
+    /// Raw arguments, whose meaning depends on the tracepoint.
+    pub args: [u64; 3],
+}
+
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a0, a1, a2] = self.args;
+        write!(
+            f,
+            "{:>16} [{:03}] {:>6}.{:06}: {}: ",
+            self.tid,
+            self.cpu,
+            self.timestamp / 1_000_000_000,
+            self.timestamp % 1_000_000_000 / 1000,
+            self.point.name()
+        )?;
+        match self.point {
+            TracePoint::SchedSwitch => write!(f, "prev_pid={a0} next_pid={a1}"),
+            TracePoint::SysEnter => write!(f, "NR {a0} ({a1:x}, {a2:x})"),
+            TracePoint::SysExit => write!(f, "NR {a0} = {}", a1 as i64),
+            TracePoint::PageFaultUser => {
+                write!(f, "address={a0:#x} ip={a1:#x} flags={a2:#x}")
+            }
+            TracePoint::PageFaultKernel => write!(f, "address={a0:#x} flags={a1:#x}"),
+            TracePoint::IrqEntry => write!(f, "name=timer"),
+        }
+    }
+}
+
+/// A fixed-size ring of records.
+struct RingBuffer {
+    records: Vec<TraceRecord>,
+    /// The index of the oldest record.
+    head: usize,
+    len: usize,
+    /// The number of records overwritten before being read.
+    overrun: u64,
+}
+
+impl RingBuffer {
+    const EMPTY: Self = Self {
+        records: Vec::new(),
+        head: 0,
+        len: 0,
+        overrun: 0,
+    };
+
+    fn push(&mut self, record: TraceRecord) {
+        let capacity = self.records.len();
+        if capacity == 0 {
+            return;
+        }
+        if self.len == capacity {
+            self.records[self.head] = record;
+            self.head = (self.head + 1) % capacity;
+            self.overrun += 1;
+        } else {
+            self.records[(self.head + self.len) % capacity] = record;
+            self.len += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<&TraceRecord> {
+        (self.len > 0).then(|| &self.records[self.head])
+    }
+
+    fn pop(&mut self) -> Option<TraceRecord> {
+        let record = *self.peek()?;
+        self.head = (self.head + 1) % self.records.len();
+        self.len -= 1;
+        Some(record)
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &TraceRecord> {
+        (0..self.len).map(|i| &self.records[(self.head + i) % self.records.len()])
+    }
+}
+
+static TRACING_ON: AtomicBool = AtomicBool::new(true);
+/// The enabled tracepoints, as a bitmap of [`TracePoint`]s.
+static ENABLED: AtomicU32 = AtomicU32::new(0);
+/// The largest size of each per-CPU buffer, in KiB.
+pub const MAX_BUFFER_SIZE_KB: usize = 16 * 1024;
+
+/// The size of each per-CPU buffer, in KiB.
+static BUFFER_SIZE_KB: AtomicUsize = AtomicUsize::new(64);
+
+static BUFFERS: [SpinNoIrq<RingBuffer>; CPU_NUM] =
+    [const { SpinNoIrq::new(RingBuffer::EMPTY) }; CPU_NUM];
+
+/// The user thread that was last switched out on each CPU.
+static LAST_TID: [AtomicU32; CPU_NUM] = [const { AtomicU32::new(0) }; CPU_NUM];
+
+lazy_static! {
+    /// Readers waiting for records.
+    static ref READERS: PollSet = PollSet::new();
+}
+
+/// Records an event at `point`, if it is enabled.
+#[inline]
+pub fn trace(point: TracePoint, args: [u64; 3]) {
+    if ENABLED.load(Ordering::Relaxed) & point.bit() != 0 && TRACING_ON.load(Ordering::Relaxed) {
+        record(point, args);
+    }
+}
+
+#[cold]
+fn record(point: TracePoint, args: [u64; 3]) {
+    let cpu = this_cpu_id();
+    let tid = axtask::current_may_uninit().map_or(0, |curr| curr.id().as_u64() as u32);
+    BUFFERS[cpu].lock().push(TraceRecord {
+        timestamp: monotonic_time_nanos(),
+        tid,
+        cpu: cpu as u16,
+        point,
+        args,
+    });
+}
+
+/// Records that the user thread `tid` is leaving the CPU.
+#[inline]
+pub fn trace_switch_out(tid: u32) {
+    LAST_TID[this_cpu_id()].store(tid, Ordering::Relaxed);
+}
+
+/// Records that the user thread `tid` is entering the CPU.
+#[inline]
+pub fn trace_switch_in(tid: u32) {
+    let prev = LAST_TID[this_cpu_id()].load(Ordering::Relaxed);
+    trace(TracePoint::SchedSwitch, [prev as u64, tid as u64, 0]);
+}
+
+/// Allocates the buffers if that hasn't been done yet.
+fn ensure_buffers() {
+    if BUFFERS[0].lock().records.is_empty()
+        && let Err(err) = resize_buffers(BUFFER_SIZE_KB.load(Ordering::Relaxed))
+    {
+        warn!("Failed to allocate trace buffers: {err:?}");
+    }
+}
+
+/// Replaces every per-CPU buffer with an empty one of `size_kb` KiB. The old
+/// buffers are kept if any of the new ones can't be allocated.
+fn resize_buffers(size_kb: usize) -> AxResult<()> {
+    let capacity =
+        size_kb.checked_mul(1024).ok_or(AxError::InvalidInput)? / size_of::<TraceRecord>();
+    let mut buffers = Vec::new();
+    buffers
+        .try_reserve_exact(CPU_NUM)
+        .map_err(|_| AxError::NoMemory)?;
+    for _ in 0..CPU_NUM {
+        let mut records = Vec::new();
+        records
+            .try_reserve_exact(capacity)
+            .map_err(|_| AxError::NoMemory)?;
+        records.resize(capacity, TraceRecord::default());
+        buffers.push(RingBuffer {
+            records,
+            ..RingBuffer::EMPTY
+        });
+    }
+    for (buffer, mut new) in BUFFERS.iter().zip(buffers) {
+        // Swap outside of the lock, so the old buffer isn't freed with
+        // interrupts disabled.
+        core::mem::swap(&mut *buffer.lock(), &mut new);
+    }
+    Ok(())
+}
+
+/// Returns whether recording is on.
+pub fn tracing_on() -> bool {
+    TRACING_ON.load(Ordering::Relaxed)
+}
+
+/// Turns recording on or off, keeping the enabled tracepoints as they are.
+pub fn set_tracing_on(on: bool) {
+    if on {
+        ensure_buffers();
+    }
+    TRACING_ON.store(on, Ordering::Relaxed);
+}
+
+/// Returns whether `point` is enabled.
+pub fn is_enabled(point: TracePoint) -> bool {
+    ENABLED.load(Ordering::Relaxed) & point.bit() != 0
+}
+
+/// Enables or disables `point`.
+pub fn set_enabled(point: TracePoint, enabled: bool) {
+    if enabled {
+        ensure_buffers();
+        ENABLED.fetch_or(point.bit(), Ordering::Relaxed);
+    } else {
+        ENABLED.fetch_and(!point.bit(), Ordering::Relaxed);
+    }
+}
+
+/// Returns the size of each per-CPU buffer, in KiB.
+pub fn buffer_size_kb() -> usize {
+    BUFFER_SIZE_KB.load(Ordering::Relaxed)
+}
+
+/// Resizes the per-CPU buffers, discarding their records.
+///
+/// Fails with `EINVAL` if `size_kb` is zero or above [`MAX_BUFFER_SIZE_KB`],
+/// and with `ENOMEM` if the buffers can't be allocated.
+pub fn set_buffer_size_kb(size_kb: usize) -> AxResult<()> {
+    if size_kb == 0 || size_kb > MAX_BUFFER_SIZE_KB {
+        return Err(AxError::InvalidInput);
+    }
+    resize_buffers(size_kb)?;
+    BUFFER_SIZE_KB.store(size_kb, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Discards all records.
+pub fn clear() {
+    for buffer in &BUFFERS {
+        let mut buffer = buffer.lock();
+        buffer.head = 0;
+        buffer.len = 0;
+        buffer.overrun = 0;
+    }
+}
+
+/// Returns the number of records lost by being overwritten.
+pub fn overrun() -> u64 {
+    BUFFERS.iter().map(|it| it.lock().overrun).sum()
+}
+
+/// Returns whether there are records to read.
+pub fn has_records() -> bool {
+    BUFFERS.iter().any(|it| it.lock().len > 0)
+}
+
+/// Formats all records, oldest first, without consuming them.
+pub fn format_records() -> String {
+    let mut records = Vec::new();
+    for buffer in &BUFFERS {
+        records.extend(buffer.lock().iter().copied());
+    }
+    records.sort_by_key(|it| it.timestamp);
+    let mut out = String::new();
+    for record in records {
+        writeln!(out, "{record}").unwrap();
+    }
+    out
+}
+
+/// Removes and returns the oldest record across all CPUs.
+pub fn pop_record() -> Option<TraceRecord> {
+    let cpu = BUFFERS
+        .iter()
+        .enumerate()
+        .filter_map(|(cpu, it)| Some((cpu, it.lock().peek()?.timestamp)))
+        .min_by_key(|(_, timestamp)| *timestamp)?
+        .0;
+    BUFFERS[cpu].lock().pop()
+}
+
+/// Registers a waker to be woken when records may be available.
+pub fn register_reader(waker: &Waker) {
+    READERS.register(waker);
+}
+
+/// Wakes the readers waiting for records, if there are any.
+///
+/// Tracepoints don't wake readers themselves, since they may fire in the
+/// middle of a context switch; this is called from the timer interrupt
+/// instead.
+pub fn wake_readers() {
+    if ENABLED.load(Ordering::Relaxed) != 0 && has_records() {
+        READERS.wake();
+    }
+}
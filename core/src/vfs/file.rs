@@ -89,6 +89,11 @@ impl SimpleFile {
     pub fn new_regular(fs: Arc<SimpleFs>, ops: impl SimpleFileOps) -> Arc<Self> {
         Self::new(fs, NodeType::RegularFile, ops)
     }
+
+    /// Sets the permission bits of the file.
+    pub fn set_permission(&self, mode: NodePermission) {
+        self.node.metadata.lock().mode = mode;
+    }
 }
 
 #[inherit_methods(from = "self.node")]
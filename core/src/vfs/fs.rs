@@ -10,20 +10,21 @@ use slab::Slab;
 
 use super::DirMaker;
 
-/// Returns a dummy filesystem statistics.
+/// Returns the statistics of a pseudo filesystem, which has no storage of
+/// its own and so reports no blocks, as Linux does for procfs and sysfs.
 pub fn dummy_stat_fs(fs_type: u32) -> StatFs {
     StatFs {
         fs_type,
-        block_size: 512,
-        blocks: 100,
-        blocks_free: 100,
-        blocks_available: 100,
+        block_size: 4096,
+        blocks: 0,
+        blocks_free: 0,
+        blocks_available: 0,
 
         file_count: 0,
         free_file_count: 0,
 
         name_length: MAX_NAME_LEN as _,
-        fragment_size: 0,
+        fragment_size: 4096,
         mount_flags: 0,
     }
 }
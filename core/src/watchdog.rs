@@ -0,0 +1,130 @@
+//! A software watchdog, which panics the kernel unless user space keeps
+//! pinging it.
+//!
+//! The watchdog runs on [`hrtimer`](crate::hrtimer), so it catches hung user
+//! space, not a kernel that no longer runs its timers.
+
+use core::time::Duration;
+
+use axerrno::{AxError, AxResult};
+use axhal::time::wall_time;
+use kspin::SpinNoIrq;
+
+use crate::{hrtimer, kmsg};
+
+/// The timeout at boot, in seconds.
+pub const DEFAULT_TIMEOUT: u32 = 60;
+/// The longest timeout that can be set, in seconds.
+pub const MAX_TIMEOUT: u32 = 65535;
+
+struct Watchdog {
+    active: bool,
+    /// The timeout in seconds.
+    timeout: u32,
+    /// When the watchdog fires unless pinged.
+    deadline: Duration,
+    /// The deadline of the earliest timer armed, if any.
+    ///
+    /// Pings only move [`deadline`](Self::deadline); the timer re-arms
+    /// itself when it finds the deadline has moved.
+    timer: Option<Duration>,
+}
+
+impl Watchdog {
+    fn arm(&mut self) {
+        if self.timer.is_some_and(|it| it <= self.deadline) {
+            return;
+        }
+        let at = self.deadline;
+        self.timer = Some(at);
+        hrtimer::start(at, move || fire(at));
+    }
+
+    fn ping(&mut self) {
+        self.deadline = wall_time() + Duration::from_secs(self.timeout as u64);
+        self.arm();
+    }
+}
+
+static WATCHDOG: SpinNoIrq<Watchdog> = SpinNoIrq::new(Watchdog {
+    active: false,
+    timeout: DEFAULT_TIMEOUT,
+    deadline: Duration::ZERO,
+    timer: None,
+});
+
+fn fire(at: Duration) {
+    let mut watchdog = WATCHDOG.lock();
+    // Only the earliest timer is tracked; later ones are superseded.
+    if watchdog.timer != Some(at) {
+        return;
+    }
+    watchdog.timer = None;
+    if !watchdog.active {
+        return;
+    }
+    if wall_time() < watchdog.deadline {
+        watchdog.arm();
+        return;
+    }
+    let timeout = watchdog.timeout;
+    drop(watchdog);
+
+    kmsg::printk(
+        0,
+        format_args!("watchdog: no keepalive for {timeout} seconds, panicking"),
+    );
+    panic!("watchdog: no keepalive for {timeout} seconds");
+}
+
+/// Starts the watchdog, with a full timeout ahead.
+pub fn start() {
+    let mut watchdog = WATCHDOG.lock();
+    watchdog.active = true;
+    watchdog.ping();
+}
+
+/// Stops the watchdog.
+pub fn stop() {
+    WATCHDOG.lock().active = false;
+}
+
+/// Returns whether the watchdog is running.
+pub fn is_active() -> bool {
+    WATCHDOG.lock().active
+}
+
+/// Restarts the countdown of a running watchdog.
+pub fn keepalive() {
+    let mut watchdog = WATCHDOG.lock();
+    if watchdog.active {
+        watchdog.ping();
+    }
+}
+
+/// Returns the timeout in seconds.
+pub fn timeout() -> u32 {
+    WATCHDOG.lock().timeout
+}
+
+/// Sets the timeout in seconds, restarting the countdown if the watchdog is
+/// running.
+pub fn set_timeout(timeout: u32) -> AxResult<()> {
+    if !(1..=MAX_TIMEOUT).contains(&timeout) {
+        return Err(AxError::InvalidInput);
+    }
+    let mut watchdog = WATCHDOG.lock();
+    watchdog.timeout = timeout;
+    if watchdog.active {
+        watchdog.ping();
+    }
+    Ok(())
+}
+
+/// Returns the seconds left before a running watchdog fires.
+pub fn time_left() -> Option<u32> {
+    let watchdog = WATCHDOG.lock();
+    watchdog
+        .active
+        .then(|| watchdog.deadline.saturating_sub(wall_time()).as_secs() as u32)
+}
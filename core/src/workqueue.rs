@@ -0,0 +1,243 @@
+//! Deferred work execution.
+//!
+//! A [`WorkQueue`] runs queued closures on a pool of kernel worker tasks, so
+//! that subsystems don't need to spawn their own tasks for background work.
+//! Workers are spawned lazily up to the queue's concurrency limit, and may be
+//! bound to a single CPU.
+
+use alloc::{
+    boxed::Box,
+    collections::{binary_heap::BinaryHeap, vec_deque::VecDeque},
+    format,
+    string::String,
+    sync::Arc,
+    vec::Vec,
+};
+use core::{cmp::Ordering, time::Duration};
+
+use axhal::time::wall_time;
+use axtask::{
+    AxCpuMask,
+    future::{block_on, timeout_at},
+};
+use event_listener::{Event, listener};
+use lazy_static::lazy_static;
+use spin::{Mutex, MutexGuard};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+struct DelayedJob {
+    deadline: Duration,
+    job: Job,
+}
+impl PartialEq for DelayedJob {
+    fn eq(&self, other: &Self) -> bool {
+        self.deadline == other.deadline
+    }
+}
+impl Eq for DelayedJob {}
+impl PartialOrd for DelayedJob {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for DelayedJob {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.deadline.cmp(&self.deadline)
+    }
+}
+
+#[derive(Default)]
+struct State {
+    ready: VecDeque<Job>,
+    delayed: BinaryHeap<DelayedJob>,
+    /// Number of spawned workers.
+    workers: usize,
+    /// Number of workers waiting for work.
+    idle: usize,
+    /// Number of jobs that are ready or running.
+    pending: usize,
+}
+
+impl State {
+    /// Moves delayed jobs whose deadline has passed to the ready queue.
+    fn promote_expired(&mut self) {
+        let now = wall_time();
+        while self.delayed.peek().is_some_and(|it| it.deadline <= now) {
+            let job = self.delayed.pop().unwrap().job;
+            self.ready.push_back(job);
+            self.pending += 1;
+        }
+    }
+}
+
+/// A queue of work items executed by kernel worker tasks.
+pub struct WorkQueue {
+    name: String,
+    cpu: Option<usize>,
+    max_active: usize,
+    state: Mutex<State>,
+    work_event: Event,
+    flush_event: Event,
+}
+
+impl WorkQueue {
+    /// Creates a work queue whose workers may run on any CPU.
+    ///
+    /// At most `max_active` work items are executed concurrently.
+    pub fn new(name: impl Into<String>, max_active: usize) -> Arc<Self> {
+        Self::new_inner(name.into(), None, max_active)
+    }
+
+    /// Creates a work queue whose workers only run on the given CPU.
+    pub fn new_bound(name: impl Into<String>, cpu: usize, max_active: usize) -> Arc<Self> {
+        Self::new_inner(name.into(), Some(cpu), max_active)
+    }
+
+    fn new_inner(name: String, cpu: Option<usize>, max_active: usize) -> Arc<Self> {
+        Arc::new(Self {
+            name,
+            cpu,
+            max_active: max_active.max(1),
+            state: Mutex::new(State::default()),
+            work_event: Event::new(),
+            flush_event: Event::new(),
+        })
+    }
+
+    /// Queues `work` for execution.
+    pub fn queue_work(self: &Arc<Self>, work: impl FnOnce() + Send + 'static) {
+        let mut state = self.state.lock();
+        state.ready.push_back(Box::new(work));
+        state.pending += 1;
+        self.wake_worker(state);
+    }
+
+    /// Queues `work` for execution after `delay` has elapsed.
+    pub fn queue_delayed_work(
+        self: &Arc<Self>,
+        delay: Duration,
+        work: impl FnOnce() + Send + 'static,
+    ) {
+        let mut state = self.state.lock();
+        state.delayed.push(DelayedJob {
+            deadline: wall_time() + delay,
+            job: Box::new(work),
+        });
+        self.wake_worker(state);
+    }
+
+    /// Waits until all work queued so far, and any work queued meanwhile, has
+    /// finished. Delayed work whose delay has not elapsed yet is not waited
+    /// for.
+    ///
+    /// This must not be called from a work item of the same queue.
+    pub fn flush(&self) {
+        loop {
+            if self.state.lock().pending == 0 {
+                return;
+            }
+            listener!(self.flush_event => listener);
+            if self.state.lock().pending == 0 {
+                return;
+            }
+            block_on(listener);
+        }
+    }
+
+    fn wake_worker(self: &Arc<Self>, mut state: MutexGuard<'_, State>) {
+        if state.idle == 0 && state.workers < self.max_active {
+            state.workers += 1;
+            let id = state.workers;
+            drop(state);
+            self.spawn_worker(id);
+        } else {
+            drop(state);
+            // Also wakes up a worker waiting for delayed work, so that it can
+            // adjust its deadline.
+            self.work_event.notify(1);
+        }
+    }
+
+    fn spawn_worker(self: &Arc<Self>, id: usize) {
+        let name = match self.cpu {
+            Some(cpu) => format!("kworker/{cpu}:{}-{id}", self.name),
+            None => format!("kworker/u:{}-{id}", self.name),
+        };
+        let wq = self.clone();
+        axtask::spawn_raw(
+            move || {
+                if let Some(cpu) = wq.cpu {
+                    let mut mask = AxCpuMask::new();
+                    mask.set(cpu, true);
+                    axtask::set_current_affinity(mask);
+                }
+                block_on(wq.worker())
+            },
+            name,
+            axconfig::TASK_STACK_SIZE,
+        );
+    }
+
+    async fn worker(&self) {
+        loop {
+            let job = {
+                let mut state = self.state.lock();
+                state.promote_expired();
+                state.ready.pop_front()
+            };
+            if let Some(job) = job {
+                job();
+                let mut state = self.state.lock();
+                state.pending -= 1;
+                if state.pending == 0 {
+                    self.flush_event.notify(usize::MAX);
+                }
+                continue;
+            }
+
+            listener!(self.work_event => listener);
+            let deadline = {
+                let mut state = self.state.lock();
+                state.promote_expired();
+                if !state.ready.is_empty() {
+                    continue;
+                }
+                state.idle += 1;
+                state.delayed.peek().map(|it| it.deadline)
+            };
+            let _ = timeout_at(deadline, listener).await;
+            self.state.lock().idle -= 1;
+        }
+    }
+}
+
+/// Maximum number of concurrently running work items on [`system_wq`].
+const SYSTEM_WQ_MAX_ACTIVE: usize = 8;
+
+lazy_static! {
+    static ref SYSTEM_WQ: Arc<WorkQueue> = WorkQueue::new("events", SYSTEM_WQ_MAX_ACTIVE);
+    static ref SYSTEM_BOUND_WQ: Vec<Arc<WorkQueue>> = (0..axconfig::plat::CPU_NUM)
+        .map(|cpu| WorkQueue::new_bound("events", cpu, 1))
+        .collect();
+}
+
+/// Returns the shared, unbound system work queue.
+pub fn system_wq() -> &'static Arc<WorkQueue> {
+    &SYSTEM_WQ
+}
+
+/// Returns the shared system work queue bound to `cpu`.
+pub fn system_bound_wq(cpu: usize) -> &'static Arc<WorkQueue> {
+    &SYSTEM_BOUND_WQ[cpu]
+}
+
+/// Queues `work` on the system work queue.
+pub fn queue_work(work: impl FnOnce() + Send + 'static) {
+    system_wq().queue_work(work);
+}
+
+/// Queues `work` on the system work queue after `delay` has elapsed.
+pub fn queue_delayed_work(delay: Duration, work: impl FnOnce() + Send + 'static) {
+    system_wq().queue_delayed_work(delay, work);
+}
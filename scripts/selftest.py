@@ -0,0 +1,77 @@
+#!/usr/bin/env python3
+"""Boots a kernel built with the `selftest` feature, runs the scripts under
+tests/selftest inside it, and exits with 0 if all of them passed."""
+
+import argparse
+import glob
+import os
+import subprocess
+import sys
+import threading
+
+MARKER = "STARRY-SELFTEST:"
+
+parser = argparse.ArgumentParser()
+parser.add_argument("arch")
+parser.add_argument("--timeout", type=int, default=300)
+parser.add_argument("--tests", default="tests/selftest")
+
+args = parser.parse_args()
+arch = args.arch
+
+
+def debugfs(image, request):
+    subprocess.run(
+        ["debugfs", "-w", "-R", request, image],
+        check=True,
+        stdout=subprocess.DEVNULL,
+        stderr=subprocess.DEVNULL,
+    )
+
+
+# Put the test scripts into a fresh copy of the rootfs image.
+subprocess.run(["make", "ARCH=" + arch, "rootfs"], check=True)
+image = "arceos/disk.img"
+debugfs(image, "mkdir /selftest")
+tests = sorted(glob.glob(os.path.join(args.tests, "*.sh")))
+for test in tests:
+    debugfs(image, f"write {test} /selftest/{os.path.basename(test)}")
+print(f"Installed {len(tests)} tests into {image}")
+
+qemu_args = "-monitor none"
+if arch == "x86_64":
+    qemu_args += " -device isa-debug-exit,iobase=0xf4,iosize=0x04"
+
+p = subprocess.Popen(
+    [
+        "make",
+        "ARCH=" + arch,
+        "ACCEL=n",
+        "APP_FEATURES=qemu selftest",
+        "QEMU_ARGS=" + qemu_args,
+        "run",
+    ],
+    stdout=subprocess.PIPE,
+    text=True,
+    errors="ignore",
+)
+
+# Kill QEMU if the tests hang.
+timer = threading.Timer(args.timeout, p.kill)
+timer.start()
+
+result = None
+for line in p.stdout:
+    print(line, end="")
+    if line.startswith(MARKER):
+        result = line[len(MARKER) :].strip()
+p.wait()
+timer.cancel()
+
+if result is None:
+    print("\x1b[31m❌ Self-test did not report a result\x1b[0m")
+    sys.exit(2)
+if result != "PASS":
+    print(f"\x1b[31m❌ Self-test failed: {result}\x1b[0m")
+    sys.exit(1)
+print("\x1b[32m✔ Self-test passed\x1b[0m")
@@ -2,6 +2,7 @@ use alloc::{
     string::{String, ToString},
     sync::Arc,
 };
+use core::sync::atomic::AtomicUsize;
 
 use axfs::FS_CONTEXT;
 use axhal::uspace::UserContext;
@@ -9,7 +10,9 @@ use axsync::Mutex;
 use axtask::{AxTaskExt, spawn_task};
 use starry_api::{file::FD_TABLE, task::new_user_task, vfs::dev::tty::N_TTY};
 use starry_core::{
-    mm::{copy_from_kernel, load_user_app, new_user_aspace_empty},
+    mm::{
+        MappedFiles, UserLayout, copy_from_kernel, load_user_app, new_user_aspace_empty, vm_size,
+    },
     task::{ProcessData, Thread, add_task_to_table},
 };
 use starry_process::{Pid, Process};
@@ -31,8 +34,11 @@ pub fn run_initproc(args: &[String], envs: &[String]) -> i32 {
         .expect("Failed to get executable absolute path");
     let name = loc.name();
 
-    let (entry_vaddr, ustack_top) = load_user_app(&mut uspace, None, args, envs)
-        .unwrap_or_else(|e| panic!("Failed to load user app: {}", e));
+    let layout = UserLayout::new(0);
+    let mut mapped_files = MappedFiles::default();
+    let (entry_vaddr, ustack_top) =
+        load_user_app(&mut uspace, &mut mapped_files, None, args, envs, &layout)
+            .unwrap_or_else(|e| panic!("Failed to load user app: {}", e));
 
     let uctx = UserContext::new(entry_vaddr.into(), ustack_top, 0);
 
@@ -43,16 +49,21 @@ pub fn run_initproc(args: &[String], envs: &[String]) -> i32 {
     let proc = Process::new_init(pid);
     proc.add_thread(pid);
 
-    N_TTY.bind_to(&proc).expect("Failed to bind ntty");
+    N_TTY.bind_to(&proc, false).expect("Failed to bind ntty");
 
+    let mapped = vm_size(&uspace);
     let proc_data = ProcessData::new(
         proc,
         path.to_string(),
         Arc::new(args.to_vec()),
         Arc::new(Mutex::new(uspace)),
+        Arc::new(AtomicUsize::new(mapped)),
         Arc::default(),
         None,
     );
+    *proc_data.environ.write() = Arc::new(envs.to_vec());
+    proc_data.set_layout(&layout);
+    *proc_data.mapped_files.lock() = mapped_files;
     {
         let mut scope = proc_data.scope.write();
         starry_api::file::add_stdio(&mut FD_TABLE.scope_mut(&mut scope).write())
@@ -13,8 +13,13 @@ use alloc::{borrow::ToOwned, vec::Vec};
 use axfs::FS_CONTEXT;
 
 mod entry;
+#[cfg(feature = "selftest")]
+mod selftest;
 
+#[cfg(not(feature = "selftest"))]
 pub const CMDLINE: &[&str] = &["/bin/sh", "-c", include_str!("init.sh")];
+#[cfg(feature = "selftest")]
+pub const CMDLINE: &[&str] = &["/bin/sh", "-c", include_str!("selftest.sh")];
 
 #[unsafe(no_mangle)]
 fn main() {
@@ -37,6 +42,10 @@ fn main() {
         .filesystem()
         .flush()
         .expect("Failed to flush rootfs");
+    drop(cx);
+
+    #[cfg(feature = "selftest")]
+    selftest::report(exit_code);
 }
 
 #[cfg(feature = "vf2")]
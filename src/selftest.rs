@@ -0,0 +1,34 @@
+//! Reporting the outcome of a self-test run to the host.
+//!
+//! With the `selftest` feature, the init process runs the test scripts under
+//! /selftest instead of an interactive shell, and exits with the number of
+//! failed tests. The result is printed on the console as a line starting with
+//! [`RESULT_MARKER`], which `scripts/selftest.py` waits for. On x86_64, it is
+//! also written to QEMU's `isa-debug-exit` device so that QEMU exits with a
+//! status telling the two apart; other architectures power off as usual.
+
+/// Prefix of the console line carrying the result.
+const RESULT_MARKER: &str = "STARRY-SELFTEST:";
+
+/// I/O port of QEMU's `isa-debug-exit` device. Writing `value` to it makes
+/// QEMU exit with status `(value << 1) | 1`. Without the device, the write is
+/// ignored.
+#[cfg(target_arch = "x86_64")]
+const ISA_DEBUG_EXIT_PORT: u16 = 0xf4;
+
+pub fn report(exit_code: i32) {
+    if exit_code == 0 {
+        ax_println!("{RESULT_MARKER} PASS");
+    } else {
+        ax_println!("{RESULT_MARKER} FAIL ({exit_code} failed)");
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        core::arch::asm!(
+            "out dx, eax",
+            in("dx") ISA_DEBUG_EXIT_PORT,
+            in("eax") (exit_code != 0) as u32,
+        );
+    }
+}
@@ -0,0 +1,23 @@
+#!/bin/sh
+
+export HOME=/root
+
+# Runs every test script under /selftest, and exits with the number of
+# failures. A test passes if its script exits with 0.
+
+total=0
+failed=0
+for test in /selftest/*.sh; do
+    [ -f "$test" ] || continue
+    total=$((total + 1))
+    echo "=== RUN  $test"
+    if (cd ~ && sh -e "$test"); then
+        echo "=== PASS $test"
+    else
+        echo "=== FAIL $test"
+        failed=$((failed + 1))
+    fi
+done
+
+echo "$((total - failed))/$total tests passed"
+exit $failed
@@ -0,0 +1,35 @@
+# Credentials: a process that drops root may not look into the processes of
+# other users.
+
+as_nobody() {
+    su -s /bin/sh -c "$1" nobody
+}
+
+test "$(id -u)" -eq 0
+test "$(as_nobody 'id -u')" -eq 65534
+test "$(as_nobody 'id -g')" -eq 65534
+as_nobody 'grep -q "^Uid:.65534.65534.65534" /proc/self/status'
+
+# The environment of another user's process is off limits, but not its own.
+cat /proc/1/environ > /dev/null
+if as_nobody 'cat /proc/1/environ' > /dev/null 2>&1; then
+    exit 1
+fi
+as_nobody 'cat /proc/self/environ' > /dev/null
+
+# hidepid=1 denies access to other users' /proc/[pid], and hidepid=2 hides
+# them as well.
+mnt=/tmp/selftest-proc.$$
+mkdir -p "$mnt"
+mount -t proc -o hidepid=1 proc "$mnt"
+as_nobody "test -d $mnt/1"
+if as_nobody "cat $mnt/1/status" > /dev/null 2>&1; then
+    exit 1
+fi
+umount "$mnt"
+mount -t proc -o hidepid=2 proc "$mnt"
+as_nobody "test ! -e $mnt/1"
+as_nobody "test -d $mnt/self/"
+test -d "$mnt/1"
+umount "$mnt"
+rmdir "$mnt"
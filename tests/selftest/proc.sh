@@ -0,0 +1,8 @@
+# The pseudo filesystems are mounted and describe the current process.
+
+test -d /proc/self
+test -d "/proc/$$"
+grep -q '^Name:' /proc/self/status
+test -c /dev/null
+test -c /dev/urandom
+test "$(head -c 16 /dev/urandom | wc -c)" -eq 16
@@ -0,0 +1,13 @@
+# Pipes, redirections, and process creation through the shell.
+
+test "$(echo hello | tr a-z A-Z)" = HELLO
+
+tmp=/tmp/selftest.$$
+mkdir -p /tmp
+seq 1 1000 > "$tmp"
+test "$(wc -l < "$tmp")" -eq 1000
+test "$(sort -rn "$tmp" | head -n 1)" = 1000
+rm "$tmp"
+test ! -e "$tmp"
+
+sh -c 'exit 3' || test $? -eq 3